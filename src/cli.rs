@@ -0,0 +1,235 @@
+// Command line options. Anything left out falls back to the [simulation] section of the
+// config and then to the usual stdin prompts (headless runs never prompt).
+
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::utils::{
+    initializer::{self, DroneImplChoice, InitOptions, CHATAPP},
+    sim_config::{parse_app, parse_drone_impl, SimConfig},
+    topology::TopologySnapshot,
+};
+
+pub const USAGE: &str = "Usage: Code [--config <file>] [--app chat|web] [--drone-impl <name>|<0-10>|mixed] [--seed <n>]
+            [--headless] [--scenario <file>] [--report <file>] [--record <file>]
+       Code --replay <file>
+       Code --editor
+       Code --config <file> --export <file.dot|file.json>
+       Code --conformance [--drone-impl <name>|<0-10>] [--report <file>]";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CliOptions {
+    pub config: Option<PathBuf>,
+    pub app: Option<u8>,
+    pub drone_impl: Option<DroneImplChoice>,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub scenario: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub editor: bool,
+    pub export: Option<PathBuf>,
+    pub conformance: bool,
+    pub help: bool,
+}
+
+// What the run actually uses once cli, config file and prompts have been merged
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub config: PathBuf,
+    pub init: InitOptions,
+    pub headless: bool,
+    pub scenario: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+}
+
+impl CliOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            // both "--key value" and "--key=value"
+            let (key, inline) = match arg.split_once('=') {
+                Some((k, v)) if k.starts_with("--") => (k, Some(v.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| iter.next().cloned())
+                    .ok_or_else(|| format!("Missing value for {}", key))
+            };
+            match key {
+                "--config" => options.config = Some(PathBuf::from(value()?)),
+                "--app" => options.app = Some(parse_app(&value()?)?),
+                "--drone-impl" => options.drone_impl = Some(parse_drone_impl(&value()?)?),
+                "--seed" => {
+                    let v = value()?;
+                    options.seed = Some(v.parse().map_err(|_| format!("Invalid seed {}", v))?);
+                }
+                "--headless" => options.headless = true,
+                "--scenario" => options.scenario = Some(PathBuf::from(value()?)),
+                "--report" => options.report = Some(PathBuf::from(value()?)),
+                "--record" => options.record = Some(PathBuf::from(value()?)),
+                "--replay" => options.replay = Some(PathBuf::from(value()?)),
+                "--editor" => options.editor = true,
+                "--export" => options.export = Some(PathBuf::from(value()?)),
+                "--conformance" => options.conformance = true,
+                "--help" | "-h" => options.help = true,
+                other if other.starts_with('-') => return Err(format!("Unknown option {}", other)),
+                // a bare path is the config
+                other if options.config.is_none() => options.config = Some(PathBuf::from(other)),
+                other => return Err(format!("Unexpected argument {}", other)),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn resolve(self) -> Result<RunOptions, String> {
+        let config = match self.config {
+            Some(path) => {
+                initializer::check_initializer(&path.to_string_lossy()).map_err(|e| {
+                    format!("Config file {} doesn't respect protocol: {}", path.to_string_lossy(), e)
+                })?;
+                path
+            }
+            None if self.headless => return Err("A headless run needs --config".to_string()),
+            None => initializer::ask_config(),
+        };
+        let sim = SimConfig::from_file(&config.to_string_lossy())?.simulation;
+        let headless = self.headless || sim.headless.unwrap_or(false);
+
+        let mut app = self.app.or(sim.app()?);
+        let mut drone_impl = self.drone_impl.or(sim.drone_impl()?);
+        if headless {
+            if app.is_none() {
+                let parsed = initializer::parse_config(&config.to_string_lossy())?;
+                app = Some(initializer::infer_app(&parsed).unwrap_or(CHATAPP));
+            }
+            drone_impl.get_or_insert(DroneImplChoice::Mixed);
+        } else if drone_impl.is_none() && !initializer::ask_one_impl() {
+            drone_impl = Some(DroneImplChoice::Mixed);
+        }
+
+        Ok(RunOptions {
+            config,
+            init: InitOptions {
+                app,
+                drone_impl,
+                seed: self.seed.or(sim.seed),
+            },
+            headless,
+            scenario: self.scenario,
+            report: self.report,
+            record: self.record,
+        })
+    }
+}
+
+// Writes the graph of a config as Graphviz or json, picked by the extension of `out`
+pub fn export_config(config: &Path, out: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(config).map_err(|e| e.to_string())?;
+    let config = toml::from_str(&text).map_err(|e| e.to_string())?;
+    let snapshot = TopologySnapshot::from_config(&config);
+    let text = match out.extension().and_then(|e| e.to_str()) {
+        Some("dot" | "gv") => {
+            let name = out.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            snapshot.to_dot(&name)
+        }
+        Some("json") => snapshot.to_json(),
+        _ => return Err(format!("Don't know how to export to {}", out.to_string_lossy())),
+    };
+    fs::write(out, text).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = CliOptions::from_args(&args(
+            "--config ./configs/config.toml --app web --drone-impl=RustRoveri --seed 42 --headless",
+        ))
+        .unwrap();
+        assert_eq!(options.config, Some(PathBuf::from("./configs/config.toml")));
+        assert_eq!(options.app, Some(crate::utils::initializer::WEBAPP));
+        assert_eq!(options.drone_impl, Some(DroneImplChoice::Single(2)));
+        assert_eq!(options.seed, Some(42));
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn test_positional() {
+        let options =
+            CliOptions::from_args(&args("--headless ./configs/config.toml --drone-impl mixed")).unwrap();
+        assert!(options.headless);
+        assert_eq!(options.config, Some(PathBuf::from("./configs/config.toml")));
+        assert_eq!(options.drone_impl, Some(DroneImplChoice::Mixed));
+        // a config file can be called anything, even headless
+        let options = CliOptions::from_args(&args("headless")).unwrap();
+        assert!(!options.headless);
+        assert_eq!(options.config, Some(PathBuf::from("headless")));
+        assert_eq!(CliOptions::from_args(&[]).unwrap(), CliOptions::default());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let options = CliOptions::from_args(&args("--config ./configs/config.toml --record run.jsonl")).unwrap();
+        assert_eq!(options.record, Some(PathBuf::from("run.jsonl")));
+        let options = CliOptions::from_args(&args("--replay=run.jsonl")).unwrap();
+        assert_eq!(options.replay, Some(PathBuf::from("run.jsonl")));
+        assert!(CliOptions::from_args(&args("--record")).is_err());
+        assert!(CliOptions::from_args(&args("--editor")).unwrap().editor);
+    }
+
+    #[test]
+    fn test_conformance() {
+        let options = CliOptions::from_args(&args("--conformance --drone-impl mock --report matrix.json")).unwrap();
+        assert!(options.conformance);
+        assert_eq!(options.drone_impl, Some(DroneImplChoice::Single(10)));
+        assert_eq!(options.report, Some(PathBuf::from("matrix.json")));
+    }
+
+    #[test]
+    fn test_export() {
+        let options = CliOptions::from_args(&args("./configs/config.toml --export net.dot")).unwrap();
+        assert_eq!(options.export, Some(PathBuf::from("net.dot")));
+
+        let out = std::env::temp_dir().join("cli_export_test.json");
+        export_config(Path::new("./configs/config.toml"), &out).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert!(!json["edges"].as_array().unwrap().is_empty());
+        assert!(export_config(Path::new("./configs/config.toml"), Path::new("net.png")).is_err());
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(CliOptions::from_args(&args("--app")).is_err());
+        assert!(CliOptions::from_args(&args("--app phone")).is_err());
+        assert!(CliOptions::from_args(&args("--seed abc")).is_err());
+        assert!(CliOptions::from_args(&args("--fast")).is_err());
+        assert!(CliOptions::from_args(&args("a.toml b.toml")).is_err());
+        assert!(CliOptions::from_args(&args("--help")).unwrap().help);
+    }
+
+    #[test]
+    fn test_resolve_headless() {
+        let options = CliOptions::from_args(&args("--headless --config ./configs/config.toml"))
+            .unwrap()
+            .resolve()
+            .unwrap();
+        assert_eq!(options.init.app, Some(CHATAPP));
+        assert_eq!(options.init.drone_impl, Some(DroneImplChoice::Mixed));
+        assert!(CliOptions::from_args(&args("--headless")).unwrap().resolve().is_err());
+        // a broken config is an error, not a panic
+        let missing = CliOptions::from_args(&args("--headless --config ./configs/missing.toml")).unwrap();
+        assert!(missing.resolve().unwrap_err().contains("missing.toml"));
+    }
+}
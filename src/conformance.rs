@@ -0,0 +1,461 @@
+// Runs every drone implementation of the registry through the same battery of protocol checks,
+// each one on a fresh drone 2 wired between two fake neighbours 1 and 3, and prints a pass/fail
+// matrix. Implementations run side by side, the checks of one implementation one after the other.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::Path,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::Serialize;
+use wg_2024::{
+    controller::{DroneCommand, DroneEvent},
+    network::{NodeId, SourceRoutingHeader},
+    packet::{FloodRequest, Fragment, Nack, NackType, NodeType, Packet, PacketType},
+};
+
+use crate::utils::{
+    drone_registry::{self, DroneArgs, DRONE_NAMES},
+    mock_drone::MockPolicy,
+};
+
+// How long a check waits for something the drone should send
+const WAIT: Duration = Duration::from_millis(500);
+// Time given to the drone to handle a command before the packet that depends on it
+const SETTLE: Duration = Duration::from_millis(50);
+const DRONE: NodeId = 2;
+const SESSION: u64 = 77;
+
+type Check = fn(&mut Bench) -> Result<(), String>;
+
+// name, PDR of the drone, check
+const CHECKS: [(&str, f32, Check); 14] = [
+    ("forward", 0.0, forward),
+    ("forward_ack", 0.0, forward_ack),
+    ("nack_error_in_routing", 0.0, nack_error_in_routing),
+    ("nack_destination_is_drone", 0.0, nack_destination_is_drone),
+    ("nack_unexpected_recipient", 0.0, nack_unexpected_recipient),
+    ("nack_dropped", 1.0, nack_dropped),
+    ("controller_shortcut", 0.0, controller_shortcut),
+    ("controller_shortcut_nack", 0.0, controller_shortcut_nack),
+    ("flood_forward", 0.0, flood_forward),
+    ("flood_response", 0.0, flood_response),
+    ("set_packet_drop_rate", 0.0, set_packet_drop_rate),
+    ("add_sender", 0.0, add_sender),
+    ("remove_sender", 0.0, remove_sender),
+    ("crash", 0.0, crash),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConformanceReport {
+    pub checks: Vec<String>,
+    pub implementations: Vec<ImplementationResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImplementationResult {
+    pub name: String,
+    pub passed: usize,
+    pub failed: BTreeMap<String, String>, // check and what went wrong
+}
+
+impl ConformanceReport {
+    pub fn all_passed(&self) -> bool {
+        self.implementations.iter().all(|i| i.failed.is_empty())
+    }
+
+    // One row per implementation, one column per check numbered as in the legend
+    pub fn matrix(&self) -> String {
+        let mut out = String::new();
+        for (n, check) in self.checks.iter().enumerate() {
+            out += &format!("{:>3} {}\n", n + 1, check);
+        }
+        let width = DRONE_NAMES.iter().map(|n| n.len()).max().unwrap_or(0);
+        out += &format!("\n{:width$}", "");
+        for n in 1..=self.checks.len() {
+            out += &format!("{:>3}", n);
+        }
+        out += "\n";
+        for implementation in &self.implementations {
+            out += &format!("{:width$}", implementation.name);
+            for check in &self.checks {
+                out += if implementation.failed.contains_key(check) { "  x" } else { "  ." };
+            }
+            out += &format!("  {}/{}\n", implementation.passed, self.checks.len());
+        }
+        for implementation in &self.implementations {
+            for (check, reason) in &implementation.failed {
+                out += &format!("\n{} {}: {}", implementation.name, check, reason);
+            }
+        }
+        out
+    }
+}
+
+// Every implementation, or only `only`; writes the json report to `report` when given
+pub fn run(only: Option<usize>, report: Option<&Path>) -> Result<ConformanceReport, Box<dyn Error + Send + Sync>> {
+    let indices = match only {
+        Some(index) => vec![index],
+        None => (0..DRONE_NAMES.len()).collect(),
+    };
+    let handles = indices
+        .into_iter()
+        .map(|index| thread::spawn(move || check_implementation(index)))
+        .collect::<Vec<_>>();
+    let implementations = handles
+        .into_iter()
+        .map(|handle| handle.join().map_err(|_| "a conformance run panicked"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let result = ConformanceReport {
+        checks: CHECKS.iter().map(|(name, _, _)| name.to_string()).collect(),
+        implementations,
+    };
+    println!("{}", result.matrix());
+    if let Some(path) = report {
+        fs::write(path, serde_json::to_string_pretty(&result)?)?;
+    }
+    Ok(result)
+}
+
+pub fn check_implementation(index: usize) -> ImplementationResult {
+    let mut failed = BTreeMap::new();
+    for (name, pdr, check) in CHECKS {
+        let mut bench = Bench::new(index, pdr);
+        if let Err(reason) = check(&mut bench) {
+            failed.insert(name.to_string(), bench.explain(reason));
+        }
+    }
+    ImplementationResult {
+        name: drone_registry::name_of(index).unwrap_or("unknown").to_string(),
+        passed: CHECKS.len() - failed.len(),
+        failed,
+    }
+}
+
+// A drone with the fake neighbours 1 and 3 and the ends of its controller channels
+struct Bench {
+    commands: Sender<DroneCommand>,
+    events: Receiver<DroneEvent>,
+    packets: Option<Sender<Packet>>, // None once the check let go of the drone
+    neighbours: HashMap<NodeId, Receiver<Packet>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Bench {
+    fn new(index: usize, pdr: f32) -> Self {
+        let (controller_send, events) = unbounded();
+        let (commands, controller_recv) = unbounded();
+        let (packets, packet_recv) = unbounded();
+        let mut packet_send = HashMap::new();
+        let mut neighbours = HashMap::new();
+        for id in [1, 3] {
+            let (send, recv) = unbounded();
+            packet_send.insert(id, send);
+            neighbours.insert(id, recv);
+        }
+        let args = DroneArgs {
+            id: DRONE,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            pdr,
+            policy: MockPolicy::default(),
+            seed: DRONE as u64,
+        };
+        let handle = drone_registry::runner(index).map(|runner| thread::spawn(move || runner(args)));
+        Self { commands, events, packets: Some(packets), neighbours, handle }
+    }
+
+    fn send(&self, packet: Packet) -> Result<(), String> {
+        let packets = self.packets.as_ref().ok_or("the channel of the drone is closed")?;
+        packets.send(packet).map_err(|_| "the drone stopped reading its packets".to_string())
+    }
+
+    fn command(&self, command: DroneCommand) -> Result<(), String> {
+        self.commands.send(command).map_err(|_| "the drone stopped reading its commands".to_string())?;
+        thread::sleep(SETTLE);
+        Ok(())
+    }
+
+    // A new neighbour, given to the drone with AddSender
+    fn add_neighbour(&mut self, id: NodeId) -> Result<(), String> {
+        let (send, recv) = unbounded();
+        self.neighbours.insert(id, recv);
+        self.command(DroneCommand::AddSender(id, send))
+    }
+
+    fn at(&self, id: NodeId) -> Result<Packet, String> {
+        self.neighbours[&id]
+            .recv_timeout(WAIT)
+            .map_err(|_| format!("nothing reached {}", id))
+    }
+
+    fn nothing_at(&self, id: NodeId) -> Result<(), String> {
+        match self.neighbours[&id].recv_timeout(WAIT) {
+            Ok(packet) => Err(format!("{} got an unexpected {}", id, describe(&packet))),
+            Err(_) => Ok(()),
+        }
+    }
+
+    // The first event `wanted` accepts, the others are skipped
+    fn event(&self, what: &str, wanted: fn(&DroneEvent) -> bool) -> Result<DroneEvent, String> {
+        let deadline = Instant::now() + WAIT;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match self.events.recv_timeout(left) {
+                Ok(event) if wanted(&event) => return Ok(event),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Err(format!("no {} event", what))
+    }
+
+    // Whether the drone thread is gone by now
+    fn stopped(&mut self, within: Duration) -> bool {
+        let deadline = Instant::now() + within;
+        while Instant::now() < deadline {
+            if self.handle.as_ref().is_none_or(|handle| handle.is_finished()) {
+                return true;
+            }
+            thread::sleep(SETTLE);
+        }
+        false
+    }
+
+    // Adds why the drone may have stopped answering
+    fn explain(&mut self, reason: String) -> String {
+        let Some(handle) = self.handle.take() else {
+            return "not in the registry".to_string();
+        };
+        if !handle.is_finished() {
+            self.handle = Some(handle);
+            return reason;
+        }
+        match handle.join() {
+            Ok(()) => format!("{}, the drone stopped", reason),
+            Err(_) => format!("{}, the drone panicked", reason),
+        }
+    }
+}
+
+fn fragment(hops: Vec<NodeId>) -> Packet {
+    Packet::new_fragment(SourceRoutingHeader::with_first_hop(hops), SESSION, Fragment::new(0, 1, [7; 128]))
+}
+
+fn ack(hops: Vec<NodeId>) -> Packet {
+    Packet::new_ack(SourceRoutingHeader::with_first_hop(hops), SESSION, 0)
+}
+
+fn nack(hops: Vec<NodeId>) -> Packet {
+    let nack = Nack { fragment_index: 0, nack_type: NackType::Dropped };
+    Packet::new_nack(SourceRoutingHeader::with_first_hop(hops), SESSION, nack)
+}
+
+fn flood_request() -> Packet {
+    let request = FloodRequest { flood_id: 5, initiator_id: 1, path_trace: vec![(1, NodeType::Client)] };
+    Packet::new_flood_request(SourceRoutingHeader::with_first_hop(Vec::new()), SESSION, request)
+}
+
+fn describe(packet: &Packet) -> String {
+    match &packet.pack_type {
+        PacketType::MsgFragment(_) => "fragment".to_string(),
+        PacketType::Ack(_) => "ack".to_string(),
+        PacketType::Nack(nack) => format!("nack {:?}", nack.nack_type),
+        PacketType::FloodRequest(_) => "flood request".to_string(),
+        PacketType::FloodResponse(_) => "flood response".to_string(),
+    }
+}
+
+// The Nack that reached `at`, on a route from the drone back to it
+fn nack_at(bench: &Bench, at: NodeId) -> Result<Nack, String> {
+    let packet = bench.at(at)?;
+    let PacketType::Nack(nack) = &packet.pack_type else {
+        return Err(format!("{} got a {} instead of a nack", at, describe(&packet)));
+    };
+    let hops = &packet.routing_header.hops;
+    if hops.first() != Some(&DRONE) || hops.last() != Some(&at) {
+        return Err(format!("nack routed on {:?}", hops));
+    }
+    Ok(nack.clone())
+}
+
+fn expect_nack(bench: &Bench, wanted: fn(&NackType) -> bool) -> Result<(), String> {
+    let nack = nack_at(bench, 1)?;
+    match wanted(&nack.nack_type) {
+        true => Ok(()),
+        false => Err(format!("wrong nack {:?}", nack.nack_type)),
+    }
+}
+
+fn forward(bench: &mut Bench) -> Result<(), String> {
+    bench.send(fragment(vec![1, DRONE, 3]))?;
+    let packet = bench.at(3)?;
+    match (&packet.pack_type, packet.routing_header.hop_index, packet.session_id) {
+        (PacketType::MsgFragment(_), 2, SESSION) => Ok(()),
+        (_, hop_index, session_id) => Err(format!(
+            "got a {} with hop index {} in session {}",
+            describe(&packet),
+            hop_index,
+            session_id
+        )),
+    }
+}
+
+fn forward_ack(bench: &mut Bench) -> Result<(), String> {
+    bench.send(ack(vec![3, DRONE, 1]))?;
+    match bench.at(1)?.pack_type {
+        PacketType::Ack(_) => Ok(()),
+        _ => Err("the ack changed on the way".to_string()),
+    }
+}
+
+fn nack_error_in_routing(bench: &mut Bench) -> Result<(), String> {
+    bench.send(fragment(vec![1, DRONE, 9]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::ErrorInRouting(9)))
+}
+
+fn nack_destination_is_drone(bench: &mut Bench) -> Result<(), String> {
+    bench.send(fragment(vec![1, DRONE]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::DestinationIsDrone))
+}
+
+fn nack_unexpected_recipient(bench: &mut Bench) -> Result<(), String> {
+    bench.send(fragment(vec![1, 5, 3]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::UnexpectedRecipient(DRONE)))
+}
+
+fn nack_dropped(bench: &mut Bench) -> Result<(), String> {
+    bench.send(fragment(vec![1, DRONE, 3]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::Dropped))?;
+    bench.event("PacketDropped", |event| matches!(event, DroneEvent::PacketDropped(_)))?;
+    bench.nothing_at(3)
+}
+
+// An ack can't be dropped, when its next hop is unknown the controller delivers it
+fn controller_shortcut(bench: &mut Bench) -> Result<(), String> {
+    bench.send(ack(vec![3, DRONE, 9]))?;
+    bench.event("ControllerShortcut", |event| matches!(event, DroneEvent::ControllerShortcut(_)))?;
+    Ok(())
+}
+
+// The same for a nack, it can't be dropped or answered with another nack either
+fn controller_shortcut_nack(bench: &mut Bench) -> Result<(), String> {
+    bench.send(nack(vec![3, DRONE, 9]))?;
+    bench.event("ControllerShortcut", |event| match event {
+        DroneEvent::ControllerShortcut(packet) => matches!(packet.pack_type, PacketType::Nack(_)),
+        _ => false,
+    })?;
+    bench.nothing_at(3)
+}
+
+fn flood_forward(bench: &mut Bench) -> Result<(), String> {
+    bench.send(flood_request())?;
+    let packet = bench.at(3)?;
+    let PacketType::FloodRequest(request) = &packet.pack_type else {
+        return Err(format!("3 got a {}", describe(&packet)));
+    };
+    if request.path_trace.last() != Some(&(DRONE, NodeType::Drone)) {
+        return Err(format!("path trace {:?}", request.path_trace));
+    }
+    bench.nothing_at(1)
+}
+
+// The same flood twice, the second time it goes back as a response
+fn flood_response(bench: &mut Bench) -> Result<(), String> {
+    bench.send(flood_request())?;
+    bench.at(3)?;
+    bench.send(flood_request())?;
+    let packet = bench.at(1)?;
+    let PacketType::FloodResponse(response) = &packet.pack_type else {
+        return Err(format!("1 got a {}", describe(&packet)));
+    };
+    if response.flood_id != 5 || response.path_trace != vec![(1, NodeType::Client), (DRONE, NodeType::Drone)] {
+        return Err(format!("response {:?}", response));
+    }
+    match packet.routing_header.hops.as_slice() {
+        [DRONE, 1] => Ok(()),
+        hops => Err(format!("response routed on {:?}", hops)),
+    }
+}
+
+fn set_packet_drop_rate(bench: &mut Bench) -> Result<(), String> {
+    bench.command(DroneCommand::SetPacketDropRate(1.0))?;
+    bench.send(fragment(vec![1, DRONE, 3]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::Dropped))
+}
+
+fn add_sender(bench: &mut Bench) -> Result<(), String> {
+    bench.add_neighbour(4)?;
+    bench.send(fragment(vec![1, DRONE, 4]))?;
+    bench.at(4).map(|_| ())
+}
+
+fn remove_sender(bench: &mut Bench) -> Result<(), String> {
+    bench.command(DroneCommand::RemoveSender(3))?;
+    bench.send(fragment(vec![1, DRONE, 3]))?;
+    expect_nack(bench, |nack| matches!(nack, NackType::ErrorInRouting(3)))
+}
+
+// Once crashed, fragments are refused and acks still go through; the drone stops when its
+// channel is closed
+fn crash(bench: &mut Bench) -> Result<(), String> {
+    bench.command(DroneCommand::Crash)?;
+    bench.send(fragment(vec![1, DRONE, 3]))?;
+    bench.send(ack(vec![3, DRONE, 1]))?;
+    let (mut refused, mut acked) = (false, false);
+    for _ in 0..2 {
+        match bench.at(1)?.pack_type {
+            PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(DRONE), .. }) => refused = true,
+            PacketType::Ack(_) => acked = true,
+            other => return Err(format!("1 got {:?}", other)),
+        }
+    }
+    if !(refused && acked) {
+        return Err("missing the ErrorInRouting nack or the ack".to_string());
+    }
+    // some stop reading commands once crashed, which is fine
+    bench.command(DroneCommand::RemoveSender(1)).ok();
+    bench.command(DroneCommand::RemoveSender(3)).ok();
+    bench.packets = None;
+    match bench.stopped(WAIT * 2) {
+        true => Ok(()),
+        false => Err("still running after its channel was closed".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mock_conforms() {
+        let result = check_implementation(drone_registry::MOCK);
+        assert_eq!(result.failed, BTreeMap::new());
+        assert_eq!(result.passed, CHECKS.len());
+        assert_eq!(result.name, "Mock");
+    }
+
+    #[test]
+    fn test_matrix() {
+        let report = ConformanceReport {
+            checks: vec!["forward".to_string(), "crash".to_string()],
+            implementations: vec![ImplementationResult {
+                name: "Mock".to_string(),
+                passed: 1,
+                failed: BTreeMap::from([("crash".to_string(), "nothing reached 1".to_string())]),
+            }],
+        };
+        let matrix = report.matrix();
+        assert!(matrix.contains("  2 crash"));
+        assert!(matrix.contains("Mock"));
+        assert!(matrix.contains("  .  x  1/2"));
+        assert!(matrix.ends_with("Mock crash: nothing reached 1"));
+        assert!(!report.all_passed());
+    }
+}
@@ -6,6 +6,9 @@ use bevy::{
     }, input::keyboard::{ KeyCode, KeyboardInput}, prelude::{default, PluginGroup},window::{Window, WindowMode, WindowPlugin, WindowResolution}, winit::{WakeUp, WinitPlugin}, DefaultPlugins
 };
 
+use std::collections::HashMap;
+
+use bevy_egui::egui;
 use bevy_simple_text_input::TextInputPlugin;
 use image::DynamicImage;
 use wg_2024::config::Config;
@@ -28,6 +31,7 @@ pub enum ChatCommand {
     GetServersType,
     GetClients(u8),
     SendMessage(u8, super::utils::fragmentation_handling::Message),
+    CancelTransfer(TransferDirection, u64, u8),
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +40,7 @@ pub enum ChatEvent {
     Clients(Vec<u8>),
     Registered(u8),
     NewMessage(super::utils::fragmentation_handling::ChatMessages),
+    Progress(TransferProgress),
 }
 
 #[derive(Debug, Clone)]
@@ -45,6 +50,7 @@ pub enum WebCommand {
     GetAllMedia(u8),
     GetText(u8, String),
     GetMedia(u8, String),
+    CancelTransfer(TransferDirection, u64, u8),
 }
 
 #[derive(Debug, Clone)]
@@ -58,7 +64,98 @@ pub enum WebEvent {
     ErrNoAllMedia,
     ErrNoAllText,
     ErrMediaNotFound,
-    ErrTextNotFound
+    ErrTextNotFound,
+    Progress(TransferProgress),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// Snapshot of a multi-fragment transfer, sent by the clients to their GUI
+/// every time a fragment is acked (uploads) or received (downloads).
+#[derive(Debug, Clone)]
+pub struct TransferProgress {
+    pub session_id: u64,
+    pub peer: u8,
+    pub direction: TransferDirection,
+    pub done: u64,
+    pub total: u64,
+    pub retransmissions: u64, // fragments resent after a nack (upload) or received twice (download)
+    pub path: Vec<u8>,
+    pub cancelled: bool,
+}
+
+impl TransferProgress {
+    pub fn new(session_id: u64, peer: u8, direction: TransferDirection, total: u64, path: Vec<u8>) -> Self {
+        Self {
+            session_id,
+            peer,
+            direction,
+            done: 0,
+            total,
+            retransmissions: 0,
+            path,
+            cancelled: false,
+        }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.
+        } else {
+            self.done as f32 / self.total as f32
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cancelled || self.done >= self.total
+    }
+}
+
+/// Ongoing transfers of a client at the top of its GUI, one row each.
+/// Returns the key of the transfer whose cancel button was clicked.
+pub fn transfers_panel(
+    ctx: &egui::Context,
+    transfers: &HashMap<(TransferDirection, u64, u8), TransferProgress>,
+) -> Option<(TransferDirection, u64, u8)> {
+    if transfers.is_empty() {
+        return None;
+    }
+    let mut cancel = None;
+    egui::TopBottomPanel::top("transfers_panel").show(ctx, |ui| {
+        for (key, progress) in transfers {
+            if transfer_row(ui, progress) {
+                cancel = Some(*key);
+            }
+        }
+    });
+    cancel
+}
+
+// Returns true when the transfer's cancel button was clicked
+fn transfer_row(ui: &mut egui::Ui, progress: &TransferProgress) -> bool {
+    let mut cancel = false;
+    ui.horizontal(|ui| {
+        let arrow = match progress.direction {
+            TransferDirection::Upload => "⬆",
+            TransferDirection::Download => "⬇",
+        };
+        ui.label(format!("{} {} [{}]", arrow, progress.peer, progress.session_id));
+        ui.add(
+            egui::ProgressBar::new(progress.fraction())
+                .desired_width(200.)
+                .text(format!("{}/{}", progress.done, progress.total)),
+        );
+        ui.label(format!("Retransmissions: {}", progress.retransmissions));
+        ui.label(format!("Path: {:?}", progress.path));
+        if ui.button("✖ Cancel").clicked() {
+            cancel = true;
+        }
+    });
+    cancel
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Resource)]
@@ -78,13 +175,86 @@ enum AppType {
 #[derive(Resource)]
 pub struct Configs(pub Config);
 
-pub fn run_app() {
-    let (config_path, the_one) = utils::initializer::choose_config_cli();
+pub fn run_app(options: crate::cli::RunOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // a broken scenario stops the run before any drone is started, as in headless runs
+    let runner = match &options.scenario {
+        Some(path) => Some(
+            utils::controller::scenario::ScenarioRunner::from_file(&path.to_string_lossy())
+                .map_err(|e| format!("Couldn't load scenario {}: {}", path.to_string_lossy(), e))?,
+        ),
+        None => None,
+    };
+    let (_handles, chat, web, mut simulation_controller, configs) =
+        utils::initializer::initialize_with(&options.config.to_string_lossy(), &options.init)?;
+    if let Some(path) = &options.record {
+        simulation_controller =
+            simulation_controller.with_recorder(utils::controller::trace::TraceRecorder::create(path)?);
+    }
 
-    let (_handles, chat, web, simulation_controller, configs) =
-        utils::initializer::initialize(config_path.to_str().unwrap(),the_one).unwrap();
+    let mut app = bevy::app::App::new();
+    add_window_plugins(&mut app);
 
+    if !chat.is_empty() {
+        app.add_plugins(ChatGuiPlugin {
+            channels: GuiControllers::new(chat),
+        });
+        app.insert_resource(AppType::Chat);
+    }
+    if !web.is_empty() {
+        app.add_plugins(WebGuiPlugin {
+            channels: web_gui::GuiControllers::new(web),
+        });
+        app.insert_resource(AppType::Web);
+    }
+    app.insert_resource(Configs(configs));
+    app.insert_resource(simulation_controller);
+    // nodes with x/y in the config are drawn there
+    if let Ok(sim) = utils::sim_config::SimConfig::from_file(&options.config.to_string_lossy()) {
+        app.insert_resource(utils::controller::layout::NodeLayout::with_pinned(sim.positions()));
+    }
+    if let Some(runner) = runner {
+        app.insert_resource(runner);
+    }
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, upds);
+    app.insert_resource(MainState::Sim);
+    app.add_plugins(super::utils::controller::SimulationControllerPlugin {});
+    app.run();
+    Ok(())
+}
+
+// Only the controller, fed by the trace instead of drones
+pub fn run_replay(path: &std::path::Path) -> Result<(), String> {
+    let (simulation_controller, replay, config) = utils::controller::replay::load_replay(path)?;
+
+    let mut app = bevy::app::App::new();
+    add_window_plugins(&mut app);
+    app.insert_resource(Configs(config));
+    app.insert_resource(simulation_controller);
+    app.insert_resource(replay);
+    app.insert_resource(MainState::Sim);
+    app.add_plugins(super::utils::controller::SimulationControllerPlugin {});
+    app.run();
+    Ok(())
+}
+
+// Only the controller, on an empty topology that is built from the editor window
+pub fn run_editor() {
     let mut app = bevy::app::App::new();
+    add_window_plugins(&mut app);
+    app.insert_resource(Configs(Config {
+        drone: Vec::new(),
+        client: Vec::new(),
+        server: Vec::new(),
+    }));
+    app.insert_resource(utils::controller::editor::editor_controller());
+    app.init_resource::<utils::controller::editor::EditorState>();
+    app.insert_resource(MainState::Sim);
+    app.add_plugins(super::utils::controller::SimulationControllerPlugin {});
+    app.run();
+}
+
+fn add_window_plugins(app: &mut bevy::app::App) {
     let mut winit_plugin = WinitPlugin::<WakeUp>::default();
 
     let exe_path = std::env::current_exe().expect("Failed to get current exe path");
@@ -121,26 +291,6 @@ pub fn run_app() {
         TextInputPlugin,
         winit_plugin,
     ));
-
-    if !chat.is_empty() {
-        app.add_plugins(ChatGuiPlugin {
-            channels: GuiControllers::new(chat),
-        });
-        app.insert_resource(AppType::Chat);
-    }
-    if !web.is_empty() {
-        app.add_plugins(WebGuiPlugin {
-            channels: web_gui::GuiControllers::new(web),
-        });
-        app.insert_resource(AppType::Web);
-    }
-    app.insert_resource(Configs(configs));
-    app.insert_resource(simulation_controller);
-    app.add_systems(Startup, setup);
-    app.add_systems(Update, upds);
-    app.insert_resource(MainState::Sim);
-    app.add_plugins(super::utils::controller::SimulationControllerPlugin {});
-    app.run();
 }
 
 fn setup(_c: Commands) {
@@ -15,7 +15,7 @@ use crossbeam_channel::{Receiver, Sender};
 use image::DynamicImage;
 
 use crate::{
-    frontend::{ChatCommand, ChatEvent},
+    frontend::{ChatCommand, ChatEvent, TransferDirection, TransferProgress, transfers_panel},
     utils::fragmentation_handling::{ChatMessages, Message},
 };
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
@@ -139,6 +139,14 @@ impl GuiChannels {
     pub fn new(receiver: Receiver<ChatEvent>, sender: Sender<ChatCommand>) -> Self {
         Self { receiver, sender }
     }
+
+    pub fn receiver(&self) -> &Receiver<ChatEvent> {
+        &self.receiver
+    }
+
+    pub fn sender(&self) -> &Sender<ChatCommand> {
+        &self.sender
+    }
 }
 
 #[derive(Resource)]
@@ -150,6 +158,11 @@ impl GuiControllers {
     pub fn new(channels: HashMap<u8, GuiChannels>) -> Self {
         Self { channels }
     }
+
+    // Clients added from the controller while the simulation runs
+    pub fn insert(&mut self, id: u8, channels: GuiChannels) {
+        self.channels.insert(id, channels);
+    }
 }
 
 #[derive(Resource, Default, Clone)]
@@ -188,6 +201,7 @@ struct ClientViewState {
     chat_pages: HashMap<(u8,u8), ChatPage>,
     input: HashMap<u8, String>,
     attachments_state: bool,
+    transfers: HashMap<(TransferDirection, u64, u8), TransferProgress>,
 }
 
 #[derive(Resource, Default)]
@@ -325,6 +339,22 @@ fn ui_system(
             }
         });
 
+        // Top: ongoing transfers of the selected client
+        if let Some(client_id) = app_state.selected_source_client {
+            let cancel = app_state
+                .client_states
+                .get(&client_id)
+                .and_then(|state| transfers_panel(ctx, &state.transfers));
+            if let Some((direction, session_id, peer)) = cancel {
+                let _ = channels
+                    .channels
+                    .get(&client_id)
+                    .unwrap()
+                    .sender
+                    .send(ChatCommand::CancelTransfer(direction, session_id, peer));
+            }
+        }
+
         // Center: Chat history
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
@@ -677,6 +707,15 @@ fn ui_system(
                         }
                     }
                 }
+                ChatEvent::Progress(progress) => {
+                    let transfers = &mut app_state.client_states.entry(cli).or_default().transfers;
+                    let key = (progress.direction, progress.session_id, progress.peer);
+                    if progress.is_finished() {
+                        transfers.remove(&key);
+                    } else {
+                        transfers.insert(key, progress);
+                    }
+                }
             }
         }
     }
@@ -9,7 +9,7 @@ use crossbeam_channel::{Receiver, Sender};
 use image::DynamicImage;
 
 use crate::{
-    frontend::{MainState, WebCommand, WebEvent},
+    frontend::{MainState, TransferDirection, TransferProgress, WebCommand, WebEvent, transfers_panel},
     utils::fragmentation_handling::{ContentResponse, DefaultResponse},
 };
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
@@ -67,6 +67,14 @@ impl GuiChannels {
     pub fn new(receiver: Receiver<WebEvent>, sender: Sender<WebCommand>) -> Self {
         Self { receiver, sender }
     }
+
+    pub fn receiver(&self) -> &Receiver<WebEvent> {
+        &self.receiver
+    }
+
+    pub fn sender(&self) -> &Sender<WebCommand> {
+        &self.sender
+    }
 }
 
 #[derive(Resource)]
@@ -78,11 +86,17 @@ impl GuiControllers {
     pub fn new(channels: HashMap<u8, GuiChannels>) -> Self {
         Self { channels }
     }
+
+    // Clients added from the controller while the simulation runs
+    pub fn insert(&mut self, id: u8, channels: GuiChannels) {
+        self.channels.insert(id, channels);
+    }
 }
 
 #[derive(Resource, Default, Clone)]
 struct WebViewState {
     web_pages: WebPage,
+    transfers: HashMap<(TransferDirection, u64, u8), TransferProgress>,
 }
 
 #[derive(Resource, Default)]
@@ -209,6 +223,21 @@ fn ui_system(
             });
         });
     
+        if let Some(client_id) = app_state.selected_source_client {
+            let cancel = app_state
+                .browsers_states
+                .get(&client_id)
+                .and_then(|web_state| transfers_panel(ctx, &web_state.transfers));
+            if let Some((direction, session_id, peer)) = cancel {
+                let _ = channels
+                    .channels
+                    .get(&client_id)
+                    .unwrap()
+                    .sender
+                    .send(WebCommand::CancelTransfer(direction, session_id, peer));
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(client_id) = app_state.selected_source_client {
                 if let Some(web_state) = app_state.browsers_states.get(&client_id) {
@@ -565,6 +594,14 @@ fn ui_system(
                             .content
                             .push(ContentResponse::NOTEXTFOUND);
                     }
+                    WebEvent::Progress(progress) => {
+                        let key = (progress.direction, progress.session_id, progress.peer);
+                        if progress.is_finished() {
+                            view.transfers.remove(&key);
+                        } else {
+                            view.transfers.insert(key, progress);
+                        }
+                    }
                 }
             }
         }
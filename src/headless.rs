@@ -0,0 +1,399 @@
+// Runs a simulation without Bevy: no window, no audio device, no stdin prompts.
+// Clients are driven by a scripted scenario and the outcome is written as a JSON report.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+use wg_2024::{controller::DroneEvent, network::NodeId, packet::PacketType};
+
+use crate::{
+    cli::RunOptions,
+    frontend::{ChatCommand, ChatEvent, WebCommand, WebEvent},
+    utils::{
+        controller::{
+            scenario::{self, ControllerScenario, ScenarioRunner, TimedAction},
+            trace::TraceRecorder,
+            NodeEvent,
+        },
+        drone_registry::DRONE_NAMES,
+        fragmentation_handling::{ChatMessages, Message},
+        initializer,
+    },
+};
+
+// How long the runner keeps collecting events after the last step when the scenario doesn't say
+const DEFAULT_TAIL_SECS: f64 = 5.0;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct Scenario {
+    // seconds to keep running after the last step
+    pub tail: Option<f64>,
+    #[serde(default)]
+    pub step: Vec<Step>,
+    // the controller actions of the same file, see controller::scenario
+    #[serde(default)]
+    pub action: Vec<TimedAction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    pub at: f64, // seconds since the start of the run
+    pub client: u8,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Action {
+    GetServersType,
+    RegisterTo { server: u8 },
+    GetClients { server: u8 },
+    SendMessage { server: u8, to: u8, text: String },
+    GetAllText { server: u8 },
+    GetAllMedia { server: u8 },
+    GetText { server: u8, path: String },
+    GetMedia { server: u8, path: String },
+}
+
+impl Scenario {
+    pub fn load(path: &PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut scenario: Scenario = toml::from_str(&fs::read_to_string(path)?)?;
+        scenario.step.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Ok(scenario)
+    }
+}
+
+impl Step {
+    fn chat_command(&self) -> Option<ChatCommand> {
+        match &self.action {
+            Action::GetServersType => Some(ChatCommand::GetServersType),
+            Action::RegisterTo { server } => Some(ChatCommand::RegisterTo(*server)),
+            Action::GetClients { server } => Some(ChatCommand::GetClients(*server)),
+            Action::SendMessage { server, to, text } => Some(ChatCommand::SendMessage(
+                *server,
+                Message::ChatMessages(ChatMessages::new_string_msg(
+                    self.client,
+                    *server,
+                    *to,
+                    text.clone(),
+                )),
+            )),
+            _ => None,
+        }
+    }
+
+    fn web_command(&self) -> Option<WebCommand> {
+        match &self.action {
+            Action::GetServersType => Some(WebCommand::GetServersType),
+            Action::GetAllText { server } => Some(WebCommand::GetAllText(*server)),
+            Action::GetAllMedia { server } => Some(WebCommand::GetAllMedia(*server)),
+            Action::GetText { server, path } => Some(WebCommand::GetText(*server, path.clone())),
+            Action::GetMedia { server, path } => Some(WebCommand::GetMedia(*server, path.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub config: String,
+    pub app: String,
+    pub seed: Option<u64>,
+    pub drones: BTreeMap<NodeId, String>, // implementation of every drone
+    pub elapsed_secs: f64,
+    pub steps: Vec<StepRecord>,
+    pub actions: Vec<ActionRecord>,
+    pub events: Vec<EventRecord>,
+    pub totals: Totals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepRecord {
+    pub at: f64,
+    pub client: u8,
+    pub command: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActionRecord {
+    pub t: f64,
+    pub action: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventRecord {
+    pub t: f64,
+    pub client: u8,
+    pub kind: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Totals {
+    pub node_packets_sent: u64,
+    pub drone_packets_sent: u64,
+    pub drone_packets_dropped: u64,
+    pub controller_shortcuts: u64,
+    pub fragments: u64,
+    pub acks: u64,
+    pub nacks: u64,
+    pub flood_requests: u64,
+    pub flood_responses: u64,
+    pub client_events: HashMap<String, u64>,
+}
+
+impl Totals {
+    fn count_packet(&mut self, pack_type: &PacketType) {
+        match pack_type {
+            PacketType::MsgFragment(_) => self.fragments += 1,
+            PacketType::Ack(_) => self.acks += 1,
+            PacketType::Nack(_) => self.nacks += 1,
+            PacketType::FloodRequest(_) => self.flood_requests += 1,
+            PacketType::FloodResponse(_) => self.flood_responses += 1,
+        }
+    }
+}
+
+pub fn run(options: &RunOptions) -> Result<Report, Box<dyn Error + Send + Sync>> {
+    // no Bevy LogPlugin here, the startup banner and the actions are logged to stderr so that
+    // stdout only holds the report; a second run in the same process keeps the first subscriber
+    let _ = bevy::log::tracing_subscriber::fmt().with_writer(std::io::stderr).try_init();
+    let scenario = match &options.scenario {
+        Some(path) => Scenario::load(path)
+            .map_err(|e| format!("Couldn't load scenario {}: {}", path.to_string_lossy(), e))?,
+        None => Scenario::default(),
+    };
+    let config_path = options.config.to_string_lossy().to_string();
+    let (_handles, chat, web, mut controller, _config) =
+        initializer::initialize_with(&config_path, &options.init)?;
+    if let Some(path) = &options.record {
+        controller = controller.with_recorder(TraceRecorder::create(path)?);
+    }
+
+    let mut report = Report {
+        config: config_path,
+        app: if chat.is_empty() { "web" } else { "chat" }.to_string(),
+        seed: options.init.seed,
+        drones: controller
+            .drone_impls()
+            .iter()
+            .map(|(id, index)| (*id, DRONE_NAMES[*index].to_string()))
+            .collect(),
+        elapsed_secs: 0.,
+        steps: Vec::new(),
+        actions: Vec::new(),
+        events: Vec::new(),
+        totals: Totals::default(),
+    };
+
+    let mut faults = ScenarioRunner::new(ControllerScenario { action: scenario.action.clone() });
+    let last_step = scenario.step.last().map(|s| s.at).unwrap_or(0.);
+    let end = last_step.max(faults.last_at()) + scenario.tail.unwrap_or(DEFAULT_TAIL_SECS);
+    let start = Instant::now();
+    let mut next_step = 0;
+
+    loop {
+        let now = start.elapsed().as_secs_f64();
+
+        while next_step < scenario.step.len() && scenario.step[next_step].at <= now {
+            let step = &scenario.step[next_step];
+            report.steps.push(send_step(step, &chat, &web, now));
+            next_step += 1;
+        }
+
+        for (t, action) in faults.due_at(now) {
+            let result = scenario::apply(&mut controller, &action);
+            scenario::log_action(t, &action, &result);
+            report.actions.push(ActionRecord {
+                t,
+                action: action.to_string(),
+                error: result.err(),
+            });
+        }
+
+        for (id, channels) in &chat {
+            while let Ok(event) = channels.receiver().try_recv() {
+                let (kind, detail) = describe_chat_event(&event);
+                record_event(&mut report, now, *id, kind, detail);
+            }
+        }
+        for (id, channels) in &web {
+            while let Ok(event) = channels.receiver().try_recv() {
+                let (kind, detail) = describe_web_event(&event);
+                record_event(&mut report, now, *id, kind, detail);
+            }
+        }
+
+        let (drone_events, node_events) = controller.poll_events();
+        for event in drone_events {
+            match event {
+                DroneEvent::PacketSent(packet) => {
+                    report.totals.drone_packets_sent += 1;
+                    report.totals.count_packet(&packet.pack_type);
+                }
+                DroneEvent::PacketDropped(_) => report.totals.drone_packets_dropped += 1,
+                DroneEvent::ControllerShortcut(_) => report.totals.controller_shortcuts += 1,
+            }
+        }
+        for event in node_events {
+            if let NodeEvent::PacketSent(packet) = &event {
+                report.totals.node_packets_sent += 1;
+                report.totals.count_packet(&packet.pack_type);
+            }
+            if let (Some(node), Some((kind, detail))) = (event.node(), event.describe()) {
+                record_event(&mut report, now, node, kind, detail);
+            }
+        }
+
+        if next_step >= scenario.step.len() && faults.is_done() && now >= end {
+            report.elapsed_secs = now;
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let json = serde_json::to_string_pretty(&report)?;
+    match &options.report {
+        Some(path) => fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+    Ok(report)
+}
+
+fn send_step(
+    step: &Step,
+    chat: &HashMap<u8, crate::frontend::chat_gui::GuiChannels>,
+    web: &HashMap<u8, crate::frontend::web_gui::GuiChannels>,
+    now: f64,
+) -> StepRecord {
+    let mut record = StepRecord {
+        at: now,
+        client: step.client,
+        command: format!("{:?}", step.action),
+        error: None,
+    };
+    let result = if let Some(channels) = chat.get(&step.client) {
+        match step.chat_command() {
+            Some(command) => channels.sender().send(command).map_err(|e| e.to_string()),
+            None => Err("Not a chat command".to_string()),
+        }
+    } else if let Some(channels) = web.get(&step.client) {
+        match step.web_command() {
+            Some(command) => channels.sender().send(command).map_err(|e| e.to_string()),
+            None => Err("Not a web command".to_string()),
+        }
+    } else {
+        Err(format!("No client with id {}", step.client))
+    };
+    if let Err(e) = result {
+        eprintln!("Step at {:.2}s for client {} failed: {}", step.at, step.client, e);
+        record.error = Some(e);
+    }
+    record
+}
+
+fn record_event(report: &mut Report, t: f64, client: u8, kind: &str, detail: String) {
+    *report.totals.client_events.entry(kind.to_string()).or_default() += 1;
+    // progress updates are summed up in the totals only, they would drown the log
+    if kind != "progress" {
+        report.events.push(EventRecord {
+            t,
+            client,
+            kind: kind.to_string(),
+            detail,
+        });
+    }
+}
+
+fn describe_chat_event(event: &ChatEvent) -> (&'static str, String) {
+    match event {
+        ChatEvent::Servers(id) => ("servers", id.to_string()),
+        ChatEvent::Clients(ids) => ("clients", format!("{:?}", ids)),
+        ChatEvent::Registered(id) => ("registered", id.to_string()),
+        ChatEvent::NewMessage(msg) => match msg {
+            ChatMessages::CHATSTRING(src, srv, _, text) => {
+                ("new_message", format!("{} via {}: {}", src, srv, text))
+            }
+            ChatMessages::CHATIMAGE(src, srv, _, img) => (
+                "new_message",
+                format!("{} via {}: image {}x{}", src, srv, img.width(), img.height()),
+            ),
+            ChatMessages::CHATAUDIO(src, srv, _, track) => (
+                "new_message",
+                format!("{} via {}: audio {} bytes", src, srv, track.bytes.len()),
+            ),
+        },
+        ChatEvent::Progress(progress) => (
+            "progress",
+            format!("{}/{}", progress.done, progress.total),
+        ),
+    }
+}
+
+fn describe_web_event(event: &WebEvent) -> (&'static str, String) {
+    match event {
+        WebEvent::Servers(server_type, id) => ("servers", format!("{} type {}", id, server_type)),
+        WebEvent::AllMedia(links) => ("all_media", format!("{:?}", links)),
+        WebEvent::AllText(links) => ("all_text", format!("{:?}", links)),
+        WebEvent::Audio(track) => ("audio", format!("{} bytes", track.bytes.len())),
+        WebEvent::Image(img) => ("image", format!("{}x{}", img.width(), img.height())),
+        WebEvent::Text(lines) => ("text", format!("{} lines", lines.len())),
+        WebEvent::ErrNoAllMedia => ("err_no_all_media", String::new()),
+        WebEvent::ErrNoAllText => ("err_no_all_text", String::new()),
+        WebEvent::ErrMediaNotFound => ("err_media_not_found", String::new()),
+        WebEvent::ErrTextNotFound => ("err_text_not_found", String::new()),
+        WebEvent::Progress(progress) => (
+            "progress",
+            format!("{}/{}", progress.done, progress.total),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            tail = 2.0
+
+            [[step]]
+            at = 1.5
+            client = 21
+            command = "register_to"
+            server = 31
+
+            [[step]]
+            at = 0.5
+            client = 21
+            command = "get_servers_type"
+
+            [[action]]
+            at = 3.0
+            kind = "crash"
+            drone = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.step.len(), 2);
+        let faults = ScenarioRunner::new(ControllerScenario { action: scenario.action.clone() });
+        assert_eq!(faults.last_at(), 3.0);
+        assert!(matches!(scenario.step[0].action, Action::RegisterTo { server: 31 }));
+        assert!(matches!(scenario.step[1].chat_command(), Some(ChatCommand::GetServersType)));
+        assert!(scenario.step[0].web_command().is_none());
+    }
+}
@@ -1,8 +1,75 @@
 // #![windows_subsystem = "windows"] // uncomment in Windows to
+pub mod cli;
+pub mod conformance;
 pub mod frontend;
+pub mod headless;
 pub mod utils;
 
+use utils::initializer::DroneImplChoice;
+
 
 fn main() {
-    frontend::run_app();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::CliOptions::from_args(&args) {
+        Ok(cli::CliOptions { help: true, .. }) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        // a replay needs nothing else, the trace holds the topology
+        Ok(cli::CliOptions { replay: Some(path), .. }) => {
+            if let Err(e) = frontend::run_replay(&path) {
+                eprintln!("Couldn't replay {}: {}", path.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        // the editor starts from an empty canvas, no config needed either
+        Ok(cli::CliOptions { editor: true, .. }) => {
+            frontend::run_editor();
+            return;
+        }
+        // dump the graph of a config for graphviz or a diff, nothing runs
+        Ok(cli::CliOptions { export: Some(out), config: Some(config), .. }) => {
+            if let Err(e) = cli::export_config(&config, &out) {
+                eprintln!("Couldn't export {}: {}", config.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(cli::CliOptions { export: Some(_), .. }) => Err("--export needs --config".to_string()),
+        // every drone implementation against the protocol, exits with 1 when one of them fails
+        Ok(cli::CliOptions { conformance: true, drone_impl, report, .. }) => {
+            let only = match drone_impl {
+                Some(DroneImplChoice::Single(index)) => Some(index),
+                _ => None,
+            };
+            match conformance::run(only, report.as_deref()) {
+                Ok(result) if result.all_passed() => return,
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Conformance run failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(options) => options.resolve(),
+        Err(e) => Err(e),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.headless {
+        if let Err(e) = headless::run(&options) {
+            eprintln!("Headless run failed: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Err(e) = frontend::run_app(options) {
+        eprintln!("Run failed: {}", e);
+        std::process::exit(1);
+    }
 }
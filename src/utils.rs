@@ -1,7 +1,13 @@
 pub mod backup_server;
 pub mod client;
 pub mod controller;
+pub mod drone_registry;
+pub mod flood;
 pub mod fragmentation_handling;
 pub mod initializer;
+pub mod links;
+pub mod mock_drone;
 pub mod server;
+pub mod sessions;
+pub mod sim_config;
 pub mod topology;
@@ -5,16 +5,18 @@ use bevy::audio::AudioSource;
 use bevy::log::info;
 use crossbeam_channel::*;
 
-use rand::RngCore;
-use rand::rngs::OsRng;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 
+use super::flood::{FloodPolicy, FloodReason, FloodScheduler};
+use super::sessions::{SessionGc, SessionPolicy};
 use super::topology::*;
 use std::{
     collections::{HashMap, HashSet},
     fs::{self, File},
     io::BufRead,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use wg_2024::{network::*, packet::*};
 
@@ -43,6 +45,10 @@ pub struct Server {
     holder_frag_index: HashMap<(u64, NodeId), Vec<u64>>, //fragment indices holder for received packets, use session_id,src_id tuple as key
     holder_rec: HashMap<(u64, NodeId), Vec<u8>>, //data holder of received messages, use session_id,src_id tuple as key
     chatters: HashSet<NodeId>,
+    rng: StdRng, // session and flood ids, seeded for reproducible runs
+    crashed: bool, // after NodeCommand::Crash packets and gui commands are dropped until a Restart
+    flood_scheduler: FloodScheduler, // when to flood next
+    sessions: SessionGc, // timeouts and budgets of the holders and flood ids
 }
 
 impl Server {
@@ -67,9 +73,28 @@ impl Server {
             holder_frag_index: HashMap::new(),
             holder_rec: HashMap::new(),
             chatters: HashSet::new(),
+            rng: StdRng::from_entropy(),
+            crashed: false,
+            flood_scheduler: FloodScheduler::new(FloodPolicy::default(), Instant::now()),
+            sessions: SessionGc::new(SessionPolicy::default()),
         }
     }
 
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn with_flood_policy(mut self, policy: FloodPolicy) -> Self {
+        self.flood_scheduler = FloodScheduler::new(policy, Instant::now());
+        self
+    }
+
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.sessions = SessionGc::new(policy);
+        self
+    }
+
     fn get_type(&self) -> u8 {
         self.serv_type
     }
@@ -213,6 +238,17 @@ impl Server {
                         );
                     }
                 }
+                DefaultsRequest::CANCEL(cancelled) => {
+                    // the client gave up: stop resending what we were sending it
+                    // and drop whatever we were reassembling from it
+                    info!("Client {} cancelled session {}", src_id, cancelled);
+                    if let Some(holder) = self.holder_sent.get_mut(&(*cancelled, self.id)) {
+                        holder.clear();
+                    }
+                    self.holder_rec.remove(&(*cancelled, src_id));
+                    self.holder_frag_index.remove(&(*cancelled, src_id));
+                    self.sessions.finish((*cancelled, src_id), Instant::now());
+                }
             },
             Message::ContentRequest(cr) => match &cr {
                 ContentRequest::GETMEDIA(path) => {
@@ -365,6 +401,17 @@ impl Server {
             },
             _ => {}
         }
+        // a cancel only stops what was going on, there is no answer to it
+        if !matches!(request, Message::DefaultsRequest(DefaultsRequest::CANCEL(_))) {
+            self.controller_send
+                .send(NodeEvent::RequestServed {
+                    node: self.id,
+                    client: src_id,
+                    session_id,
+                    request: request_name(&request),
+                })
+                .ok();
+        }
     }
     fn handle_packet(&mut self, packet: Packet) {
         match packet.clone().pack_type {
@@ -439,12 +486,14 @@ impl Server {
 
     pub fn handle_channels(&mut self) {
         loop {
+            let wait = self.flood_if_due().min(self.collect_garbage());
             select_biased! {
                 recv(self.controller_recv) -> command_res => {
                     if let Ok(command) = command_res {
                         match command {
                             NodeCommand::AddSender(id,sender)=>{
                                 self.packet_send.insert(id, sender);
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             },
                             NodeCommand::RemoveSender(id)=>{
                                 info!("\n\n\nRemove sender to: {}\n\n\n",id);
@@ -455,25 +504,100 @@ impl Server {
                                         std::thread::sleep(Duration::from_millis(1000));
                                     }
                                 }
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             }
+                            NodeCommand::Crash=>{
+                                info!("Server[{}] crashed", self.id);
+                                self.crashed = true;
+                                self.reset();
+                            },
+                            NodeCommand::Restart=>{
+                                info!("Server[{}] restarted", self.id);
+                                self.crashed = false;
+                                self.reset();
+                                self.flood_scheduler.restart(Instant::now());
+                            },
+                            NodeCommand::ShowTopology(reply)=>{
+                                let _ = reply.send(self.server_topology.snapshot());
+                            },
                         }
+                    } else {
+                        // the controller is gone, nobody can crash or restart this server anymore
+                        return;
                     }
                 },
                 recv(self.packet_recv) -> packet_res => {
-                    if let Ok(packet) = packet_res {
+                    if let (Ok(packet), false) = (packet_res, self.crashed) {
                         self.handle_packet(packet.clone());
                     }
                 },
-                default(Duration::from_secs(5)) => {
-                    let mut session_id = 0;
-                    while self.session_id_already_used(session_id) {
-                        session_id = rand_session_id();
-                    }
-                    let flood_id = generate_flood_id(&mut self.flood_ids,self.id);
-                    let _= self.send_new_flood_request(session_id, flood_id);
-                }
+                default(wait) => {}
+            }
+        }
+    }
+
+    // Frees the buffers of reassemblies that won't complete
+    fn drop_reassemblies(&mut self, keys: &[(u64, NodeId)]) {
+        for key in keys {
+            self.holder_rec.remove(key);
+            self.holder_frag_index.remove(key);
+        }
+    }
+
+    // Drops the sessions that are over or timed out, says how long until it looks again
+    fn collect_garbage(&mut self) -> Duration {
+        let now = Instant::now();
+        let holder_sent = &self.holder_sent;
+        let acked = |key: &(u64, NodeId)| holder_sent.get(key).is_none_or(|holder| holder.is_empty());
+        if let Some(sweep) = self.sessions.sweep(now, acked) {
+            self.drop_reassemblies(&sweep.incoming);
+            for key in &sweep.outgoing {
+                self.holder_sent.remove(key);
+            }
+            for key in &sweep.flood_ids {
+                self.flood_ids.remove(key);
+            }
+            if !sweep.stats.is_empty() {
+                self.controller_send
+                    .send(NodeEvent::SessionsEvicted { node: self.id, evicted: sweep.stats })
+                    .ok();
             }
         }
+        self.sessions.until_sweep(now)
+    }
+
+    // Sends the flood that is due, if any, and says how long the loop can wait for the next one
+    fn flood_if_due(&mut self) -> Duration {
+        if self.crashed {
+            return Duration::from_secs(1);
+        }
+        let now = Instant::now();
+        if let Some(reason) = self.flood_scheduler.poll(now) {
+            info!("Server[{}] flood {} ({:?})", self.id, self.flood_scheduler.floods(), reason);
+            self.flood();
+        }
+        self.flood_scheduler.timeout(now)
+    }
+
+    fn flood(&mut self) {
+        let mut session_id = 0;
+        while self.session_id_already_used(session_id) {
+            session_id = rand_session_id(&mut self.rng);
+        }
+        let flood_id = generate_flood_id(&mut self.flood_ids, self.id, &mut self.rng);
+        self.sessions.flood_seen((flood_id, self.id), Instant::now());
+        let _= self.send_new_flood_request(session_id, flood_id);
+    }
+
+    // Forgets everything learned since the start, links to the neighbours are kept
+    fn reset(&mut self) {
+        self.sessions.clear();
+        self.server_topology = Topology::new();
+        self.flood_ids.clear();
+        self.holder_sent.clear();
+        self.holder_frag_index.clear();
+        self.holder_rec.clear();
+        self.chatters.clear();
     }
 
     fn send_new_flood_request(&mut self, session_id: u64, flood_id: u64) -> Result<(), &str> {
@@ -558,10 +682,52 @@ impl Server {
                 return Err("No sender found");
             }
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
 
+    // Resends a nacked fragment on the best path left, gives up after MAX_RESEND_ATTEMPTS
+    fn resend_fragment(
+        &mut self,
+        session_id: u64,
+        sent: &Packet,
+        fragment: Fragment,
+        avoid: NodeId,
+    ) -> Result<(), &'static str> {
+        let dst = *sent.routing_header.hops.last().unwrap();
+        for _ in 0..MAX_RESEND_ATTEMPTS {
+            self.server_topology.set_path_based_on_dst(dst);
+            if self.send_new_generic_fragment(dst, session_id, fragment.clone()).is_ok() {
+                if let Some((new_path, _)) = self.server_topology.get_current_path() {
+                    if new_path != sent.routing_header.hops {
+                        self.controller_send
+                            .send(NodeEvent::Rerouted {
+                                node: self.id,
+                                session_id,
+                                old_path: sent.routing_header.hops.clone(),
+                                new_path,
+                            })
+                            .ok();
+                    }
+                }
+                return Ok(());
+            }
+            // increase weights for the failed path to avoid it
+            self.server_topology.increment_weights_for_node(avoid);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        self.controller_send
+            .send(NodeEvent::RetryExhausted {
+                node: self.id,
+                session_id,
+                fragment_index: fragment.fragment_index,
+                attempts: MAX_RESEND_ATTEMPTS,
+            })
+            .ok();
+        Err("Gave up resending the fragment")
+    }
+
     fn send_new_generic_fragment(
         &mut self,
         server_id: NodeId,
@@ -598,6 +764,7 @@ impl Server {
         } else {
             self.server_topology.find_all_paths(self.id,server_id);
             // info!("No current path");
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
@@ -606,9 +773,10 @@ impl Server {
         self.server_topology.find_all_paths(self.id, dst);
         self.server_topology.set_path_based_on_dst(dst);
         self.server_topology.get_current_path();
-        let mut session_id = 0;
+        // random, the receiver remembers the ids of finished sessions for a while
+        let mut session_id = rand_session_id(&mut self.rng);
         while self.session_id_already_used(session_id) {
-            session_id = rand_session_id();
+            session_id = rand_session_id(&mut self.rng);
         }
 
         let hops = self.get_hops(dst);
@@ -620,6 +788,7 @@ impl Server {
             let packets = fragment_packetization(&mut frags, hops, session_id);
             self.holder_sent
                 .insert((session_id, self.id), packets.clone());
+            self.sessions.sent((session_id, self.id), Instant::now());
             for packet in packets {
                 self.send_new_packet(packet).ok();
             }
@@ -646,8 +815,16 @@ impl Server {
     }
 
     fn recv_flood_response_n_handle(&mut self, flood_packet: FloodResponse) -> Result<(), &str> {
+        let before = self.server_topology.size();
         self.server_topology
             .update_topology((self.id, NodeType::Server), flood_packet.path_trace.clone());
+        let (nodes, edges) = self.server_topology.size();
+        if (nodes, edges) != before {
+            self.flood_scheduler.topology_changed();
+            self.controller_send
+                .send(NodeEvent::TopologyUpdated { node: self.id, nodes, edges })
+                .ok();
+        }
         // println!("Path trace in server {:?}", flood_packet.path_trace.clone());
         let cl = self.server_topology.get_all_clients();
         if !cl.is_empty() {
@@ -672,6 +849,8 @@ impl Server {
         {
             self.flood_ids
                 .insert((flood_packet.flood_id, flood_packet.initiator_id));
+            self.sessions
+                .flood_seen((flood_packet.flood_id, flood_packet.initiator_id), Instant::now());
         }
         let mut hops = path_trace
             .clone()
@@ -700,11 +879,6 @@ impl Server {
         nack: Nack,
         packet: &Packet,
     ) -> Result<(), &str> {
-        let flood_id = generate_flood_id(&mut self.flood_ids, self.id);
-        let mut session = 0;
-        while self.session_id_already_used(session) {
-            session = rand_session_id();
-        }
         match nack.clone().nack_type {
             NackType::DestinationIsDrone => {
                 //check route, it shouldn't happen if the routing was done right
@@ -716,22 +890,7 @@ impl Server {
                                 if f.fragment_index == nack.fragment_index {
                                     self.server_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.server_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.server_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(10));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -755,8 +914,6 @@ impl Server {
                 }
             }
             NackType::Dropped => {
-                self.send_new_flood_request(session, flood_id).ok();
-
                 //update weight of the path used and change it there's one with less
                 // println!("Dropped by drone nacked");
 
@@ -767,22 +924,7 @@ impl Server {
                                 if f.fragment_index == nack.fragment_index {
                                     self.server_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.server_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.server_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                            std::thread::sleep(Duration::from_millis(10));
-                                        }
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             _ => {
@@ -798,6 +940,7 @@ impl Server {
                 // println!("Error in routing nacked");
                 //Could be a drone in crash mode so remove the node id from topology and update it
                 self.server_topology.remove_node(id);
+                self.flood_scheduler.trigger(FloodReason::ErrorInRouting, Instant::now());
 
                 if let Some(packets) = { self.holder_sent.get(&(session_id, self.id)).cloned() } {
                     //update the path since it might mean a drone has crashed or bad routing
@@ -806,22 +949,7 @@ impl Server {
                         match p.clone().pack_type {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
-                                    loop {
-                                        self.server_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.server_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(10));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -852,22 +980,7 @@ impl Server {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
                                     // self.server_topology.increment_weights_for_node(id);
-                                    loop {
-                                        self.server_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.server_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(10));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -924,6 +1037,8 @@ impl Server {
                     .get_mut(&(session_id, self.id))
                     .unwrap()
                     .remove(i);
+
+                self.sessions.sent((session_id, self.id), Instant::now());
                 return Ok(());
             } else {
                 return Err("Fragment Index was not supposed to be 0");
@@ -943,7 +1058,12 @@ impl Server {
         self.server_topology.find_all_paths(self.id, src);
         self.server_topology.set_path_based_on_dst(src);
         self.send_ack(session_id, &src, frag.fragment_index).ok();
+        // finished, cancelled or given up on, a late fragment doesn't start it over
+        if self.sessions.is_over(&(session_id, src)) {
+            return None;
+        }
         if let Some(holder) = self.holder_frag_index.get_mut(&(session_id, src)) {
+            self.sessions.received((session_id, src), Instant::now());
             if !holder.contains(&frag.fragment_index) {
                 // println!("Fragm n: 1  < n <  tot");
                 let target = self.holder_rec.get_mut(&(session_id, src)).unwrap();
@@ -960,6 +1080,7 @@ impl Server {
             if holder.len() == (frag.total_n_fragments) as usize {
                 if let Some(mut data) = self.holder_rec.get_mut(&(session_id, src)) {
                     remove_trailing_zeros(&mut data);
+                    let (kind, size) = (message_kind(data[0]).unwrap_or("Unknown"), data.len());
                     let mut f_serialized = serialize(data.clone());
                     let result = super::fragmentation_handling::reconstruct_message(
                         data[0],
@@ -968,10 +1089,27 @@ impl Server {
                     if let Ok(msg) = result {
                         self.holder_rec.remove(&(session_id, src));
                         self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::MessageDelivered {
+                                node: self.id,
+                                from: src,
+                                session_id,
+                                kind: kind.to_string(),
+                                size,
+                            })
+                            .ok();
                         // println!("Message Reconstructed");
                         return Some(msg.clone());
                     } else {
                         // println!("Message reconstruction failed");
+                        // a broken message never completes, its buffers would only take space
+                        self.holder_rec.remove(&(session_id, src));
+                        self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::ReassemblyFailed { node: self.id, from: src, session_id })
+                            .ok();
                         return None;
                     }
                 }
@@ -979,6 +1117,13 @@ impl Server {
             
             None
         } else {
+            match self.sessions.admit((session_id, src), frag.total_n_fragments, Instant::now()) {
+                Ok(evicted) => self.drop_reassemblies(&evicted),
+                Err(e) => {
+                    info!("Server[{}] refused session {}: {}", self.id, session_id, e);
+                    return None;
+                }
+            }
             self.holder_rec.insert(
                 (session_id, src),
                 vec![0; (frag.clone().total_n_fragments * 128) as usize],
@@ -1011,14 +1156,15 @@ impl Server {
         if let Some(hops) = self.server_topology.get_current_path(){
             Some(hops.0.clone())
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             None
         }
     }
 }
 
-fn rand_session_id() -> u64 {
+fn rand_session_id(rng: &mut impl RngCore) -> u64 {
     let mut bytes = [0u8; 8];
-    OsRng.fill_bytes(&mut bytes);
+    rng.fill_bytes(&mut bytes);
     u64::from_ne_bytes(bytes)
 }
 fn fragment_packetization(
@@ -1050,12 +1196,12 @@ fn remove_trailing_zeros(vec: &mut Vec<u8>) {
     }
 }
 
-fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId) -> u64 {
-    let mut rng = 1;
-    while !flood_ids.insert((rng, id)) {
-        rng = rand::random::<u64>();
+fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId, rng: &mut impl RngCore) -> u64 {
+    let mut flood_id = 1;
+    while !flood_ids.insert((flood_id, id)) {
+        flood_id = rng.next_u64();
     }
-    rng
+    flood_id
 }
 
 fn update_holder_rec(
@@ -1,18 +1,21 @@
 use super::super::super::frontend::ChatCommand;
 use super::super::super::frontend::ChatEvent;
+use super::super::super::frontend::{TransferDirection, TransferProgress};
 use super::super::controller::*;
 use super::super::fragmentation_handling::DefaultsRequest;
 use super::super::fragmentation_handling::*;
 use bevy::log::info;
 use crossbeam_channel::*;
-use rand::RngCore;
-use rand::rngs::OsRng;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 
+use super::super::flood::{FloodPolicy, FloodReason, FloodScheduler};
+use super::super::sessions::{SessionGc, SessionPolicy};
 use super::super::topology::*;
 use std::thread;
 use std::{
     collections::{HashMap, HashSet},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use wg_2024::{network::*, packet::*};
 
@@ -51,6 +54,12 @@ pub struct ChatClient {
     sent: HashMap<(u64, u8), Message>,
     gui_command_receiver: Receiver<ChatCommand>,
     gui_event_sender: Sender<ChatEvent>,
+    transfers: HashMap<(u64, NodeId), TransferProgress>, //progress of ongoing transfers, same keys as the holders
+    cancelled: HashSet<(u64, NodeId)>, //downloads cancelled from the gui, late fragments are only acked
+    rng: StdRng, // session and flood ids, seeded for reproducible runs
+    crashed: bool, // after NodeCommand::Crash packets and gui commands are dropped until a Restart
+    flood_scheduler: FloodScheduler, // when to flood next
+    sessions: SessionGc, // timeouts and budgets of the holders and flood ids
 }
 
 impl ChatClient {
@@ -80,9 +89,30 @@ impl ChatClient {
             sent: HashMap::new(),
             gui_command_receiver,
             gui_event_sender,
+            transfers: HashMap::new(),
+            cancelled: HashSet::new(),
+            rng: StdRng::from_entropy(),
+            crashed: false,
+            flood_scheduler: FloodScheduler::new(FloodPolicy::default(), Instant::now()),
+            sessions: SessionGc::new(SessionPolicy::default()),
         }
     }
 
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn with_flood_policy(mut self, policy: FloodPolicy) -> Self {
+        self.flood_scheduler = FloodScheduler::new(policy, Instant::now());
+        self
+    }
+
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.sessions = SessionGc::new(policy);
+        self
+    }
+
     fn send_register(&mut self, dst: NodeId) -> Result<(), String> {
         let new_req = Message::DefaultsRequest(DefaultsRequest::REGISTER);
         self.send_from_chat_client(dst, new_req)
@@ -106,9 +136,10 @@ impl ChatClient {
         match deconstruct_message(msg.clone()) {
             Ok(bytes_res) => {
                 let mut fragments: Vec<Fragment> = serialize(bytes_res);
-                let mut session_id = 0;
+                // random, the receiver remembers the ids of finished sessions for a while
+                let mut session_id = rand_session_id(&mut self.rng);
                 while self.session_id_alredy_used(session_id) {
-                    session_id = rand_session_id();
+                    session_id = rand_session_id(&mut self.rng);
                 }
                 self.client_topology.find_all_paths(self.id, dst);
                 self.client_topology.set_path_based_on_dst(dst);
@@ -118,6 +149,20 @@ impl ChatClient {
                     self.sent.insert((session_id, self.id), msg.clone());
                     self.holder_sent
                         .insert((session_id, self.id), packets.clone());
+                    self.sessions.sent((session_id, self.id), Instant::now());
+                    if !matches!(msg, Message::DefaultsRequest(_)) {
+                        self.transfers.insert(
+                            (session_id, self.id),
+                            TransferProgress::new(
+                                session_id,
+                                dst,
+                                TransferDirection::Upload,
+                                packets.len() as u64,
+                                hops.clone().unwrap_or_default(),
+                            ),
+                        );
+                        self.report_progress((session_id, self.id));
+                    }
                     for pack in packets {
                         match self.send_new_packet(&pack.clone()) {
                             Ok(_) => {
@@ -297,6 +342,7 @@ impl ChatClient {
                     packet.session_id,
                     packet.clone().routing_header.hops[0],
                     &fragment,
+                    &packet.routing_header.hops,
                 ) {
                     Some(m) => {
                         // println!("Handled Frag in Client");
@@ -317,6 +363,7 @@ impl ChatClient {
 
     pub fn handle_channels(&mut self) {
         loop {
+            let wait = self.flood_if_due().min(self.collect_garbage());
             select_biased! {
                 recv(self.controller_recv) -> command_res => {
                     if let Ok(command) = command_res {
@@ -324,6 +371,7 @@ impl ChatClient {
                             NodeCommand::AddSender(id,sender)=>{
                                 info!("\n\n\nAdd sender to: {}\n\n\n",id);
                                 self.packet_send.insert(id, sender);
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             },
                             NodeCommand::RemoveSender(id)=>{
                                 info!("\n\n\nRemove sender to: {}\n\n\n",id);
@@ -334,17 +382,35 @@ impl ChatClient {
                                         thread::sleep(Duration::from_millis(1000));
                                     }
                                 }
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             }
+                            NodeCommand::Crash=>{
+                                info!("Client[{}] crashed", self.id);
+                                self.crashed = true;
+                                self.reset();
+                            },
+                            NodeCommand::Restart=>{
+                                info!("Client[{}] restarted", self.id);
+                                self.crashed = false;
+                                self.reset();
+                                self.flood_scheduler.restart(Instant::now());
+                            },
+                            NodeCommand::ShowTopology(reply)=>{
+                                let _ = reply.send(self.client_topology.snapshot());
+                            },
                         }
+                    } else {
+                        // the controller is gone, nobody can crash or restart this client anymore
+                        return;
                     }
                 },
                 recv(self.packet_recv) -> packet_res => {
-                    if let Ok(packet) = packet_res {
+                    if let (Ok(packet), false) = (packet_res, self.crashed) {
                         self.handle_packet(packet.clone());
                     }
                 },
                 recv(self.gui_command_receiver) -> gui_command => {
-                    if let Ok(command) = gui_command {
+                    if let (Ok(command), false) = (gui_command, self.crashed) {
                         match command {
                             ChatCommand::RegisterTo(dst)=>{
                                 self.send_register(dst).ok();
@@ -360,19 +426,96 @@ impl ChatClient {
                             ChatCommand::SendMessage(dst,msg) =>{
                                 self.send_msg_to(dst, msg).ok();
                             },
+                            ChatCommand::CancelTransfer(direction,session_id,peer) =>{
+                                self.cancel_transfer(direction, session_id, peer);
+                            },
                         }
                     }
                 },
-                default(Duration::from_secs(5)) => {
-                    let mut session_id = 0;
-                    while self.session_id_alredy_used(session_id) {
-                        session_id = rand_session_id();
-                    }
-                    let flood_id = generate_flood_id(&mut self.flood_ids,self.id);
-                    let _= self.send_new_flood_request(session_id, flood_id);
+                default(wait) => {}
+            }
+        }
+    }
+
+    // Frees the buffers of reassemblies that won't complete, the gui sees them as cancelled
+    fn drop_reassemblies(&mut self, keys: &[(u64, NodeId)]) {
+        for key in keys {
+            self.holder_rec.remove(key);
+            self.holder_frag_index.remove(key);
+            if let Some(progress) = self.transfers.get_mut(key) {
+                progress.cancelled = true;
+            }
+            self.report_progress(*key);
+        }
+    }
+
+    // Drops the sessions that are over or timed out, says how long until it looks again
+    fn collect_garbage(&mut self) -> Duration {
+        let now = Instant::now();
+        let holder_sent = &self.holder_sent;
+        let acked = |key: &(u64, NodeId)| holder_sent.get(key).is_none_or(|holder| holder.is_empty());
+        if let Some(sweep) = self.sessions.sweep(now, acked) {
+            self.drop_reassemblies(&sweep.incoming);
+            for key in &sweep.outgoing {
+                self.holder_sent.remove(key);
+                self.sent.remove(key);
+                if let Some(progress) = self.transfers.get_mut(key) {
+                    progress.cancelled = true;
                 }
+                self.report_progress(*key);
+            }
+            for key in &sweep.finished {
+                self.cancelled.remove(key);
             }
+            for key in &sweep.flood_ids {
+                self.flood_ids.remove(key);
+            }
+            if !sweep.stats.is_empty() {
+                self.controller_send
+                    .send(NodeEvent::SessionsEvicted { node: self.id, evicted: sweep.stats })
+                    .ok();
+            }
+        }
+        self.sessions.until_sweep(now)
+    }
+
+    // Sends the flood that is due, if any, and says how long the loop can wait for the next one
+    fn flood_if_due(&mut self) -> Duration {
+        if self.crashed {
+            return Duration::from_secs(1);
+        }
+        let now = Instant::now();
+        if let Some(reason) = self.flood_scheduler.poll(now) {
+            info!("Client[{}] flood {} ({:?})", self.id, self.flood_scheduler.floods(), reason);
+            self.flood();
         }
+        self.flood_scheduler.timeout(now)
+    }
+
+    fn flood(&mut self) {
+        let mut session_id = 0;
+        while self.session_id_alredy_used(session_id) {
+            session_id = rand_session_id(&mut self.rng);
+        }
+        let flood_id = generate_flood_id(&mut self.flood_ids, self.id, &mut self.rng);
+        self.sessions.flood_seen((flood_id, self.id), Instant::now());
+        let _= self.send_new_flood_request(session_id, flood_id);
+    }
+
+    // Forgets everything learned since the start, links to the neighbours are kept
+    fn reset(&mut self) {
+        self.sessions.clear();
+        self.client_topology = Topology::new();
+        self.flood_ids.clear();
+        self.holder_sent.clear();
+        self.holder_frag_index.clear();
+        self.holder_rec.clear();
+        self.registered_to.clear();
+        self.chat_servers.clear();
+        self.chat_contacts.clear();
+        self.sent.clear();
+        self.transfers.clear();
+        self.cancelled.clear();
     }
 
     fn send_new_flood_request(&mut self, session_id: u64, flood_id: u64) -> Result<(), &str> {
@@ -464,10 +607,52 @@ impl ChatClient {
                 return Err("No sender found");
             }
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
 
+    // Resends a nacked fragment on the best path left, gives up after MAX_RESEND_ATTEMPTS
+    fn resend_fragment(
+        &mut self,
+        session_id: u64,
+        sent: &Packet,
+        fragment: Fragment,
+        avoid: NodeId,
+    ) -> Result<(), &'static str> {
+        let dst = *sent.routing_header.hops.last().unwrap();
+        for _ in 0..MAX_RESEND_ATTEMPTS {
+            self.client_topology.set_path_based_on_dst(dst);
+            if self.send_new_generic_fragment(dst, session_id, fragment.clone()).is_ok() {
+                if let Some((new_path, _)) = self.client_topology.get_current_path() {
+                    if new_path != sent.routing_header.hops {
+                        self.controller_send
+                            .send(NodeEvent::Rerouted {
+                                node: self.id,
+                                session_id,
+                                old_path: sent.routing_header.hops.clone(),
+                                new_path,
+                            })
+                            .ok();
+                    }
+                }
+                return Ok(());
+            }
+            // increase weights for the failed path to avoid it
+            self.client_topology.increment_weights_for_node(avoid);
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        self.controller_send
+            .send(NodeEvent::RetryExhausted {
+                node: self.id,
+                session_id,
+                fragment_index: fragment.fragment_index,
+                attempts: MAX_RESEND_ATTEMPTS,
+            })
+            .ok();
+        Err("Gave up resending the fragment")
+    }
+
     fn send_new_generic_fragment(
         &mut self,
         server_id: NodeId,
@@ -490,6 +675,11 @@ impl ChatClient {
                     self.controller_send
                         .send(NodeEvent::PacketSent(packet.clone()))
                         .ok();
+                    if let Some(progress) = self.transfers.get_mut(&(session_id, self.id)) {
+                        progress.retransmissions += 1;
+                        progress.path = trace.clone();
+                    }
+                    self.report_progress((session_id, self.id));
                     return Ok(());
                 } else {
                     self.client_topology.remove_node(trace[1]);
@@ -500,6 +690,7 @@ impl ChatClient {
                 return Err("Sender not found");
             }
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
@@ -530,8 +721,16 @@ impl ChatClient {
         &mut self,
         flood_packet: FloodResponse,
     ) -> Result<(), &str> {
+        let before = self.client_topology.size();
         self.client_topology
             .update_topology((self.id, NodeType::Client), flood_packet.path_trace.clone());
+        let (nodes, edges) = self.client_topology.size();
+        if (nodes, edges) != before {
+            self.flood_scheduler.topology_changed();
+            self.controller_send
+                .send(NodeEvent::TopologyUpdated { node: self.id, nodes, edges })
+                .ok();
+        }
         // println!("Path trace in client {:?}", flood_packet.path_trace.clone());
         let serv = self.client_topology.get_all_servers();
         if !serv.is_empty() {
@@ -555,6 +754,8 @@ impl ChatClient {
         {
             self.flood_ids
                 .insert((flood_packet.flood_id, flood_packet.initiator_id));
+            self.sessions
+                .flood_seen((flood_packet.flood_id, flood_packet.initiator_id), Instant::now());
         }
         let mut hops = path_trace
             .clone()
@@ -580,11 +781,6 @@ impl ChatClient {
         nack: Nack,
         packet: &Packet,
     ) -> Result<(), &str> {
-        let flood_id = generate_flood_id(&mut self.flood_ids, self.id);
-        let mut session = 0;
-        while self.session_id_alredy_used(session) {
-            session = rand_session_id();
-        }
         match nack.clone().nack_type {
             NackType::DestinationIsDrone => {
                 //check route, it shouldn't happen if the routing was done right
@@ -596,22 +792,7 @@ impl ChatClient {
                                 if f.fragment_index == nack.fragment_index {
                                     self.client_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -635,8 +816,6 @@ impl ChatClient {
                 }
             }
             NackType::Dropped => {
-                self.send_new_flood_request(session, flood_id).ok();
-
                 //update weight of the path used and change it there's one with less
                 // println!("Dropped by drone nacked");
 
@@ -647,22 +826,7 @@ impl ChatClient {
                                 if f.fragment_index == nack.fragment_index {
                                     self.client_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                            std::thread::sleep(Duration::from_millis(100));
-                                        }
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             _ => {
@@ -678,6 +842,7 @@ impl ChatClient {
                 // println!("Error in routing nacked");
                 //Could be a drone in crash mode so remove the node id from topology and update it
                 self.client_topology.remove_node(id);
+                self.flood_scheduler.trigger(FloodReason::ErrorInRouting, Instant::now());
 
                 if let Some(packets) = { self.holder_sent.get(&(session_id, self.id)).cloned() } {
                     //update the path since it might mean a drone has crashed or bad routing
@@ -686,22 +851,7 @@ impl ChatClient {
                         match p.clone().pack_type {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -732,22 +882,7 @@ impl ChatClient {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
                                     // self.client_topology.increment_weights_for_node(id);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -802,6 +937,13 @@ impl ChatClient {
                     .get_mut(&(session_id, self.id))
                     .unwrap()
                     .remove(i);
+                self.sessions.sent((session_id, self.id), Instant::now());
+                let remaining = self.holder_sent[&(session_id, self.id)].len() as u64;
+                if let Some(progress) = self.transfers.get_mut(&(session_id, self.id)) {
+                    progress.done = progress.total.saturating_sub(remaining);
+                    progress.path = hops.iter().rev().copied().collect();
+                }
+                self.report_progress((session_id, self.id));
                 return Ok(());
             } else {
                 return Err("Fragment Index was not supposed to be 0");
@@ -816,12 +958,18 @@ impl ChatClient {
         session_id: u64,
         src: NodeId,
         frag: &Fragment,
+        path: &[NodeId],
     ) -> Option<Message> {
         self.client_topology.find_all_paths(self.id, src);
         self.client_topology.set_path_based_on_dst(src);
         while self.send_ack(session_id, &src, frag.fragment_index).is_err(){};
+        if self.cancelled.contains(&(session_id, src)) || self.sessions.is_over(&(session_id, src)) {
+            return None;
+        }
         if let Some(holder) = self.holder_frag_index.get_mut(&(session_id, src)) {
-            if !holder.contains(&frag.fragment_index) {
+            self.sessions.received((session_id, src), Instant::now());
+            let duplicate = holder.contains(&frag.fragment_index);
+            if !duplicate {
                 let target = self.holder_rec.get_mut(&(session_id, src)).unwrap();
                 update_holder_rec(
                     target,
@@ -832,9 +980,19 @@ impl ChatClient {
                 );
                 holder.push(frag.fragment_index);
             }
-            if holder.len() == (frag.total_n_fragments) as usize {
+            let received = holder.len() as u64;
+            if let Some(progress) = self.transfers.get_mut(&(session_id, src)) {
+                progress.done = received;
+                progress.path = path.to_vec();
+                if duplicate {
+                    progress.retransmissions += 1;
+                }
+            }
+            self.report_progress((session_id, src));
+            if received == frag.total_n_fragments {
                 if let Some(mut data) = self.holder_rec.get_mut(&(session_id, src)) {
                     remove_trailing_zeros(&mut data);
+                    let (kind, size) = (message_kind(data[0]).unwrap_or("Unknown"), data.len());
                     let mut f_serialized = serialize(data.clone());
                     let result = super::super::fragmentation_handling::reconstruct_message(
                         data[0],
@@ -844,10 +1002,27 @@ impl ChatClient {
                  if let Ok(msg) = result {
                         self.holder_rec.remove(&(session_id, src));
                         self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::MessageDelivered {
+                                node: self.id,
+                                from: src,
+                                session_id,
+                                kind: kind.to_string(),
+                                size,
+                            })
+                            .ok();
                         // println!("Message Reconstructed");
                         return Some(msg.clone());
                     } else {
                         // println!("Message reconstruction failed");
+                        // a broken message never completes, its buffers would only take space
+                        self.holder_rec.remove(&(session_id, src));
+                        self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::ReassemblyFailed { node: self.id, from: src, session_id })
+                            .ok();
                         return None;
                     }
                 }
@@ -855,6 +1030,13 @@ impl ChatClient {
             
             None
         } else {
+            match self.sessions.admit((session_id, src), frag.total_n_fragments, Instant::now()) {
+                Ok(evicted) => self.drop_reassemblies(&evicted),
+                Err(e) => {
+                    info!("Client[{}] refused session {}: {}", self.id, session_id, e);
+                    return None;
+                }
+            }
             self.holder_rec.insert(
                 (session_id, src),
                 vec![0; (frag.clone().total_n_fragments * 128) as usize],
@@ -869,10 +1051,54 @@ impl ChatClient {
             );
             self.holder_frag_index
                 .insert((session_id, src), [frag.fragment_index].to_vec());
+            let mut progress = TransferProgress::new(
+                session_id,
+                src,
+                TransferDirection::Download,
+                frag.total_n_fragments,
+                path.to_vec(),
+            );
+            progress.done = 1;
+            self.transfers.insert((session_id, src), progress);
+            self.report_progress((session_id, src));
             return None;
         }
     }
 
+    fn report_progress(&mut self, key: (u64, NodeId)) {
+        if let Some(progress) = self.transfers.get(&key) {
+            let _ = self.gui_event_sender.send(ChatEvent::Progress(progress.clone()));
+            if progress.is_finished() {
+                self.transfers.remove(&key);
+            }
+        }
+    }
+
+    fn cancel_transfer(&mut self, direction: TransferDirection, session_id: u64, peer: NodeId) {
+        let key = match direction {
+            TransferDirection::Upload => {
+                //an empty holder keeps the session_id reserved and stops retransmissions
+                if let Some(holder) = self.holder_sent.get_mut(&(session_id, self.id)) {
+                    holder.clear();
+                }
+                self.sent.remove(&(session_id, self.id));
+                (session_id, self.id)
+            }
+            TransferDirection::Download => {
+                self.holder_rec.remove(&(session_id, peer));
+                self.holder_frag_index.remove(&(session_id, peer));
+                self.cancelled.insert((session_id, peer));
+                self.sessions.finish((session_id, peer), Instant::now());
+                (session_id, peer)
+            }
+        };
+        if let Some(progress) = self.transfers.get_mut(&key) {
+            progress.cancelled = true;
+        }
+        self.report_progress(key);
+        let _ = self.send_from_chat_client(peer, Message::DefaultsRequest(DefaultsRequest::CANCEL(session_id)));
+    }
+
     fn session_id_alredy_used(&self, session_id: u64) -> bool {
         if self.holder_sent.contains_key(&(session_id, self.id)) {
             true
@@ -887,14 +1113,15 @@ impl ChatClient {
         if let Some(hops) = self.client_topology.get_current_path(){
             Some(hops.0.clone())
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             None
         }
     }
 }
 
-fn rand_session_id() -> u64 {
+fn rand_session_id(rng: &mut impl RngCore) -> u64 {
     let mut bytes = [0u8; 8];
-    OsRng.fill_bytes(&mut bytes);
+    rng.fill_bytes(&mut bytes);
     u64::from_ne_bytes(bytes)
 }
 
@@ -927,12 +1154,12 @@ fn remove_trailing_zeros(vec: &mut Vec<u8>) {
     }
 }
 
-fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId) -> u64 {
-    let mut rng = 1;
-    while !flood_ids.insert((rng, id)) {
-        rng = rand::random::<u64>();
+fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId, rng: &mut impl RngCore) -> u64 {
+    let mut flood_id = 1;
+    while !flood_ids.insert((flood_id, id)) {
+        flood_id = rng.next_u64();
     }
-    rng
+    flood_id
 }
 
 fn update_holder_rec(
@@ -961,7 +1188,93 @@ fn update_holder_rec(
 mod tests {
 
     use super::*;
+    use crate::utils::backup_server::{Server, TEXTSERVER};
+    use crate::utils::mock_drone::{MockDrone, MockPolicy};
     use crossbeam_channel::unbounded;
+    use wg_2024::{
+        controller::{DroneCommand, DroneEvent},
+        drone::Drone,
+    };
+
+    // Client 0 and a mock drone 1 in front of server 2, whose channel is left to the test.
+    // The drone stops once the command sender is dropped.
+    fn through_mock(policy: MockPolicy) -> (ChatClient, Receiver<Packet>, Sender<DroneCommand>) {
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, _c4) = unbounded::<NodeEvent>();
+        let (c5, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let (drone_send, drone_recv) = unbounded::<Packet>();
+        let (server_send, at_server) = unbounded::<Packet>();
+        let (command_send, command_recv) = unbounded::<DroneCommand>();
+        let (event_send, _event_recv) = unbounded::<DroneEvent>();
+        let mut drone = MockDrone::new(
+            1,
+            event_send,
+            command_recv,
+            drone_recv,
+            HashMap::from([(0, c5), (2, server_send)]),
+            0.0,
+        )
+        .with_policy(policy);
+        thread::spawn(move || drone.run());
+
+        let mut dummy = ChatClient::new(0, c3, c2, c6, HashMap::from([(1, drone_send)]), c7, c8);
+        dummy.client_topology.update_topology(
+            (0, NodeType::Client),
+            vec![(0, NodeType::Client), (1, NodeType::Drone), (2, NodeType::Server)],
+        );
+        (dummy, at_server, command_send)
+    }
+
+    fn hello() -> Message {
+        Message::ChatMessages(ChatMessages::CHATSTRING(0, 2, 2, "Hello".to_string()))
+    }
+
+    #[test]
+    fn test_send_through_mock() {
+        let (mut dummy, at_server, _commands) = through_mock(MockPolicy::default());
+        dummy.send_from_chat_client(2, hello()).unwrap();
+        let packet = at_server.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(packet.routing_header.hops, vec![0, 1, 2]);
+        assert_eq!(packet.routing_header.hop_index, 2);
+        assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        assert!(dummy.holder_sent.contains_key(&(packet.session_id, 0)));
+    }
+
+    #[test]
+    fn test_ack_through_mock() {
+        let (mut dummy, at_server, _commands) = through_mock(MockPolicy::default());
+        dummy.send_ack(7, &2, 3).unwrap();
+        let packet = at_server.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(packet.session_id, 7);
+        assert_eq!(packet.routing_header.hops, vec![0, 1, 2]);
+        assert!(matches!(&packet.pack_type, PacketType::Ack(ack) if ack.fragment_index == 3));
+    }
+
+    #[test]
+    fn test_nack_through_mock() {
+        let wait = Duration::from_secs(1);
+        let (mut dummy, at_server, _commands) = through_mock(MockPolicy { nack: vec![0], ..Default::default() });
+        dummy.send_from_chat_client(2, hello()).unwrap();
+
+        // the drone answers fragment 0 with a Nack instead of forwarding it
+        let nack = dummy.packet_recv.recv_timeout(wait).unwrap();
+        assert_eq!(nack.routing_header.hops, vec![1, 0]);
+        assert!(matches!(
+            &nack.pack_type,
+            PacketType::Nack(Nack { fragment_index: 0, nack_type: NackType::Dropped })
+        ));
+        let session_id = nack.session_id;
+        dummy.handle_packet(nack);
+
+        // the resend gets through, the policy only nacks a fragment once per session
+        let resent = std::iter::from_fn(|| at_server.recv_timeout(wait).ok())
+            .find(|p| matches!(&p.pack_type, PacketType::MsgFragment(f) if f.fragment_index == 0))
+            .unwrap();
+        assert_eq!(resent.session_id, session_id);
+        assert_eq!(dummy.transfers[&(session_id, 0)].retransmissions, 1);
+    }
 
     #[test]
     fn test_send_chat_client() {
@@ -992,6 +1305,45 @@ mod tests {
         assert_eq!(1, 2);
     }
 
+    #[test]
+    fn test_seeded_run_is_reproducible() {
+        // the packets a client sends and the flood ids it picks only depend on its seed
+        let run = |seed: u64| {
+            let (_c1, c2) = unbounded::<NodeCommand>();
+            let (c3, c4) = unbounded::<NodeEvent>();
+            let (c5, c6) = unbounded::<Packet>();
+            let (_, c7) = unbounded::<ChatCommand>();
+            let (c8, _) = unbounded::<ChatEvent>();
+            let mut hm = HashMap::new();
+            hm.insert(1, c5);
+            let mut dummy = ChatClient::new(0, c3, c2, c6, hm, c7, c8).with_seed(seed);
+            dummy
+                .client_topology
+                .update_topology((0, NodeType::Client), vec![(1, NodeType::Server)]);
+            for text in ["Hello", "there", "again"] {
+                let msg = Message::ChatMessages(ChatMessages::CHATSTRING(0, 1, 1, text.to_string()));
+                dummy.send_from_chat_client(1, msg).unwrap();
+            }
+            let flood_ids = (0..3)
+                .map(|_| generate_flood_id(&mut dummy.flood_ids, dummy.id, &mut dummy.rng))
+                .collect::<Vec<_>>();
+            let sent = c4
+                .try_iter()
+                .filter_map(|event| match event {
+                    NodeEvent::PacketSent(packet) => {
+                        Some((packet.session_id, packet.routing_header.hops.clone()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            (sent, flood_ids)
+        };
+        let first = run(42);
+        assert!(!first.0.is_empty());
+        assert_eq!(first, run(42));
+        assert_ne!(first, run(43));
+    }
+
     #[test]
     fn test_recv_fragmnt() {
         let (_c1, c2) = unbounded::<NodeCommand>();
@@ -1020,6 +1372,242 @@ mod tests {
         assert_eq!(1, 2);
     }
 
+    #[test]
+    fn test_telemetry() {
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, c4) = unbounded::<NodeEvent>();
+        let (_c5, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let mut dummy = ChatClient::new(0, c3, c2, c6, HashMap::new(), c7, c8);
+        let response = FloodResponse {
+            flood_id: 1,
+            path_trace: vec![(0, NodeType::Client), (1, NodeType::Drone), (2, NodeType::Server)],
+        };
+        dummy.recv_flood_response_n_handle(response.clone()).unwrap();
+        // nothing new the second time
+        dummy.recv_flood_response_n_handle(response).unwrap();
+
+        // no sender towards drone 1, every attempt fails
+        let sent = Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1, 2]),
+            5,
+            Fragment::new(0, 1, [0; 128]),
+        );
+        let PacketType::MsgFragment(fragment) = sent.pack_type.clone() else {
+            unreachable!()
+        };
+        assert!(dummy.resend_fragment(5, &sent, fragment, 1).is_err());
+
+        let events = c4.try_iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], NodeEvent::TopologyUpdated { node: 0, nodes: 3, edges: 2 }));
+        assert!(matches!(
+            events[1],
+            NodeEvent::RetryExhausted { node: 0, session_id: 5, attempts: MAX_RESEND_ATTEMPTS, .. }
+        ));
+    }
+
+    #[test]
+    fn test_flood_triggers() {
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, _c4) = unbounded::<NodeEvent>();
+        let (c5, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let neighbour = c6.clone();
+        let mut hm = HashMap::new();
+        hm.insert(1, c5);
+        let policy = FloodPolicy { min_gap_ms: 0, ..FloodPolicy::default() };
+        let mut dummy = ChatClient::new(0, c3, c2, c6, hm, c7, c8).with_flood_policy(policy);
+        let floods = |dummy: &mut ChatClient| {
+            dummy.flood_if_due();
+            neighbour
+                .try_iter()
+                .filter(|p| matches!(p.pack_type, PacketType::FloodRequest(_)))
+                .count()
+        };
+        // startup, then quiet until the interval is over
+        assert_eq!(floods(&mut dummy), 1);
+        assert_eq!(floods(&mut dummy), 0);
+
+        // a crashed drone on the path brings the next flood forward
+        let nack = Nack { fragment_index: 0, nack_type: NackType::ErrorInRouting(2) };
+        let packet = Packet::new_nack(SourceRoutingHeader::with_first_hop(vec![1, 0]), 5, nack.clone());
+        let _ = dummy.recv_nack_n_handle(5, nack, &packet);
+        assert_eq!(floods(&mut dummy), 1);
+
+        // and so does a destination it has no route to
+        assert!(dummy.get_hops(31).is_none());
+        assert_eq!(floods(&mut dummy), 1);
+        assert_eq!(floods(&mut dummy), 0);
+        assert_eq!(dummy.flood_scheduler.floods(), 3);
+    }
+
+    // Runs the client loop in the test thread for `time`, returns the packets it handled
+    fn pump(dummy: &mut ChatClient, time: Duration) -> Vec<Packet> {
+        let mut handled = Vec::new();
+        let deadline = Instant::now() + time;
+        while Instant::now() < deadline {
+            dummy.flood_if_due();
+            if let Ok(packet) = dummy.packet_recv.recv_timeout(Duration::from_millis(10)) {
+                handled.push(packet.clone());
+                dummy.handle_packet(packet);
+            }
+        }
+        handled
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        // client 0 and a real server 2 joined by mock drones 1 and 3, the one on the route crashes
+        const MESSAGES: usize = 8;
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, _c4) = unbounded::<NodeEvent>();
+        let (to_client, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let (to_server, server_recv) = unbounded::<Packet>();
+        let (server_event_send, server_events) = unbounded::<NodeEvent>();
+        let (_server_commands, server_command_recv) = unbounded::<NodeCommand>();
+        let mut to_drones = HashMap::new();
+        let mut drone_commands = HashMap::new();
+        let mut drone_events = HashMap::new();
+        for id in [1, 3] {
+            let (packet_send, packet_recv) = unbounded::<Packet>();
+            let (command_send, command_recv) = unbounded::<DroneCommand>();
+            let (event_send, event_recv) = unbounded::<DroneEvent>();
+            let neighbours = HashMap::from([(0, to_client.clone()), (2, to_server.clone())]);
+            let mut drone = MockDrone::new(id, event_send, command_recv, packet_recv, neighbours, 0.0);
+            thread::spawn(move || drone.run());
+            to_drones.insert(id, packet_send);
+            drone_commands.insert(id, command_send);
+            drone_events.insert(id, event_recv);
+        }
+        let mut server =
+            Server::new(2, TEXTSERVER, server_event_send, server_command_recv, server_recv, to_drones.clone());
+        thread::spawn(move || server.handle_channels());
+        let mut dummy = ChatClient::new(0, c3, c2, c6, to_drones, c7, c8);
+
+        // the startup flood finds both routes, the client picks one
+        pump(&mut dummy, Duration::from_millis(300));
+        let route = dummy.get_hops(2).unwrap();
+        let (crashed, spare) = if route[1] == 1 { (1, 3) } else { (3, 1) };
+        assert_eq!(route, vec![0, crashed, 2]);
+
+        // every message goes out on the old route before the first nack comes back
+        drone_commands[&crashed].send(DroneCommand::Crash).unwrap();
+        drone_events[&spare].try_iter().for_each(drop); // only the floods after the crash count
+        for _ in 0..MESSAGES {
+            dummy.send_from_chat_client(2, hello()).unwrap();
+        }
+        let handled = pump(&mut dummy, Duration::from_secs(1));
+
+        let nacks = handled
+            .iter()
+            .filter(|p| {
+                matches!(&p.pack_type, PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(id), .. }) if *id == crashed)
+            })
+            .count();
+        assert_eq!(nacks, MESSAGES);
+        // the resends took the spare drone and all got through
+        assert_eq!(dummy.get_hops(2), Some(vec![0, spare, 2]));
+        let delivered = server_events
+            .try_iter()
+            .filter(|event| matches!(event, NodeEvent::MessageDelivered { from: 0, .. }))
+            .count();
+        assert_eq!(delivered, MESSAGES);
+
+        // floods of the client that went past the spare drone since the crash: the old client
+        // flooded on every ErrorInRouting, now the nacks share what the minimum gap allows
+        let floods = drone_events[&spare]
+            .try_iter()
+            .filter(|event| {
+                matches!(event, DroneEvent::PacketSent(p) if matches!(&p.pack_type, PacketType::FloodRequest(f) if f.initiator_id == 0))
+            })
+            .count();
+        assert!((1..=2).contains(&floods), "{} floods for {} nacks", floods, nacks);
+        assert!(floods < nacks);
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, c4) = unbounded::<NodeEvent>();
+        let (c5, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let mut hm = HashMap::new();
+        hm.insert(1, c5);
+        let policy = SessionPolicy { max_fragments: 1_000, ..SessionPolicy::default() };
+        let mut dummy = ChatClient::new(0, c3, c2, c6, hm, c7, c8).with_session_policy(policy);
+        dummy
+            .client_topology
+            .update_topology((0, NodeType::Client), vec![(1, NodeType::Server)]);
+
+        // a sender announcing more fragments than allowed gets nothing allocated
+        let huge = Fragment::new(0, u64::MAX, [0; 128]);
+        assert!(dummy.recv_frag_n_handle(5, 1, &huge, &[1, 0]).is_none());
+        assert!(dummy.holder_rec.is_empty());
+        let small = Fragment::new(0, 2, [0; 128]);
+        assert!(dummy.recv_frag_n_handle(6, 1, &small, &[1, 0]).is_none());
+        assert_eq!(dummy.holder_rec.len(), 1);
+
+        dummy.collect_garbage();
+        let evicted = c4
+            .try_iter()
+            .find_map(|event| match event {
+                NodeEvent::SessionsEvicted { evicted, .. } => Some(evicted),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((evicted.refused, evicted.timed_out), (1, 0));
+    }
+
+    #[test]
+    fn test_late_fragments() {
+        let (_c1, c2) = unbounded::<NodeCommand>();
+        let (c3, _c4) = unbounded::<NodeEvent>();
+        let (c5, c6) = unbounded::<Packet>();
+        let (_, c7) = unbounded::<ChatCommand>();
+        let (c8, _) = unbounded::<ChatEvent>();
+        let mut hm = HashMap::new();
+        hm.insert(1, c5);
+        let policy = SessionPolicy {
+            peer_budget_bytes: 2 * FRAGMENT_DSIZE,
+            incomplete_timeout_ms: 0,
+            ..SessionPolicy::default()
+        };
+        let mut dummy = ChatClient::new(0, c3, c2, c6, hm, c7, c8).with_session_policy(policy);
+        dummy
+            .client_topology
+            .update_topology((0, NodeType::Client), vec![(1, NodeType::Server)]);
+        let first = Fragment::new(0, 2, [0; 128]);
+        let late = Fragment::new(1, 2, [0; 128]);
+
+        // session 6 takes the whole budget of 1, session 5 is evicted
+        assert!(dummy.recv_frag_n_handle(5, 1, &first, &[1, 0]).is_none());
+        assert!(dummy.recv_frag_n_handle(6, 1, &first, &[1, 0]).is_none());
+        assert!(!dummy.holder_rec.contains_key(&(5, 1)));
+        // its late fragment is acked but doesn't start it over and evict 6 in turn
+        assert!(dummy.recv_frag_n_handle(5, 1, &late, &[1, 0]).is_none());
+        assert!(!dummy.holder_rec.contains_key(&(5, 1)));
+        assert!(dummy.holder_rec.contains_key(&(6, 1)));
+
+        // the same once 6 times out
+        dummy.collect_garbage();
+        assert!(dummy.holder_rec.is_empty());
+        assert!(dummy.recv_frag_n_handle(6, 1, &late, &[1, 0]).is_none());
+        assert!(dummy.holder_rec.is_empty());
+
+        let acks = dummy
+            .packet_recv
+            .try_iter()
+            .filter(|packet| matches!(packet.pack_type, PacketType::Ack(_)))
+            .count();
+        assert_eq!(acks, 4);
+    }
+
     #[test]
     fn test_ack() {
         let (_c1, c2) = unbounded::<NodeCommand>();
@@ -1,17 +1,20 @@
 use crate::frontend::WebCommand;
 use crate::frontend::WebEvent;
+use crate::frontend::{TransferDirection, TransferProgress};
 
 use super::super::controller::*;
 use super::super::fragmentation_handling::DefaultsRequest;
 use super::super::fragmentation_handling::*;
+use super::super::flood::{FloodPolicy, FloodReason, FloodScheduler};
+use super::super::sessions::{SessionGc, SessionPolicy};
 use super::super::topology::*;
 use bevy::log::info;
 use crossbeam_channel::*;
-use rand::rngs::OsRng;
+use rand::rngs::StdRng;
 use rand::*;
 use std::{
     collections::{HashMap, HashSet},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use wg_2024::{network::*, packet::*};
 
@@ -53,6 +56,12 @@ pub struct WebBrowser {
     text: HashMap<(u64, u8), Vec<String>>,
     gui_command_receiver: Receiver<WebCommand>,
     gui_event_sender: Sender<WebEvent>,
+    transfers: HashMap<(u64, NodeId), TransferProgress>, //progress of ongoing transfers, same keys as the holders
+    cancelled: HashSet<(u64, NodeId)>, //downloads cancelled from the gui, late fragments are only acked
+    rng: StdRng, // session and flood ids, seeded for reproducible runs
+    crashed: bool, // after NodeCommand::Crash packets and gui commands are dropped until a Restart
+    flood_scheduler: FloodScheduler, // when to flood next
+    sessions: SessionGc, // timeouts and budgets of the holders and flood ids
 }
 
 impl WebBrowser {
@@ -84,9 +93,30 @@ impl WebBrowser {
             text: HashMap::new(),
             gui_command_receiver,
             gui_event_sender,
+            transfers: HashMap::new(),
+            cancelled: HashSet::new(),
+            rng: StdRng::from_entropy(),
+            crashed: false,
+            flood_scheduler: FloodScheduler::new(FloodPolicy::default(), Instant::now()),
+            sessions: SessionGc::new(SessionPolicy::default()),
         }
     }
 
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn with_flood_policy(mut self, policy: FloodPolicy) -> Self {
+        self.flood_scheduler = FloodScheduler::new(policy, Instant::now());
+        self
+    }
+
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.sessions = SessionGc::new(policy);
+        self
+    }
+
     fn handle_packet(&mut self, packet: Packet) {
         match packet.clone().pack_type {
             PacketType::Ack(ack) => {
@@ -142,6 +172,7 @@ impl WebBrowser {
                     packet.session_id,
                     packet.clone().routing_header.hops[0],
                     &fragment,
+                    &packet.routing_header.hops,
                 ) {
                     Some(m) => {
                         // println!("Handled Frag in Client");
@@ -271,9 +302,10 @@ impl WebBrowser {
         match deconstruct_message(msg.clone()) {
             Ok(bytes_res) => {
                 let mut fragments: Vec<Fragment> = serialize(bytes_res);
-                let mut session_id = 0;
+                // random, the receiver remembers the ids of finished sessions for a while
+                let mut session_id = rand_session_id(&mut self.rng);
                 while self.session_id_alredy_used(session_id) {
-                    session_id = rand_session_id();
+                    session_id = rand_session_id(&mut self.rng);
                 }
                 self.client_topology.find_all_paths(self.id, dst);
                 self.client_topology.set_path_based_on_dst(dst);
@@ -283,6 +315,20 @@ impl WebBrowser {
                     self.sent.insert((session_id, self.id), msg.clone());
                     self.holder_sent
                         .insert((session_id, self.id), packets.clone());
+                    self.sessions.sent((session_id, self.id), Instant::now());
+                    if !matches!(msg, Message::DefaultsRequest(_)) {
+                        self.transfers.insert(
+                            (session_id, self.id),
+                            TransferProgress::new(
+                                session_id,
+                                dst,
+                                TransferDirection::Upload,
+                                packets.len() as u64,
+                                hops.clone().unwrap_or_default(),
+                            ),
+                        );
+                        self.report_progress((session_id, self.id));
+                    }
                     for pack in packets {
                         match self.send_new_packet(&pack.clone()) {
                             Ok(_) => {
@@ -310,6 +356,7 @@ impl WebBrowser {
 
     pub fn handle_channels(&mut self) {
         loop {
+            let wait = self.flood_if_due().min(self.collect_garbage());
             select_biased! {
                 recv(self.controller_recv) -> command_res => {
                     if let Ok(command) = command_res {
@@ -319,6 +366,7 @@ impl WebBrowser {
                             // },
                             NodeCommand::AddSender(id,sender)=>{
                                 self.packet_send.insert(id, sender);
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             },
                             NodeCommand::RemoveSender(id)=>{
                                 info!("\n\n\nRemove sender to: {}\n\n\n",id);
@@ -329,17 +377,35 @@ impl WebBrowser {
                                         std::thread::sleep(Duration::from_millis(1000));
                                     }
                                 }
+                                self.flood_scheduler.trigger(FloodReason::NeighbourChanged, Instant::now());
                             }
+                            NodeCommand::Crash=>{
+                                info!("Client[{}] crashed", self.id);
+                                self.crashed = true;
+                                self.reset();
+                            },
+                            NodeCommand::Restart=>{
+                                info!("Client[{}] restarted", self.id);
+                                self.crashed = false;
+                                self.reset();
+                                self.flood_scheduler.restart(Instant::now());
+                            },
+                            NodeCommand::ShowTopology(reply)=>{
+                                let _ = reply.send(self.client_topology.snapshot());
+                            },
                         }
+                    } else {
+                        // the controller is gone, nobody can crash or restart this client anymore
+                        return;
                     }
                 },
                 recv(self.packet_recv) -> packet_res => {
-                    if let Ok(packet) = packet_res {
+                    if let (Ok(packet), false) = (packet_res, self.crashed) {
                         self.handle_packet(packet.clone());
                     }
                 },
                 recv(self.gui_command_receiver) -> gui_command => {
-                    if let Ok(command) = gui_command {
+                    if let (Ok(command), false) = (gui_command, self.crashed) {
                         match command {
                             WebCommand::GetServersType=>{
                                 for dst in self.client_topology.get_all_servers() {
@@ -367,20 +433,101 @@ impl WebBrowser {
                                 if self.media_servers.contains(&id) {
                                     let _ = self.send_new_media_req(id,path);
                                 }
+                            },
+                            WebCommand::CancelTransfer(direction,session_id,peer)=>{
+                                self.cancel_transfer(direction, session_id, peer);
                             }
                         }
                     }
                 },
-                default(Duration::from_secs(5)) => {
-                    let mut session_id = 0;
-                    while self.session_id_alredy_used(session_id) {
-                        session_id = rand_session_id();
-                    }
-                    let flood_id = generate_flood_id(&mut self.flood_ids,self.id);
-                    let _= self.send_new_flood_request(session_id, flood_id);
+                default(wait) => {}
+            }
+        }
+    }
+
+    // Frees the buffers of reassemblies that won't complete, the gui sees them as cancelled
+    fn drop_reassemblies(&mut self, keys: &[(u64, NodeId)]) {
+        for key in keys {
+            self.holder_rec.remove(key);
+            self.holder_frag_index.remove(key);
+            if let Some(progress) = self.transfers.get_mut(key) {
+                progress.cancelled = true;
+            }
+            self.report_progress(*key);
+        }
+    }
+
+    // Drops the sessions that are over or timed out, says how long until it looks again
+    fn collect_garbage(&mut self) -> Duration {
+        let now = Instant::now();
+        let holder_sent = &self.holder_sent;
+        let acked = |key: &(u64, NodeId)| holder_sent.get(key).is_none_or(|holder| holder.is_empty());
+        if let Some(sweep) = self.sessions.sweep(now, acked) {
+            self.drop_reassemblies(&sweep.incoming);
+            for key in &sweep.outgoing {
+                self.holder_sent.remove(key);
+                self.sent.remove(key);
+                if let Some(progress) = self.transfers.get_mut(key) {
+                    progress.cancelled = true;
                 }
+                self.report_progress(*key);
+            }
+            for key in &sweep.finished {
+                self.cancelled.remove(key);
+                self.text.remove(key);
+                self.media.remove(key);
+            }
+            for key in &sweep.flood_ids {
+                self.flood_ids.remove(key);
             }
+            if !sweep.stats.is_empty() {
+                self.controller_send
+                    .send(NodeEvent::SessionsEvicted { node: self.id, evicted: sweep.stats })
+                    .ok();
+            }
+        }
+        self.sessions.until_sweep(now)
+    }
+
+    // Sends the flood that is due, if any, and says how long the loop can wait for the next one
+    fn flood_if_due(&mut self) -> Duration {
+        if self.crashed {
+            return Duration::from_secs(1);
+        }
+        let now = Instant::now();
+        if let Some(reason) = self.flood_scheduler.poll(now) {
+            info!("Client[{}] flood {} ({:?})", self.id, self.flood_scheduler.floods(), reason);
+            self.flood();
+        }
+        self.flood_scheduler.timeout(now)
+    }
+
+    fn flood(&mut self) {
+        let mut session_id = 0;
+        while self.session_id_alredy_used(session_id) {
+            session_id = rand_session_id(&mut self.rng);
         }
+        let flood_id = generate_flood_id(&mut self.flood_ids, self.id, &mut self.rng);
+        self.sessions.flood_seen((flood_id, self.id), Instant::now());
+        let _= self.send_new_flood_request(session_id, flood_id);
+    }
+
+    // Forgets everything learned since the start, links to the neighbours are kept
+    fn reset(&mut self) {
+        self.sessions.clear();
+        self.client_topology = Topology::new();
+        self.flood_ids.clear();
+        self.holder_sent.clear();
+        self.holder_frag_index.clear();
+        self.holder_rec.clear();
+        self.sent.clear();
+        self.text_servers.clear();
+        self.media_servers.clear();
+        self.media.clear();
+        self.text.clear();
+        self.transfers.clear();
+        self.cancelled.clear();
+        self.pre_processed = None;
     }
 
     fn send_new_flood_request(&mut self, session_id: u64, flood_id: u64) -> Result<(), &str> {
@@ -470,10 +617,52 @@ impl WebBrowser {
                 return Err("No sender found");
             }
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
 
+    // Resends a nacked fragment on the best path left, gives up after MAX_RESEND_ATTEMPTS
+    fn resend_fragment(
+        &mut self,
+        session_id: u64,
+        sent: &Packet,
+        fragment: Fragment,
+        avoid: NodeId,
+    ) -> Result<(), &'static str> {
+        let dst = *sent.routing_header.hops.last().unwrap();
+        for _ in 0..MAX_RESEND_ATTEMPTS {
+            self.client_topology.set_path_based_on_dst(dst);
+            if self.send_new_generic_fragment(dst, session_id, fragment.clone()).is_ok() {
+                if let Some((new_path, _)) = self.client_topology.get_current_path() {
+                    if new_path != sent.routing_header.hops {
+                        self.controller_send
+                            .send(NodeEvent::Rerouted {
+                                node: self.id,
+                                session_id,
+                                old_path: sent.routing_header.hops.clone(),
+                                new_path,
+                            })
+                            .ok();
+                    }
+                }
+                return Ok(());
+            }
+            // increase weights for the failed path to avoid it
+            self.client_topology.increment_weights_for_node(avoid);
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        self.controller_send
+            .send(NodeEvent::RetryExhausted {
+                node: self.id,
+                session_id,
+                fragment_index: fragment.fragment_index,
+                attempts: MAX_RESEND_ATTEMPTS,
+            })
+            .ok();
+        Err("Gave up resending the fragment")
+    }
+
     fn send_new_generic_fragment(
         &mut self,
         server_id: NodeId,
@@ -495,6 +684,11 @@ impl WebBrowser {
                     self.controller_send
                         .send(NodeEvent::PacketSent(packet.clone()))
                         .ok();
+                    if let Some(progress) = self.transfers.get_mut(&(session_id, self.id)) {
+                        progress.retransmissions += 1;
+                        progress.path = trace.clone();
+                    }
+                    self.report_progress((session_id, self.id));
                     return Ok(());
                 } else {
                     self.client_topology.remove_node(trace[1]);
@@ -506,6 +700,7 @@ impl WebBrowser {
             }
         } else {
             self.client_topology.find_all_paths(self.id,server_id);
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             return Err("No current path");
         }
     }
@@ -535,8 +730,16 @@ impl WebBrowser {
         &mut self,
         flood_packet: FloodResponse,
     ) -> Result<(), &str> {
+        let before = self.client_topology.size();
         self.client_topology
             .update_topology((self.id, NodeType::Client), flood_packet.path_trace.clone());
+        let (nodes, edges) = self.client_topology.size();
+        if (nodes, edges) != before {
+            self.flood_scheduler.topology_changed();
+            self.controller_send
+                .send(NodeEvent::TopologyUpdated { node: self.id, nodes, edges })
+                .ok();
+        }
         let serv = self.client_topology.get_all_servers();
         if !serv.is_empty() {
             for s in serv {
@@ -559,6 +762,8 @@ impl WebBrowser {
         {
             self.flood_ids
                 .insert((flood_packet.flood_id, flood_packet.initiator_id));
+            self.sessions
+                .flood_seen((flood_packet.flood_id, flood_packet.initiator_id), Instant::now());
         }
         let mut hops = path_trace
             .clone()
@@ -584,11 +789,6 @@ impl WebBrowser {
         nack: Nack,
         packet: &Packet,
     ) -> Result<(), &str> {
-        let flood_id = generate_flood_id(&mut self.flood_ids, self.id);
-        let mut session = 0;
-        while self.session_id_alredy_used(session) {
-            session = rand_session_id();
-        }
         match nack.clone().nack_type {
             NackType::DestinationIsDrone => {
                 //check route, it shouldn't happen if the routing was done right
@@ -600,22 +800,7 @@ impl WebBrowser {
                                 if f.fragment_index == nack.fragment_index {
                                     self.client_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -639,8 +824,6 @@ impl WebBrowser {
                 }
             }
             NackType::Dropped => {
-                self.send_new_flood_request(session, flood_id).ok();
-
                 //update weight of the path used and change it there's one with less
                 // println!("Dropped by drone nacked");
 
@@ -651,22 +834,7 @@ impl WebBrowser {
                                 if f.fragment_index == nack.fragment_index {
                                     self.client_topology
                                         .increment_weights_for_node(packet.routing_header.hops[0]);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(packet.routing_header.hops[0]);
-                                            std::thread::sleep(Duration::from_millis(100));
-                                        }
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), packet.routing_header.hops[0]);
                                 }
                             }
                             _ => {
@@ -682,6 +850,7 @@ impl WebBrowser {
                 // println!("Error in routing nacked");
                 //Could be a drone in crash mode so remove the node id from topology and update it
                 self.client_topology.remove_node(id);
+                self.flood_scheduler.trigger(FloodReason::ErrorInRouting, Instant::now());
 
                 if let Some(packets) = { self.holder_sent.get(&(session_id, self.id)).cloned() } {
                     //update the path since it might mean a drone has crashed or bad routing
@@ -690,22 +859,7 @@ impl WebBrowser {
                         match p.clone().pack_type {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-                                                                        
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -736,22 +890,7 @@ impl WebBrowser {
                             PacketType::MsgFragment(f) => {
                                 if f.fragment_index == nack.fragment_index {
                                     // self.client_topology.increment_weights_for_node(id);
-                                    loop {
-                                        self.client_topology.set_path_based_on_dst(*p.routing_header.hops.last().unwrap());
-
-                                        if self.send_new_generic_fragment(
-                                            *p.routing_header.hops.last().unwrap(),
-                                            session_id,
-                                            f.clone(),
-                                        ).is_ok() {
-                                            break;
-                                        } else {
-                                            // Optionally: increase weights for the failed path to avoid it
-                                            self.client_topology.increment_weights_for_node(id);
-                                        }
-                                        std::thread::sleep(Duration::from_millis(100));
-                                    }
-                                    return Ok(());
+                                    return self.resend_fragment(session_id, &p, f.clone(), id);
                                 }
                             }
                             PacketType::Ack(a) => {
@@ -806,6 +945,13 @@ impl WebBrowser {
                     .get_mut(&(session_id, self.id))
                     .unwrap()
                     .remove(i);
+                self.sessions.sent((session_id, self.id), Instant::now());
+                let remaining = self.holder_sent[&(session_id, self.id)].len() as u64;
+                if let Some(progress) = self.transfers.get_mut(&(session_id, self.id)) {
+                    progress.done = progress.total.saturating_sub(remaining);
+                    progress.path = hops.iter().rev().copied().collect();
+                }
+                self.report_progress((session_id, self.id));
                 return Ok(());
             } else {
                 return Err("Fragment Index was not supposed to be 0");
@@ -820,12 +966,18 @@ impl WebBrowser {
         session_id: u64,
         src: NodeId,
         frag: &Fragment,
+        path: &[NodeId],
     ) -> Option<Message> {
         self.client_topology.find_all_paths(self.id, src);
         self.client_topology.set_path_based_on_dst(src);
         self.send_ack(session_id, &src, frag.fragment_index).ok();
+        if self.cancelled.contains(&(session_id, src)) || self.sessions.is_over(&(session_id, src)) {
+            return None;
+        }
         if let Some(holder) = self.holder_frag_index.get_mut(&(session_id, src)) {
-            if !holder.contains(&frag.fragment_index) {
+            self.sessions.received((session_id, src), Instant::now());
+            let duplicate = holder.contains(&frag.fragment_index);
+            if !duplicate {
                 // println!("Fragm n: 1  < n <  tot");
                 let target = self.holder_rec.get_mut(&(session_id, src)).unwrap();
                 update_holder_rec(
@@ -838,9 +990,19 @@ impl WebBrowser {
                 holder.push(frag.fragment_index);
             }
             // print!("{} {}\n\n\n", holder.len(), frag.total_n_fragments);
-            if holder.len() == (frag.total_n_fragments) as usize {
+            let received = holder.len() as u64;
+            if let Some(progress) = self.transfers.get_mut(&(session_id, src)) {
+                progress.done = received;
+                progress.path = path.to_vec();
+                if duplicate {
+                    progress.retransmissions += 1;
+                }
+            }
+            self.report_progress((session_id, src));
+            if received == frag.total_n_fragments {
                 if let Some(mut data) = self.holder_rec.get_mut(&(session_id, src)) {
                     remove_trailing_zeros(&mut data);
+                    let (kind, size) = (message_kind(data[0]).unwrap_or("Unknown"), data.len());
                     let mut f_serialized = serialize(data.clone());
                     let result = super::super::fragmentation_handling::reconstruct_message(
                         data[0],
@@ -849,12 +1011,29 @@ impl WebBrowser {
                     if let Ok(msg) = result {
                         self.holder_rec.remove(&(session_id, src));
                         self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::MessageDelivered {
+                                node: self.id,
+                                from: src,
+                                session_id,
+                                kind: kind.to_string(),
+                                size,
+                            })
+                            .ok();
                         self.pre_processed = Some(((session_id, src), msg.clone()));
                         // println!("Message Reconstructed");
                         return Some(msg.clone());
                     } else {
                         self.pre_processed = None;
                         // println!("Message reconstruction failed");
+                        // a broken message never completes, its buffers would only take space
+                        self.holder_rec.remove(&(session_id, src));
+                        self.holder_frag_index.remove(&(session_id, src));
+                        self.sessions.finish((session_id, src), Instant::now());
+                        self.controller_send
+                            .send(NodeEvent::ReassemblyFailed { node: self.id, from: src, session_id })
+                            .ok();
                         return None;
                     }
                 }
@@ -862,6 +1041,13 @@ impl WebBrowser {
             
             None
         } else {
+            match self.sessions.admit((session_id, src), frag.total_n_fragments, Instant::now()) {
+                Ok(evicted) => self.drop_reassemblies(&evicted),
+                Err(e) => {
+                    info!("Client[{}] refused session {}: {}", self.id, session_id, e);
+                    return None;
+                }
+            }
             self.holder_rec.insert(
                 (session_id, src),
                 vec![0; (frag.clone().total_n_fragments * 128) as usize],
@@ -876,10 +1062,54 @@ impl WebBrowser {
             );
             self.holder_frag_index
                 .insert((session_id, src), [frag.fragment_index].to_vec());
+            let mut progress = TransferProgress::new(
+                session_id,
+                src,
+                TransferDirection::Download,
+                frag.total_n_fragments,
+                path.to_vec(),
+            );
+            progress.done = 1;
+            self.transfers.insert((session_id, src), progress);
+            self.report_progress((session_id, src));
             return None;
         }
     }
 
+    fn report_progress(&mut self, key: (u64, NodeId)) {
+        if let Some(progress) = self.transfers.get(&key) {
+            let _ = self.gui_event_sender.send(WebEvent::Progress(progress.clone()));
+            if progress.is_finished() {
+                self.transfers.remove(&key);
+            }
+        }
+    }
+
+    fn cancel_transfer(&mut self, direction: TransferDirection, session_id: u64, peer: NodeId) {
+        let key = match direction {
+            TransferDirection::Upload => {
+                //an empty holder keeps the session_id reserved and stops retransmissions
+                if let Some(holder) = self.holder_sent.get_mut(&(session_id, self.id)) {
+                    holder.clear();
+                }
+                self.sent.remove(&(session_id, self.id));
+                (session_id, self.id)
+            }
+            TransferDirection::Download => {
+                self.holder_rec.remove(&(session_id, peer));
+                self.holder_frag_index.remove(&(session_id, peer));
+                self.cancelled.insert((session_id, peer));
+                self.sessions.finish((session_id, peer), Instant::now());
+                (session_id, peer)
+            }
+        };
+        if let Some(progress) = self.transfers.get_mut(&key) {
+            progress.cancelled = true;
+        }
+        self.report_progress(key);
+        let _ = self.send_from_web_client(peer, Message::DefaultsRequest(DefaultsRequest::CANCEL(session_id)));
+    }
+
     fn session_id_alredy_used(&self, session_id: u64) -> bool {
         if self.holder_sent.contains_key(&(session_id, self.id)) {
             true
@@ -894,14 +1124,15 @@ impl WebBrowser {
         if let Some(hops) = self.client_topology.get_current_path(){
             Some(hops.0.clone())
         } else {
+            self.flood_scheduler.trigger(FloodReason::NoRoute, Instant::now());
             None
         }
     }
 }
 
-fn rand_session_id() -> u64 {
+fn rand_session_id(rng: &mut impl RngCore) -> u64 {
     let mut bytes = [0u8; 8];
-    OsRng.fill_bytes(&mut bytes);
+    rng.fill_bytes(&mut bytes);
     u64::from_ne_bytes(bytes)
 }
 
@@ -935,12 +1166,12 @@ fn remove_trailing_zeros(vec: &mut Vec<u8>) {
     }
 }
 
-fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId) -> u64 {
-    let mut rng = 1;
-    while !flood_ids.insert((rng, id)) {
-        rng = rand::random::<u64>();
+fn generate_flood_id(flood_ids: &mut HashSet<(u64, NodeId)>, id: NodeId, rng: &mut impl RngCore) -> u64 {
+    let mut flood_id = 1;
+    while !flood_ids.insert((flood_id, id)) {
+        flood_id = rng.next_u64();
     }
-    rng
+    flood_id
 }
 
 fn update_holder_rec(
@@ -8,8 +8,6 @@ use bevy::{
     render::mesh::{Mesh, PrimitiveTopology},
     ui::FocusPolicy,
     window::*,
-    winit::WakeUp,
-    winit::WinitPlugin,
 };
 use bevy_simple_text_input::*;
 use crossbeam_channel::*;
@@ -24,44 +22,141 @@ use std::{
 };
 use toml::to_string;
 use toml::{self};
-use wg_2024::{config::Config, controller::*, drone::Drone, network::*, packet::*};
+use wg_2024::{config::Config, controller::*, network::*, packet::*};
+
+use super::flood::FloodPolicy;
+use super::links::{LinkDrop, Links};
+use super::mock_drone::MockPolicy;
+use super::sessions::{EvictionStats, SessionPolicy};
+use super::topology::TopologySnapshot;
 
 pub mod components;
+pub mod editor;
+pub mod emulation;
+pub mod heatmap;
+pub mod inspector;
+pub mod layout;
+pub mod learned;
 pub mod logic;
+pub mod replay;
+pub mod scenario;
 pub mod setup;
+pub mod stats;
 pub mod systems;
+pub mod trace;
 
 use components::*;
+use editor::*;
+use emulation::*;
+use heatmap::*;
+use inspector::*;
+use layout::*;
+use learned::*;
 use logic::*;
+use replay::*;
+use scenario::ScenarioRunner;
 use setup::*;
+use stats::*;
 use systems::*;
+use trace::TraceRecorder;
 
 pub enum NodeCommand {
     AddSender(NodeId,Sender<Packet>),
     RemoveSender(NodeId),
+    Crash,   // stop handling packets and gui commands, the thread stays alive
+    Restart, // forget the learned state and flood again
+    ShowTopology(Sender<TopologySnapshot>), // reply with what was learned by flooding
 }
 #[derive(Debug)]
 pub enum NodeEvent {
     PacketSent(Packet),
     ControllerShortcut(Packet),
+    // what the client or server `node` did with its packets
+    MessageDelivered { node: NodeId, from: NodeId, session_id: u64, kind: String, size: usize },
+    RequestServed { node: NodeId, client: NodeId, session_id: u64, request: String },
+    Rerouted { node: NodeId, session_id: u64, old_path: Vec<NodeId>, new_path: Vec<NodeId> },
+    TopologyUpdated { node: NodeId, nodes: usize, edges: usize },
+    ReassemblyFailed { node: NodeId, from: NodeId, session_id: u64 },
+    RetryExhausted { node: NodeId, session_id: u64, fragment_index: u64, attempts: u32 },
+    SessionsEvicted { node: NodeId, evicted: EvictionStats }, // since the last report
+    LinkDropped { from: NodeId, to: NodeId, session_id: u64, reason: LinkDrop }, // by the link shim, not a node
 }
 
-const DRONE_NAMES: [&str; 10] = [
-    "BagelBomber",
-    "BetterCallDrone",
-    "RustRoveri",
-    "GetDroned",
-    "CppEnjoyers",
-    "D.R.O.N.E",
-    "NullPointer",
-    "Rustafarian",
-    "DrOnes",
-    "Rusteze",
-];
+impl NodeEvent {
+    // The client or server that reported it, None for the packet events
+    pub fn node(&self) -> Option<NodeId> {
+        match self {
+            NodeEvent::PacketSent(_) | NodeEvent::ControllerShortcut(_) => None,
+            NodeEvent::MessageDelivered { node, .. }
+            | NodeEvent::RequestServed { node, .. }
+            | NodeEvent::Rerouted { node, .. }
+            | NodeEvent::TopologyUpdated { node, .. }
+            | NodeEvent::ReassemblyFailed { node, .. }
+            | NodeEvent::RetryExhausted { node, .. }
+            | NodeEvent::SessionsEvicted { node, .. }
+            | NodeEvent::LinkDropped { from: node, .. } => Some(*node),
+        }
+    }
+
+    // Kind and detail of the telemetry events for logs, None for the packet events
+    pub fn describe(&self) -> Option<(&'static str, String)> {
+        Some(match self {
+            NodeEvent::PacketSent(_) | NodeEvent::ControllerShortcut(_) => return None,
+            NodeEvent::MessageDelivered { from, session_id, kind, size, .. } => (
+                "delivered",
+                format!("{} of {} bytes from {}, session {}", kind, size, from, session_id),
+            ),
+            NodeEvent::RequestServed { client, session_id, request, .. } => (
+                "served",
+                format!("{} for {}, session {}", request, client, session_id),
+            ),
+            NodeEvent::Rerouted { session_id, old_path, new_path, .. } => (
+                "rerouted",
+                format!("session {} from {:?} to {:?}", session_id, old_path, new_path),
+            ),
+            NodeEvent::TopologyUpdated { nodes, edges, .. } => {
+                ("topology", format!("{} nodes, {} links known", nodes, edges))
+            }
+            NodeEvent::ReassemblyFailed { from, session_id, .. } => (
+                "reassembly_failed",
+                format!("message from {}, session {}", from, session_id),
+            ),
+            NodeEvent::RetryExhausted { session_id, fragment_index, attempts, .. } => (
+                "retry_exhausted",
+                format!("fragment {} of session {} after {} attempts", fragment_index, session_id, attempts),
+            ),
+            // finished sessions and old flood ids go all the time, only losses are worth a line
+            NodeEvent::SessionsEvicted { evicted, .. } if evicted.lost() == 0 => return None,
+            NodeEvent::SessionsEvicted { evicted, .. } => (
+                "evicted",
+                format!(
+                    "{} timed out, {} over budget, {} refused",
+                    evicted.timed_out, evicted.over_budget, evicted.refused
+                ),
+            ),
+            NodeEvent::LinkDropped { to, session_id, reason, .. } => (
+                "link_dropped",
+                format!("session {} towards {}, {}", session_id, to, reason.as_str()),
+            ),
+        })
+    }
+}
+
+pub use super::drone_registry::DRONE_NAMES;
 const UI_HEIGHT: f32 = 10.0;
 const WINDOW_WIDTH: f32 = 1920.0;
 const WINDOW_HEIGHT: f32 = 1080.0 - 1080.0 / UI_HEIGHT;
 const PACKET_SPEED: f32 = 1000.0;
+const MAX_LIVE_PACKETS: usize = 64; // sprites on screen at once in a live run, the rest isn't drawn
+
+// What the controller knows about a node, kept up to date by the helpers in logic
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeInfo {
+    pub node_type: components::NodeType,
+    pub connected_node_ids: Vec<NodeId>,
+    pub pdr: f32,
+    pub crashed: bool, // clients and servers only, drones are removed on crash
+}
 
 #[derive(Resource)]
 pub struct SimulationController {
@@ -71,6 +166,18 @@ pub struct SimulationController {
     receiver_client_server_event: Receiver<NodeEvent>, //for sc use (animations)
     sender_drone_event: Sender<DroneEvent>,     //for new drones (they use it to send sc events)
     sender_node_packet: HashMap<NodeId, Sender<Packet>>, // SERVER & CLIENT SENDERS ALSO NEEDED, for sc use (connecting nodes with add sender)
+    drone_impls: HashMap<NodeId, usize>, // index in DRONE_NAMES of every drone, for logs and respawns
+    seed: Option<u64>, // run seed, keeps the node layout the same between runs
+    nodes: HashMap<NodeId, NodeInfo>, // current topology, starts from the config
+    sender_node_event: Option<Sender<NodeEvent>>, // for new clients and servers, None if they can't be added
+    app: u8, // CHATAPP or WEBAPP, decides what kind of client and server gets added
+    last_server_type: u8, // web servers alternate between text and media
+    base_seed: u64, // new nodes derive their seed from it, like the initial ones
+    recorder: Option<TraceRecorder>, // set with --record
+    flood_policy: FloodPolicy, // for new clients and servers, [simulation.flood] of the config
+    session_policy: SessionPolicy, // for new clients and servers, [simulation.sessions] of the config
+    mock_policy: MockPolicy, // for new mock drones, [simulation.mock] of the config
+    links: Option<Links>, // shims of the packet channels, None when nodes are wired directly (replays, tests)
 }
 
 impl SimulationController {
@@ -81,6 +188,7 @@ impl SimulationController {
         receiver_client_server_event: Receiver<NodeEvent>,
         sender_drone_event: Sender<DroneEvent>,
         sender_node_packet: HashMap<NodeId, Sender<Packet>>,
+        drone_impls: HashMap<NodeId, usize>,
     ) -> Self {
         Self {
             sender_drone_command,
@@ -89,66 +197,183 @@ impl SimulationController {
             receiver_client_server_event,
             sender_drone_event,
             sender_node_packet,
+            drone_impls,
+            seed: None,
+            nodes: HashMap::new(),
+            sender_node_event: None,
+            app: 0,
+            last_server_type: 0,
+            base_seed: 0,
+            flood_policy: FloodPolicy::default(),
+            session_policy: SessionPolicy::default(),
+            mock_policy: MockPolicy::default(),
+            links: None,
+            recorder: None,
         }
     }
 
-    pub fn run(self) {
-        let mut winit_plugin = WinitPlugin::<WakeUp>::default();
-        winit_plugin.run_on_any_thread = true; //TODO?: BEVY IN UN THREAD NON MAIN NON FUNZIONA SU MAC / BEVY IN A NON MAIN THREAD DOESNT WORK ON MAC
-
-        App::new()
-            .insert_resource(ClearColor(Color::srgb(0.1, 0.5, 0.9)))
-            .add_plugins((
-                DefaultPlugins
-                    .build()
-                    .disable::<bevy::winit::WinitPlugin>()
-                    .set(WindowPlugin {
-                        primary_window: Some(Window {
-                            resolution: WindowResolution::new(1600., 900.),
-                            mode: WindowMode::Windowed,
-                            title: "Simulation Controller".to_string(),
-                            //resizable: false,
-                            ..default()
-                        }),
-                        ..default()
-                    }),
-                TextInputPlugin,
-                winit_plugin,
-            ))
-            .add_event::<UpdateNodesEvent>()
-            .add_event::<NodeMovedEvent>()
-            .add_event::<MakeLinesEvent>()
-            .add_event::<SpawnDroneEvent>()
-            .add_event::<ChangePdrEvent>()
-            .add_event::<PacketCreateEvent>()
-            .add_event::<PacketAddHopEvent>()
-            .insert_resource(ActiveMode::None)
-            .insert_resource(self)
-            .insert_resource(SelectedNode(None))
-            .add_systems(Startup, (setup, setup_ui))
-            .add_systems(
-                Update,
-                (
-                    crossbeam_listener,
-                    packet_spawn,
-                    packet_add_hop.after(packet_spawn),
-                    packet_move,
-                    update_packet_ends,
-                    button_system,
-                    button_action,
-                    update_selector,
-                    change_pdr,
-                    spawn_drone.before(update_nodes),
-                    update_nodes,
-                    make_lines.after(update_nodes),
-                ),
-            )
-            .add_observer(crash_target)
-            .add_observer(manage_highlight)
-            .add_observer(reset_highlight)
-            .add_observer(connect_nodes)
-            .add_observer(change_pdr_target)
-            .run();
+    pub fn with_recorder(mut self, recorder: TraceRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn record_drone_event(&mut self, event: &DroneEvent) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.drone_event(event);
+        }
+    }
+
+    pub fn record_node_event(&mut self, event: &NodeEvent) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.node_event(event);
+        }
+    }
+
+    pub fn record_action(&mut self, action: String) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.action(action);
+        }
+    }
+
+    // Once per frame, after the events and actions of that frame
+    pub fn record_topology(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.topology(&self.nodes);
+        }
+    }
+
+    // Replays have no threads behind the controller, the topology comes from the trace
+    pub fn set_nodes(&mut self, nodes: HashMap<NodeId, NodeInfo>) {
+        self.nodes = nodes;
+    }
+
+    // Lets the controller add clients and servers of the running app
+    pub fn with_app(
+        mut self,
+        app: u8,
+        last_server_type: u8,
+        base_seed: u64,
+        sender_node_event: Sender<NodeEvent>,
+    ) -> Self {
+        self.app = app;
+        self.last_server_type = last_server_type;
+        self.base_seed = base_seed;
+        self.sender_node_event = Some(sender_node_event);
+        self
+    }
+
+    pub fn with_topology(mut self, config: &Config) -> Self {
+        for drone in &config.drone {
+            self.nodes.insert(drone.id, NodeInfo {
+                node_type: components::NodeType::Drone,
+                connected_node_ids: drone.connected_node_ids.clone(),
+                pdr: drone.pdr,
+                crashed: false,
+            });
+        }
+        for client in &config.client {
+            self.nodes.insert(client.id, NodeInfo {
+                node_type: components::NodeType::Client,
+                connected_node_ids: client.connected_drone_ids.clone(),
+                pdr: 0.0,
+                crashed: false,
+            });
+        }
+        for server in &config.server {
+            self.nodes.insert(server.id, NodeInfo {
+                node_type: components::NodeType::Server,
+                connected_node_ids: server.connected_drone_ids.clone(),
+                pdr: 0.0,
+                crashed: false,
+            });
+        }
+        self
+    }
+
+    pub fn nodes(&self) -> &HashMap<NodeId, NodeInfo> {
+        &self.nodes
+    }
+
+    fn link(&mut self, id: NodeId, to: NodeId) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            if !node.connected_node_ids.contains(&to) {
+                node.connected_node_ids.push(to);
+            }
+        }
+    }
+
+    fn unlink(&mut self, id: NodeId, from: NodeId) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.connected_node_ids.retain(|&n| n != from);
+        }
+    }
+
+    pub fn with_flood_policy(mut self, flood_policy: FloodPolicy) -> Self {
+        self.flood_policy = flood_policy;
+        self
+    }
+
+    pub fn flood_policy(&self) -> FloodPolicy {
+        self.flood_policy
+    }
+
+    pub fn with_session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.session_policy = session_policy;
+        self
+    }
+
+    pub fn session_policy(&self) -> SessionPolicy {
+        self.session_policy
+    }
+
+    pub fn with_mock_policy(mut self, mock_policy: MockPolicy) -> Self {
+        self.mock_policy = mock_policy;
+        self
+    }
+
+    pub fn mock_policy(&self) -> &MockPolicy {
+        &self.mock_policy
+    }
+
+    pub fn with_links(mut self, links: Links) -> Self {
+        self.links = Some(links);
+        self
+    }
+
+    pub fn links(&self) -> Option<&Links> {
+        self.links.as_ref()
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn drone_impls(&self) -> &HashMap<NodeId, usize> {
+        &self.drone_impls
+    }
+
+    /// Drains the pending drone and node events outside of Bevy, forwarding
+    /// controller shortcuts the same way `crossbeam_listener` does.
+    pub fn poll_events(&mut self) -> (Vec<DroneEvent>, Vec<NodeEvent>) {
+        let node_events: Vec<NodeEvent> = self.receiver_client_server_event.try_iter().collect();
+        for event in &node_events {
+            self.record_node_event(event);
+        }
+        let mut drone_events = Vec::new();
+        while let Ok(event) = self.receiver_drone_event.try_recv() {
+            self.record_drone_event(&event);
+            if let DroneEvent::ControllerShortcut(ref packet) = event {
+                shortcut(self, packet.clone());
+            }
+            drone_events.push(event);
+        }
+        self.record_topology();
+        (drone_events, node_events)
     }
 }
 
@@ -164,16 +389,29 @@ impl Plugin for SimulationControllerPlugin {
         app.add_event::<ChangePdrEvent>();
         app.add_event::<PacketCreateEvent>();
         app.add_event::<PacketAddHopEvent>();
+        app.add_event::<TopologyChangedEvent>();
         app.insert_resource(ActiveMode::None);
         app.insert_resource(SelectedNode(None));
+        app.init_resource::<LinkEnd>();
+        app.init_resource::<NetworkStats>();
+        app.init_resource::<StatsSelection>();
+        app.init_resource::<PacketLog>();
+        app.init_resource::<InspectorState>();
+        app.init_resource::<NodeLayout>(); // run_app inserts it first when the config has coordinates
+        app.init_resource::<Heatmap>();
+        app.init_resource::<LearnedView>();
+        app.init_resource::<LinkPanel>();
+        // the chat and web guis bring their own
+        if !app.is_plugin_added::<bevy_egui::EguiPlugin>() {
+            app.add_plugins(bevy_egui::EguiPlugin {
+                enable_multipass_for_primary_context: false,
+            });
+        }
         app.add_systems(Startup, (setup, setup_ui));
         app.add_systems(
             Update,
             (
                 crossbeam_listener,
-                // packet_spawn,
-                // packet_add_hop.after(packet_spawn),
-                // packet_move,
                 update_packet_ends,
                 button_system,
                 button_action,
@@ -182,12 +420,52 @@ impl Plugin for SimulationControllerPlugin {
                 spawn_drone.before(update_nodes),
                 update_nodes,
                 make_lines.after(update_nodes),
+                run_scenario.before(sync_topology),
+                sync_topology.before(update_nodes),
+                stats_panel,
+                inspector_window,
+                layout_window,
+                heatmap_tick.before(make_lines),
+                heatmap_window,
+                learned_window,
+                learned_overlay,
+                links_window,
+                pause_window,
+            ),
+        );
+        // live runs animate up to MAX_LIVE_PACKETS packets, replays every one of them
+        app.add_systems(
+            Update,
+            (
+                packet_spawn.after(crossbeam_listener),
+                packet_add_hop.after(packet_spawn),
+                packet_move,
             ),
         );
+        app.add_systems(
+            Update,
+            (
+                replay_tick.before(crossbeam_listener).before(sync_topology),
+                replay_window,
+            )
+                .run_if(resource_exists::<ReplayState>),
+        );
+        app.add_systems(
+            Update,
+            editor_window
+                .before(sync_topology)
+                .run_if(resource_exists::<EditorState>),
+        );
         app.add_observer(crash_target);
         app.add_observer(manage_highlight);
         app.add_observer(reset_highlight);
         app.add_observer(connect_nodes);
+        app.add_observer(disconnect_nodes);
+        app.add_observer(restart_target);
+        app.add_observer(select_stats_node);
+        app.add_observer(inspect_packet);
+        app.add_observer(editor_click);
+        app.add_observer(drag_node);
         app.add_observer(change_pdr_target);
         app.add_observer(change_spawn_target);
     }
@@ -21,6 +21,7 @@ pub struct SpawnDroneEvent {
 pub struct ChangePdrEvent {
     pub entity: Entity,
     pub new_pdr: f32,
+    pub link_to: Option<NodeId>, // the value is the loss of the link from `entity` to this node instead
 }
 
 #[derive(Event)]
@@ -34,6 +35,11 @@ pub struct PacketAddHopEvent {
     pub drone_event: DroneEvent,
 }
 
+// The controller's topology was changed outside of the buttons (scenario, replay),
+// the sprites have to be brought in line with it
+#[derive(Event)]
+pub struct TopologyChangedEvent;
+
 #[derive(Component)]
 pub struct HopQueue(pub VecDeque<(u8, Option<u8>)>);
 
@@ -42,9 +48,10 @@ pub struct PacketInfo {
     pub session_id: u64,
     pub hops: Vec<u8>,
     pub last_hop_index: usize,
+    pub log_id: Option<u64>, // entry in the PacketLog, opened when the sprite is clicked
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum NodeType {
     Drone,
     Client,
@@ -59,6 +66,12 @@ pub struct ScNode {
     pub pdr: f32,
 }
 
+// Crashed client or server, kept on screen greyed out until restarted
+#[derive(Component)]
+pub struct Crashed;
+
+pub const CRASHED_COLOR: Color = Color::srgb(0.4, 0.4, 0.4);
+
 #[derive(Component)]
 pub struct DroneText;
 
@@ -77,12 +90,19 @@ pub enum ActiveMode {
     Add,
     Crash,
     Connect,
+    Disconnect,
     Pdr,
+    Restart,
 }
 
 #[derive(Resource)]
 pub struct SelectedNode(pub Option<Entity>);
 
+// In Pdr mode, the neighbour of the selected node whose link gets the value as its loss rate
+#[derive(Resource, Default)]
+pub struct LinkEnd(pub Option<Entity>);
+pub const LINK_END_COLOR: Color = Color::srgb(0.5, 0.0, 0.6);
+
 #[derive(Component)]
 pub struct DroneAdd;
 
@@ -97,7 +117,10 @@ pub enum ButtonLabel {
     Add,
     Crash,
     Connect,
+    Disconnect,
     Pdr,
+    Restart,
+    Save, // writes the current topology to configs/
     Done,
 }
 
@@ -108,11 +131,23 @@ pub struct ButtonColors {
     pub pressed: Color,
 }
 
+// Goes through every drone implementation, then a client and a server of the running app
 #[derive(Component)]
 pub struct DroneSelector {
     pub index: usize,
 }
 
+pub const SELECTOR_CLIENT: usize = DRONE_NAMES.len();
+pub const SELECTOR_SERVER: usize = DRONE_NAMES.len() + 1;
+
+pub fn selector_label(index: usize) -> &'static str {
+    match index {
+        SELECTOR_CLIENT => "Client",
+        SELECTOR_SERVER => "Server",
+        _ => DRONE_NAMES[index],
+    }
+}
+
 #[derive(Component, Clone, Copy, PartialEq, Eq)]
 pub enum SelectorDirection {
     Left,
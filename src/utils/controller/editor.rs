@@ -0,0 +1,348 @@
+// Builds a topology from an empty canvas: nodes are added from the editor window, links are
+// drawn by clicking two nodes, and the result is checked live with the initializer's rules
+// before it can be exported to configs/.
+
+use std::path::PathBuf;
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::backup_server::{CHATSERVER, MEDIASERVER, TEXTSERVER};
+use super::super::controller::*;
+use super::super::initializer::{self, CHATAPP, WEBAPP};
+use super::super::sim_config::server_kind_name;
+
+#[derive(Resource, Default)]
+pub struct EditorState {
+    pub kinds: HashMap<NodeId, u8>, // kind of every server, as the backup_server constants
+    pub selected: Option<NodeId>,
+    pub link_from: Option<NodeId>, // set by "Link", the next clicked node gets linked to it
+    pub file_name: String,
+    pub message: Option<String>,
+}
+
+// A controller with nothing running behind it, only its topology is used
+pub fn editor_controller() -> SimulationController {
+    let (drone_send, drone_recv) = unbounded();
+    let (_, node_recv) = unbounded();
+    SimulationController::new(
+        HashMap::new(),
+        HashMap::new(),
+        drone_recv,
+        node_recv,
+        drone_send,
+        HashMap::new(),
+        HashMap::new(),
+    )
+}
+
+// Drones from 1, clients from 21 and servers from 31 like the shipped configs, skipping used ids
+pub fn place_node(simulation_controller: &mut SimulationController, node_type: components::NodeType) -> Option<NodeId> {
+    let start = match node_type {
+        components::NodeType::Drone => 1,
+        components::NodeType::Client => 21,
+        components::NodeType::Server => 31,
+    };
+    let id = (start..=NodeId::MAX)
+        .chain(1..start)
+        .find(|id| !simulation_controller.nodes.contains_key(id))?;
+    simulation_controller.nodes.insert(id, NodeInfo {
+        node_type,
+        connected_node_ids: Vec::new(),
+        pdr: 0.0,
+        crashed: false,
+    });
+    Some(id)
+}
+
+pub fn remove_node(simulation_controller: &mut SimulationController, id: NodeId) {
+    if let Some(info) = simulation_controller.nodes.remove(&id) {
+        for neighbour in info.connected_node_ids {
+            simulation_controller.unlink(neighbour, id);
+        }
+    }
+}
+
+// Links two nodes with the Connect button's rules, or removes the link if they already share one
+pub fn toggle_link(simulation_controller: &mut SimulationController, a: NodeId, b: NodeId) -> Result<(), String> {
+    let linked = simulation_controller
+        .nodes
+        .get(&a)
+        .is_some_and(|info| info.connected_node_ids.contains(&b));
+    if linked {
+        simulation_controller.unlink(a, b);
+        simulation_controller.unlink(b, a);
+    } else {
+        check_connect(simulation_controller, a, b)?;
+        simulation_controller.link(a, b);
+        simulation_controller.link(b, a);
+    }
+    Ok(())
+}
+
+// Chat servers next to chat servers, web ones alternate between text and media
+fn next_server_kind(kinds: &HashMap<NodeId, u8>) -> u8 {
+    let count = |kind: u8| kinds.values().filter(|k| **k == kind).count();
+    if kinds.is_empty() || count(CHATSERVER) > 0 {
+        CHATSERVER
+    } else if count(TEXTSERVER) > count(MEDIASERVER) {
+        MEDIASERVER
+    } else {
+        TEXTSERVER
+    }
+}
+
+// Chat servers only work with the chat app, text and media ones with the web app
+pub fn app_of(config: &Config, kinds: &HashMap<NodeId, u8>) -> Result<u8, String> {
+    let chat = config
+        .server
+        .iter()
+        .filter(|s| kinds.get(&s.id).copied().unwrap_or(CHATSERVER) == CHATSERVER)
+        .count();
+    if chat == config.server.len() {
+        Ok(CHATAPP)
+    } else if chat == 0 {
+        Ok(WEBAPP)
+    } else {
+        Err("Chat servers can't be mixed with text and media servers".to_string())
+    }
+}
+
+// The initializer's checks plus the server kinds, the app the topology runs is returned
+pub fn validate(nodes: &HashMap<NodeId, NodeInfo>, kinds: &HashMap<NodeId, u8>) -> Result<u8, String> {
+    let config = topology_config(nodes);
+    initializer::check_config(&config)?;
+    app_of(&config, kinds)
+}
+
+pub fn editor_toml(
+    nodes: &HashMap<NodeId, NodeInfo>,
+    kinds: &HashMap<NodeId, u8>,
+    positions: &HashMap<NodeId, Vec2>,
+) -> Result<String, String> {
+    let app = validate(nodes, kinds)?;
+    let config = topology_config(nodes);
+    // every server gets its kind written, chat ones included
+    let kinds = config
+        .server
+        .iter()
+        .map(|s| (s.id, kinds.get(&s.id).copied().unwrap_or(CHATSERVER)))
+        .collect();
+    topology_toml(&config, Some(app), &kinds, positions)
+}
+
+pub fn export(
+    nodes: &HashMap<NodeId, NodeInfo>,
+    kinds: &HashMap<NodeId, u8>,
+    positions: &HashMap<NodeId, Vec2>,
+    name: &str,
+) -> Result<PathBuf, String> {
+    let name = name.trim().trim_end_matches(".toml");
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err("Pick a file name".to_string());
+    }
+    let text = editor_toml(nodes, kinds, positions)?;
+    let path = initializer::configs_dir().join(format!("{}.toml", name));
+    if path.exists() {
+        return Err(format!("{} already exists", path.to_string_lossy()));
+    }
+    fs::write(&path, text).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+pub fn editor_click(
+    trigger: Trigger<Pointer<Click>>,
+    editor: Option<ResMut<EditorState>>,
+    node_query: Query<&ScNode>,
+    mut simulation_controller: ResMut<SimulationController>,
+    mut topology_writer: EventWriter<TopologyChangedEvent>,
+) {
+    let Some(mut editor) = editor else {
+        return;
+    };
+    let Ok(node) = node_query.get(trigger.target()) else {
+        return;
+    };
+    match editor.link_from.take() {
+        Some(from) if from != node.id => {
+            editor.message = toggle_link(&mut simulation_controller, from, node.id).err();
+            topology_writer.write(TopologyChangedEvent);
+        }
+        _ => editor.selected = Some(node.id),
+    }
+}
+
+pub fn editor_window(
+    mut egui_ctx: EguiContexts,
+    mut editor: ResMut<EditorState>,
+    mut simulation_controller: ResMut<SimulationController>,
+    mut topology_writer: EventWriter<TopologyChangedEvent>,
+    layout: Res<NodeLayout>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let mut changed = false;
+    let ctx = egui_ctx.ctx_mut();
+    egui::Window::new("Topology editor")
+        .anchor(egui::Align2::LEFT_TOP, [10.0, 130.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (label, node_type) in [
+                    ("Add drone", components::NodeType::Drone),
+                    ("Add client", components::NodeType::Client),
+                    ("Add server", components::NodeType::Server),
+                ] {
+                    if ui.button(label).clicked() {
+                        if let Some(id) = place_node(&mut simulation_controller, node_type) {
+                            if node_type == components::NodeType::Server {
+                                let kind = next_server_kind(&editor.kinds);
+                                editor.kinds.insert(id, kind);
+                            }
+                            editor.selected = Some(id);
+                            changed = true;
+                        }
+                    }
+                }
+            });
+            ui.separator();
+
+            let selected = editor
+                .selected
+                .and_then(|id| simulation_controller.nodes.get(&id).cloned().map(|info| (id, info)));
+            match selected {
+                Some((id, info)) => {
+                    ui.label(format!("{:?} {}", info.node_type, id));
+                    if info.node_type == components::NodeType::Drone {
+                        let mut pdr = info.pdr;
+                        if ui.add(egui::Slider::new(&mut pdr, 0.0..=1.0).text("PDR")).changed() {
+                            if let Some(node) = simulation_controller.nodes.get_mut(&id) {
+                                node.pdr = pdr;
+                            }
+                            changed = true;
+                        }
+                    }
+                    if info.node_type == components::NodeType::Server {
+                        let mut kind = editor.kinds.get(&id).copied().unwrap_or(CHATSERVER);
+                        ui.horizontal(|ui| {
+                            ui.label("Kind");
+                            for k in [TEXTSERVER, MEDIASERVER, CHATSERVER] {
+                                ui.selectable_value(&mut kind, k, server_kind_name(k));
+                            }
+                        });
+                        editor.kinds.insert(id, kind);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Link").clicked() {
+                            editor.link_from = Some(id);
+                        }
+                        if ui.button("Delete").clicked() {
+                            remove_node(&mut simulation_controller, id);
+                            editor.kinds.remove(&id);
+                            editor.selected = None;
+                            changed = true;
+                        }
+                    });
+                    for neighbour in &info.connected_node_ids {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("linked to {}", neighbour));
+                            if ui.small_button("Remove").clicked() {
+                                editor.message = toggle_link(&mut simulation_controller, id, *neighbour).err();
+                                changed = true;
+                            }
+                        });
+                    }
+                }
+                None => {
+                    ui.label("Click a node to edit it");
+                }
+            }
+            if let Some(from) = editor.link_from {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Click the node to link {} to", from));
+                    if ui.small_button("Cancel").clicked() {
+                        editor.link_from = None;
+                    }
+                });
+            }
+            ui.separator();
+
+            match validate(&simulation_controller.nodes, &editor.kinds) {
+                Ok(app) => {
+                    let app = if app == CHATAPP { "chat" } else { "web" };
+                    ui.colored_label(egui::Color32::LIGHT_GREEN, format!("Valid {} topology", app));
+                }
+                Err(e) => {
+                    ui.colored_label(egui::Color32::LIGHT_RED, e);
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.label("configs/");
+                ui.text_edit_singleline(&mut editor.file_name);
+                if ui.button("Export").clicked() {
+                    editor.message = Some(
+                        match export(&simulation_controller.nodes, &editor.kinds, &layout.positions, &editor.file_name) {
+                            Ok(path) => format!("Saved to {}", path.to_string_lossy()),
+                            Err(e) => e,
+                        },
+                    );
+                }
+            });
+            if let Some(message) = &editor.message {
+                ui.label(message);
+            }
+        });
+    if changed {
+        topology_writer.write(TopologyChangedEvent);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_and_export() {
+        let mut controller = editor_controller();
+        let drones = (0..3)
+            .map(|_| place_node(&mut controller, components::NodeType::Drone).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(drones, vec![1, 2, 3]);
+        let clients = (0..2)
+            .map(|_| place_node(&mut controller, components::NodeType::Client).unwrap())
+            .collect::<Vec<_>>();
+        let server = place_node(&mut controller, components::NodeType::Server).unwrap();
+        assert_eq!((clients.clone(), server), (vec![21, 22], 31));
+        let mut kinds = HashMap::from([(server, CHATSERVER)]);
+        assert!(validate(controller.nodes(), &kinds).is_err());
+
+        toggle_link(&mut controller, 1, 2).unwrap();
+        toggle_link(&mut controller, 2, 3).unwrap();
+        for client in clients {
+            toggle_link(&mut controller, client, 1).unwrap();
+        }
+        toggle_link(&mut controller, server, 2).unwrap();
+        toggle_link(&mut controller, server, 3).unwrap();
+        assert!(toggle_link(&mut controller, 21, 31).is_err());
+        assert_eq!(validate(controller.nodes(), &kinds), Ok(CHATAPP));
+
+        let text = editor_toml(controller.nodes(), &kinds, &HashMap::new()).unwrap();
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(initializer::check_config(&config), Ok(()));
+        let sim = super::super::super::sim_config::SimConfig::from_str(&text).unwrap();
+        assert_eq!(sim.simulation.app(), Ok(Some(CHATAPP)));
+        assert_eq!(sim.server_kinds(), Ok(HashMap::from([(31, CHATSERVER)])));
+
+        kinds.insert(32, TEXTSERVER);
+        place_node(&mut controller, components::NodeType::Server);
+        toggle_link(&mut controller, 32, 1).unwrap();
+        toggle_link(&mut controller, 32, 3).unwrap();
+        assert!(validate(controller.nodes(), &kinds).is_err());
+
+        remove_node(&mut controller, 32);
+        toggle_link(&mut controller, 2, 3).unwrap();
+        assert!(!controller.nodes()[&3].connected_node_ids.contains(&2));
+    }
+}
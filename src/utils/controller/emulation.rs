@@ -0,0 +1,190 @@
+// Changes the emulated links while the network runs: delay, jitter, bandwidth, queue and loss
+// of a single direction, of both directions of a link or of every link at once. Also pauses the
+// whole network and steps it one packet hop at a time.
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+use crate::utils::links::LinkParams;
+
+#[derive(Resource, Default)]
+pub struct LinkPanel {
+    open: bool,
+    from: String,
+    to: String,
+    both: bool,
+    params: LinkParams,
+    message: String,
+}
+
+impl LinkPanel {
+    fn directions(&self) -> Result<Vec<(NodeId, NodeId)>, String> {
+        let (Ok(from), Ok(to)) = (self.from.trim().parse::<NodeId>(), self.to.trim().parse::<NodeId>()) else {
+            return Err("Not a node id".to_string());
+        };
+        Ok(if self.both { vec![(from, to), (to, from)] } else { vec![(from, to)] })
+    }
+}
+
+pub fn describe_link(params: &LinkParams) -> String {
+    let unlimited = |value: String, zero: bool| if zero { "unlimited".to_string() } else { value };
+    format!(
+        "{}±{} ms, {}, queue {}, loss {:.0}%",
+        params.delay_ms,
+        params.jitter_ms,
+        unlimited(format!("{} B/s", params.bandwidth), params.bandwidth == 0),
+        unlimited(params.queue.to_string(), params.queue == 0),
+        params.loss * 100.0
+    )
+}
+
+pub fn links_window(
+    mut egui_ctx: EguiContexts,
+    mut panel: ResMut<LinkPanel>,
+    mut simulation_controller: ResMut<SimulationController>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let ctx = egui_ctx.ctx_mut();
+    if !panel.open {
+        egui::Area::new(egui::Id::new("links_toggle"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 70.0))
+            .show(ctx, |ui| {
+                if ui.button("Links").clicked() {
+                    panel.open = true;
+                }
+            });
+        return;
+    }
+    let mut open = panel.open;
+    egui::Window::new("Links")
+        .open(&mut open)
+        .default_size(egui::vec2(360.0, 280.0))
+        .show(ctx, |ui| {
+            let Some(links) = simulation_controller.links() else {
+                ui.label("Links can't be changed in this run");
+                return;
+            };
+            let (defaults, custom) = (links.defaults(), links.custom());
+            ui.horizontal(|ui| {
+                ui.label("From");
+                ui.add(egui::TextEdit::singleline(&mut panel.from).desired_width(40.0));
+                ui.label("To");
+                ui.add(egui::TextEdit::singleline(&mut panel.to).desired_width(40.0));
+                ui.checkbox(&mut panel.both, "Both ways");
+                if ui.button("Load").clicked() {
+                    match panel.directions() {
+                        Ok(directions) => {
+                            let (from, to) = directions[0];
+                            panel.params = links.params(from, to);
+                            panel.message.clear();
+                        }
+                        Err(e) => panel.message = e,
+                    }
+                }
+            });
+            egui::Grid::new("link_params").show(ui, |ui| {
+                ui.label("Delay (ms)");
+                ui.add(egui::DragValue::new(&mut panel.params.delay_ms));
+                ui.end_row();
+                ui.label("Jitter (ms)");
+                ui.add(egui::DragValue::new(&mut panel.params.jitter_ms));
+                ui.end_row();
+                ui.label("Bandwidth (B/s, 0 unlimited)");
+                ui.add(egui::DragValue::new(&mut panel.params.bandwidth).speed(100));
+                ui.end_row();
+                ui.label("Queue (packets, 0 unlimited)");
+                ui.add(egui::DragValue::new(&mut panel.params.queue));
+                ui.end_row();
+                ui.label("Loss");
+                ui.add(egui::DragValue::new(&mut panel.params.loss).speed(0.01).range(0.0..=1.0));
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                let params = panel.params;
+                if ui.button("Apply to link").clicked() {
+                    let result = panel
+                        .directions()
+                        .and_then(|directions| set_link(&mut simulation_controller, &directions, params));
+                    panel.message = result.err().unwrap_or_default();
+                }
+                if ui.button("Apply to every link").clicked() {
+                    panel.message = set_all_links(&mut simulation_controller, params).err().unwrap_or_default();
+                }
+            });
+            if !panel.message.is_empty() {
+                ui.colored_label(egui::Color32::LIGHT_RED, &panel.message);
+            }
+            ui.separator();
+            ui.label(format!("Every other link: {}", describe_link(&defaults)));
+            egui::ScrollArea::vertical().max_height(120.0).show(ui, |ui| {
+                egui::Grid::new("custom_links").show(ui, |ui| {
+                    for ((from, to), params) in custom {
+                        ui.label(format!("{} -> {}", from, to));
+                        ui.label(describe_link(&params));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    panel.open = open;
+}
+
+pub fn pause_window(
+    mut egui_ctx: EguiContexts,
+    mut simulation_controller: ResMut<SimulationController>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    // replays have their own controls
+    let Some(gate) = simulation_controller.links().map(|links| links.gate().clone()) else {
+        return;
+    };
+    egui::Area::new(egui::Id::new("pause_bar"))
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                let paused = gate.is_paused();
+                let toggle = ui.button(if paused { "Resume" } else { "Pause" }).clicked();
+                let step = ui.button("Step").clicked();
+                let result = match (toggle, step) {
+                    (true, _) if paused => resume_network(&mut simulation_controller),
+                    (true, _) => pause_network(&mut simulation_controller),
+                    (false, true) => step_network(&mut simulation_controller),
+                    (false, false) => Ok(()),
+                };
+                if let Err(e) = result {
+                    warn!("{}", e);
+                }
+                if gate.is_paused() {
+                    let pending = gate.pending_steps();
+                    ui.colored_label(egui::Color32::YELLOW, match pending {
+                        0 => "Paused".to_string(),
+                        n => format!("Paused, {} hop(s) waiting for a packet", n),
+                    });
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_panel() {
+        let mut panel = LinkPanel { from: "1".to_string(), to: " 21".to_string(), ..default() };
+        assert_eq!(panel.directions(), Ok(vec![(1, 21)]));
+        panel.both = true;
+        assert_eq!(panel.directions(), Ok(vec![(1, 21), (21, 1)]));
+        panel.to = "x".to_string();
+        assert!(panel.directions().is_err());
+        let params = LinkParams { delay_ms: 40, jitter_ms: 5, queue: 8, ..Default::default() };
+        assert_eq!(describe_link(&params), "40±5 ms, unlimited, queue 8, loss 0%");
+    }
+}
@@ -0,0 +1,295 @@
+// Colors the links by how many packets crossed them and tints the drones by how many packets
+// they dropped, both over a sliding time window of the drone event stream.
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+
+// Seconds the window selector offers, the samples of the longest one are kept
+pub const HEAT_WINDOWS: [f64; 4] = [10.0, 30.0, 60.0, 300.0];
+const MAX_SAMPLES: usize = 100_000;
+const REFRESH_SECS: f32 = 1.0;
+const MIN_WIDTH: f32 = 2.0;
+const MAX_WIDTH: f32 = 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeatSample {
+    t: f64,
+    from: NodeId,
+    to: Option<NodeId>,
+    dropped: bool,
+}
+
+#[derive(Resource)]
+pub struct Heatmap {
+    pub enabled: bool,
+    pub legend: bool,
+    pub window: f64,
+    samples: VecDeque<HeatSample>,
+    refresh: Timer,
+    shown: bool, // whether the lines and sprites on screen are the heat ones
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            legend: true,
+            window: HEAT_WINDOWS[1],
+            samples: VecDeque::new(),
+            refresh: Timer::from_seconds(REFRESH_SECS, TimerMode::Repeating),
+            shown: false,
+        }
+    }
+}
+
+impl Heatmap {
+    pub fn record_drone_event(&mut self, event: &DroneEvent, t: f64) {
+        let (packet, dropped) = match event {
+            DroneEvent::PacketSent(packet) => (packet, false),
+            DroneEvent::PacketDropped(packet) => (packet, true),
+            DroneEvent::ControllerShortcut(_) => return,
+        };
+        // flood requests have no next hop, they only count for the drop ratio
+        let (from, to) = match &packet.pack_type {
+            PacketType::FloodRequest(request) => match request.path_trace.last() {
+                Some((id, _)) => (*id, None),
+                None => return,
+            },
+            _ => match current_hop(packet) {
+                Some(hop) => hop,
+                None => return,
+            },
+        };
+        self.samples.push_back(HeatSample { t, from, to, dropped });
+        let oldest = t - HEAT_WINDOWS[HEAT_WINDOWS.len() - 1];
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| s.t < oldest || self.samples.len() > MAX_SAMPLES)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn in_window(&self, now: f64) -> impl Iterator<Item = &HeatSample> {
+        let first = now - self.window;
+        self.samples.iter().rev().take_while(move |s| s.t >= first)
+    }
+
+    // Packets sent over each link in the window, both directions together, keyed by (lower id, higher id)
+    pub fn link_load(&self, now: f64) -> HashMap<(NodeId, NodeId), u64> {
+        let mut load = HashMap::new();
+        for sample in self.in_window(now).filter(|s| !s.dropped) {
+            if let Some(to) = sample.to {
+                *load.entry((sample.from.min(to), sample.from.max(to))).or_default() += 1;
+            }
+        }
+        load
+    }
+
+    // Dropped over handled packets of every node that handled any in the window
+    pub fn drop_ratio(&self, now: f64) -> HashMap<NodeId, f32> {
+        let mut counts: HashMap<NodeId, (u64, u64)> = HashMap::new();
+        for sample in self.in_window(now) {
+            let (dropped, total) = counts.entry(sample.from).or_default();
+            *total += 1;
+            if sample.dropped {
+                *dropped += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(id, (dropped, total))| (id, dropped as f32 / total as f32))
+            .collect()
+    }
+}
+
+// Cold links are dark blue, the busiest ones red, `heat` goes from 0 to 1
+pub fn heat_color(heat: f32) -> Color {
+    let heat = heat.clamp(0.0, 1.0);
+    if heat < 0.5 {
+        let k = heat * 2.0;
+        Color::srgb(0.1 + 0.9 * k, 0.2 + 0.7 * k, 0.6 * (1.0 - k))
+    } else {
+        let k = (heat - 0.5) * 2.0;
+        Color::srgb(1.0, 0.9 * (1.0 - k), 0.0)
+    }
+}
+
+pub fn heat_width(heat: f32) -> f32 {
+    MIN_WIDTH + (MAX_WIDTH - MIN_WIDTH) * heat.clamp(0.0, 1.0)
+}
+
+// White for a drone that drops nothing, red for one that drops everything
+pub fn drop_tint(ratio: f32) -> Color {
+    let ratio = ratio.clamp(0.0, 1.0);
+    Color::srgb(1.0, 1.0 - 0.8 * ratio, 1.0 - 0.8 * ratio)
+}
+
+// A line list is always one pixel wide, thick links are drawn as thin rectangles
+pub fn create_heat_line(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    from: Vec2,
+    to: Vec2,
+    color: Color,
+    width: f32,
+) {
+    let delta = to - from;
+    let middle = (from + to) / 2.0;
+    commands.spawn((
+        Mesh2d(meshes.add(Rectangle::new(delta.length(), width))),
+        MeshMaterial2d(materials.add(color)),
+        Transform::from_xyz(middle.x, middle.y, -1.0).with_rotation(Quat::from_rotation_z(delta.to_angle())),
+    ));
+}
+
+// Redraws the links every second while the heatmap is on and tints the drones, and puts the
+// plain look back once when it is turned off
+pub fn heatmap_tick(
+    mut heatmap: ResMut<Heatmap>,
+    mut node_query: Query<(&ScNode, &mut Sprite, Has<Crashed>)>,
+    mut lines_writer: EventWriter<MakeLinesEvent>,
+    active_mode: Res<ActiveMode>,
+    time: Res<Time>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    heatmap.refresh.tick(time.delta());
+    let toggled = heatmap.enabled != heatmap.shown;
+    if !toggled && !(heatmap.enabled && heatmap.refresh.just_finished()) {
+        return;
+    }
+    heatmap.shown = heatmap.enabled;
+    lines_writer.write(MakeLinesEvent);
+    // the modes use the sprite color to show the selected node
+    if *active_mode != ActiveMode::None {
+        return;
+    }
+    let ratios = heatmap.drop_ratio(time.elapsed_secs_f64());
+    for (node, mut sprite, crashed) in &mut node_query {
+        if crashed || node.node_type != components::NodeType::Drone {
+            continue;
+        }
+        sprite.color = match ratios.get(&node.id) {
+            Some(ratio) if heatmap.enabled => drop_tint(*ratio),
+            _ => Color::WHITE,
+        };
+    }
+}
+
+pub fn heatmap_window(
+    mut egui_ctx: EguiContexts,
+    mut heatmap: ResMut<Heatmap>,
+    time: Res<Time>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let busiest = if heatmap.enabled && heatmap.legend {
+        heatmap.link_load(time.elapsed_secs_f64()).into_values().max().unwrap_or(0)
+    } else {
+        0
+    };
+    let ctx = egui_ctx.ctx_mut();
+    egui::Area::new(egui::Id::new("heatmap_area"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut heatmap.enabled, "Heatmap");
+                    ui.add_enabled_ui(heatmap.enabled, |ui| {
+                        for secs in HEAT_WINDOWS {
+                            let label = if secs >= 60.0 {
+                                format!("{}m", secs / 60.0)
+                            } else {
+                                format!("{}s", secs)
+                            };
+                            if ui.selectable_label(heatmap.window == secs, label).clicked() {
+                                heatmap.window = secs;
+                                heatmap.shown = false; // redraw now, not at the next tick
+                            }
+                        }
+                        ui.checkbox(&mut heatmap.legend, "Legend");
+                    });
+                });
+                if heatmap.enabled && heatmap.legend {
+                    ui.separator();
+                    legend_row(ui, "Link load", "0", &format!("{} pkts", busiest), heat_color);
+                    legend_row(ui, "Drone drops", "0%", "100%", drop_tint);
+                }
+            });
+        });
+}
+
+fn legend_row(ui: &mut egui::Ui, title: &str, low: &str, high: &str, color: impl Fn(f32) -> Color) {
+    const STEPS: usize = 16;
+    ui.horizontal(|ui| {
+        ui.label(title);
+        ui.label(low);
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(120.0, 12.0), egui::Sense::hover());
+        let step = rect.width() / STEPS as f32;
+        for i in 0..STEPS {
+            let [r, g, b, _] = color(i as f32 / (STEPS - 1) as f32).to_srgba().to_u8_array();
+            let min = rect.min + egui::vec2(step * i as f32, 0.0);
+            ui.painter().rect_filled(
+                egui::Rect::from_min_size(min, egui::vec2(step, rect.height())),
+                0.0,
+                egui::Color32::from_rgb(r, g, b),
+            );
+        }
+        ui.label(high);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fragment(hops: Vec<NodeId>, hop_index: usize) -> Packet {
+        Packet::new_fragment(
+            SourceRoutingHeader { hop_index, hops },
+            1,
+            Fragment::new(0, 1, [0; 128]),
+        )
+    }
+
+    #[test]
+    fn test_window() {
+        let mut heatmap = Heatmap { window: 10.0, ..default() };
+        heatmap.record_drone_event(&DroneEvent::PacketSent(fragment(vec![21, 1, 2, 31], 2)), 1.0);
+        heatmap.record_drone_event(&DroneEvent::PacketSent(fragment(vec![31, 2, 1, 21], 2)), 5.0);
+        heatmap.record_drone_event(&DroneEvent::PacketSent(fragment(vec![21, 1, 2, 31], 3)), 6.0);
+        heatmap.record_drone_event(&DroneEvent::PacketDropped(fragment(vec![21, 1, 2, 31], 2)), 7.0);
+
+        let load = heatmap.link_load(8.0);
+        assert_eq!(load.get(&(1, 2)), Some(&2));
+        assert_eq!(load.get(&(2, 31)), Some(&1));
+        let ratios = heatmap.drop_ratio(8.0);
+        assert_eq!(ratios[&1], 0.5);
+        assert_eq!(ratios[&2], 0.0);
+
+        // the first sample falls out of the window, it is still kept for the longer ones
+        assert_eq!(heatmap.link_load(12.0).get(&(1, 2)), Some(&1));
+        heatmap.window = 60.0;
+        assert_eq!(heatmap.link_load(12.0).get(&(1, 2)), Some(&2));
+
+        // past the longest window the samples are gone for good
+        heatmap.record_drone_event(&DroneEvent::PacketSent(fragment(vec![1, 2], 1)), 400.0);
+        assert_eq!(heatmap.samples.len(), 1);
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(heat_width(0.0), MIN_WIDTH);
+        assert_eq!(heat_width(2.0), MAX_WIDTH);
+        assert_eq!(heat_color(1.0), Color::srgb(1.0, 0.0, 0.0));
+        assert_eq!(drop_tint(0.0), Color::WHITE);
+    }
+}
@@ -0,0 +1,454 @@
+// Log of the recent drone and node events with what was inside every packet.
+// The inspector window lists it with filters, clicking a packet sprite opens its entry.
+
+use std::collections::BTreeMap;
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+use crate::utils::fragmentation_handling::{carries_media, message_kind};
+
+// Entries kept in the log, the oldest go first
+const LOG_CAPACITY: usize = 500;
+// Sessions whose fragments are kept for the preview
+const SESSION_CAPACITY: usize = 100;
+// Characters of text shown in a preview
+const PREVIEW_LEN: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Sent,
+    Dropped,
+    Shortcut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    MsgFragment,
+    Ack,
+    Nack,
+    FloodRequest,
+    FloodResponse,
+}
+
+impl PacketKind {
+    const ALL: [PacketKind; 5] = [
+        PacketKind::MsgFragment,
+        PacketKind::Ack,
+        PacketKind::Nack,
+        PacketKind::FloodRequest,
+        PacketKind::FloodResponse,
+    ];
+
+    fn of(packet_type: &PacketType) -> Self {
+        match packet_type {
+            PacketType::MsgFragment(_) => PacketKind::MsgFragment,
+            PacketType::Ack(_) => PacketKind::Ack,
+            PacketType::Nack(_) => PacketKind::Nack,
+            PacketType::FloodRequest(_) => PacketKind::FloodRequest,
+            PacketType::FloodResponse(_) => PacketKind::FloodResponse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub id: u64,
+    pub t: f64,
+    pub from_drone: bool, // DroneEvent or NodeEvent
+    pub event: EventKind,
+    pub kind: PacketKind,
+    pub detail: String, // nack type, flood id...
+    pub trace: Vec<NodeId>, // path trace of floods, they have no route
+    pub session_id: u64,
+    pub fragment: Option<(u64, u64)>, // index and total
+    pub hops: Vec<NodeId>,
+    pub hop_index: usize,
+}
+
+// What a client or server reported besides its packets
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityEntry {
+    pub id: u64,
+    pub t: f64,
+    pub node: NodeId,
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+impl LogEntry {
+    // Source of the message, the key its fragments are grouped by
+    fn source(&self) -> Option<NodeId> {
+        self.hops.first().copied()
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct PacketLog {
+    entries: VecDeque<LogEntry>,
+    activity: VecDeque<ActivityEntry>,
+    next_id: u64,
+    fragments: HashMap<(u64, NodeId), BTreeMap<u64, Fragment>>,
+    sessions: VecDeque<(u64, NodeId)>, // insertion order of `fragments`, for eviction
+}
+
+impl PacketLog {
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    pub fn activity(&self) -> impl DoubleEndedIterator<Item = &ActivityEntry> {
+        self.activity.iter()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&LogEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    // Most recent entry of a packet on this route, what an animated sprite shows
+    pub fn last_matching(&self, session_id: u64, hops: &[NodeId]) -> Option<u64> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.session_id == session_id && e.hops == hops)
+            .map(|e| e.id)
+    }
+
+    pub fn record_drone_event(&mut self, event: &DroneEvent, t: f64) -> u64 {
+        match event {
+            DroneEvent::PacketSent(packet) => self.record(packet, true, EventKind::Sent, t),
+            DroneEvent::PacketDropped(packet) => self.record(packet, true, EventKind::Dropped, t),
+            DroneEvent::ControllerShortcut(packet) => self.record(packet, true, EventKind::Shortcut, t),
+        }
+    }
+
+    pub fn record_node_event(&mut self, event: &NodeEvent, t: f64) -> u64 {
+        match event {
+            NodeEvent::PacketSent(packet) => self.record(packet, false, EventKind::Sent, t),
+            NodeEvent::ControllerShortcut(packet) => self.record(packet, false, EventKind::Shortcut, t),
+            _ => {
+                let id = self.next_id;
+                self.next_id += 1;
+                if let (Some(node), Some((kind, detail))) = (event.node(), event.describe()) {
+                    self.activity.push_back(ActivityEntry { id, t, node, kind, detail });
+                    if self.activity.len() > LOG_CAPACITY {
+                        self.activity.pop_front();
+                    }
+                }
+                id
+            }
+        }
+    }
+
+    fn record(&mut self, packet: &Packet, from_drone: bool, event: EventKind, t: f64) -> u64 {
+        let mut trace = Vec::new();
+        let (detail, fragment) = match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => {
+                self.keep_fragment(packet, fragment);
+                (String::new(), Some((fragment.fragment_index, fragment.total_n_fragments)))
+            }
+            PacketType::Ack(ack) => (String::new(), Some((ack.fragment_index, 0))),
+            PacketType::Nack(nack) => (format!("{:?}", nack.nack_type), Some((nack.fragment_index, 0))),
+            PacketType::FloodRequest(request) => {
+                trace = request.path_trace.iter().map(|(id, _)| *id).collect();
+                (format!("flood {} from {}", request.flood_id, request.initiator_id), None)
+            }
+            PacketType::FloodResponse(response) => {
+                trace = response.path_trace.iter().map(|(id, _)| *id).collect();
+                (format!("flood {}", response.flood_id), None)
+            }
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push_back(LogEntry {
+            id,
+            t,
+            from_drone,
+            event,
+            kind: PacketKind::of(&packet.pack_type),
+            detail,
+            trace,
+            session_id: packet.session_id,
+            fragment,
+            hops: packet.routing_header.hops.clone(),
+            hop_index: packet.routing_header.hop_index,
+        });
+        if self.entries.len() > LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        id
+    }
+
+    fn keep_fragment(&mut self, packet: &Packet, fragment: &Fragment) {
+        let Some(source) = packet.routing_header.hops.first() else {
+            return;
+        };
+        let key = (packet.session_id, *source);
+        if !self.fragments.contains_key(&key) {
+            self.sessions.push_back(key);
+            if self.sessions.len() > SESSION_CAPACITY {
+                if let Some(old) = self.sessions.pop_front() {
+                    self.fragments.remove(&old);
+                }
+            }
+        }
+        self.fragments
+            .entry(key)
+            .or_default()
+            .insert(fragment.fragment_index, fragment.clone());
+    }
+
+    // Message kind and, once every fragment went by, the start of its text
+    pub fn preview(&self, entry: &LogEntry) -> Option<String> {
+        let fragments = self.fragments.get(&(entry.session_id, entry.source()?))?;
+        let total = fragments.values().next()?.total_n_fragments;
+        // the recognition bit travels alone in fragment 1
+        let bit = fragments.get(&1).map(|f| f.data[0]);
+        let kind = bit.and_then(message_kind).unwrap_or("unknown message");
+        if (fragments.len() as u64) < total {
+            return Some(format!("{}, {}/{} fragments seen", kind, fragments.len(), total));
+        }
+        let data = fragments
+            .values()
+            .filter(|f| f.fragment_index != 1)
+            .flat_map(|f| f.data[..f.length as usize].iter().copied())
+            .collect::<Vec<_>>();
+        if bit.is_none_or(carries_media) {
+            return Some(format!("{}, {} bytes", kind, data.len()));
+        }
+        let text = data
+            .iter()
+            .map(|b| *b as char)
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .take(PREVIEW_LEN)
+            .collect::<String>();
+        Some(format!("{}, {} bytes: \"{}\"", kind, data.len(), text))
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct InspectorState {
+    open: bool,
+    node: String,
+    session: String,
+    kind: Option<PacketKind>,
+    selected: Option<u64>,
+}
+
+impl InspectorState {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        if let Ok(node) = self.node.trim().parse::<NodeId>() {
+            if !entry.hops.contains(&node) && !entry.trace.contains(&node) {
+                return false;
+            }
+        }
+        if let Ok(session) = self.session.trim().parse::<u64>() {
+            if entry.session_id != session {
+                return false;
+            }
+        }
+        self.kind.is_none_or(|kind| entry.kind == kind)
+    }
+}
+
+pub fn inspect_packet(
+    trigger: Trigger<Pointer<Click>>,
+    packet_query: Query<&PacketInfo>,
+    mut inspector: ResMut<InspectorState>,
+    state: Res<MainState>,
+) {
+    if let MainState::Sim = *state {
+        if let Ok(info) = packet_query.get(trigger.target()) {
+            inspector.open = true;
+            inspector.selected = info.log_id;
+        }
+    }
+}
+
+pub fn inspector_window(
+    mut egui_ctx: EguiContexts,
+    mut inspector: ResMut<InspectorState>,
+    log: Res<PacketLog>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let ctx = egui_ctx.ctx_mut();
+    let mut open = inspector.open;
+    if !open {
+        egui::Area::new(egui::Id::new("inspector_toggle"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+            .show(ctx, |ui| {
+                if ui.button("Packet inspector").clicked() {
+                    inspector.open = true;
+                }
+            });
+        return;
+    }
+    egui::Window::new("Packet inspector")
+        .open(&mut open)
+        .default_size(egui::vec2(520.0, 420.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Node");
+                ui.add(egui::TextEdit::singleline(&mut inspector.node).desired_width(40.0));
+                ui.label("Session");
+                ui.add(egui::TextEdit::singleline(&mut inspector.session).desired_width(120.0));
+                egui::ComboBox::from_id_salt("inspector_kind")
+                    .selected_text(inspector.kind.map_or("All".to_string(), |k| format!("{:?}", k)))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut inspector.kind, None, "All");
+                        for kind in PacketKind::ALL {
+                            ui.selectable_value(&mut inspector.kind, Some(kind), format!("{:?}", kind));
+                        }
+                    });
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(220.0)
+                .show(ui, |ui| {
+                    let shown = log.entries().rev().filter(|e| inspector.matches(e)).collect::<Vec<_>>();
+                    for entry in shown {
+                        let fragment = match (entry.kind, entry.fragment) {
+                            (PacketKind::MsgFragment, Some((index, total))) => format!("{}/{}", index, total),
+                            (_, Some((index, _))) => format!("{}", index),
+                            _ => String::new(),
+                        };
+                        let text = format!(
+                            "{:>7.2}s  {:?} {:?}  session {}  {}  {:?}",
+                            entry.t, entry.event, entry.kind, entry.session_id, fragment, entry.hops
+                        );
+                        if ui
+                            .selectable_label(inspector.selected == Some(entry.id), text)
+                            .clicked()
+                        {
+                            inspector.selected = Some(entry.id);
+                        }
+                    }
+                });
+            ui.separator();
+
+            match inspector.selected.and_then(|id| log.get(id)) {
+                Some(entry) => entry_details(ui, &log, entry),
+                None => {
+                    ui.label("Select an event, or click a packet in the simulation");
+                }
+            }
+            ui.separator();
+
+            egui::CollapsingHeader::new("Client and server events").show(ui, |ui| {
+                let node = inspector.node.trim().parse::<NodeId>().ok();
+                egui::ScrollArea::vertical()
+                    .id_salt("inspector_activity")
+                    .max_height(120.0)
+                    .show(ui, |ui| {
+                        for entry in log.activity().rev().filter(|e| node.is_none_or(|n| e.node == n)) {
+                            ui.label(format!("{:>7.2}s  {}  {}  {}", entry.t, entry.node, entry.kind, entry.detail));
+                        }
+                    });
+            });
+        });
+    inspector.open = open;
+}
+
+fn entry_details(ui: &mut egui::Ui, log: &PacketLog, entry: &LogEntry) {
+    egui::Grid::new("inspector_entry").show(ui, |ui| {
+        ui.label("Event");
+        ui.label(format!(
+            "{:?} by a {} at {:.2}s",
+            entry.event,
+            if entry.from_drone { "drone" } else { "client or server" },
+            entry.t
+        ));
+        ui.end_row();
+        ui.label("Type");
+        ui.label(if entry.detail.is_empty() || entry.kind != PacketKind::Nack {
+            format!("{:?}", entry.kind)
+        } else {
+            format!("Nack ({})", entry.detail)
+        });
+        ui.end_row();
+        ui.label("Session");
+        ui.label(entry.session_id.to_string());
+        ui.end_row();
+        if let Some((index, total)) = entry.fragment {
+            ui.label("Fragment");
+            ui.label(if entry.kind == PacketKind::MsgFragment {
+                format!("{} of {}", index, total)
+            } else {
+                index.to_string()
+            });
+            ui.end_row();
+        }
+        ui.label("Route");
+        ui.label(
+            entry
+                .hops
+                .iter()
+                .enumerate()
+                .map(|(i, hop)| if i == entry.hop_index { format!("[{}]", hop) } else { hop.to_string() })
+                .collect::<Vec<_>>()
+                .join(" -> "),
+        );
+        ui.end_row();
+        if matches!(entry.kind, PacketKind::FloodRequest | PacketKind::FloodResponse) {
+            ui.label("Flood");
+            ui.label(format!("{}, trace {:?}", entry.detail, entry.trace));
+            ui.end_row();
+        }
+        if let Some(preview) = log.preview(entry) {
+            ui.label("Message");
+            ui.label(preview);
+            ui.end_row();
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::fragmentation_handling::{deconstruct_message, serialize, Message};
+
+    #[test]
+    fn test_log_and_preview() {
+        let mut log = PacketLog::default();
+        let fragments = serialize(deconstruct_message(Message::String("hello drones".to_string())).unwrap());
+        let total = fragments.len();
+        let mut last = 0;
+        for fragment in fragments {
+            let packet = Packet::new_fragment(
+                SourceRoutingHeader { hop_index: 1, hops: vec![21, 1, 31] },
+                7,
+                fragment,
+            );
+            last = log.record_node_event(&NodeEvent::PacketSent(packet), 0.1);
+        }
+        log.record_drone_event(
+            &DroneEvent::PacketSent(Packet::new_ack(SourceRoutingHeader { hop_index: 1, hops: vec![31, 1, 21] }, 7, 1)),
+            0.2,
+        );
+
+        assert_eq!(log.entries().count(), total + 1);
+        assert_eq!(log.last_matching(7, &[21, 1, 31]), Some(last));
+        let entry = log.get(last).unwrap();
+        assert_eq!(entry.kind, PacketKind::MsgFragment);
+        assert_eq!(entry.fragment, Some((total as u64, total as u64)));
+        assert_eq!(log.preview(entry).unwrap(), "String, 12 bytes: \"hello drones\"");
+
+        let inspector = InspectorState {
+            kind: Some(PacketKind::Ack),
+            node: "21".to_string(),
+            ..default()
+        };
+        assert_eq!(log.entries().filter(|e| inspector.matches(e)).count(), 1);
+
+        let report = NodeEvent::Rerouted { node: 21, session_id: 7, old_path: vec![21, 1, 31], new_path: vec![21, 2, 31] };
+        assert!(log.record_node_event(&report, 0.3) > last);
+        assert_eq!(log.entries().count(), total + 1);
+        let activity = log.activity().next().unwrap();
+        assert_eq!((activity.node, activity.kind), (21, "rerouted"));
+    }
+}
@@ -0,0 +1,214 @@
+// Where the controller draws the nodes: the classic circle and columns, or a force-directed
+// layout that starts from the current positions so adding a node doesn't reshuffle the rest.
+// Dragged nodes and nodes with x/y in the config stay where they were put.
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+
+const FORCE_ITERATIONS: usize = 300;
+// half of the area the force layout may use, the bar at the top is left free
+const HALF_EXTENT: Vec2 = Vec2::new(WINDOW_WIDTH * 0.45, WINDOW_HEIGHT * 0.45);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LayoutMode {
+    #[default]
+    Classic,
+    Force,
+}
+
+#[derive(Resource, Default)]
+pub struct NodeLayout {
+    pub mode: LayoutMode,
+    pub pinned: HashMap<NodeId, Vec2>,    // from the config or dragged, the layout never moves them
+    pub positions: HashMap<NodeId, Vec2>, // where every node was last drawn, saved with the topology
+}
+
+impl NodeLayout {
+    pub fn with_pinned(pinned: HashMap<NodeId, (f32, f32)>) -> Self {
+        Self {
+            pinned: pinned.into_iter().map(|(id, (x, y))| (id, Vec2::new(x, y))).collect(),
+            ..default()
+        }
+    }
+}
+
+// Fruchterman-Reingold on the node graph. Nodes in `start` begin where they are and the
+// temperature stays low, so an existing layout only settles around the new nodes.
+pub fn force_layout(
+    graph: &[(NodeId, Vec<NodeId>)],
+    start: &HashMap<NodeId, Vec2>,
+    pinned: &HashMap<NodeId, Vec2>,
+) -> HashMap<NodeId, Vec2> {
+    let mut ids = graph.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    ids.sort();
+    let n = ids.len();
+    if n == 0 {
+        return HashMap::new();
+    }
+    let index = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect::<HashMap<_, _>>();
+    let mut edges = Vec::new();
+    for (id, neighbours) in graph {
+        for neighbour in neighbours {
+            if let (Some(&a), Some(&b)) = (index.get(id), index.get(neighbour)) {
+                if a < b {
+                    edges.push((a, b));
+                }
+            }
+        }
+    }
+
+    let known = |id: &NodeId| pinned.get(id).or_else(|| start.get(id)).copied();
+    let mut positions = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            known(id).unwrap_or_else(|| {
+                // next to a neighbour that is already placed, else on a circle
+                let angle = *id as f32 * 2.4;
+                let anchor = graph
+                    .iter()
+                    .find(|(node, _)| node == id)
+                    .and_then(|(_, neighbours)| neighbours.iter().find_map(known));
+                match anchor {
+                    Some(anchor) => anchor + Vec2::from_angle(angle) * 80.0,
+                    None => Vec2::from_angle(2.0 * PI * i as f32 / n as f32) * HALF_EXTENT.y * 0.8
+                        + Vec2::from_angle(angle),
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let fixed = ids.iter().map(|id| pinned.contains_key(id)).collect::<Vec<_>>();
+    let new = ids.iter().filter(|id| known(id).is_none()).count();
+
+    let k = 0.75 * (4.0 * HALF_EXTENT.x * HALF_EXTENT.y / n as f32).sqrt();
+    let t0 = if new == n { HALF_EXTENT.x / 5.0 } else { k / 2.0 };
+    for iteration in 0..FORCE_ITERATIONS {
+        let t = t0 * (1.0 - iteration as f32 / FORCE_ITERATIONS as f32);
+        let mut shift = vec![Vec2::ZERO; n];
+        for a in 0..n {
+            for b in a + 1..n {
+                let delta = positions[a] - positions[b];
+                let d = delta.length().max(1.0);
+                let push = delta / d * (k * k / d);
+                shift[a] += push;
+                shift[b] -= push;
+            }
+        }
+        for (a, b) in &edges {
+            let delta = positions[*a] - positions[*b];
+            let d = delta.length().max(1.0);
+            let pull = delta / d * (d * d / k);
+            shift[*a] -= pull;
+            shift[*b] += pull;
+        }
+        for i in (0..n).filter(|i| !fixed[*i]) {
+            let step = shift[i].normalize_or_zero() * shift[i].length().min(t);
+            positions[i] = (positions[i] + step).clamp(-HALF_EXTENT, HALF_EXTENT);
+        }
+    }
+    ids.into_iter().zip(positions).collect()
+}
+
+pub fn drag_node(
+    trigger: Trigger<Pointer<Drag>>,
+    mut node_query: Query<(&ScNode, &mut Transform)>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut layout: ResMut<NodeLayout>,
+    mut moved_writer: EventWriter<NodeMovedEvent>,
+    mut lines_writer: EventWriter<MakeLinesEvent>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let Ok((node, mut transform)) = node_query.get_mut(trigger.target()) else {
+        return;
+    };
+    let Ok((camera, camera_transform)) = camera_query.single() else {
+        return;
+    };
+    let Ok(position) = camera.viewport_to_world_2d(camera_transform, trigger.pointer_location.position) else {
+        return;
+    };
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+    layout.pinned.insert(node.id, position);
+    layout.positions.insert(node.id, position);
+    moved_writer.write(NodeMovedEvent {
+        node_id: node.id,
+        new_position: Some(transform.translation),
+    });
+    lines_writer.write(MakeLinesEvent);
+}
+
+pub fn layout_window(
+    mut egui_ctx: EguiContexts,
+    mut layout: ResMut<NodeLayout>,
+    mut nodes_writer: EventWriter<UpdateNodesEvent>,
+    mut lines_writer: EventWriter<MakeLinesEvent>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let ctx = egui_ctx.ctx_mut();
+    egui::Area::new(egui::Id::new("layout_area"))
+        .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let mut mode = layout.mode;
+                    ui.label("Layout");
+                    ui.selectable_value(&mut mode, LayoutMode::Classic, "Classic");
+                    ui.selectable_value(&mut mode, LayoutMode::Force, "Force");
+                    let unpin = ui
+                        .add_enabled(!layout.pinned.is_empty(), egui::Button::new("Unpin all"))
+                        .clicked();
+                    if mode != layout.mode || unpin {
+                        layout.mode = mode;
+                        if unpin {
+                            layout.pinned.clear();
+                        }
+                        // the force layout starts over instead of settling the classic one
+                        layout.positions.clear();
+                        nodes_writer.write(UpdateNodesEvent);
+                        lines_writer.write(MakeLinesEvent);
+                    }
+                });
+            });
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_force_layout() {
+        let graph = vec![
+            (1, vec![2, 3, 21]),
+            (2, vec![1, 3, 31]),
+            (3, vec![1, 2]),
+            (21, vec![1]),
+            (31, vec![2]),
+        ];
+        let pinned = HashMap::from([(21, Vec2::new(-500.0, 0.0))]);
+        let positions = force_layout(&graph, &HashMap::new(), &pinned);
+        assert_eq!(positions.len(), 5);
+        assert_eq!(positions[&21], Vec2::new(-500.0, 0.0));
+        assert!(positions.values().all(|p| p.abs().cmple(HALF_EXTENT).all()));
+        assert!(positions[&1].distance(positions[&2]) > 1.0);
+        // same input, same layout
+        assert_eq!(positions, force_layout(&graph, &HashMap::new(), &pinned));
+
+        // a new node gets a place too, the pinned one still doesn't move
+        let mut grown = graph.clone();
+        grown.push((4, vec![3]));
+        grown[2].1.push(4);
+        let settled = force_layout(&grown, &positions, &pinned);
+        assert_eq!(settled.len(), 6);
+        assert_eq!(settled[&21], Vec2::new(-500.0, 0.0));
+    }
+}
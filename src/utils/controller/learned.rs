@@ -0,0 +1,267 @@
+// Shows the topology a client or server learned from its floods over the real one, so
+// adjacencies it never saw and links or nodes it still believes in after a crash stand out.
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+
+const REFRESH_SECS: f32 = 1.0;
+const LEARNED_COLOR: Color = Color::srgb(0.2, 0.8, 0.3);
+const MISSING_COLOR: Color = Color::srgb(0.9, 0.1, 0.1);
+const PHANTOM_COLOR: Color = Color::srgb(1.0, 0.6, 0.0);
+
+// Learned edges against the real ones, all with the lower id first
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TopologyDiff {
+    pub matching: Vec<(NodeId, NodeId)>,
+    pub missing: Vec<(NodeId, NodeId)>, // real links the node doesn't know
+    pub phantom: Vec<(NodeId, NodeId)>, // links the node knows that aren't there
+    pub stale: Vec<NodeId>,             // nodes the node knows that are gone or crashed
+}
+
+pub fn compare(snapshot: &TopologySnapshot, nodes: &HashMap<NodeId, NodeInfo>) -> TopologyDiff {
+    let mut real = nodes
+        .iter()
+        .flat_map(|(id, info)| {
+            info.connected_node_ids
+                .iter()
+                .filter(|other| nodes.contains_key(other))
+                .map(move |other| (*id.min(other), *id.max(other)))
+        })
+        .collect::<Vec<_>>();
+    real.sort();
+    real.dedup();
+    let mut diff = TopologyDiff::default();
+    for edge in &snapshot.edges {
+        if real.contains(edge) {
+            diff.matching.push(*edge);
+        } else {
+            diff.phantom.push(*edge);
+        }
+    }
+    diff.missing = real.into_iter().filter(|edge| !snapshot.edges.contains(edge)).collect();
+    diff.stale = snapshot
+        .nodes
+        .iter()
+        .map(|(id, _)| *id)
+        .filter(|id| nodes.get(id).is_none_or(|info| info.crashed))
+        .collect();
+    diff
+}
+
+#[derive(Resource, Default)]
+pub struct LearnedView {
+    open: bool,
+    node: String,
+    auto: bool, // ask again every second
+    pending: Option<(NodeId, Receiver<TopologySnapshot>)>,
+    shown: Option<(NodeId, TopologySnapshot)>,
+    message: String,
+    since_request: f32,
+}
+
+impl LearnedView {
+    fn request(&mut self, simulation_controller: &SimulationController, id: NodeId) {
+        self.since_request = 0.0;
+        match request_topology(simulation_controller, id) {
+            Ok(reply) => {
+                self.pending = Some((id, reply));
+                self.message.clear();
+            }
+            Err(e) => self.message = e,
+        }
+    }
+}
+
+pub fn learned_window(
+    mut egui_ctx: EguiContexts,
+    mut view: ResMut<LearnedView>,
+    simulation_controller: Res<SimulationController>,
+    time: Res<Time>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let answer = view.pending.as_ref().map(|(id, reply)| (*id, reply.try_recv()));
+    match answer {
+        Some((id, Ok(snapshot))) => {
+            view.shown = Some((id, snapshot));
+            view.pending = None;
+        }
+        Some((id, Err(TryRecvError::Disconnected))) => {
+            view.message = format!("{} didn't answer", id);
+            view.pending = None;
+        }
+        _ => {}
+    }
+    view.since_request += time.delta_secs();
+    let shown = view.shown.as_ref().map(|(id, _)| *id);
+    if let (true, true, Some(id)) = (view.auto, view.pending.is_none(), shown) {
+        if view.since_request >= REFRESH_SECS {
+            view.request(&simulation_controller, id);
+        }
+    }
+
+    let ctx = egui_ctx.ctx_mut();
+    if !view.open {
+        egui::Area::new(egui::Id::new("learned_toggle"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 40.0))
+            .show(ctx, |ui| {
+                if ui.button("Learned topology").clicked() {
+                    view.open = true;
+                }
+            });
+        return;
+    }
+    let mut open = view.open;
+    egui::Window::new("Learned topology")
+        .open(&mut open)
+        .default_size(egui::vec2(360.0, 320.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Client or server");
+                ui.add(egui::TextEdit::singleline(&mut view.node).desired_width(40.0));
+                if ui.button("Show").clicked() {
+                    match view.node.trim().parse::<NodeId>() {
+                        Ok(id) => view.request(&simulation_controller, id),
+                        Err(_) => view.message = "Not a node id".to_string(),
+                    }
+                }
+                ui.checkbox(&mut view.auto, "Follow");
+            });
+            // .dot and .json files for graphviz or a diff in a bug report
+            ui.horizontal(|ui| {
+                ui.label("Export");
+                let mut exported = Vec::new();
+                if ui.button("Network").clicked() {
+                    exported.push(export_graph(&real_snapshot(simulation_controller.nodes()), "network"));
+                }
+                if let Some((id, snapshot)) = &view.shown {
+                    if ui.button(format!("Learned by {}", id)).clicked() {
+                        exported.push(export_graph(snapshot, &format!("learned_{}", id)));
+                    }
+                }
+                for result in exported {
+                    view.message = match result {
+                        Ok((dot, _)) => format!("Exported to {}", dot.with_extension("{dot,json}").to_string_lossy()),
+                        Err(e) => format!("Export failed: {}", e),
+                    };
+                }
+            });
+            if !view.message.is_empty() {
+                ui.colored_label(egui::Color32::LIGHT_RED, &view.message);
+            }
+            let Some((id, snapshot)) = &view.shown else {
+                ui.label("The links it learned are drawn green, the ones it misses red and the ones that aren't there orange");
+                return;
+            };
+            let diff = compare(snapshot, simulation_controller.nodes());
+            ui.separator();
+            ui.label(format!(
+                "Node {} knows {} nodes and {} links: {} right, {} missing, {} phantom",
+                id,
+                snapshot.nodes.len(),
+                snapshot.edges.len(),
+                diff.matching.len(),
+                diff.missing.len(),
+                diff.phantom.len()
+            ));
+            if !diff.stale.is_empty() {
+                ui.label(format!("Still knows about {:?}, gone or crashed", diff.stale));
+            }
+            if let Some((path, weight)) = &snapshot.current_path {
+                ui.label(format!("Current path {:?}, weight {}", path, weight));
+            }
+            ui.separator();
+            ui.label("Known paths (weight)");
+            egui::ScrollArea::vertical().max_height(160.0).show(ui, |ui| {
+                let mut paths = snapshot.paths.clone();
+                paths.sort_by_key(|(path, weight)| (*path.last().unwrap_or(&0), *weight));
+                for (path, weight) in paths {
+                    ui.label(format!("{:?}  ({})", path, weight));
+                }
+            });
+        });
+    view.open = open;
+}
+
+// Drawn over the links every frame while the window is open
+pub fn learned_overlay(
+    mut gizmos: Gizmos,
+    view: Res<LearnedView>,
+    node_query: Query<(&ScNode, &Transform)>,
+    simulation_controller: Res<SimulationController>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let (true, Some((id, snapshot))) = (view.open, &view.shown) else {
+        return;
+    };
+    let positions = node_query
+        .iter()
+        .map(|(node, transform)| (node.id, transform.translation.truncate()))
+        .collect::<HashMap<_, _>>();
+    let diff = compare(snapshot, simulation_controller.nodes());
+    for (edges, color) in [
+        (&diff.matching, LEARNED_COLOR),
+        (&diff.missing, MISSING_COLOR),
+        (&diff.phantom, PHANTOM_COLOR),
+    ] {
+        for (a, b) in edges {
+            if let (Some(a), Some(b)) = (positions.get(a), positions.get(b)) {
+                gizmos.line_2d(*a, *b, color);
+            }
+        }
+    }
+    if let Some(position) = positions.get(id) {
+        gizmos.circle_2d(*position, 40.0, LEARNED_COLOR);
+    }
+    for stale in &diff.stale {
+        if let Some(position) = positions.get(stale) {
+            gizmos.circle_2d(*position, 40.0, PHANTOM_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::topology::Topology;
+    use wg_2024::packet::NodeType as PacketNodeType;
+
+    fn info(node_type: components::NodeType, connected_node_ids: Vec<NodeId>) -> NodeInfo {
+        NodeInfo { node_type, connected_node_ids, pdr: 0.0, crashed: false }
+    }
+
+    #[test]
+    fn test_compare() {
+        let nodes = HashMap::from([
+            (1, info(components::NodeType::Drone, vec![2, 21])),
+            (2, info(components::NodeType::Drone, vec![1, 31])),
+            (21, info(components::NodeType::Client, vec![1])),
+            (31, info(components::NodeType::Server, vec![2])),
+        ]);
+        // learned before 3 crashed, never saw 2-31
+        let mut topology = Topology::new();
+        topology.update_topology(
+            (21, PacketNodeType::Client),
+            vec![(1, PacketNodeType::Drone), (2, PacketNodeType::Drone)],
+        );
+        topology.update_topology(
+            (21, PacketNodeType::Client),
+            vec![(1, PacketNodeType::Drone), (3, PacketNodeType::Drone)],
+        );
+        let snapshot = topology.snapshot();
+        assert_eq!(snapshot.edges, vec![(1, 2), (1, 3), (1, 21)]);
+
+        let diff = compare(&snapshot, &nodes);
+        assert_eq!(diff.matching, vec![(1, 2), (1, 21)]);
+        assert_eq!(diff.missing, vec![(2, 31)]);
+        assert_eq!(diff.phantom, vec![(1, 3)]);
+        assert_eq!(diff.stale, vec![3]);
+    }
+}
@@ -1,6 +1,16 @@
 use super::super::controller::*;
+use crate::frontend::{ChatCommand, ChatEvent, WebCommand, WebEvent};
+use crate::utils::drone_registry::{self, DroneArgs};
+use crate::utils::initializer;
+use crate::utils::links::LinkParams;
+use crate::utils::mock_drone::MockPolicy;
+use crate::utils::sim_config::server_kind_name;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
 
-pub fn spawn(simulation_controller: &mut SimulationController, val: usize, id: u8) {
+// `policy` is only used by a mock drone
+pub fn spawn(simulation_controller: &mut SimulationController, val: usize, id: u8, policy: MockPolicy) {
     let (drone_command_sender, drone_command_receiver) = unbounded::<DroneCommand>();
     simulation_controller
         .sender_drone_command
@@ -15,134 +25,35 @@ pub fn spawn(simulation_controller: &mut SimulationController, val: usize, id: u
     let packet_senders: HashMap<NodeId, Sender<Packet>> = HashMap::new();
 
     let pdr = 0.0;
+    simulation_controller.record_action(format!(
+        "spawn {} drone {}",
+        drone_registry::name_of(val).unwrap_or("unknown"),
+        id
+    ));
+    simulation_controller.drone_impls.insert(id, val);
+    simulation_controller.nodes.insert(id, NodeInfo {
+        node_type: components::NodeType::Drone,
+        connected_node_ids: Vec::new(),
+        pdr,
+        crashed: false,
+    });
+    let seed = initializer::node_seed(simulation_controller.base_seed, id);
     let mut handles = Vec::new();
     handles.push(thread::spawn(move || {
         //TODO non credo serva  joinare thread, ma non sono sicuro
-        match val {
-            0 => {
-                info!("BagelBomber Id[{}]", id);
-                let mut drone = bagel_bomber::BagelBomber::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            1 => {
-                info!("BetteCallDrone Id[{}]", id);
-                let mut drone = drone_bettercalldrone::BetterCallDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            2 => {
-                info!("RustRoveri Id[{}]", id);
-                let mut drone = rust_roveri::drone::RustRoveri::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            3 => {
-                info!("GetDroned Id[{}]", id);
-                let mut drone = getdroned::GetDroned::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            4 => {
-                info!("C++Enjoyers Id[{}]", id);
-                let mut drone = ap2024_unitn_cppenjoyers_drone::CppEnjoyersDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            5 => {
-                info!("D.R.O.N.E Id[{}]", id);
-                let mut drone = d_r_o_n_e_drone::MyDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            6 => {
-                info!("NNP Id[{}]", id);
-                let mut drone = null_pointer_drone::MyDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            7 => {
-                info!("Rustafarian Id[{}]", id);
-                let mut drone = rustafarian_drone::RustafarianDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            8 => {
-                info!("DrOnes[{}]", id);
-                let mut drone = dr_ones::Drone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            9 => {
-                info!("Rusteze Id[{}]", id);
-                let mut drone = rusteze_drone::RustezeDrone::new(
-                    id,
-                    sender_clone,
-                    drone_command_receiver,
-                    drone_packet_receiver,
-                    packet_senders,
-                    pdr,
-                );
-                drone.run();
-            }
-            _ => {
-                warn!("Error modulo");
-            }
-        }
+        drone_registry::run(
+            val,
+            DroneArgs {
+                id,
+                controller_send: sender_clone,
+                controller_recv: drone_command_receiver,
+                packet_recv: drone_packet_receiver,
+                packet_send: packet_senders,
+                pdr,
+                policy,
+                seed,
+            },
+        );
     }));
 }
 
@@ -155,10 +66,15 @@ pub fn crash(simulation_controller: &mut SimulationController, id: u8, neighbour
     {
         Ok(_) => {
             info!("Sent crash to {}", id);
+            simulation_controller.record_action(format!("crash {}", id));
             for neighbour in neighbours {
                 remove_sender(simulation_controller, neighbour, id);
+                if let Some(links) = simulation_controller.links.as_mut() {
+                    links.disconnect(id, neighbour);
+                }
             }
             simulation_controller.sender_node_packet.remove(&id);
+            simulation_controller.nodes.remove(&id);
             //simulation_controller.sender_drone_command.remove(&id); //NNP panics if this is on
         }
         Err(_) => {
@@ -188,13 +104,21 @@ pub fn remove_sender(simulation_controller: &mut SimulationController, id: u8, r
             }
         }
     }
+    if let Some(links) = simulation_controller.links.as_mut() {
+        links.disconnect(id, removed_id);
+    }
+    simulation_controller.unlink(id, removed_id);
 }
 
 pub fn add_sender(simulation_controller: &mut SimulationController, id: u8, receiver_id: u8) {
-    let sender = simulation_controller
-        .sender_node_packet
-        .get(&receiver_id)
-        .unwrap();
+    let receiver = simulation_controller.sender_node_packet[&receiver_id].clone();
+    let sender = match simulation_controller.links.as_mut() {
+        Some(links) => {
+            let back = simulation_controller.sender_node_packet[&id].clone();
+            links.connect(id, receiver_id, receiver, back)
+        }
+        None => receiver,
+    };
 
     if let Some(drone) = simulation_controller.sender_drone_command.get(&id) {
         match drone.send(DroneCommand::AddSender(receiver_id, sender.clone())) {
@@ -216,6 +140,7 @@ pub fn add_sender(simulation_controller: &mut SimulationController, id: u8, rece
             }
         }
     }
+    simulation_controller.link(id, receiver_id);
 }
 pub fn set_pdr(simulation_controller: &mut SimulationController, id: u8, pdr: f32) {
     match simulation_controller
@@ -226,6 +151,10 @@ pub fn set_pdr(simulation_controller: &mut SimulationController, id: u8, pdr: f3
     {
         Ok(_) => {
             info!("Sent set_pdr to {}", id);
+            simulation_controller.record_action(format!("set pdr of {} to {}", id, pdr));
+            if let Some(node) = simulation_controller.nodes.get_mut(&id) {
+                node.pdr = pdr;
+            }
         }
         Err(_) => {
             warn!("set_pdr to {} error", id);
@@ -233,10 +162,467 @@ pub fn set_pdr(simulation_controller: &mut SimulationController, id: u8, pdr: f3
     }
 }
 
+// Changes one direction of a link, or both
+pub fn set_link(
+    simulation_controller: &mut SimulationController,
+    directions: &[(u8, u8)],
+    params: LinkParams,
+) -> Result<(), String> {
+    for (from, to) in directions {
+        if !simulation_controller.nodes.get(from).is_some_and(|n| n.connected_node_ids.contains(to)) {
+            return Err(format!("{} and {} aren't connected", from, to));
+        }
+    }
+    let Some(links) = simulation_controller.links.as_mut() else {
+        return Err("Links can't be changed in this run".to_string());
+    };
+    for (from, to) in directions {
+        links.set(*from, *to, params)?;
+    }
+    for (from, to) in directions {
+        simulation_controller.record_action(format!("set link {} -> {} to {:?}", from, to, params));
+    }
+    Ok(())
+}
+
+// The loss rate of one direction of a link, set from the Pdr mode; the rest of it stays
+pub fn set_link_loss(simulation_controller: &mut SimulationController, from: u8, to: u8, loss: f32) -> Result<(), String> {
+    let current = simulation_controller
+        .links()
+        .map_or(LinkParams::default(), |links| links.params(from, to));
+    set_link(simulation_controller, &[(from, to)], LinkParams { loss, ..current })
+}
+
+pub fn set_all_links(simulation_controller: &mut SimulationController, params: LinkParams) -> Result<(), String> {
+    let Some(links) = simulation_controller.links.as_mut() else {
+        return Err("Links can't be changed in this run".to_string());
+    };
+    links.set_all(params)?;
+    simulation_controller.record_action(format!("set every link to {:?}", params));
+    Ok(())
+}
+
+// Holds every packet on the links, the nodes keep running and their timers too
+pub fn pause_network(simulation_controller: &mut SimulationController) -> Result<(), String> {
+    let Some(links) = simulation_controller.links() else {
+        return Err("The network can't be paused in this run".to_string());
+    };
+    links.gate().pause();
+    simulation_controller.record_action("pause".to_string());
+    Ok(())
+}
+
+pub fn resume_network(simulation_controller: &mut SimulationController) -> Result<(), String> {
+    let Some(links) = simulation_controller.links() else {
+        return Err("The network can't be paused in this run".to_string());
+    };
+    links.gate().resume();
+    simulation_controller.record_action("resume".to_string());
+    Ok(())
+}
+
+// A single packet crosses a single link, the network stays paused after it
+pub fn step_network(simulation_controller: &mut SimulationController) -> Result<(), String> {
+    let Some(links) = simulation_controller.links() else {
+        return Err("The network can't be paused in this run".to_string());
+    };
+    links.gate().step();
+    simulation_controller.record_action("step".to_string());
+    Ok(())
+}
+
+// Same rules the Crash button enforces: clients keep a drone, servers keep two, no partitions
+pub fn check_crash(simulation_controller: &SimulationController, id: u8) -> Result<(), String> {
+    let nodes = &simulation_controller.nodes;
+    let Some(node) = nodes.get(&id) else {
+        return Err(format!("No node with id {}", id));
+    };
+    if node.node_type != components::NodeType::Drone {
+        // clients and servers keep their links while crashed, nothing else to check
+        if node.crashed {
+            return Err(format!("Node {} already crashed", id));
+        }
+        return Ok(());
+    }
+    for connected_id in &node.connected_node_ids {
+        if let Some(connected) = nodes.get(connected_id) {
+            match connected.node_type {
+                components::NodeType::Client if connected.connected_node_ids.len() <= 1 => {
+                    return Err("A Client must always be connected to at least 1 drone".to_string());
+                }
+                components::NodeType::Server if connected.connected_node_ids.len() <= 2 => {
+                    return Err("A Server must always be connected to at least 2 drones".to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !stays_connected(nodes, Some(id), None) {
+        return Err("No network partitoning allowed".to_string());
+    }
+    Ok(())
+}
+
+// Same degree and partition rules as a crash, for a single link
+pub fn check_disconnect(simulation_controller: &SimulationController, a: u8, b: u8) -> Result<(), String> {
+    let nodes = &simulation_controller.nodes;
+    let (Some(first), Some(second)) = (nodes.get(&a), nodes.get(&b)) else {
+        return Err(format!("Can't disconnect {} and {}, unknown node", a, b));
+    };
+    if !first.connected_node_ids.contains(&b) {
+        return Err("Nodes aren't connected".to_string());
+    }
+    for node in [first, second] {
+        match node.node_type {
+            components::NodeType::Client if node.connected_node_ids.len() <= 1 => {
+                return Err("A Client must always be connected to at least 1 drone".to_string());
+            }
+            components::NodeType::Server if node.connected_node_ids.len() <= 2 => {
+                return Err("A Server must always be connected to at least 2 drones".to_string());
+            }
+            _ => {}
+        }
+    }
+    if !stays_connected(nodes, None, Some((a, b))) {
+        return Err("No network partitoning allowed".to_string());
+    }
+    Ok(())
+}
+
+// Whether every node can still reach every other one without `node` and without `link`
+fn stays_connected(nodes: &HashMap<u8, NodeInfo>, node: Option<u8>, link: Option<(u8, u8)>) -> bool {
+    let removed_link = |from: u8, to: u8| link == Some((from, to)) || link == Some((to, from));
+    let Some(start) = nodes.keys().find(|&&n| Some(n) != node) else {
+        return true;
+    };
+    let mut visited = HashSet::new();
+    let mut stack = VecDeque::from([*start]);
+    while let Some(current) = stack.pop_front() {
+        if Some(current) != node && visited.insert(current) {
+            if let Some(info) = nodes.get(&current) {
+                stack.extend(
+                    info.connected_node_ids
+                        .iter()
+                        .copied()
+                        .filter(|&next| !removed_link(current, next)),
+                );
+            }
+        }
+    }
+    visited.len() == nodes.len() - usize::from(node.is_some_and(|n| nodes.contains_key(&n)))
+}
+
+// Same rules the Connect button enforces
+pub fn check_connect(simulation_controller: &SimulationController, a: u8, b: u8) -> Result<(), String> {
+    let (Some(first), Some(second)) = (simulation_controller.nodes.get(&a), simulation_controller.nodes.get(&b)) else {
+        return Err(format!("Can't connect {} and {}, unknown node", a, b));
+    };
+    if a == b {
+        return Err("Invalid connection".to_string());
+    }
+    if first.connected_node_ids.contains(&b) || second.connected_node_ids.contains(&a) {
+        return Err("Nodes already connected".to_string());
+    }
+    if first.node_type != components::NodeType::Drone && second.node_type != components::NodeType::Drone {
+        return Err("Invalid connection".to_string());
+    }
+    if (first.node_type == components::NodeType::Client && first.connected_node_ids.len() >= 2)
+        || (second.node_type == components::NodeType::Client && second.connected_node_ids.len() >= 2)
+    {
+        return Err("Client can have at most 2 drones connected".to_string());
+    }
+    Ok(())
+}
+
+pub fn connect(simulation_controller: &mut SimulationController, a: u8, b: u8) {
+    simulation_controller.record_action(format!("connect {} and {}", a, b));
+    add_sender(simulation_controller, a, b);
+    add_sender(simulation_controller, b, a);
+}
+
+pub fn disconnect(simulation_controller: &mut SimulationController, a: u8, b: u8) {
+    simulation_controller.record_action(format!("disconnect {} and {}", a, b));
+    remove_sender(simulation_controller, a, b);
+    remove_sender(simulation_controller, b, a);
+}
+
+// Channels the gui needs to talk to a new client, only the pair of the running app is set
+pub type ClientChannels = (
+    Option<Sender<ChatCommand>>,
+    Option<Receiver<ChatEvent>>,
+    Option<Sender<WebCommand>>,
+    Option<Receiver<WebEvent>>,
+);
+
+// Registers a new client or server with no links, the caller connects it
+fn add_node(
+    simulation_controller: &mut SimulationController,
+    id: u8,
+    node_type: components::NodeType,
+) -> Result<(Sender<NodeEvent>, Receiver<NodeCommand>, Receiver<Packet>), String> {
+    let Some(sender_node_event) = simulation_controller.sender_node_event.clone() else {
+        return Err("Clients and servers can't be added in this run".to_string());
+    };
+    if simulation_controller.nodes.contains_key(&id) {
+        return Err(format!("Id {} is already used", id));
+    }
+    let (node_command_sender, node_command_receiver) = unbounded::<NodeCommand>();
+    simulation_controller
+        .sender_client_server_command
+        .insert(id, node_command_sender);
+
+    let (node_packet_sender, node_packet_receiver) = unbounded::<Packet>();
+    simulation_controller
+        .sender_node_packet
+        .insert(id, node_packet_sender);
+
+    simulation_controller.nodes.insert(id, NodeInfo {
+        node_type,
+        connected_node_ids: Vec::new(),
+        pdr: 0.0,
+        crashed: false,
+    });
+    Ok((sender_node_event, node_command_receiver, node_packet_receiver))
+}
+
+pub fn spawn_client(simulation_controller: &mut SimulationController, id: u8) -> Result<ClientChannels, String> {
+    let (controller_send, controller_recv, packet_recv) =
+        add_node(simulation_controller, id, components::NodeType::Client)?;
+    simulation_controller.record_action(format!("add client {}", id));
+    Ok(initializer::build_and_run_client(
+        id,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        HashMap::new(),
+        simulation_controller.app,
+        initializer::node_seed(simulation_controller.base_seed, id),
+        simulation_controller.flood_policy(),
+        simulation_controller.session_policy(),
+    ))
+}
+
+pub fn spawn_server(simulation_controller: &mut SimulationController, id: u8) -> Result<(), String> {
+    let (controller_send, controller_recv, packet_recv) =
+        add_node(simulation_controller, id, components::NodeType::Server)?;
+    simulation_controller.record_action(format!("add server {}", id));
+    simulation_controller.last_server_type = initializer::build_and_run_server(
+        id,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        HashMap::new(),
+        simulation_controller.app,
+        simulation_controller.last_server_type,
+        None,
+        initializer::node_seed(simulation_controller.base_seed, id),
+        simulation_controller.flood_policy(),
+        simulation_controller.session_policy(),
+    );
+    Ok(())
+}
+
+// Crash for clients and servers, drones go through `crash`
+pub fn crash_node(simulation_controller: &mut SimulationController, id: u8) {
+    send_node_command(simulation_controller, id, NodeCommand::Crash, true);
+}
+
+pub fn restart_node(simulation_controller: &mut SimulationController, id: u8) {
+    send_node_command(simulation_controller, id, NodeCommand::Restart, false);
+}
+
+fn send_node_command(simulation_controller: &mut SimulationController, id: u8, command: NodeCommand, crashed: bool) {
+    let Some(client_server) = simulation_controller.sender_client_server_command.get(&id) else {
+        return;
+    };
+    match client_server.send(command) {
+        Ok(_) => {
+            let name = if crashed { "crash" } else { "restart" };
+            info!("Sent {} to client or server {}", name, id);
+            simulation_controller.record_action(format!("{} {}", name, id));
+            if let Some(node) = simulation_controller.nodes.get_mut(&id) {
+                node.crashed = crashed;
+            }
+        }
+        Err(_) => {
+            warn!("command to client or server {} error", id);
+        }
+    }
+}
+
+// Asks a client or server for the topology it learned, the answer comes on the returned channel
+pub fn request_topology(simulation_controller: &SimulationController, id: u8) -> Result<Receiver<TopologySnapshot>, String> {
+    let Some(client_server) = simulation_controller.sender_client_server_command.get(&id) else {
+        return Err(format!("{} is not a running client or server", id));
+    };
+    let (reply_send, reply_recv) = bounded(1);
+    client_server
+        .send(NodeCommand::ShowTopology(reply_send))
+        .map_err(|_| format!("Client or server {} is gone", id))?;
+    Ok(reply_recv)
+}
+
+pub fn check_restart(simulation_controller: &SimulationController, id: u8) -> Result<(), String> {
+    match simulation_controller.nodes.get(&id) {
+        None => Err(format!("No node with id {}", id)),
+        Some(node) if node.node_type == components::NodeType::Drone => {
+            Err("Crashed drones can't be restarted".to_string())
+        }
+        Some(node) if !node.crashed => Err(format!("Node {} isn't crashed", id)),
+        Some(_) => Ok(()),
+    }
+}
+
+// Config of the network as it is now, links to nodes that are gone (crashed drones) are left out
+pub fn topology_config(nodes: &HashMap<NodeId, NodeInfo>) -> Config {
+    let mut snapshot = trace::snapshot(nodes);
+    for node in snapshot.iter_mut() {
+        node.connected_node_ids.retain(|id| nodes.contains_key(id));
+    }
+    trace::config_of(&snapshot)
+}
+
+// A config file as the controller writes it: the protocol fields plus the optional extras
+// read back by sim_config (app, server kinds and node coordinates)
+#[derive(Serialize)]
+struct TopologyFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    simulation: Option<FileSimulation>,
+    drone: Vec<FileDrone>,
+    client: Vec<FileClient>,
+    server: Vec<FileServer>,
+}
+
+#[derive(Serialize)]
+struct FileSimulation {
+    app: String,
+}
+
+#[derive(Serialize)]
+struct FileDrone {
+    id: NodeId,
+    connected_node_ids: Vec<NodeId>,
+    pdr: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct FileClient {
+    id: NodeId,
+    connected_drone_ids: Vec<NodeId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct FileServer {
+    id: NodeId,
+    connected_drone_ids: Vec<NodeId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<f32>,
+}
+
+pub fn topology_toml(
+    config: &Config,
+    app: Option<u8>,
+    kinds: &HashMap<NodeId, u8>,
+    positions: &HashMap<NodeId, Vec2>,
+) -> Result<String, String> {
+    // a tenth of a pixel is plenty and keeps the file readable
+    let x = |id: &NodeId| positions.get(id).map(|p| (p.x * 10.0).round() / 10.0);
+    let y = |id: &NodeId| positions.get(id).map(|p| (p.y * 10.0).round() / 10.0);
+    let file = TopologyFile {
+        simulation: app.map(|app| FileSimulation {
+            app: if app == initializer::CHATAPP { "chat" } else { "web" }.to_string(),
+        }),
+        drone: config
+            .drone
+            .iter()
+            .map(|d| FileDrone {
+                id: d.id,
+                connected_node_ids: d.connected_node_ids.clone(),
+                pdr: d.pdr,
+                x: x(&d.id),
+                y: y(&d.id),
+            })
+            .collect(),
+        client: config
+            .client
+            .iter()
+            .map(|c| FileClient {
+                id: c.id,
+                connected_drone_ids: c.connected_drone_ids.clone(),
+                x: x(&c.id),
+                y: y(&c.id),
+            })
+            .collect(),
+        server: config
+            .server
+            .iter()
+            .map(|s| FileServer {
+                id: s.id,
+                connected_drone_ids: s.connected_drone_ids.clone(),
+                kind: kinds.get(&s.id).map(|kind| server_kind_name(*kind).to_string()),
+                x: x(&s.id),
+                y: y(&s.id),
+            })
+            .collect(),
+    };
+    to_string(&file).map_err(|e| e.to_string())
+}
+
+// Checks the topology with the same rules as the config files and writes it as configs/saved_<n>.toml,
+// with the position of every node so the next run shows the same layout
+pub fn save_topology(nodes: &HashMap<NodeId, NodeInfo>, positions: &HashMap<NodeId, Vec2>) -> Result<PathBuf, String> {
+    let config = topology_config(nodes);
+    initializer::check_config(&config)?;
+    let text = topology_toml(&config, None, &HashMap::new(), positions)?;
+    let dir = initializer::configs_dir();
+    let path = (1..)
+        .map(|n| dir.join(format!("saved_{}.toml", n)))
+        .find(|path| !path.exists())
+        .unwrap();
+    fs::write(&path, text).map_err(|e| e.to_string())?;
+    info!("Saved topology to {}", path.to_string_lossy());
+    Ok(path)
+}
+
+// The network as the controller sees it, in the same shape a node reports what it learned
+pub fn real_snapshot(nodes: &HashMap<NodeId, NodeInfo>) -> TopologySnapshot {
+    TopologySnapshot::from_config(&topology_config(nodes))
+}
+
+// Writes <name>.dot and <name>.json in the exports folder next to the configs
+pub fn export_graph(snapshot: &TopologySnapshot, name: &str) -> Result<(PathBuf, PathBuf), String> {
+    let dir = initializer::configs_dir().with_file_name("exports");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let dot = dir.join(format!("{}.dot", name));
+    let json = dir.join(format!("{}.json", name));
+    fs::write(&dot, snapshot.to_dot(name)).map_err(|e| e.to_string())?;
+    fs::write(&json, snapshot.to_json()).map_err(|e| e.to_string())?;
+    info!("Exported {} to {}", name, dir.to_string_lossy());
+    Ok((dot, json))
+}
 
 pub fn shortcut(simulation_controller: &mut SimulationController, packet: Packet) {
-    let rec_id = packet.routing_header.hops[packet.routing_header.hops.len() - 1];
-    match simulation_controller.sender_node_packet.get(&rec_id).unwrap().send(packet) {
+    let Some(&rec_id) = packet.routing_header.hops.last() else {
+        return;
+    };
+    // replays have no channels to forward to
+    let Some(sender) = simulation_controller.sender_node_packet.get(&rec_id) else {
+        return;
+    };
+    match sender.send(packet) {
         Ok(_)=>{
             info!("Sent packet to node {} wit a shortcut",rec_id,);
         },
@@ -245,3 +631,164 @@ pub fn shortcut(simulation_controller: &mut SimulationController, packet: Packet
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::sim_config::SimConfig;
+
+    fn controller() -> SimulationController {
+        let config: Config =
+            toml::from_str(&fs::read_to_string("./configs/config.toml").unwrap()).unwrap();
+        let (drone_event_send, drone_event_recv) = unbounded();
+        let (_, node_event_recv) = unbounded();
+        let senders = config
+            .drone
+            .iter()
+            .map(|d| d.id)
+            .chain(config.client.iter().map(|c| c.id))
+            .chain(config.server.iter().map(|s| s.id))
+            .map(|id| (id, unbounded().0))
+            .collect();
+        SimulationController::new(
+            HashMap::new(),
+            HashMap::new(),
+            drone_event_recv,
+            node_event_recv,
+            drone_event_send,
+            senders,
+            HashMap::new(),
+        )
+        .with_topology(&config)
+    }
+
+    #[test]
+    fn test_topology_config() {
+        let mut controller = controller();
+        // a crashed drone leaves the map but its neighbours may still list it
+        controller.nodes.remove(&3);
+        let config = topology_config(controller.nodes());
+        assert_eq!(config.drone.iter().map(|d| d.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(config.server[0].connected_drone_ids, vec![1, 2]);
+        assert_eq!(initializer::check_config(&config), Ok(()));
+
+        let positions = HashMap::from([(1, Vec2::new(-120.04, 300.0))]);
+        let text = topology_toml(&config, None, &HashMap::new(), &positions).unwrap();
+        let parsed: Config = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.client.len(), 2);
+        assert_eq!(parsed.drone[1].connected_node_ids, vec![1, 21, 22, 31]);
+        let sim = SimConfig::from_str(&text).unwrap();
+        assert_eq!(sim.positions(), HashMap::from([(1, (-120.0, 300.0))]));
+        assert!(sim.simulation.app.is_none());
+    }
+
+    #[test]
+    fn test_check_crash() {
+        let controller = controller();
+        // 21 and 22 only have drones 1 and 2
+        assert!(check_crash(&controller, 3).is_ok());
+        assert!(check_crash(&controller, 21).is_ok());
+        assert!(check_crash(&controller, 42).is_err());
+    }
+
+    #[test]
+    fn test_crash_and_restart_node() {
+        let (command_send, command_recv) = unbounded();
+        let mut controller = controller();
+        controller.sender_client_server_command.insert(21, command_send);
+        assert!(check_restart(&controller, 21).is_err());
+        assert!(check_crash(&controller, 21).is_ok());
+        crash_node(&mut controller, 21);
+        assert!(matches!(command_recv.try_recv(), Ok(NodeCommand::Crash)));
+        assert!(controller.nodes()[&21].crashed);
+        assert!(check_crash(&controller, 21).is_err());
+        assert!(check_restart(&controller, 21).is_ok());
+        restart_node(&mut controller, 21);
+        assert!(matches!(command_recv.try_recv(), Ok(NodeCommand::Restart)));
+        assert!(!controller.nodes()[&21].crashed);
+        assert!(check_restart(&controller, 3).is_err());
+    }
+
+    #[test]
+    fn test_request_topology() {
+        let (command_send, command_recv) = unbounded();
+        let mut controller = controller();
+        controller.sender_client_server_command.insert(21, command_send);
+        let reply = request_topology(&controller, 21).unwrap();
+        let Ok(NodeCommand::ShowTopology(answer)) = command_recv.try_recv() else {
+            panic!("no ShowTopology sent");
+        };
+        answer.send(TopologySnapshot::default()).unwrap();
+        assert_eq!(reply.try_recv(), Ok(TopologySnapshot::default()));
+        assert!(request_topology(&controller, 1).is_err());
+    }
+
+    #[test]
+    fn test_real_snapshot() {
+        let config: Config =
+            toml::from_str(&fs::read_to_string("./configs/config.toml").unwrap()).unwrap();
+        let controller = controller();
+        // nothing changed yet, the controller's view is the config's
+        let snapshot = real_snapshot(controller.nodes());
+        assert_eq!(snapshot, TopologySnapshot::from_config(&config));
+        assert!(snapshot.paths.is_empty());
+        assert!(snapshot.edges.iter().all(|(a, b)| a < b));
+        assert!(snapshot.to_dot("network").contains(&format!("{} -- {};", snapshot.edges[0].0, snapshot.edges[0].1)));
+    }
+
+    #[test]
+    fn test_check_disconnect() {
+        let mut controller = controller();
+        assert!(check_disconnect(&controller, 1, 2).is_ok());
+        assert!(check_disconnect(&controller, 21, 3).is_err()); // not connected
+        disconnect(&mut controller, 1, 21);
+        assert_eq!(controller.nodes()[&21].connected_node_ids, vec![2]);
+        assert!(!controller.nodes()[&1].connected_node_ids.contains(&21));
+        assert!(check_disconnect(&controller, 2, 21).is_err()); // last drone of 21
+        disconnect(&mut controller, 3, 31);
+        assert!(check_disconnect(&controller, 1, 31).is_err()); // 31 would keep one drone
+        disconnect(&mut controller, 1, 3);
+        assert!(check_disconnect(&controller, 2, 3).is_err()); // 3 would be cut off
+    }
+
+    #[test]
+    fn test_set_link() {
+        let slow = LinkParams { delay_ms: 50, jitter_ms: 10, ..Default::default() };
+        let mut controller = controller();
+        assert!(set_link(&mut controller, &[(1, 2)], slow).is_err()); // no shims to change
+        let (event_send, _event_recv) = unbounded();
+        let (controller_send, _controller_recv) = unbounded();
+        let links = Links::new(LinkParams::default(), HashMap::new(), event_send, controller_send, 0);
+        let mut controller = controller.with_links(links);
+        set_link(&mut controller, &[(1, 31), (31, 1)], slow).unwrap();
+        assert_eq!(controller.links().unwrap().params(31, 1), slow);
+        assert_eq!(controller.links().unwrap().params(1, 2), LinkParams::default());
+        assert!(set_link(&mut controller, &[(21, 31)], slow).is_err());
+        assert!(set_link(&mut controller, &[(1, 2)], LinkParams { jitter_ms: 1, ..slow }).is_ok());
+        assert!(set_link(&mut controller, &[(1, 2)], LinkParams { jitter_ms: 60, ..slow }).is_err());
+        set_link_loss(&mut controller, 31, 1, 0.3).unwrap();
+        assert_eq!(controller.links().unwrap().params(31, 1), LinkParams { loss: 0.3, ..slow });
+        assert_eq!(controller.links().unwrap().params(1, 31), slow); // the other way stays clean
+        assert!(set_link_loss(&mut controller, 31, 1, 1.2).is_err());
+        set_all_links(&mut controller, slow).unwrap();
+        assert!(controller.links().unwrap().custom().is_empty());
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut controller = controller();
+        assert!(pause_network(&mut controller).is_err());
+        let (event_send, _event_recv) = unbounded();
+        let (controller_send, _controller_recv) = unbounded();
+        let links = Links::new(LinkParams::default(), HashMap::new(), event_send, controller_send, 0);
+        let mut controller = controller.with_links(links);
+        step_network(&mut controller).unwrap();
+        let gate = controller.links().unwrap().gate();
+        assert!(gate.is_paused());
+        assert_eq!(gate.pending_steps(), 1);
+        resume_network(&mut controller).unwrap();
+        assert!(!controller.links().unwrap().gate().is_paused());
+        pause_network(&mut controller).unwrap();
+        assert!(controller.links().unwrap().gate().is_paused());
+    }
+}
@@ -0,0 +1,248 @@
+// Plays a recorded trace back in the controller: the events go through the same channels the
+// drones would use, so animations, stats and the packet inspector work as in a live run.
+
+use std::path::Path;
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+use super::trace::{self, TraceRecord};
+
+const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+#[derive(Resource)]
+pub struct ReplayState {
+    records: Vec<TraceRecord>,
+    next: usize,      // first record not played yet
+    clock: f64,       // trace time shown right now
+    pub speed: f64,
+    pub paused: bool,
+    seek_to: Option<f64>, // asked by the window, done by replay_tick
+    drone_send: Sender<DroneEvent>,
+    node_send: Sender<NodeEvent>,
+}
+
+impl ReplayState {
+    pub fn duration(&self) -> f64 {
+        self.records.last().map_or(0.0, |r| r.t())
+    }
+
+    pub fn clock(&self) -> f64 {
+        self.clock
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.records.len()
+    }
+
+    // Records up to `clock`, the ones already played are skipped
+    fn due(&mut self) -> &[TraceRecord] {
+        let first = self.next;
+        while self.next < self.records.len() && self.records[self.next].t() <= self.clock {
+            self.next += 1;
+        }
+        &self.records[first..self.next]
+    }
+
+    // Moves the clock without playing the events in between, only the topology of that moment matters
+    fn seek(&mut self, to: f64) -> Option<HashMap<NodeId, NodeInfo>> {
+        self.clock = to.clamp(0.0, self.duration());
+        self.next = self.records.partition_point(|r| r.t() <= self.clock);
+        self.records[..self.next].iter().rev().find_map(|r| match r {
+            TraceRecord::Topology { nodes, .. } => Some(trace::nodes_of(nodes)),
+            _ => None,
+        })
+    }
+}
+
+// Reads the trace and builds a controller with no threads behind it, starting from the first topology
+pub fn load_replay(path: &Path) -> Result<(SimulationController, ReplayState, Config), String> {
+    let records = trace::read_trace(path)?;
+    let first = records
+        .iter()
+        .find_map(|r| match r {
+            TraceRecord::Topology { nodes, .. } => Some(nodes.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| "The trace has no topology record".to_string())?;
+    let config = trace::config_of(&first);
+
+    let (drone_send, drone_recv) = unbounded();
+    let (node_send, node_recv) = unbounded();
+    let controller = SimulationController::new(
+        HashMap::new(),
+        HashMap::new(),
+        drone_recv,
+        node_recv,
+        drone_send.clone(),
+        HashMap::new(),
+        HashMap::new(),
+    )
+    .with_topology(&config);
+    let state = ReplayState {
+        records,
+        next: 0,
+        clock: 0.0,
+        speed: 1.0,
+        paused: false,
+        seek_to: None,
+        drone_send,
+        node_send,
+    };
+    Ok((controller, state, config))
+}
+
+pub fn replay_tick(
+    mut replay: ResMut<ReplayState>,
+    mut simulation_controller: ResMut<SimulationController>,
+    mut topology_writer: EventWriter<TopologyChangedEvent>,
+    mut stats: ResMut<NetworkStats>,
+    mut log: ResMut<PacketLog>,
+    packet_query: Query<Entity, With<PacketMotion>>,
+    mut commands: Commands,
+    time: Res<Time>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    if let Some(to) = replay.seek_to.take() {
+        // the counters and the packets on screen belong to the old position
+        *stats = NetworkStats::default();
+        *log = PacketLog::default();
+        for entity in &packet_query {
+            commands.entity(entity).despawn();
+        }
+        if let Some(nodes) = replay.seek(to) {
+            simulation_controller.set_nodes(nodes);
+            topology_writer.write(TopologyChangedEvent);
+        }
+        return;
+    }
+    if replay.paused {
+        return;
+    }
+    replay.clock += time.delta_secs_f64() * replay.speed;
+    let drone_send = replay.drone_send.clone();
+    let node_send = replay.node_send.clone();
+    for record in replay.due() {
+        match record {
+            TraceRecord::Topology { nodes, .. } => {
+                simulation_controller.set_nodes(trace::nodes_of(nodes));
+                topology_writer.write(TopologyChangedEvent);
+            }
+            TraceRecord::Action { t, action } => println!("[{:>8.2}s] {}", t, action),
+            TraceRecord::DroneEvent { event, packet, .. } => {
+                let _ = drone_send.send(trace::to_drone_event(*event, packet));
+            }
+            TraceRecord::NodeEvent { event, packet, .. } => {
+                if let Some(event) = trace::to_node_event(*event, packet) {
+                    let _ = node_send.send(event);
+                }
+            }
+            TraceRecord::NodeReport { report, .. } => {
+                let _ = node_send.send(NodeEvent::from(report));
+            }
+        }
+    }
+    if replay.is_done() {
+        replay.paused = true;
+    }
+}
+
+pub fn replay_window(mut egui_ctx: EguiContexts, mut replay: ResMut<ReplayState>, state: Res<MainState>) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let ctx = egui_ctx.ctx_mut();
+    egui::Window::new("Replay")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+        .resizable(false)
+        .collapsible(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let label = if replay.paused { "Play" } else { "Pause" };
+                if ui.button(label).clicked() {
+                    if replay.paused && replay.is_done() {
+                        replay.seek_to = Some(0.0);
+                    }
+                    replay.paused = !replay.paused;
+                }
+                let duration = replay.duration();
+                let mut position = replay.clock;
+                let slider = ui.add(
+                    egui::Slider::new(&mut position, 0.0..=duration)
+                        .suffix("s")
+                        .fixed_decimals(1),
+                );
+                if slider.changed() {
+                    replay.seek_to = Some(position);
+                }
+                ui.label(format!("/ {:.1}s", duration));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Speed");
+                for speed in SPEEDS {
+                    if ui.selectable_label(replay.speed == speed, format!("x{}", speed)).clicked() {
+                        replay.speed = speed;
+                    }
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::trace::{TraceEvent, TraceNode, TraceNodeType, TracePacket};
+
+    fn node(id: NodeId, connected_node_ids: Vec<NodeId>) -> TraceNode {
+        TraceNode {
+            id,
+            node_type: TraceNodeType::Drone,
+            connected_node_ids,
+            pdr: 0.0,
+            crashed: false,
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        let (drone_send, _) = unbounded();
+        let (node_send, _) = unbounded();
+        let packet = TracePacket::from(&Packet::new_ack(
+            SourceRoutingHeader { hop_index: 1, hops: vec![1, 2] },
+            1,
+            0,
+        ));
+        let mut replay = ReplayState {
+            records: vec![
+                TraceRecord::Topology { t: 0.0, nodes: vec![node(1, vec![2]), node(2, vec![1])] },
+                TraceRecord::DroneEvent { t: 1.0, event: TraceEvent::PacketSent, packet },
+                TraceRecord::Topology { t: 2.0, nodes: vec![node(1, vec![])] },
+                TraceRecord::Action { t: 3.0, action: "crash 2".to_string() },
+            ],
+            next: 0,
+            clock: 0.0,
+            speed: 1.0,
+            paused: false,
+            seek_to: None,
+            drone_send,
+            node_send,
+        };
+
+        replay.clock = 1.5;
+        assert_eq!(replay.due().len(), 2);
+        assert!(replay.due().is_empty());
+
+        let nodes = replay.seek(2.5).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(replay.next, 3);
+        assert_eq!(replay.seek(0.5).unwrap().len(), 2);
+        assert_eq!(replay.next, 1);
+        replay.seek(10.0);
+        assert_eq!(replay.clock(), 3.0);
+        assert!(replay.is_done());
+    }
+}
@@ -0,0 +1,356 @@
+// Timed controller actions read from a scenario file, the scripted version of the
+// Crash / Pdr / Connect / Disconnect / Add buttons.
+//
+// [[action]]
+// at = 10.0            # seconds since the simulation started
+// kind = "crash"       # crash | restart | set_pdr | connect | disconnect | spawn | set_link
+// drone = 3            # `node = 21` also works, clients and servers can crash and restart
+// set_link takes the fields of a [[link]] in the config: from, to, both, delay_ms, ...
+// spawn takes `implementation`, `connect_to` and, for a mock drone, a `mock` policy
+// (see mock_drone::MockPolicy, [simulation.mock] of the config without it)
+
+use std::{fmt, fs, time::Instant};
+
+use serde::Deserialize;
+
+use super::super::controller::*;
+use crate::utils::{drone_registry, links::LinkParams, mock_drone::MockPolicy, sim_config::LinkSection};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ControllerScenario {
+    #[serde(default)]
+    pub action: Vec<TimedAction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimedAction {
+    pub at: f64,
+    #[serde(flatten)]
+    pub action: ControllerAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ControllerAction {
+    Crash {
+        #[serde(alias = "node")]
+        drone: NodeId,
+    },
+    Restart { node: NodeId },
+    SetPdr { drone: NodeId, pdr: f32 },
+    Connect { a: NodeId, b: NodeId },
+    Disconnect { a: NodeId, b: NodeId },
+    Spawn {
+        drone: NodeId,
+        implementation: String,
+        connect_to: Vec<NodeId>,
+        #[serde(default)]
+        mock: Option<MockPolicy>,
+    },
+    SetLink(LinkSection),
+}
+
+impl fmt::Display for ControllerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerAction::Crash { drone } => write!(f, "crash {}", drone),
+            ControllerAction::Restart { node } => write!(f, "restart {}", node),
+            ControllerAction::SetPdr { drone, pdr } => write!(f, "set pdr of {} to {}", drone, pdr),
+            ControllerAction::Connect { a, b } => write!(f, "connect {} and {}", a, b),
+            ControllerAction::Disconnect { a, b } => write!(f, "disconnect {} and {}", a, b),
+            ControllerAction::Spawn { drone, implementation, connect_to, .. } => write!(
+                f,
+                "spawn {} drone {} connected to {:?}",
+                implementation, drone, connect_to
+            ),
+            ControllerAction::SetLink(link) => write!(
+                f,
+                "set link {} {} {}",
+                link.from,
+                if link.both { "<->" } else { "->" },
+                link.to
+            ),
+        }
+    }
+}
+
+// Hands out the actions whose time has come, the clock starts on the first call
+#[derive(Resource, Debug, Default)]
+pub struct ScenarioRunner {
+    actions: Vec<TimedAction>,
+    next: usize,
+    start: Option<Instant>,
+}
+
+impl ScenarioRunner {
+    pub fn new(mut scenario: ControllerScenario) -> Self {
+        scenario.action.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Self {
+            actions: scenario.action,
+            next: 0,
+            start: None,
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let file_str = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let scenario: ControllerScenario = toml::from_str(&file_str).map_err(|e| e.to_string())?;
+        Ok(Self::new(scenario))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.actions.len()
+    }
+
+    pub fn last_at(&self) -> f64 {
+        self.actions.last().map(|a| a.at).unwrap_or(0.)
+    }
+
+    pub fn due(&mut self) -> Vec<(f64, ControllerAction)> {
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed().as_secs_f64();
+        self.due_at(elapsed)
+    }
+
+    pub fn due_at(&mut self, elapsed: f64) -> Vec<(f64, ControllerAction)> {
+        let mut due = Vec::new();
+        while let Some(timed) = self.actions.get(self.next) {
+            if timed.at > elapsed {
+                break;
+            }
+            due.push((elapsed, timed.action.clone()));
+            self.next += 1;
+        }
+        due
+    }
+}
+
+// Runs one action with the same checks the buttons do
+pub fn apply(
+    simulation_controller: &mut SimulationController,
+    action: &ControllerAction,
+) -> Result<(), String> {
+    match action {
+        ControllerAction::Crash { drone } => {
+            check_crash(simulation_controller, *drone)?;
+            let node = &simulation_controller.nodes[drone];
+            if node.node_type == components::NodeType::Drone {
+                let neighbours = node.connected_node_ids.clone();
+                crash(simulation_controller, *drone, neighbours);
+            } else {
+                crash_node(simulation_controller, *drone);
+            }
+        }
+        ControllerAction::Restart { node } => {
+            check_restart(simulation_controller, *node)?;
+            restart_node(simulation_controller, *node);
+        }
+        ControllerAction::SetPdr { drone, pdr } => {
+            match simulation_controller.nodes.get(drone) {
+                Some(node) if node.node_type == components::NodeType::Drone => {}
+                _ => return Err(format!("Node {} is not a drone", drone)),
+            }
+            if !(0.0..=1.0).contains(pdr) {
+                return Err(format!("Invalid pdr {}", pdr));
+            }
+            set_pdr(simulation_controller, *drone, *pdr);
+        }
+        ControllerAction::Connect { a, b } => {
+            check_connect(simulation_controller, *a, *b)?;
+            connect(simulation_controller, *a, *b);
+        }
+        ControllerAction::Disconnect { a, b } => {
+            check_disconnect(simulation_controller, *a, *b)?;
+            disconnect(simulation_controller, *a, *b);
+        }
+        ControllerAction::Spawn { drone, implementation, connect_to, mock } => {
+            if simulation_controller.nodes.contains_key(drone) {
+                return Err(format!("Id {} is already used", drone));
+            }
+            let index = drone_registry::index_of(implementation)
+                .ok_or_else(|| format!("Unknown drone implementation {}", implementation))?;
+            if connect_to.is_empty() {
+                return Err("A new drone needs at least one neighbour".to_string());
+            }
+            let mut neighbours = Vec::new();
+            for id in connect_to {
+                if !neighbours.contains(id) {
+                    neighbours.push(*id);
+                }
+            }
+            for id in &neighbours {
+                match simulation_controller.nodes.get(id) {
+                    None => return Err(format!("No node with id {}", id)),
+                    Some(node)
+                        if node.node_type == components::NodeType::Client
+                            && node.connected_node_ids.len() >= 2 =>
+                    {
+                        return Err("Client can have at most 2 drones connected".to_string());
+                    }
+                    _ => {}
+                }
+            }
+            let policy = mock.clone().unwrap_or_else(|| simulation_controller.mock_policy().clone());
+            spawn(simulation_controller, index, *drone, policy);
+            for id in neighbours {
+                check_connect(simulation_controller, *drone, id)?;
+                connect(simulation_controller, *drone, id);
+            }
+        }
+        ControllerAction::SetLink(link) => {
+            for (from, to) in link.directions() {
+                let current = simulation_controller
+                    .links()
+                    .map_or(LinkParams::default(), |links| links.params(from, to));
+                set_link(simulation_controller, &[(from, to)], link.apply(current))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn log_action(t: f64, action: &ControllerAction, result: &Result<(), String>) {
+    match result {
+        Ok(_) => info!("[{:>8.2}s] {}", t, action),
+        Err(e) => warn!("[{:>8.2}s] {} failed: {}", t, action, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_order() {
+        let scenario: ControllerScenario = toml::from_str(
+            r#"
+            [[action]]
+            at = 10.0
+            kind = "crash"
+            drone = 3
+
+            [[action]]
+            at = 12.0
+            kind = "restart"
+            node = 21
+
+            [[action]]
+            at = 2.5
+            kind = "set_pdr"
+            drone = 2
+            pdr = 0.4
+
+            [[action]]
+            at = 5.0
+            kind = "spawn"
+            drone = 7
+            implementation = "RustRoveri"
+            connect_to = [1, 2]
+
+            [[action]]
+            at = 3.0
+            kind = "set_link"
+            from = 1
+            to = 21
+            delay_ms = 200
+
+            [[step]]
+            at = 1.0
+            client = 21
+            command = "get_servers_type"
+            "#,
+        )
+        .unwrap();
+        let mut runner = ScenarioRunner::new(scenario);
+        assert!(runner.due_at(1.0).is_empty());
+        assert_eq!(
+            runner.due_at(6.0).into_iter().map(|(_, a)| a).collect::<Vec<_>>(),
+            vec![
+                ControllerAction::SetPdr { drone: 2, pdr: 0.4 },
+                ControllerAction::SetLink(LinkSection {
+                    from: 1,
+                    to: 21,
+                    both: false,
+                    delay_ms: Some(200),
+                    jitter_ms: None,
+                    bandwidth: None,
+                    queue: None,
+                    loss: None,
+                }),
+                ControllerAction::Spawn {
+                    drone: 7,
+                    implementation: "RustRoveri".to_string(),
+                    connect_to: vec![1, 2],
+                    mock: None,
+                },
+            ]
+        );
+        assert!(!runner.is_done());
+        assert_eq!(runner.due_at(10.0).len(), 1);
+        assert_eq!(
+            runner.due_at(12.0).into_iter().map(|(_, a)| a).collect::<Vec<_>>(),
+            vec![ControllerAction::Restart { node: 21 }]
+        );
+        assert!(runner.is_done());
+        assert_eq!(runner.last_at(), 12.0);
+    }
+
+    #[test]
+    fn test_spawn_neighbours() {
+        let config: wg_2024::config::Config =
+            toml::from_str(&fs::read_to_string("./configs/config.toml").unwrap()).unwrap();
+        let (drone_event_send, drone_event_recv) = unbounded();
+        let (_, node_event_recv) = unbounded();
+        let senders = config
+            .drone
+            .iter()
+            .map(|d| d.id)
+            .chain(config.client.iter().map(|c| c.id))
+            .chain(config.server.iter().map(|s| s.id))
+            .map(|id| (id, unbounded().0))
+            .collect();
+        let mut controller = SimulationController::new(
+            HashMap::new(),
+            HashMap::new(),
+            drone_event_recv,
+            node_event_recv,
+            drone_event_send,
+            senders,
+            HashMap::new(),
+        )
+        .with_topology(&config);
+
+        // a neighbour listed twice is connected once
+        let spawn = ControllerAction::Spawn {
+            drone: 7,
+            implementation: "mock".to_string(),
+            connect_to: vec![3, 3],
+            mock: None,
+        };
+        apply(&mut controller, &spawn).unwrap();
+        assert_eq!(controller.nodes[&7].connected_node_ids, vec![3]);
+        assert_eq!(controller.nodes[&3].connected_node_ids.iter().filter(|id| **id == 7).count(), 1);
+
+        // clients 21 and 22 already have two drones
+        let spawn = ControllerAction::Spawn {
+            drone: 8,
+            implementation: "mock".to_string(),
+            connect_to: vec![3, 21],
+            mock: None,
+        };
+        assert_eq!(apply(&mut controller, &spawn), Err("Client can have at most 2 drones connected".to_string()));
+        assert!(!controller.nodes.contains_key(&8));
+    }
+
+    #[test]
+    fn test_unknown_kind() {
+        let res = toml::from_str::<ControllerScenario>(
+            r#"
+            [[action]]
+            at = 1.0
+            kind = "explode"
+            drone = 3
+            "#,
+        );
+        assert!(res.is_err());
+    }
+}
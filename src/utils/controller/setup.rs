@@ -175,7 +175,10 @@ pub fn setup_ui(mut commands: Commands, state: Res<MainState>) {
                                                     ("Add", ButtonLabel::Add),
                                                     ("Crash", ButtonLabel::Crash),
                                                     ("Connect", ButtonLabel::Connect),
+                                                    ("Disconnect", ButtonLabel::Disconnect),
                                                     ("Set PDR", ButtonLabel::Pdr),
+                                                    ("Restart", ButtonLabel::Restart),
+                                                    ("Save", ButtonLabel::Save),
                                                 ];
                                                 for (text, label) in labels {
                                                     row.spawn((
@@ -292,7 +295,7 @@ pub fn setup_ui(mut commands: Commands, state: Res<MainState>) {
                                                         .with_children(|center| {
                                                             center.spawn((
                                                                 DroneSelector { index: 0 },
-                                                                Text::new(DRONE_NAMES[0]),
+                                                                Text::new(selector_label(0)),
                                                                 TextColor(text_color),
                                                             ));
                                                         });
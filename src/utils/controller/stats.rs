@@ -0,0 +1,344 @@
+// Per-node and per-link counters built from the event stream, shown in the stats panel
+// when a node is clicked with no mode active.
+
+use bevy_egui::{EguiContexts, egui};
+
+use super::super::super::frontend::MainState;
+use super::super::controller::*;
+
+// Seconds of throughput kept for every node
+const HISTORY_SECS: u64 = 60;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NodeStats {
+    pub fragments: u64,
+    pub dropped: u64,
+    pub shortcuts: u64,
+    pub floods: u64,
+    pub acks: u64,
+    pub nacks: u64,
+    pub delivered: u64, // messages reassembled by a client or server
+    pub served: u64,
+    pub reroutes: u64,
+    pub failed: u64, // broken reassemblies and resends given up
+    pub evicted: EvictionStats, // sessions and flood ids dropped by the node
+    pub link_dropped: u64, // packets the emulated links out of the node dropped
+    history: VecDeque<(u64, u64)>, // (second, packets sent in that second)
+}
+
+impl NodeStats {
+    fn sent_at(&mut self, t: f64) {
+        let second = t as u64;
+        match self.history.back_mut() {
+            Some((last, count)) if *last == second => *count += 1,
+            _ => self.history.push_back((second, 1)),
+        }
+        while self
+            .history
+            .front()
+            .is_some_and(|(s, _)| *s + HISTORY_SECS <= second)
+        {
+            self.history.pop_front();
+        }
+    }
+
+    // Packets sent in each of the last `secs` seconds before `now`, oldest first
+    pub fn throughput(&self, now: f64, secs: u64) -> Vec<u64> {
+        let now = now as u64;
+        let first = now.saturating_sub(secs.saturating_sub(1));
+        (first..=now)
+            .map(|second| {
+                self.history
+                    .iter()
+                    .find(|(s, _)| *s == second)
+                    .map_or(0, |(_, count)| *count)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct NetworkStats {
+    nodes: HashMap<NodeId, NodeStats>,
+    links: HashMap<(NodeId, NodeId), LinkStats>,
+}
+
+// The node that sent the packet and the next one on its route
+pub fn current_hop(packet: &Packet) -> Option<(NodeId, Option<NodeId>)> {
+    let hops = &packet.routing_header.hops;
+    let index = packet.routing_header.hop_index;
+    let from = *hops.get(index.checked_sub(1)?)?;
+    Some((from, hops.get(index).copied()))
+}
+
+impl NetworkStats {
+    pub fn node(&self, id: NodeId) -> Option<&NodeStats> {
+        self.nodes.get(&id)
+    }
+
+    // Both directions of every link of `id`, keyed by the neighbour
+    pub fn links_of(&self, id: NodeId) -> Vec<(NodeId, LinkStats, LinkStats)> {
+        let mut neighbours = self
+            .links
+            .keys()
+            .filter_map(|(a, b)| if *a == id { Some(*b) } else if *b == id { Some(*a) } else { None })
+            .collect::<Vec<_>>();
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+            .into_iter()
+            .map(|n| {
+                (
+                    n,
+                    self.links.get(&(id, n)).copied().unwrap_or_default(),
+                    self.links.get(&(n, id)).copied().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn record_drone_event(&mut self, event: &DroneEvent, t: f64) {
+        match event {
+            DroneEvent::PacketSent(packet) => self.record_sent(packet, t),
+            DroneEvent::PacketDropped(packet) => {
+                if let Some((from, to)) = current_hop(packet) {
+                    self.nodes.entry(from).or_default().dropped += 1;
+                    if let Some(to) = to {
+                        self.links.entry((from, to)).or_default().dropped += 1;
+                    }
+                }
+            }
+            DroneEvent::ControllerShortcut(packet) => self.record_shortcut(packet),
+        }
+    }
+
+    pub fn record_node_event(&mut self, event: &NodeEvent, t: f64) {
+        match event {
+            NodeEvent::PacketSent(packet) => self.record_sent(packet, t),
+            NodeEvent::ControllerShortcut(packet) => self.record_shortcut(packet),
+            NodeEvent::MessageDelivered { node, .. } => self.nodes.entry(*node).or_default().delivered += 1,
+            NodeEvent::RequestServed { node, .. } => self.nodes.entry(*node).or_default().served += 1,
+            NodeEvent::Rerouted { node, .. } => self.nodes.entry(*node).or_default().reroutes += 1,
+            NodeEvent::ReassemblyFailed { node, .. } | NodeEvent::RetryExhausted { node, .. } => {
+                self.nodes.entry(*node).or_default().failed += 1
+            }
+            NodeEvent::SessionsEvicted { node, evicted } => self.nodes.entry(*node).or_default().evicted.add(evicted),
+            NodeEvent::LinkDropped { from, to, .. } => {
+                self.nodes.entry(*from).or_default().link_dropped += 1;
+                self.links.entry((*from, *to)).or_default().dropped += 1;
+            }
+            NodeEvent::TopologyUpdated { .. } => {}
+        }
+    }
+
+    fn record_shortcut(&mut self, packet: &Packet) {
+        if let Some((from, _)) = current_hop(packet) {
+            self.nodes.entry(from).or_default().shortcuts += 1;
+        }
+    }
+
+    fn record_sent(&mut self, packet: &Packet, t: f64) {
+        // flood requests travel without a route, the sender is the last one in the path trace
+        let (from, to) = match &packet.pack_type {
+            PacketType::FloodRequest(request) => match request.path_trace.last() {
+                Some((id, _)) => (*id, None),
+                None => return,
+            },
+            _ => match current_hop(packet) {
+                Some(hop) => hop,
+                None => return,
+            },
+        };
+        let node = self.nodes.entry(from).or_default();
+        match &packet.pack_type {
+            PacketType::MsgFragment(_) => node.fragments += 1,
+            PacketType::Ack(_) => node.acks += 1,
+            PacketType::Nack(_) => node.nacks += 1,
+            PacketType::FloodRequest(_) | PacketType::FloodResponse(_) => node.floods += 1,
+        }
+        node.sent_at(t);
+        if let Some(to) = to {
+            self.links.entry((from, to)).or_default().sent += 1;
+        }
+    }
+}
+
+// Node whose stats are shown, None hides the panel
+#[derive(Resource, Default)]
+pub struct StatsSelection(pub Option<NodeId>);
+
+pub fn select_stats_node(
+    trigger: Trigger<Pointer<Click>>,
+    active_mode: Res<ActiveMode>,
+    node_query: Query<&ScNode>,
+    mut selection: ResMut<StatsSelection>,
+    editor: Option<Res<EditorState>>,
+    state: Res<MainState>,
+) {
+    if let MainState::Sim = *state {
+        // the editor handles its own clicks
+        if *active_mode != ActiveMode::None || editor.is_some() {
+            return;
+        }
+        if let Ok(node) = node_query.get(trigger.target()) {
+            selection.0 = if selection.0 == Some(node.id) { None } else { Some(node.id) };
+        }
+    }
+}
+
+pub fn stats_panel(
+    mut egui_ctx: EguiContexts,
+    mut selection: ResMut<StatsSelection>,
+    stats: Res<NetworkStats>,
+    simulation_controller: Res<SimulationController>,
+    time: Res<Time>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let Some(id) = selection.0 else {
+        return;
+    };
+    let ctx = egui_ctx.ctx_mut();
+    egui::SidePanel::right("stats_panel").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.heading(format!("Node {}", id));
+            if ui.button("Close").clicked() {
+                selection.0 = None;
+            }
+        });
+        match simulation_controller.nodes().get(&id) {
+            Some(info) if info.node_type == components::NodeType::Drone => {
+                ui.label(format!("PDR: {:.2}", info.pdr));
+            }
+            Some(info) => {
+                ui.label(format!("{:?}{}", info.node_type, if info.crashed { " (crashed)" } else { "" }));
+            }
+            None => {
+                ui.label("Crashed");
+            }
+        }
+        ui.separator();
+
+        let node = stats.node(id).cloned().unwrap_or_default();
+        egui::Grid::new("stats_counters").show(ui, |ui| {
+            for (name, value) in [
+                ("Fragments", node.fragments),
+                ("Dropped", node.dropped),
+                ("Shortcuts", node.shortcuts),
+                ("Floods", node.floods),
+                ("Acks", node.acks),
+                ("Nacks", node.nacks),
+                ("Messages delivered", node.delivered),
+                ("Requests served", node.served),
+                ("Reroutes", node.reroutes),
+                ("Failed", node.failed),
+                ("Sessions timed out", node.evicted.timed_out),
+                ("Over budget", node.evicted.over_budget),
+                ("Refused", node.evicted.refused),
+                ("Finished sessions freed", node.evicted.finished),
+                ("Dropped by its links", node.link_dropped),
+            ] {
+                ui.label(name);
+                ui.label(value.to_string());
+                ui.end_row();
+            }
+        });
+        ui.separator();
+
+        ui.label(format!("Packets sent per second, last {}s", HISTORY_SECS));
+        throughput_bars(ui, &node.throughput(time.elapsed_secs_f64(), HISTORY_SECS));
+        ui.separator();
+
+        ui.label("Links (sent / dropped)");
+        egui::Grid::new("stats_links").show(ui, |ui| {
+            for (neighbour, out, inc) in stats.links_of(id) {
+                ui.label(format!("{} -> {}", id, neighbour));
+                ui.label(format!("{} / {}", out.sent, out.dropped));
+                ui.end_row();
+                ui.label(format!("{} -> {}", neighbour, id));
+                ui.label(format!("{} / {}", inc.sent, inc.dropped));
+                ui.end_row();
+            }
+        });
+    });
+}
+
+fn throughput_bars(ui: &mut egui::Ui, values: &[u64]) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(30));
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let width = rect.width() / values.len().max(1) as f32;
+    for (i, value) in values.iter().enumerate() {
+        let height = rect.height() * *value as f32 / max;
+        let left = rect.left() + i as f32 * width;
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left, rect.bottom() - height),
+                egui::pos2(left + width - 1.0, rect.bottom()),
+            ),
+            0.0,
+            egui::Color32::from_rgb(80, 160, 230),
+        );
+    }
+    ui.label(format!("max {}", max as u64));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fragment(hops: Vec<NodeId>, hop_index: usize) -> Packet {
+        Packet::new_fragment(
+            SourceRoutingHeader { hop_index, hops },
+            1,
+            Fragment::new(0, 1, [0; 128]),
+        )
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut stats = NetworkStats::default();
+        stats.record_node_event(&NodeEvent::PacketSent(fragment(vec![21, 1, 2, 31], 1)), 0.5);
+        stats.record_drone_event(&DroneEvent::PacketSent(fragment(vec![21, 1, 2, 31], 2)), 0.7);
+        stats.record_drone_event(&DroneEvent::PacketDropped(fragment(vec![21, 1, 2, 31], 3)), 2.1);
+        stats.record_drone_event(
+            &DroneEvent::PacketSent(Packet::new_ack(SourceRoutingHeader { hop_index: 1, hops: vec![1, 21] }, 1, 0)),
+            2.2,
+        );
+
+        assert_eq!(stats.node(21).unwrap().fragments, 1);
+        let drone = stats.node(1).unwrap();
+        assert_eq!((drone.fragments, drone.acks), (1, 1));
+        assert_eq!(stats.node(2).unwrap().dropped, 1);
+        assert_eq!(drone.throughput(2.5, 3), vec![1, 0, 1]);
+        stats.record_node_event(
+            &NodeEvent::MessageDelivered { node: 31, from: 21, session_id: 1, kind: "String".to_string(), size: 5 },
+            2.3,
+        );
+        stats.record_node_event(&NodeEvent::RetryExhausted { node: 31, session_id: 2, fragment_index: 0, attempts: 30 }, 2.4);
+        let server = stats.node(31).unwrap();
+        assert_eq!((server.delivered, server.failed, server.fragments), (1, 1, 0));
+        let evicted = EvictionStats { timed_out: 1, finished: 2, ..Default::default() };
+        stats.record_node_event(&NodeEvent::SessionsEvicted { node: 31, evicted }, 2.5);
+        stats.record_node_event(&NodeEvent::SessionsEvicted { node: 31, evicted }, 2.6);
+        assert_eq!(stats.node(31).unwrap().evicted.finished, 4);
+        stats.record_node_event(&NodeEvent::LinkDropped { from: 21, to: 1, session_id: 3, reason: LinkDrop::QueueFull }, 2.7);
+        assert_eq!(stats.node(21).unwrap().link_dropped, 1);
+        assert_eq!(
+            stats.links_of(1),
+            vec![
+                (2, LinkStats { sent: 1, dropped: 0 }, LinkStats::default()),
+                (21, LinkStats { sent: 1, dropped: 0 }, LinkStats { sent: 1, dropped: 1 }),
+            ]
+        );
+    }
+}
@@ -1,8 +1,7 @@
-use std::collections::HashSet;
 use bevy::transform;
-use super::super::super::frontend::MainState;
+use super::super::super::frontend::{chat_gui, web_gui, MainState};
 use super::super::controller::*;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 pub fn spawn_drone(
     mut commands: Commands,
@@ -14,43 +13,86 @@ pub fn spawn_drone(
     mut lines_writer: EventWriter<MakeLinesEvent>,
     mut simulation_controller: ResMut<SimulationController>,
     selector_query: Query<&DroneSelector>,
+    mut chat_channels: Option<ResMut<chat_gui::GuiControllers>>,
+    mut web_channels: Option<ResMut<web_gui::GuiControllers>>,
     state : Res<MainState>,
 ) {
     if let MainState::Sim = *state {
         for SpawnDroneEvent { id, connect_to } in reader.read() {
             
             if let Some((_, mut node)) = node_query.iter_mut().filter(|(e, _)| *e == *connect_to).next() {
+                let selector = selector_query.single().unwrap();
+                let node_type = match selector.index {
+                    SELECTOR_CLIENT => components::NodeType::Client,
+                    SELECTOR_SERVER => components::NodeType::Server,
+                    _ => components::NodeType::Drone,
+                };
                 if node.node_type == components::NodeType::Client && node.connected_node_ids.len() >= 2 {
                     let mut warn_text = warn_query.single_mut().unwrap();
                     *warn_text = Text::from("Client can have at most 2 drones connected");
                 }
+                else if node_type != components::NodeType::Drone && node.node_type != components::NodeType::Drone {
+                    let mut warn_text = warn_query.single_mut().unwrap();
+                    *warn_text = Text::from("Clients and servers can only be connected to drones");
+                }
                 else{
-                    let selector = selector_query.single().unwrap();
-                    spawn(&mut simulation_controller, selector.index, *id);
+                    let spawned = match node_type {
+                        components::NodeType::Drone => {
+                            let policy = simulation_controller.mock_policy().clone();
+                            spawn(&mut simulation_controller, selector.index, *id, policy);
+                            Ok(())
+                        }
+                        components::NodeType::Client => {
+                            spawn_client(&mut simulation_controller, *id).map(|(cs, ce, ws, we)| {
+                                if let (Some(channels), Some(ce), Some(cs)) = (chat_channels.as_mut(), ce, cs) {
+                                    channels.insert(*id, chat_gui::GuiChannels::new(ce, cs));
+                                }
+                                if let (Some(channels), Some(we), Some(ws)) = (web_channels.as_mut(), we, ws) {
+                                    channels.insert(*id, web_gui::GuiChannels::new(we, ws));
+                                }
+                            })
+                        }
+                        components::NodeType::Server => spawn_server(&mut simulation_controller, *id),
+                    };
+                    if let Err(e) = spawned {
+                        let mut warn_text = warn_query.single_mut().unwrap();
+                        *warn_text = Text::from(e);
+                        continue;
+                    }
                     
-                    add_sender(&mut simulation_controller, *id, node.id);
-                    add_sender(&mut simulation_controller, node.id, *id);
+                    connect(&mut simulation_controller, *id, node.id);
                     
                     node.connected_node_ids.push(*id);
                     
+                    let (image, label) = match node_type {
+                        components::NodeType::Drone => ("controller/Drone.png", format!("id: {}  pdr: {}", *id, 0.0)),
+                        components::NodeType::Client => ("controller/Client.png", format!("id: {}", *id)),
+                        components::NodeType::Server => ("controller/Server.png", format!("id: {}", *id)),
+                    };
                     commands.spawn((
                         ScNode {
                             id: *id,
                             connected_node_ids: [node.id].to_vec(),
-                            node_type: components::NodeType::Drone,
+                            node_type,
                             pdr: 0.0,
                         },
-                        Sprite::from_image(asset_server.load("controller/Drone.png")),
+                        Sprite::from_image(asset_server.load(image)),
                         Transform::from_xyz(0.0, 0.0, 1.0),
                         Pickable::default(),
                     )).with_children(|parent| {
-                        parent.spawn((
-                            DroneText,
-                            Text2d::new(format!("id: {}  pdr: {}", *id, 0.0)),
+                        let mut text = parent.spawn((
+                            Text2d::new(label),
                             Transform::from_xyz(0.0, -50.0, 1.0),
                         ));
+                        if node_type == components::NodeType::Drone {
+                            text.insert(DroneText);
+                        }
                     });
                     
+                    if node_type == components::NodeType::Server {
+                        let mut warn_text = warn_query.single_mut().unwrap();
+                        *warn_text = Text::from("Connect the new server to a second drone");
+                    }
                     
                     nodes_writer.write(UpdateNodesEvent);
                     lines_writer.write(MakeLinesEvent);
@@ -98,81 +140,119 @@ pub fn update_nodes(
     mut node_query: Query<(&ScNode, &mut Transform)>,
     mut reader: EventReader<UpdateNodesEvent>,
     mut writer: EventWriter<NodeMovedEvent>,
-    state: Res<MainState>
+    state: Res<MainState>,
+    simulation_controller: Res<SimulationController>,
+    mut layout: ResMut<NodeLayout>,
 ) {
     if let MainState::Sim = *state {
         for _ in reader.read() {
-            let mut rng = rand::thread_rng();
+            if layout.mode == LayoutMode::Classic {
+                let mut rng = match simulation_controller.seed() {
+                    Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+                    None => rand::rngs::StdRng::from_entropy(),
+                };
+            
+                let r = WINDOW_HEIGHT / 3.0;
+                let drone_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Drone)).count();
+                if drone_num > 1 {
+                    let mut i = 0;
+                    for (node, mut transform) in node_query.iter_mut() {
+                        if node.node_type == components::NodeType::Drone {
+                            let theta = 2.0 * PI * i as f32 / drone_num as f32 + PI / 2.0;
+                            let x = r * theta.cos();
+                            let y = r * theta.sin();
+                        
+                            transform.translation.x = x + rng.gen_range(-30.0..30.0);
+                            transform.translation.y = y + rng.gen_range(-30.0..30.0);
+                            i += 1;
+                        
+                            writer.write(NodeMovedEvent {
+                                node_id: node.id,
+                                new_position: Some(transform.translation),
+                            });
+                        }
+                    }
+                }
+            
+                let x = -(WINDOW_WIDTH / 8.0) * 3.0;
+                let client_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Client)).count();
+                let dist = (WINDOW_HEIGHT - WINDOW_HEIGHT / 3.0) / (client_num as f32 - 1.0);
             
-            let r = WINDOW_HEIGHT / 3.0;
-            let drone_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Drone)).count();
-            if drone_num > 1 {
                 let mut i = 0;
                 for (node, mut transform) in node_query.iter_mut() {
-                    if node.node_type == components::NodeType::Drone {
-                        let theta = 2.0 * PI * i as f32 / drone_num as f32 + PI / 2.0;
-                        let x = r * theta.cos();
-                        let y = r * theta.sin();
-                        
-                        transform.translation.x = x + rng.gen_range(-30.0..30.0);
-                        transform.translation.y = y + rng.gen_range(-30.0..30.0);
+                    if node.node_type == components::NodeType::Client {
+                        let y = WINDOW_HEIGHT / 6.0 - WINDOW_HEIGHT / 2.0 + (i as f32) * dist;
+                        transform.translation.y = y;
+                        transform.translation.x = x;
+                        if client_num == 1 {
+                            transform.translation.y = 0.0;
+                            transform.translation.x = x;
+                        }
                         i += 1;
-                        
+                    
                         writer.write(NodeMovedEvent {
                             node_id: node.id,
                             new_position: Some(transform.translation),
                         });
                     }
+                
                 }
-            }
-            
-            let x = -(WINDOW_WIDTH / 8.0) * 3.0;
-            let client_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Client)).count();
-            let dist = (WINDOW_HEIGHT - WINDOW_HEIGHT / 3.0) / (client_num as f32 - 1.0);
             
-            let mut i = 0;
-            for (node, mut transform) in node_query.iter_mut() {
-                if node.node_type == components::NodeType::Client {
-                    let y = WINDOW_HEIGHT / 6.0 - WINDOW_HEIGHT / 2.0 + (i as f32) * dist;
-                    transform.translation.y = y;
-                    transform.translation.x = x;
-                    if client_num == 1 {
-                        transform.translation.y = 0.0;
+                let x = (WINDOW_WIDTH / 8.0) * 3.0;
+                let server_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Server)).count() ;
+                let dist = (WINDOW_HEIGHT - WINDOW_HEIGHT / 3.0) / (server_num as f32 - 1.0);
+                let mut i = 0;
+                for (node, mut transform) in node_query.iter_mut() {
+                    if node.node_type == components::NodeType::Server {
+                        let y = WINDOW_HEIGHT / 6.0 - WINDOW_HEIGHT / 2.0 + (i as f32) * dist;
+                        transform.translation.y = y;
                         transform.translation.x = x;
-                    }
-                    i += 1;
+                        if server_num == 1 {
+                            transform.translation.y = 0.0;
+                            transform.translation.x = x;
+                        }
+                        i += 1;
                     
-                    writer.write(NodeMovedEvent {
-                        node_id: node.id,
-                        new_position: Some(transform.translation),
-                    });
-                }
+                        writer.write(NodeMovedEvent {
+                            node_id: node.id,
+                            new_position: Some(transform.translation),
+                        });
+                    }
                 
+                }
+            } else {
+                let graph = node_query
+                    .iter()
+                    .map(|(node, _)| (node.id, node.connected_node_ids.clone()))
+                    .collect::<Vec<_>>();
+                let positions = force_layout(&graph, &layout.positions, &layout.pinned);
+                for (node, mut transform) in node_query.iter_mut() {
+                    if let Some(position) = positions.get(&node.id) {
+                        transform.translation.x = position.x;
+                        transform.translation.y = position.y;
+                        writer.write(NodeMovedEvent {
+                            node_id: node.id,
+                            new_position: Some(transform.translation),
+                        });
+                    }
+                }
             }
-            
-            let x = (WINDOW_WIDTH / 8.0) * 3.0;
-            let server_num = node_query.iter().filter(|(node, _)| matches!(node.node_type, components::NodeType::Server)).count() ;
-            let dist = (WINDOW_HEIGHT - WINDOW_HEIGHT / 3.0) / (server_num as f32 - 1.0);
-            let mut i = 0;
+
+            // dragged nodes and the ones with coordinates in the config stay put
+            let layout = &mut *layout;
             for (node, mut transform) in node_query.iter_mut() {
-                if node.node_type == components::NodeType::Server {
-                    let y = WINDOW_HEIGHT / 6.0 - WINDOW_HEIGHT / 2.0 + (i as f32) * dist;
-                    transform.translation.y = y;
-                    transform.translation.x = x;
-                    if server_num == 1 {
-                        transform.translation.y = 0.0;
-                        transform.translation.x = x;
-                    }
-                    i += 1;
-                    
+                if let Some(position) = layout.pinned.get(&node.id) {
+                    transform.translation.x = position.x;
+                    transform.translation.y = position.y;
                     writer.write(NodeMovedEvent {
                         node_id: node.id,
                         new_position: Some(transform.translation),
                     });
                 }
-                
+                layout.positions.insert(node.id, transform.translation.truncate());
             }
-            
+            let shown = node_query.iter().map(|(node, _)| node.id).collect::<Vec<_>>();
+            layout.positions.retain(|id, _| shown.contains(id));
         }
     }
 }
@@ -184,6 +264,8 @@ pub fn make_lines(
     node_query: Query<(&ScNode, &mut Transform), With<ScNode>>,
     lines_query: Query<Entity, With<Mesh2d>>,
     mut reader: EventReader<MakeLinesEvent>,
+    heatmap: Res<Heatmap>,
+    time: Res<Time>,
     state: Res<MainState>,
 ){
     if let MainState::Sim = *state {
@@ -191,7 +273,33 @@ pub fn make_lines(
             for line in &mut lines_query.iter() {
                 commands.entity(line).despawn();
             }
-            
+
+            if heatmap.enabled {
+                // one line per link, colored and sized by its share of the busiest link
+                let load = heatmap.link_load(time.elapsed_secs_f64());
+                let busiest = load.values().copied().max().unwrap_or(0).max(1) as f32;
+                let positions = node_query
+                    .iter()
+                    .map(|(node, transform)| (node.id, transform.translation.truncate()))
+                    .collect::<HashMap<_, _>>();
+                let mut drawn = Vec::new();
+                for (node, _) in node_query.iter() {
+                    for connected_node in node.connected_node_ids.iter() {
+                        let key = (node.id.min(*connected_node), node.id.max(*connected_node));
+                        let (Some(from), Some(to)) = (positions.get(&key.0), positions.get(&key.1)) else {
+                            continue;
+                        };
+                        if drawn.contains(&key) {
+                            continue;
+                        }
+                        drawn.push(key);
+                        let heat = load.get(&key).copied().unwrap_or(0) as f32 / busiest;
+                        create_heat_line(&mut commands, &mut meshes, &mut materials, *from, *to, heat_color(heat), heat_width(heat));
+                    }
+                }
+                continue;
+            }
+
             for (node, transform) in node_query.iter() {
                 for connected_node in node.connected_node_ids.iter() {
                     for (c_node, c_transform) in node_query.iter() {
@@ -248,6 +356,7 @@ pub fn crash_target(
     mut lines_writer: EventWriter<MakeLinesEvent>,
     mut moved_writer: EventWriter<NodeMovedEvent>,
     mut warn_query: Query<&mut Text, With<TextWarn>>,
+    mut sprite_query: Query<&mut Sprite>,
     mut simulation_controller: ResMut<SimulationController>,
     state: Res<MainState>
 ) {
@@ -262,65 +371,26 @@ pub fn crash_target(
         return;
     };
 
-    if node_to_remove.node_type != components::NodeType::Drone {
-        return;
-    }
-
     let id_to_remove = node_to_remove.id;
     let connected_to_removed = node_to_remove.connected_node_ids.clone();
 
     let mut warn_text = warn_query.single_mut().unwrap();
 
-    for &connected_id in &node_to_remove.connected_node_ids {
-        if let Some((_, connected_node)) = node_query.iter().find(|(_, n)| n.id == connected_id) {
-            match connected_node.node_type {
-                components::NodeType::Client if connected_node.connected_node_ids.len() <= 1 => {
-                    *warn_text = Text::from(format!("A Client must always be connected to at least 1 drone"));
-                    return;
-                }
-                components::NodeType::Server if connected_node.connected_node_ids.len() <= 2 => {
-                    *warn_text = Text::from(format!("A Server must always be connected to at least 2 drones"));
-                    return;
-                }
-                _ => {}
-        }
-
-            let mut topology = HashMap::<u8, Vec<u8>>::new();
-            let mut id = 0;
-            for (_, node) in node_query.iter().filter(|(_, n)| n.id != id_to_remove) {
-                let filtered_ids = node
-                    .connected_node_ids
-                    .iter()
-                    .filter(|&id| *id != node_to_remove.id)
-                    .cloned()
-                    .collect();
-
-                id = node.id.clone();
-                topology.insert(node.id, filtered_ids);
-            }
-
-            let mut visited = HashSet::new();
-            let mut stack = VecDeque::new();
-
-            stack.push_back(id);
-
-            while let Some(current) = stack.pop_front() {
-                if visited.insert(current) {
-                    for id in topology.get(&current).unwrap() {
-                        stack.push_back(*id);
-                    }
-                }
-            }
-
-            if visited.len() != topology.len() {
-                *warn_text = Text::from(format!("No network partitoning allowed"));
-                return;
-            }
+    if let Err(e) = check_crash(&simulation_controller, id_to_remove) {
+        *warn_text = Text::from(e);
+        return;
+    }
 
+    // clients and servers stay in place, greyed out, so they can be restarted
+    if node_to_remove.node_type != components::NodeType::Drone {
+        crash_node(&mut simulation_controller, id_to_remove);
+        if let Ok(mut sprite) = sprite_query.get_mut(entity_to_remove) {
+            sprite.color = CRASHED_COLOR;
         }
+        commands.entity(entity_to_remove).insert(Crashed);
+        return;
     }
 
-
     crash(&mut simulation_controller, id_to_remove, connected_to_removed);
 
     for (entity, mut node) in node_query.iter_mut() {
@@ -344,30 +414,69 @@ pub fn crash_target(
     }
 }
 
+pub fn restart_target(
+    trigger: Trigger<Pointer<Click>>,
+    active_mode: Res<ActiveMode>,
+    mut node_query: Query<(&ScNode, &mut Sprite), With<Crashed>>,
+    mut commands: Commands,
+    mut warn_query: Query<&mut Text, With<TextWarn>>,
+    mut simulation_controller: ResMut<SimulationController>,
+    state: Res<MainState>
+) {
+    if let MainState::Sim = *state {
+    if *active_mode != ActiveMode::Restart {
+        return;
+    }
+
+    let clicked_entity = trigger.target();
+
+    let Ok((node, mut sprite)) = node_query.get_mut(clicked_entity) else {
+        return;
+    };
+
+    if let Err(e) = check_restart(&simulation_controller, node.id) {
+        let mut warn_text = warn_query.single_mut().unwrap();
+        *warn_text = Text::from(e);
+        return;
+    }
+
+    restart_node(&mut simulation_controller, node.id);
+    sprite.color = Color::WHITE;
+    commands.entity(clicked_entity).remove::<Crashed>();
+    }
+}
+
 pub fn manage_highlight(
     trigger: Trigger<Pointer<Over>>,
     active_mode: Res<ActiveMode>,
-    mut sprite_query: Query<(&mut Sprite, &ScNode)>,
+    mut sprite_query: Query<(&mut Sprite, &ScNode, Has<Crashed>)>,
     state: Res<MainState>
 ) {
     if let MainState::Sim = *state {
-    let Ok((mut sprite, node)) = sprite_query.get_mut(trigger.target()) else {
+    let Ok((mut sprite, node, crashed)) = sprite_query.get_mut(trigger.target()) else {
         return;
     };
 
     match *active_mode {
         ActiveMode::Crash => {
-            if node.node_type == components::NodeType::Drone {
+            if !crashed {
                 sprite.color = Color::srgb(0.9, 0.0, 0.0);
             }
         }
+        ActiveMode::Restart => {
+            if crashed {
+                sprite.color = Color::srgb(0.0, 0.9, 0.9);
+            }
+        }
         ActiveMode::Connect | ActiveMode::Add => {
             sprite.color = Color::srgb(0.0, 0.9, 0.0);
         }
+        ActiveMode::Disconnect => {
+            sprite.color = Color::srgb(0.9, 0.5, 0.0);
+        }
         ActiveMode::Pdr => {
-            if node.node_type == components::NodeType::Drone {
-                sprite.color = Color::srgb(0.0, 0.0, 0.9);
-            }
+            // drones for their PDR, any node for the loss of one of its links
+            sprite.color = Color::srgb(0.0, 0.0, 0.9);
         }
         _ => {}
     }
@@ -376,9 +485,10 @@ pub fn manage_highlight(
 
 pub fn reset_highlight(
     trigger: Trigger<Pointer<Out>>,
-    mut sprite_query: Query<(&mut Sprite, &ScNode)>,
+    mut sprite_query: Query<(&mut Sprite, &ScNode, Has<Crashed>)>,
     active_mode: Res<ActiveMode>,
     connect_selected: Res<SelectedNode>,
+    link_end: Res<LinkEnd>,
     state: Res<MainState>
 ) {
     if let MainState::Sim = *state {
@@ -386,19 +496,25 @@ pub fn reset_highlight(
 
     let mut selected_id = None;
     if let Some(selected_entity) = connect_selected.0 {
-        if let Ok((_, node)) = sprite_query.get(selected_entity) {
+        if let Ok((_, node, _)) = sprite_query.get(selected_entity) {
             selected_id = Some(node.id);
         }
     }
 
-    if let Ok((mut sprite, exited_node)) = sprite_query.get_mut(exited_entity) {
+    if let Ok((mut sprite, exited_node, crashed)) = sprite_query.get_mut(exited_entity) {
         if Some(exited_node.id) == selected_id {
             if *active_mode == ActiveMode::Connect || *active_mode == ActiveMode::Add {
                 sprite.color = Color::srgb(0.0, 0.6, 0.0);
+            }else if *active_mode == ActiveMode::Disconnect {
+                sprite.color = Color::srgb(0.6, 0.3, 0.0);
             }else if *active_mode == ActiveMode::Pdr {
                 sprite.color = Color::srgb(0.0, 0.0, 0.6);
             }
 
+        } else if link_end.0 == Some(exited_entity) && *active_mode == ActiveMode::Pdr {
+            sprite.color = LINK_END_COLOR;
+        } else if crashed {
+            sprite.color = CRASHED_COLOR;
         } else {
             sprite.color = Color::WHITE;
         }
@@ -414,8 +530,14 @@ pub fn change_pdr(
     state: Res<MainState>
 ) {
     if let MainState::Sim = *state{
-    for ChangePdrEvent { entity, new_pdr } in reader.read() {
+    for ChangePdrEvent { entity, new_pdr, link_to } in reader.read() {
         if let Ok(mut node) = node_query.get_mut(*entity) {
+            if let Some(to) = link_to {
+                if let Err(e) = set_link_loss(&mut simulation_controller, node.id, *to, *new_pdr) {
+                    warn!("Can't set the loss of {} -> {}: {}", node.id, to, e);
+                }
+                continue;
+            }
             set_pdr(&mut simulation_controller, node.id, *new_pdr);
             node.pdr = *new_pdr;
 
@@ -433,7 +555,9 @@ pub fn change_pdr_target(
     trigger: Trigger<Pointer<Click>>,
     active_mode: Res<ActiveMode>,
     mut change_selected: ResMut<SelectedNode>,
-    mut nodes: Query<(Entity, &mut ScNode)>,
+    mut link_end: ResMut<LinkEnd>,
+    nodes: Query<(Entity, &ScNode)>,
+    mut sprite_query: Query<(&mut Sprite, Has<Crashed>)>,
     state: Res<MainState>,
 ) {
     if let MainState::Sim = *state {
@@ -443,14 +567,10 @@ pub fn change_pdr_target(
 
     let clicked_entity = trigger.target();
 
-    let Ok((clicked_entity, clicked_node)) = nodes.get_mut(clicked_entity) else {
+    let Ok((clicked_entity, clicked_node)) = nodes.get(clicked_entity) else {
         return;
     };
 
-    if clicked_node.node_type != components::NodeType::Drone {
-        return;
-    }
-
     if change_selected.0.is_none() {
         change_selected.0 = Some(clicked_entity);
         return;
@@ -458,10 +578,29 @@ pub fn change_pdr_target(
 
     let entity = change_selected.0.unwrap();
 
+    // clicking the link end again, or the selected node, deselects it
+    let previous = link_end.0.take();
+    if let Some((mut sprite, crashed)) = previous.and_then(|e| sprite_query.get_mut(e).ok()) {
+        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
+    }
     if entity == clicked_entity {
         change_selected.0 = None;
         return;
     }
+    if previous == Some(clicked_entity) {
+        return;
+    }
+
+    // a neighbour of the selected node picks the link towards it
+    let Ok((_, selected)) = nodes.get(entity) else {
+        return;
+    };
+    if selected.connected_node_ids.contains(&clicked_node.id) {
+        link_end.0 = Some(clicked_entity);
+        if let Ok((mut sprite, _)) = sprite_query.get_mut(clicked_entity) {
+            sprite.color = LINK_END_COLOR;
+        }
+    }
     }
 }
 
@@ -470,7 +609,7 @@ pub fn connect_nodes(
     active_mode: Res<ActiveMode>,
     mut connect_selected: ResMut<SelectedNode>,
     mut nodes: Query<(Entity, &mut ScNode)>,
-    mut sprite_query: Query<&mut Sprite>,
+    mut sprite_query: Query<(&mut Sprite, Has<Crashed>)>,
     mut warn_query: Query<&mut Text, With<TextWarn>>,
     mut writer: EventWriter<MakeLinesEvent>,
     mut simulation_controller: ResMut<SimulationController>,
@@ -517,30 +656,94 @@ pub fn connect_nodes(
     }
 
     if let (Some(mut first_node), Some(mut second_node)) = (first_node_opt, second_node_opt) {
-        if !first_node.connected_node_ids.contains(&second_node.id) && !second_node.connected_node_ids.contains(&first_node.id){
-            if first_node.node_type == components::NodeType::Drone || second_node.node_type == components::NodeType::Drone {
-
-                if (first_node.node_type == components::NodeType::Client && first_node.connected_node_ids.len() >= 2) ||
-                    (second_node.node_type == components::NodeType::Client && second_node.connected_node_ids.len() >= 2){
-                    *warn_text = Text::from("Client can have at most 2 drones connected");
-                    return;
-                }
-
-                add_sender(&mut simulation_controller, first_node.id, second_node.id);
-                add_sender(&mut simulation_controller, second_node.id, first_node.id);
+        match check_connect(&simulation_controller, first_node.id, second_node.id) {
+            Ok(_) => {
+                connect(&mut simulation_controller, first_node.id, second_node.id);
 
                 first_node.connected_node_ids.push(second_node.id);
                 second_node.connected_node_ids.push(first_node.id);
-            }else{
-                *warn_text = Text::from("Invalid connection");
             }
-        }else{
-            *warn_text = Text::from("Nodes already connected");
+            Err(e) => {
+                *warn_text = Text::from(e);
+            }
+        }
+    }
+    writer.write(MakeLinesEvent);
+    if let Ok((mut sprite, crashed)) = sprite_query.get_mut(first_entity) {
+        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
+    }
+
+    connect_selected.0 = None;
+    }
+}
+
+pub fn disconnect_nodes(
+    trigger: Trigger<Pointer<Click>>,
+    active_mode: Res<ActiveMode>,
+    mut connect_selected: ResMut<SelectedNode>,
+    mut nodes: Query<(Entity, &mut ScNode)>,
+    mut sprite_query: Query<(&mut Sprite, Has<Crashed>)>,
+    mut warn_query: Query<&mut Text, With<TextWarn>>,
+    mut writer: EventWriter<MakeLinesEvent>,
+    mut simulation_controller: ResMut<SimulationController>,
+    state: Res<MainState>,
+) {
+    if let MainState::Sim = *state {
+    if *active_mode != ActiveMode::Disconnect {
+        return;
+    }
+
+    let mut warn_text = warn_query.single_mut().unwrap();
+
+    let clicked_entity = trigger.target();
+
+    let Ok((clicked_entity, _)) = nodes.get_mut(clicked_entity) else {
+        return;
+    };
+
+    if connect_selected.0.is_none() {
+        connect_selected.0 = Some(clicked_entity);
+        return;
+    }
+
+    let first_entity = connect_selected.0.unwrap();
+
+    if first_entity == clicked_entity {
+        connect_selected.0 = None;
+        return;
+    }
+
+    let mut first_node_opt = None;
+    let mut second_node_opt = None;
+
+    for (entity, node) in &mut nodes {
+        if entity == first_entity {
+            first_node_opt = Some(node);
+        } else if entity == clicked_entity {
+            second_node_opt = Some(node);
+        }
+
+        if first_node_opt.is_some() && second_node_opt.is_some() {
+            break;
+        }
+    }
+
+    if let (Some(mut first_node), Some(mut second_node)) = (first_node_opt, second_node_opt) {
+        match check_disconnect(&simulation_controller, first_node.id, second_node.id) {
+            Ok(_) => {
+                disconnect(&mut simulation_controller, first_node.id, second_node.id);
+
+                first_node.connected_node_ids.retain(|&id| id != second_node.id);
+                second_node.connected_node_ids.retain(|&id| id != first_node.id);
+            }
+            Err(e) => {
+                *warn_text = Text::from(e);
+            }
         }
     }
     writer.write(MakeLinesEvent);
-    if let Ok(mut sprite) = sprite_query.get_mut(first_entity) {
-        sprite.color = Color::WHITE;
+    if let Ok((mut sprite, crashed)) = sprite_query.get_mut(first_entity) {
+        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
     }
 
     connect_selected.0 = None;
@@ -558,7 +761,9 @@ pub fn button_system(
             (ActiveMode::Add, Some(ButtonLabel::Add)) => true,
             (ActiveMode::Crash, Some(ButtonLabel::Crash)) => true,
             (ActiveMode::Connect, Some(ButtonLabel::Connect)) => true,
+            (ActiveMode::Disconnect, Some(ButtonLabel::Disconnect)) => true,
             (ActiveMode::Pdr, Some(ButtonLabel::Pdr)) => true,
+            (ActiveMode::Restart, Some(ButtonLabel::Restart)) => true,
             _ => false,
         };
 
@@ -584,11 +789,17 @@ pub fn button_action(
     mut warn_query: Query<&mut Text, (With<TextWarn>, Without<TextboxTopText>)>,
     interaction_query: Query<(&Interaction, Option<&ButtonLabel>), (Changed<Interaction>, With<Button>)>,
     mut text_query: Query<(&mut TextInputValue, &mut TextInputInactive), With<DroneIdInput>>,
-    mut node_query: Query<(&ScNode, &mut Sprite)>,
+    mut node_query: Query<(&ScNode, &mut Sprite, Has<Crashed>)>,
     mut spawn_writer: EventWriter<SpawnDroneEvent>,
     mut pdr_writer: EventWriter<ChangePdrEvent>,
     mut selected_node: ResMut<SelectedNode>,
     mut active_mode: ResMut<ActiveMode>,
+    (replay, editor, layout, mut link_end): (
+        Option<Res<ReplayState>>,
+        Option<Res<EditorState>>,
+        Res<NodeLayout>,
+        ResMut<LinkEnd>,
+    ),
     state: Res<MainState>,
 ) {
     if let MainState::Sim = *state {
@@ -603,6 +814,23 @@ pub fn button_action(
         let mut textbox_top_text = textbox_top_query.single_mut().unwrap();
         let mut warn_text = warn_query.single_mut().unwrap();
 
+        // nothing runs behind a replay, the topology only follows the trace
+        if replay.is_some() {
+            *warn_text = Text::from("Commands are disabled during a replay");
+            continue;
+        }
+        if editor.is_some() {
+            *warn_text = Text::from("Use the editor window to change the topology");
+            continue;
+        }
+        // any button ends the link selection, Done uses it first
+        let link_to = link_end.0.take();
+        if let Some(entity) = link_to {
+            if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
+            }
+        }
+
         match label {
             Some(ButtonLabel::Add) => {
                 *warn_text = Text::from("");
@@ -613,8 +841,8 @@ pub fn button_action(
                 *textbox_top_text = Text::from("Drone ID");
 
                 if let Some(entity) = selected_node.0 {
-                    if let Ok((_, mut sprite)) = node_query.get_mut(entity) {
-                        sprite.color = Color::WHITE;
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
                     }
                     selected_node.0 = None;
                 }
@@ -632,8 +860,8 @@ pub fn button_action(
                 *confirm_visibility = Visibility::Visible;
 
                 if let Some(entity) = selected_node.0 {
-                    if let Ok((_, mut sprite)) = node_query.get_mut(entity) {
-                        sprite.color = Color::WHITE;
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
                     }
                     selected_node.0 = None;
                 }
@@ -651,8 +879,46 @@ pub fn button_action(
                 *confirm_visibility = Visibility::Visible;
 
                 if let Some(entity) = selected_node.0 {
-                    if let Ok((_, mut sprite)) = node_query.get_mut(entity) {
-                        sprite.color = Color::WHITE;
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
+                    }
+                    selected_node.0 = None;
+                }
+
+                if let Ok((mut value, mut inactive)) = text_query.single_mut() {
+                    value.0.clear();
+                    inactive.0 = true;
+                }
+            }
+            Some(ButtonLabel::Disconnect) => {
+                *warn_text = Text::from("");
+                *active_mode = ActiveMode::Disconnect;
+                *drone_add_visibility = Visibility::Hidden;
+                *textbox_visibility = Visibility::Hidden;
+                *confirm_visibility = Visibility::Visible;
+
+                if let Some(entity) = selected_node.0 {
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
+                    }
+                    selected_node.0 = None;
+                }
+
+                if let Ok((mut value, mut inactive)) = text_query.single_mut() {
+                    value.0.clear();
+                    inactive.0 = true;
+                }
+            }
+            Some(ButtonLabel::Restart) => {
+                *warn_text = Text::from("");
+                *active_mode = ActiveMode::Restart;
+                *drone_add_visibility = Visibility::Hidden;
+                *textbox_visibility = Visibility::Hidden;
+                *confirm_visibility = Visibility::Visible;
+
+                if let Some(entity) = selected_node.0 {
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
                     }
                     selected_node.0 = None;
                 }
@@ -668,11 +934,11 @@ pub fn button_action(
                 *drone_add_visibility = Visibility::Hidden;
                 *textbox_visibility = Visibility::Visible;
                 *confirm_visibility = Visibility::Visible;
-                *textbox_top_text = Text::from("Set PDR");
+                *textbox_top_text = Text::from("Set PDR (or link loss)");
 
                 if let Some(entity) = selected_node.0 {
-                    if let Ok((_, mut sprite)) = node_query.get_mut(entity) {
-                        sprite.color = Color::WHITE;
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
                     }
                     selected_node.0 = None;
                 }
@@ -682,6 +948,24 @@ pub fn button_action(
                     inactive.0 = false;
                 }
             }
+            Some(ButtonLabel::Save) => {
+                // what is on screen is what gets saved
+                let nodes = node_query
+                    .iter()
+                    .map(|(node, _, crashed)| {
+                        (node.id, NodeInfo {
+                            node_type: node.node_type,
+                            connected_node_ids: node.connected_node_ids.clone(),
+                            pdr: node.pdr,
+                            crashed,
+                        })
+                    })
+                    .collect();
+                *warn_text = match save_topology(&nodes, &layout.positions) {
+                    Ok(path) => Text::from(format!("Saved to {}", path.to_string_lossy())),
+                    Err(e) => Text::from(format!("Can't save: {}", e)),
+                };
+            }
             Some(ButtonLabel::Done) => {
                 let mut warn = false;
                 //if matches!(*drone_add_visibility, Visibility::Visible) {
@@ -689,7 +973,7 @@ pub fn button_action(
                     if *active_mode == ActiveMode::Add {
                         if let Some(entity) = selected_node.0 {
                             if let Ok(id) = value.0.parse::<u8>() {
-                                if !node_query.iter().any(|(node, _)| node.id == id) {
+                                if !node_query.iter().any(|(node, _, _)| node.id == id) {
                                     //info!("Drone ID: {}", value.0);
                                     spawn_writer.write(SpawnDroneEvent { id, connect_to: entity });
                                 } else {
@@ -707,14 +991,26 @@ pub fn button_action(
                             *warn_text = Text::from("Drone must be connected to at least 1 other node");
                         }
                     } else if *active_mode == ActiveMode::Pdr {
-                        if let Some(entity) = selected_node.0 {
-                            if let Ok((node, _)) = node_query.get(entity) {
+                        let link_to = link_to.and_then(|end| node_query.get(end).ok()).map(|(node, _, _)| node.id);
+                        if let (Some(entity), Some(to)) = (selected_node.0, link_to) {
+                            match value.0.parse::<f32>() {
+                                Ok(loss) if (0.0..=1.0).contains(&loss) => {
+                                    pdr_writer.write(ChangePdrEvent { entity, new_pdr: loss, link_to: Some(to) });
+                                }
+                                _ => {
+                                    warn = true;
+                                    *warn_text = Text::from(format!("Invalid loss value: '{}' (must be between 0.0 and 1.0)", value.0));
+                                }
+                            }
+                        } else if let Some(entity) = selected_node.0 {
+                            if let Ok((node, _, _)) = node_query.get(entity) {
                                 if node.node_type == components::NodeType::Drone {
                                     if let Ok(pdr_val) = value.0.parse::<f32>()  {
                                         if pdr_val >= 0.0 && pdr_val <= 1.0 {
                                             pdr_writer.write(ChangePdrEvent {
                                                 entity,
                                                 new_pdr: pdr_val,
+                                                link_to: None,
                                             });
                                         }else{
                                             warn = true;
@@ -728,7 +1024,8 @@ pub fn button_action(
                                     }
                                     //println!("{}", value.0);
                                 } else {
-                                    warn!("Not a Drone");
+                                    warn = true;
+                                    *warn_text = Text::from("Only drones have a PDR, click one of its drones for the loss of that link");
                                 }
                             } else {
                                 warn!("Entity Deleted");
@@ -752,8 +1049,8 @@ pub fn button_action(
                 *active_mode = ActiveMode::None;
 
                 if let Some(entity) = selected_node.0 {
-                    if let Ok((_, mut sprite)) = node_query.get_mut(entity) {
-                        sprite.color = Color::WHITE;
+                    if let Ok((_, mut sprite, crashed)) = node_query.get_mut(entity) {
+                        sprite.color = if crashed { CRASHED_COLOR } else { Color::WHITE };
                     }
                     selected_node.0 = None;
                 }
@@ -784,13 +1081,13 @@ pub fn update_selector(
                 SelectorDirection::Left if selector.index > 0 => {
                     selector.index -= 1;
                 }
-                SelectorDirection::Right if selector.index + 1 < DRONE_NAMES.len() => {
+                SelectorDirection::Right if selector.index < SELECTOR_SERVER => {
                     selector.index += 1;
                 }
                 _ => {}
             }
 
-            *display_text = Text::new(DRONE_NAMES[selector.index]);
+            *display_text = Text::new(selector_label(selector.index));
         }
     }
     }
@@ -800,22 +1097,35 @@ pub fn crossbeam_listener(
     mut create_writer: EventWriter<PacketCreateEvent>,
     mut add_writer: EventWriter<PacketAddHopEvent>,
     mut simulation_controller: ResMut<SimulationController>,
+    mut stats: ResMut<NetworkStats>,
+    mut log: ResMut<PacketLog>,
+    mut heatmap: ResMut<Heatmap>,
+    time: Res<Time>,
     state: Res<MainState>
 ){
     if let MainState::Sim = *state {
+    let now = time.elapsed_secs_f64();
     while let Ok(event) = simulation_controller.receiver_client_server_event.try_recv(){
         //println!("client or server sent a packet");
+        stats.record_node_event(&event, now);
+        log.record_node_event(&event, now);
+        simulation_controller.record_node_event(&event);
         create_writer.write(PacketCreateEvent::NodeEvent(event));
 
     }
     while let Ok(event) = simulation_controller.receiver_drone_event.try_recv() {
         //println!("sent addhop event: {:?}",event);
+        stats.record_drone_event(&event, now);
+        log.record_drone_event(&event, now);
+        heatmap.record_drone_event(&event, now);
+        simulation_controller.record_drone_event(&event);
         if let DroneEvent::ControllerShortcut(ref packet) = event {
             shortcut(&mut simulation_controller, packet.clone());
         }
         create_writer.write(PacketCreateEvent::DroneEvent(event.clone()));
         add_writer.write(PacketAddHopEvent{ drone_event: event});
     }
+    simulation_controller.record_topology();
     }
 }
 
@@ -825,16 +1135,33 @@ pub fn packet_spawn(
     //mut packet_query: Query<(&mut Sprite, &mut HopQueue, &mut PacketInfo)>,
     node_query: Query<(Entity, &ScNode, &mut Transform)>,
     mut reader: EventReader<PacketCreateEvent>,
+    log: Res<PacketLog>,
+    sprites: Query<(), With<PacketMotion>>,
+    replay: Option<Res<ReplayState>>,
     state: Res<MainState>,
 ){
     if let MainState::Sim = *state {
+    // real drones can send far more packets than can be drawn, a replay only has what was recorded
+    let mut budget = match replay {
+        Some(_) => usize::MAX,
+        None => MAX_LIVE_PACKETS.saturating_sub(sprites.iter().count()),
+    };
     for create_event in reader.read() {
         //let mut spawned: Vec<(u64,Vec<NodeId>)> = Vec::new();
         //let NodeEvent::PacketSent(packet) = &create_event.node_event;
         match create_event {
             PacketCreateEvent::NodeEvent(NodeEvent::PacketSent(packet)) => {
-                let start_id = packet.routing_header.hops[packet.routing_header.hop_index - 1];
-                let end_id = packet.routing_header.hops[packet.routing_header.hop_index];
+                // flood requests have no route to follow
+                let hops = &packet.routing_header.hops;
+                let (Some(&start_id), Some(&end_id)) = (
+                    packet.routing_header.hop_index.checked_sub(1).and_then(|i| hops.get(i)),
+                    hops.get(packet.routing_header.hop_index),
+                ) else {
+                    continue;
+                };
+                if budget == 0 {
+                    continue;
+                }
 
                 if let Some((_, _, start_transform)) = node_query.iter().find(|(_, node, _)| node.id == start_id) {
                     if let Some((_, _, end_transform)) = node_query.iter().find(|(_, node, _)| node.id == end_id) {
@@ -850,9 +1177,12 @@ pub fn packet_spawn(
                                 session_id: packet.session_id.clone(),
                                 hops: packet.routing_header.hops.clone(),
                                 last_hop_index: 1,
+                                log_id: log.last_matching(packet.session_id, &packet.routing_header.hops),
                             },
-                            HopQueue(VecDeque::from([(start_id, Some(end_id))]))
+                            HopQueue(VecDeque::from([(start_id, Some(end_id))])),
+                            Pickable::default(),
                         ));
+                        budget -= 1;
                     }
                 }
             }
@@ -1013,9 +1343,16 @@ pub fn packet_move(
     mut commands: Commands,
     node_query: Query<(&ScNode, &Transform), Without<PacketMotion>>,
     mut packet_query: Query<(Entity, &mut Transform, &mut Sprite, &mut PacketMotion, &mut HopQueue), Without<ScNode>>,
+    simulation_controller: Res<SimulationController>,
+    replay: Option<Res<ReplayState>>,
     state: Res<MainState>,
 ) {
     if let MainState::Sim = *state {
+    // packets stay where they are while the network or the replay is paused
+    let paused = simulation_controller.links().is_some_and(|links| links.gate().is_paused());
+    if paused || replay.is_some_and(|replay| replay.paused) {
+        return;
+    }
     for (entity, mut transform, mut sprite, mut motion, mut queue) in &mut packet_query {
         motion.progress += PACKET_SPEED * time.delta_secs() / motion.start.distance(motion.end);
         motion.progress = motion.progress.clamp(0.0, 1.0);
@@ -1111,3 +1448,176 @@ pub fn update_packet_ends(
 
 
  */
+
+pub fn run_scenario(
+    runner: Option<ResMut<ScenarioRunner>>,
+    mut simulation_controller: ResMut<SimulationController>,
+    mut topology_writer: EventWriter<TopologyChangedEvent>,
+    state: Res<MainState>,
+) {
+    let MainState::Sim = *state else {
+        return;
+    };
+    let Some(mut runner) = runner else {
+        return;
+    };
+    if runner.is_done() {
+        return;
+    }
+    let due = runner.due();
+    if due.is_empty() {
+        return;
+    }
+    for (t, action) in due {
+        let result = scenario::apply(&mut simulation_controller, &action);
+        scenario::log_action(t, &action, &result);
+    }
+    topology_writer.write(TopologyChangedEvent);
+}
+
+pub fn sync_topology(
+    mut reader: EventReader<TopologyChangedEvent>,
+    simulation_controller: Res<SimulationController>,
+    mut node_query: Query<(Entity, &mut ScNode, &mut Sprite, Has<Crashed>)>,
+    mut text_query: Query<(&ChildOf, &mut Text2d), With<DroneText>>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut nodes_writer: EventWriter<UpdateNodesEvent>,
+    mut lines_writer: EventWriter<MakeLinesEvent>,
+    mut moved_writer: EventWriter<NodeMovedEvent>,
+) {
+    if reader.read().count() == 0 {
+        return;
+    }
+
+    // the controller's topology is the reference, bring the sprites in line with it
+    let nodes = simulation_controller.nodes();
+    let mut shown = Vec::new();
+    for (entity, mut node, mut sprite, crashed) in node_query.iter_mut() {
+        match nodes.get(&node.id) {
+            Some(info) => {
+                node.connected_node_ids = info.connected_node_ids.clone();
+                if info.crashed && !crashed {
+                    sprite.color = CRASHED_COLOR;
+                    commands.entity(entity).insert(Crashed);
+                } else if !info.crashed && crashed {
+                    sprite.color = Color::WHITE;
+                    commands.entity(entity).remove::<Crashed>();
+                }
+                if node.pdr != info.pdr {
+                    node.pdr = info.pdr;
+                    for (child_of, mut text) in &mut text_query {
+                        if child_of.parent() == entity {
+                            text.0 = format!("id: {}  pdr: {:.1}", node.id, node.pdr);
+                        }
+                    }
+                }
+                shown.push(node.id);
+            }
+            None => {
+                moved_writer.write(NodeMovedEvent {
+                    node_id: node.id,
+                    new_position: None,
+                });
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+    for (id, info) in nodes.iter().filter(|(id, _)| !shown.contains(id)) {
+        let (image, label) = match info.node_type {
+            components::NodeType::Drone => ("controller/Drone.png", format!("id: {}  pdr: {:.1}", *id, info.pdr)),
+            components::NodeType::Client => ("controller/Client.png", format!("id: {}", *id)),
+            components::NodeType::Server => ("controller/Server.png", format!("id: {}", *id)),
+        };
+        let mut sprite = Sprite::from_image(asset_server.load(image));
+        if info.crashed {
+            sprite.color = CRASHED_COLOR;
+        }
+        let mut entity = commands.spawn((
+            ScNode {
+                id: *id,
+                connected_node_ids: info.connected_node_ids.clone(),
+                node_type: info.node_type,
+                pdr: info.pdr,
+            },
+            sprite,
+            Transform::from_xyz(0.0, 0.0, 1.0),
+            Pickable::default(),
+        ));
+        if info.crashed {
+            entity.insert(Crashed);
+        }
+        entity.with_children(|parent| {
+            let mut text = parent.spawn((
+                Text2d::new(label),
+                Transform::from_xyz(0.0, -50.0, 1.0),
+            ));
+            if info.node_type == components::NodeType::Drone {
+                text.insert(DroneText);
+            }
+        });
+    }
+
+    nodes_writer.write(UpdateNodesEvent);
+    lines_writer.write(MakeLinesEvent);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::links::LinkParams;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    // A live controller with emulated links and one packet on its way from (0,0) to (100,0)
+    fn world() -> (World, Entity) {
+        let (drone_event_send, drone_event_recv) = unbounded();
+        let (node_event_send, node_event_recv) = unbounded();
+        let links = Links::new(LinkParams::default(), HashMap::new(), node_event_send, drone_event_send.clone(), 0);
+        let controller = SimulationController::new(
+            HashMap::new(),
+            HashMap::new(),
+            drone_event_recv,
+            node_event_recv,
+            drone_event_send,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .with_links(links);
+        let mut world = World::new();
+        world.insert_resource(controller);
+        world.insert_resource(MainState::Sim);
+        world.insert_resource(Time::<()>::default());
+        let packet = world
+            .spawn((
+                Transform::default(),
+                Sprite::default(),
+                PacketMotion { start: Vec3::ZERO, end: Vec3::new(100.0, 0.0, 0.0), progress: 0.0 },
+                HopQueue(VecDeque::from([(1, Some(2))])),
+            ))
+            .id();
+        (world, packet)
+    }
+
+    // One 20ms frame of packet_move, returns how far the packet got along its link
+    fn frame(world: &mut World, packet: Entity) -> f32 {
+        world.resource_mut::<Time>().advance_by(Duration::from_millis(20));
+        world.run_system_once(packet_move).unwrap();
+        world.get::<PacketMotion>(packet).unwrap().progress
+    }
+
+    #[test]
+    fn test_paused_packets_stay() {
+        let (mut world, packet) = world();
+        pause_network(&mut world.resource_mut::<SimulationController>()).unwrap();
+        assert_eq!(frame(&mut world, packet), 0.0);
+        // a step lets one packet over the links, the gate stays paused and so do the sprites
+        step_network(&mut world.resource_mut::<SimulationController>()).unwrap();
+        assert_eq!(frame(&mut world, packet), 0.0);
+        resume_network(&mut world.resource_mut::<SimulationController>()).unwrap();
+        let progress = frame(&mut world, packet);
+        assert!(progress > 0.0 && progress < 1.0, "{}", progress);
+        let x = world.get::<Transform>(packet).unwrap().translation.x;
+        assert!((x - 100.0 * progress).abs() < 1e-3, "{} at {}", x, progress);
+    }
+}
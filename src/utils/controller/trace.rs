@@ -0,0 +1,546 @@
+// Trace of a run, one JSON object per line: every drone and node event, the controller actions
+// and the topology after each change. `--record <file>` writes it, `--replay <file>` plays it
+// back in the controller without starting any drone.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use wg_2024::config::{Client as ConfigClient, Drone as ConfigDrone, Server as ConfigServer};
+
+use super::super::controller::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceNodeType {
+    Drone,
+    Client,
+    Server,
+}
+
+impl From<components::NodeType> for TraceNodeType {
+    fn from(node_type: components::NodeType) -> Self {
+        match node_type {
+            components::NodeType::Drone => TraceNodeType::Drone,
+            components::NodeType::Client => TraceNodeType::Client,
+            components::NodeType::Server => TraceNodeType::Server,
+        }
+    }
+}
+
+impl From<TraceNodeType> for components::NodeType {
+    fn from(node_type: TraceNodeType) -> Self {
+        match node_type {
+            TraceNodeType::Drone => components::NodeType::Drone,
+            TraceNodeType::Client => components::NodeType::Client,
+            TraceNodeType::Server => components::NodeType::Server,
+        }
+    }
+}
+
+impl From<&wg_2024::packet::NodeType> for TraceNodeType {
+    fn from(node_type: &wg_2024::packet::NodeType) -> Self {
+        match node_type {
+            wg_2024::packet::NodeType::Drone => TraceNodeType::Drone,
+            wg_2024::packet::NodeType::Client => TraceNodeType::Client,
+            wg_2024::packet::NodeType::Server => TraceNodeType::Server,
+        }
+    }
+}
+
+impl From<TraceNodeType> for wg_2024::packet::NodeType {
+    fn from(node_type: TraceNodeType) -> Self {
+        match node_type {
+            TraceNodeType::Drone => wg_2024::packet::NodeType::Drone,
+            TraceNodeType::Client => wg_2024::packet::NodeType::Client,
+            TraceNodeType::Server => wg_2024::packet::NodeType::Server,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceNode {
+    pub id: NodeId,
+    pub node_type: TraceNodeType,
+    pub connected_node_ids: Vec<NodeId>,
+    pub pdr: f32,
+    #[serde(default)]
+    pub crashed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceNack {
+    ErrorInRouting(NodeId),
+    DestinationIsDrone,
+    Dropped,
+    UnexpectedRecipient(NodeId),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceBody {
+    MsgFragment { index: u64, total: u64, data: Vec<u8> },
+    Ack { index: u64 },
+    Nack { index: u64, nack: TraceNack },
+    FloodRequest { flood_id: u64, initiator_id: NodeId, path_trace: Vec<(NodeId, TraceNodeType)> },
+    FloodResponse { flood_id: u64, path_trace: Vec<(NodeId, TraceNodeType)> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TracePacket {
+    pub session_id: u64,
+    pub hops: Vec<NodeId>,
+    pub hop_index: usize,
+    #[serde(flatten)]
+    pub body: TraceBody,
+}
+
+impl From<&Packet> for TracePacket {
+    fn from(packet: &Packet) -> Self {
+        let trace = |path: &Vec<(NodeId, wg_2024::packet::NodeType)>| {
+            path.iter().map(|(id, t)| (*id, t.into())).collect()
+        };
+        let body = match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => TraceBody::MsgFragment {
+                index: fragment.fragment_index,
+                total: fragment.total_n_fragments,
+                data: fragment.data[..fragment.length as usize].to_vec(),
+            },
+            PacketType::Ack(ack) => TraceBody::Ack { index: ack.fragment_index },
+            PacketType::Nack(nack) => TraceBody::Nack {
+                index: nack.fragment_index,
+                nack: match nack.nack_type {
+                    NackType::ErrorInRouting(id) => TraceNack::ErrorInRouting(id),
+                    NackType::DestinationIsDrone => TraceNack::DestinationIsDrone,
+                    NackType::Dropped => TraceNack::Dropped,
+                    NackType::UnexpectedRecipient(id) => TraceNack::UnexpectedRecipient(id),
+                },
+            },
+            PacketType::FloodRequest(request) => TraceBody::FloodRequest {
+                flood_id: request.flood_id,
+                initiator_id: request.initiator_id,
+                path_trace: trace(&request.path_trace),
+            },
+            PacketType::FloodResponse(response) => TraceBody::FloodResponse {
+                flood_id: response.flood_id,
+                path_trace: trace(&response.path_trace),
+            },
+        };
+        Self {
+            session_id: packet.session_id,
+            hops: packet.routing_header.hops.clone(),
+            hop_index: packet.routing_header.hop_index,
+            body,
+        }
+    }
+}
+
+impl From<&TracePacket> for Packet {
+    fn from(packet: &TracePacket) -> Self {
+        let trace = |path: &Vec<(NodeId, TraceNodeType)>| {
+            path.iter().map(|(id, t)| (*id, (*t).into())).collect()
+        };
+        let pack_type = match &packet.body {
+            TraceBody::MsgFragment { index, total, data } => {
+                let mut bytes = [0; 128];
+                let length = data.len().min(128);
+                bytes[..length].copy_from_slice(&data[..length]);
+                PacketType::MsgFragment(Fragment {
+                    fragment_index: *index,
+                    total_n_fragments: *total,
+                    length: length as u8,
+                    data: bytes,
+                })
+            }
+            TraceBody::Ack { index } => PacketType::Ack(Ack { fragment_index: *index }),
+            TraceBody::Nack { index, nack } => PacketType::Nack(Nack {
+                fragment_index: *index,
+                nack_type: match nack {
+                    TraceNack::ErrorInRouting(id) => NackType::ErrorInRouting(*id),
+                    TraceNack::DestinationIsDrone => NackType::DestinationIsDrone,
+                    TraceNack::Dropped => NackType::Dropped,
+                    TraceNack::UnexpectedRecipient(id) => NackType::UnexpectedRecipient(*id),
+                },
+            }),
+            TraceBody::FloodRequest { flood_id, initiator_id, path_trace } => {
+                PacketType::FloodRequest(FloodRequest {
+                    flood_id: *flood_id,
+                    initiator_id: *initiator_id,
+                    path_trace: trace(path_trace),
+                })
+            }
+            TraceBody::FloodResponse { flood_id, path_trace } => {
+                PacketType::FloodResponse(FloodResponse {
+                    flood_id: *flood_id,
+                    path_trace: trace(path_trace),
+                })
+            }
+        };
+        Packet {
+            routing_header: SourceRoutingHeader {
+                hop_index: packet.hop_index,
+                hops: packet.hops.clone(),
+            },
+            session_id: packet.session_id,
+            pack_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceEvent {
+    PacketSent,
+    PacketDropped,
+    ControllerShortcut,
+}
+
+// The node events that carry no packet, field for field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "report", rename_all = "snake_case")]
+pub enum TraceReport {
+    MessageDelivered { node: NodeId, from: NodeId, session_id: u64, kind: String, size: usize },
+    RequestServed { node: NodeId, client: NodeId, session_id: u64, request: String },
+    Rerouted { node: NodeId, session_id: u64, old_path: Vec<NodeId>, new_path: Vec<NodeId> },
+    TopologyUpdated { node: NodeId, nodes: usize, edges: usize },
+    ReassemblyFailed { node: NodeId, from: NodeId, session_id: u64 },
+    RetryExhausted { node: NodeId, session_id: u64, fragment_index: u64, attempts: u32 },
+    SessionsEvicted { node: NodeId, evicted: EvictionStats },
+    LinkDropped { from: NodeId, to: NodeId, session_id: u64, reason: LinkDrop },
+}
+
+impl TraceReport {
+    fn of(event: &NodeEvent) -> Option<Self> {
+        Some(match event {
+            NodeEvent::MessageDelivered { node, from, session_id, kind, size } => TraceReport::MessageDelivered {
+                node: *node,
+                from: *from,
+                session_id: *session_id,
+                kind: kind.clone(),
+                size: *size,
+            },
+            NodeEvent::RequestServed { node, client, session_id, request } => TraceReport::RequestServed {
+                node: *node,
+                client: *client,
+                session_id: *session_id,
+                request: request.clone(),
+            },
+            NodeEvent::Rerouted { node, session_id, old_path, new_path } => TraceReport::Rerouted {
+                node: *node,
+                session_id: *session_id,
+                old_path: old_path.clone(),
+                new_path: new_path.clone(),
+            },
+            NodeEvent::TopologyUpdated { node, nodes, edges } => {
+                TraceReport::TopologyUpdated { node: *node, nodes: *nodes, edges: *edges }
+            }
+            NodeEvent::ReassemblyFailed { node, from, session_id } => {
+                TraceReport::ReassemblyFailed { node: *node, from: *from, session_id: *session_id }
+            }
+            NodeEvent::RetryExhausted { node, session_id, fragment_index, attempts } => TraceReport::RetryExhausted {
+                node: *node,
+                session_id: *session_id,
+                fragment_index: *fragment_index,
+                attempts: *attempts,
+            },
+            NodeEvent::SessionsEvicted { node, evicted } => {
+                TraceReport::SessionsEvicted { node: *node, evicted: *evicted }
+            }
+            NodeEvent::LinkDropped { from, to, session_id, reason } => {
+                TraceReport::LinkDropped { from: *from, to: *to, session_id: *session_id, reason: *reason }
+            }
+            NodeEvent::PacketSent(_) | NodeEvent::ControllerShortcut(_) => return None,
+        })
+    }
+}
+
+impl From<&TraceReport> for NodeEvent {
+    fn from(report: &TraceReport) -> Self {
+        match report.clone() {
+            TraceReport::MessageDelivered { node, from, session_id, kind, size } => {
+                NodeEvent::MessageDelivered { node, from, session_id, kind, size }
+            }
+            TraceReport::RequestServed { node, client, session_id, request } => {
+                NodeEvent::RequestServed { node, client, session_id, request }
+            }
+            TraceReport::Rerouted { node, session_id, old_path, new_path } => {
+                NodeEvent::Rerouted { node, session_id, old_path, new_path }
+            }
+            TraceReport::TopologyUpdated { node, nodes, edges } => NodeEvent::TopologyUpdated { node, nodes, edges },
+            TraceReport::ReassemblyFailed { node, from, session_id } => {
+                NodeEvent::ReassemblyFailed { node, from, session_id }
+            }
+            TraceReport::RetryExhausted { node, session_id, fragment_index, attempts } => {
+                NodeEvent::RetryExhausted { node, session_id, fragment_index, attempts }
+            }
+            TraceReport::SessionsEvicted { node, evicted } => NodeEvent::SessionsEvicted { node, evicted },
+            TraceReport::LinkDropped { from, to, session_id, reason } => {
+                NodeEvent::LinkDropped { from, to, session_id, reason }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum TraceRecord {
+    Topology { t: f64, nodes: Vec<TraceNode> },
+    Action { t: f64, action: String },
+    DroneEvent { t: f64, event: TraceEvent, packet: TracePacket },
+    NodeEvent { t: f64, event: TraceEvent, packet: TracePacket },
+    NodeReport { t: f64, report: TraceReport },
+}
+
+impl TraceRecord {
+    pub fn t(&self) -> f64 {
+        match self {
+            TraceRecord::Topology { t, .. }
+            | TraceRecord::Action { t, .. }
+            | TraceRecord::DroneEvent { t, .. }
+            | TraceRecord::NodeEvent { t, .. }
+            | TraceRecord::NodeReport { t, .. } => *t,
+        }
+    }
+
+    pub fn drone_event(t: f64, event: &DroneEvent) -> Self {
+        let (event, packet) = match event {
+            DroneEvent::PacketSent(packet) => (TraceEvent::PacketSent, packet),
+            DroneEvent::PacketDropped(packet) => (TraceEvent::PacketDropped, packet),
+            DroneEvent::ControllerShortcut(packet) => (TraceEvent::ControllerShortcut, packet),
+        };
+        TraceRecord::DroneEvent { t, event, packet: packet.into() }
+    }
+
+    pub fn node_event(t: f64, event: &NodeEvent) -> Self {
+        let (event, packet) = match event {
+            NodeEvent::PacketSent(packet) => (TraceEvent::PacketSent, packet),
+            NodeEvent::ControllerShortcut(packet) => (TraceEvent::ControllerShortcut, packet),
+            _ => {
+                let report = TraceReport::of(event).expect("every other node event is a report");
+                return TraceRecord::NodeReport { t, report };
+            }
+        };
+        TraceRecord::NodeEvent { t, event, packet: packet.into() }
+    }
+}
+
+pub fn to_drone_event(event: TraceEvent, packet: &TracePacket) -> DroneEvent {
+    match event {
+        TraceEvent::PacketSent => DroneEvent::PacketSent(packet.into()),
+        TraceEvent::PacketDropped => DroneEvent::PacketDropped(packet.into()),
+        TraceEvent::ControllerShortcut => DroneEvent::ControllerShortcut(packet.into()),
+    }
+}
+
+// Nodes never report drops, so there is nothing to rebuild for those
+pub fn to_node_event(event: TraceEvent, packet: &TracePacket) -> Option<NodeEvent> {
+    match event {
+        TraceEvent::PacketSent => Some(NodeEvent::PacketSent(packet.into())),
+        TraceEvent::ControllerShortcut => Some(NodeEvent::ControllerShortcut(packet.into())),
+        TraceEvent::PacketDropped => None,
+    }
+}
+
+pub fn snapshot(nodes: &HashMap<NodeId, NodeInfo>) -> Vec<TraceNode> {
+    let mut snapshot = nodes
+        .iter()
+        .map(|(id, info)| TraceNode {
+            id: *id,
+            node_type: info.node_type.into(),
+            connected_node_ids: info.connected_node_ids.clone(),
+            pdr: info.pdr,
+            crashed: info.crashed,
+        })
+        .collect::<Vec<_>>();
+    snapshot.sort_by_key(|node| node.id);
+    snapshot
+}
+
+pub fn nodes_of(snapshot: &[TraceNode]) -> HashMap<NodeId, NodeInfo> {
+    snapshot
+        .iter()
+        .map(|node| {
+            (node.id, NodeInfo {
+                node_type: node.node_type.into(),
+                connected_node_ids: node.connected_node_ids.clone(),
+                pdr: node.pdr,
+                crashed: node.crashed,
+            })
+        })
+        .collect()
+}
+
+// The topology a replay starts from, laid out like a config file
+pub fn config_of(snapshot: &[TraceNode]) -> Config {
+    let mut config = Config {
+        drone: Vec::new(),
+        client: Vec::new(),
+        server: Vec::new(),
+    };
+    for node in snapshot {
+        match node.node_type {
+            TraceNodeType::Drone => config.drone.push(ConfigDrone {
+                id: node.id,
+                connected_node_ids: node.connected_node_ids.clone(),
+                pdr: node.pdr,
+            }),
+            TraceNodeType::Client => config.client.push(ConfigClient {
+                id: node.id,
+                connected_drone_ids: node.connected_node_ids.clone(),
+            }),
+            TraceNodeType::Server => config.server.push(ConfigServer {
+                id: node.id,
+                connected_drone_ids: node.connected_node_ids.clone(),
+            }),
+        }
+    }
+    config
+}
+
+pub fn read_trace(path: &Path) -> Result<Vec<TraceRecord>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).map_err(|e| format!("line {}: {}", n + 1, e))?);
+    }
+    records.sort_by(|a: &TraceRecord, b| a.t().total_cmp(&b.t()));
+    Ok(records)
+}
+
+// Appends records to the trace file, the clock starts when it's created
+pub struct TraceRecorder {
+    out: BufWriter<File>,
+    start: Instant,
+    topology: Vec<TraceNode>, // last one written, only changes are recorded
+}
+
+impl TraceRecorder {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Can't create {}: {}", path.to_string_lossy(), e))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            start: Instant::now(),
+            topology: Vec::new(),
+        })
+    }
+
+    pub fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        let written = serde_json::to_writer(&mut self.out, record)
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.out.write_all(b"\n").map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            warn!("Couldn't write the trace: {}", e);
+        }
+    }
+
+    pub fn drone_event(&mut self, event: &DroneEvent) {
+        self.write(&TraceRecord::drone_event(self.now(), event));
+    }
+
+    pub fn node_event(&mut self, event: &NodeEvent) {
+        self.write(&TraceRecord::node_event(self.now(), event));
+    }
+
+    pub fn action(&mut self, action: String) {
+        self.write(&TraceRecord::Action { t: self.now(), action });
+    }
+
+    // Writes the topology if it changed and flushes, called once per frame
+    pub fn topology(&mut self, nodes: &HashMap<NodeId, NodeInfo>) {
+        let snapshot = snapshot(nodes);
+        if snapshot != self.topology {
+            self.write(&TraceRecord::Topology { t: self.now(), nodes: snapshot.clone() });
+            self.topology = snapshot;
+        }
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip() {
+        let packet = |hops: Vec<NodeId>, hop_index, pack_type| Packet {
+            routing_header: SourceRoutingHeader { hop_index, hops },
+            session_id: 3,
+            pack_type,
+        };
+        let packets = [
+            packet(vec![21, 1, 31], 1, PacketType::MsgFragment(Fragment::new(2, 4, [7; 128]))),
+            packet(
+                vec![1, 21],
+                1,
+                PacketType::Nack(Nack { fragment_index: 2, nack_type: NackType::ErrorInRouting(2) }),
+            ),
+            packet(
+                vec![],
+                0,
+                PacketType::FloodRequest(FloodRequest {
+                    flood_id: 5,
+                    initiator_id: 21,
+                    path_trace: vec![(21, wg_2024::packet::NodeType::Client), (1, wg_2024::packet::NodeType::Drone)],
+                }),
+            ),
+        ];
+        for packet in packets {
+            let record = TraceRecord::drone_event(1.5, &DroneEvent::PacketSent(packet.clone()));
+            let line = serde_json::to_string(&record).unwrap();
+            let TraceRecord::DroneEvent { t, event, packet: traced } = serde_json::from_str(&line).unwrap() else {
+                panic!("not a drone event: {}", line);
+            };
+            assert_eq!(t, 1.5);
+            assert_eq!(event, TraceEvent::PacketSent);
+            assert_eq!(format!("{:?}", Packet::from(&traced)), format!("{:?}", packet));
+        }
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let event = NodeEvent::Rerouted { node: 21, session_id: 3, old_path: vec![21, 1, 31], new_path: vec![21, 2, 31] };
+        let line = serde_json::to_string(&TraceRecord::node_event(2.0, &event)).unwrap();
+        let TraceRecord::NodeReport { t, report } = serde_json::from_str(&line).unwrap() else {
+            panic!("not a node report: {}", line);
+        };
+        assert_eq!(t, 2.0);
+        assert_eq!(format!("{:?}", NodeEvent::from(&report)), format!("{:?}", event));
+    }
+
+    #[test]
+    fn test_snapshot_and_config() {
+        let config: Config =
+            toml::from_str(&fs::read_to_string("./configs/config.toml").unwrap()).unwrap();
+        let (drone_event_send, drone_event_recv) = unbounded();
+        let (_, node_event_recv) = unbounded();
+        let controller = SimulationController::new(
+            HashMap::new(),
+            HashMap::new(),
+            drone_event_recv,
+            node_event_recv,
+            drone_event_send,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .with_topology(&config);
+        let snapshot = snapshot(controller.nodes());
+        assert_eq!(nodes_of(&snapshot), *controller.nodes());
+        let rebuilt = config_of(&snapshot);
+        assert_eq!(rebuilt.drone.len(), config.drone.len());
+        assert_eq!(rebuilt.client.len(), config.client.len());
+        assert_eq!(rebuilt.server.len(), config.server.len());
+    }
+}
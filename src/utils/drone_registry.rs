@@ -0,0 +1,135 @@
+// Every drone implementation the simulation can run, in one place.
+// The initializer and the controller's spawn both go through here, so an index (or a name)
+// always means the same crate.
+
+use std::collections::HashMap;
+
+use bevy::log::{info, warn};
+use crossbeam_channel::{Receiver, Sender};
+use wg_2024::{
+    controller::{DroneCommand, DroneEvent},
+    drone::Drone,
+    network::NodeId,
+    packet::Packet,
+};
+
+use super::mock_drone::{MockDrone, MockPolicy};
+
+pub const DRONE_NAMES: [&str; 11] = [
+    "BagelBomber",
+    "BetterCallDrone",
+    "RustRoveri",
+    "GetDroned",
+    "CppEnjoyers",
+    "D.R.O.N.E",
+    "NullPointer",
+    "Rustafarian",
+    "DrOnes",
+    "Rusteze",
+    "Mock",
+];
+
+// The in-repo drone of mock_drone.rs, mixed runs only pick among the crates before it
+pub const MOCK: usize = 10;
+
+// Same order as DRONE_NAMES
+const RUNNERS: [fn(DroneArgs); 11] = [
+    run_drone::<bagel_bomber::BagelBomber>,
+    run_drone::<drone_bettercalldrone::BetterCallDrone>,
+    run_drone::<rust_roveri::drone::RustRoveri>,
+    run_drone::<getdroned::GetDroned>,
+    run_drone::<ap2024_unitn_cppenjoyers_drone::CppEnjoyersDrone>,
+    run_drone::<d_r_o_n_e_drone::MyDrone>,
+    run_drone::<null_pointer_drone::MyDrone>,
+    run_drone::<rustafarian_drone::RustafarianDrone>,
+    run_drone::<dr_ones::Drone>,
+    run_drone::<rusteze_drone::RustezeDrone>,
+    run_mock,
+];
+
+// What `Drone::new` needs
+pub struct DroneArgs {
+    pub id: NodeId,
+    pub controller_send: Sender<DroneEvent>,
+    pub controller_recv: Receiver<DroneCommand>,
+    pub packet_recv: Receiver<Packet>,
+    pub packet_send: HashMap<NodeId, Sender<Packet>>,
+    pub pdr: f32,
+    pub policy: MockPolicy, // only read by the mock drone
+    pub seed: u64,          // same, the crates roll their PDR on their own
+}
+
+fn run_drone<D: Drone>(args: DroneArgs) {
+    let mut drone = D::new(
+        args.id,
+        args.controller_send,
+        args.controller_recv,
+        args.packet_recv,
+        args.packet_send,
+        args.pdr,
+    );
+    drone.run();
+}
+
+fn run_mock(args: DroneArgs) {
+    let mut drone = MockDrone::new(
+        args.id,
+        args.controller_send,
+        args.controller_recv,
+        args.packet_recv,
+        args.packet_send,
+        args.pdr,
+    )
+    .with_seed(args.seed)
+    .with_policy(args.policy);
+    drone.run();
+}
+
+// Index of a drone implementation, by name (case insensitive) or by its number in DRONE_NAMES
+pub fn index_of(s: &str) -> Option<usize> {
+    if let Ok(n) = s.parse::<usize>() {
+        return (n < DRONE_NAMES.len()).then_some(n);
+    }
+    DRONE_NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(s))
+}
+
+pub fn name_of(index: usize) -> Option<&'static str> {
+    DRONE_NAMES.get(index).copied()
+}
+
+// Same as `run` without the banner, for the conformance checks that start hundreds of drones
+pub fn runner(index: usize) -> Option<fn(DroneArgs)> {
+    RUNNERS.get(index).copied()
+}
+
+// Blocks until the drone stops, meant to be called inside its own thread
+pub fn run(index: usize, args: DroneArgs) {
+    match runner(index) {
+        Some(runner) => {
+            info!("{} Id[{}]", DRONE_NAMES[index], args.id);
+            runner(args);
+        }
+        None => warn!("No drone implementation at index {}", index),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_of() {
+        assert_eq!(index_of("bagelbomber"), Some(0));
+        assert_eq!(index_of("NullPointer"), Some(6));
+        assert_eq!(index_of("9"), Some(9));
+        assert_eq!(index_of("mock"), Some(MOCK));
+        assert_eq!(index_of("11"), None);
+        assert_eq!(index_of("Parrot"), None);
+        for (i, name) in DRONE_NAMES.iter().enumerate() {
+            assert_eq!(index_of(name), Some(i));
+            assert_eq!(name_of(i), Some(*name));
+        }
+    }
+}
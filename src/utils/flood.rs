@@ -0,0 +1,215 @@
+// When a client or server floods the network. Floods are asked for by what can change the
+// topology (startup, a neighbour added or removed, an ErrorInRouting nack, no route to a
+// destination) and otherwise spaced out more and more while the floods find nothing new.
+// The scheduler never reads the clock itself, the node passes `Instant::now()` in.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+// [simulation.flood] for every client and server, `flood = { ... }` in a [[client]] or
+// [[server]] for a single one; missing fields keep the defaults
+// min_gap_ms = 500   (floods asked for closer than this are merged into one)
+// base_ms = 2000     (interval after a flood that changed the topology)
+// max_ms = 60000     (interval reached while nothing changes)
+// factor = 2         (how the interval grows after each flood that changed nothing)
+// The old fixed timer is base_ms = max_ms = 5000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FloodPolicy {
+    pub min_gap_ms: u64,
+    pub base_ms: u64,
+    pub max_ms: u64,
+    pub factor: u32,
+}
+
+impl Default for FloodPolicy {
+    fn default() -> Self {
+        Self {
+            min_gap_ms: 500,
+            base_ms: 2_000,
+            max_ms: 60_000,
+            factor: 2,
+        }
+    }
+}
+
+impl FloodPolicy {
+    pub fn check(&self) -> Result<(), String> {
+        if self.base_ms == 0 || self.max_ms < self.base_ms || self.factor == 0 {
+            return Err(format!(
+                "Invalid flood policy {:?}, needs 0 < base_ms <= max_ms and factor >= 1",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodReason {
+    Periodic,
+    Startup,
+    NeighbourChanged,
+    ErrorInRouting,
+    NoRoute,
+}
+
+#[derive(Debug, Clone)]
+pub struct FloodScheduler {
+    policy: FloodPolicy,
+    interval: Duration, // until the next flood nobody asked for
+    next: Instant,
+    last: Option<Instant>,
+    changed: bool, // the topology changed since the last flood
+    asked: Option<FloodReason>, // why the next flood is due early
+    floods: u64,
+}
+
+impl FloodScheduler {
+    // The first flood is due right away
+    pub fn new(policy: FloodPolicy, now: Instant) -> Self {
+        Self {
+            policy,
+            interval: Duration::from_millis(policy.base_ms),
+            next: now,
+            last: None,
+            changed: false,
+            asked: Some(FloodReason::Startup),
+            floods: 0,
+        }
+    }
+
+    pub fn floods(&self) -> u64 {
+        self.floods
+    }
+
+    // Starts over as on startup, keeps the flood count
+    pub fn restart(&mut self, now: Instant) {
+        let floods = self.floods;
+        *self = Self::new(self.policy, now);
+        self.floods = floods;
+    }
+
+    // Something may have changed: flood as soon as the minimum gap allows and stop backing off
+    pub fn trigger(&mut self, reason: FloodReason, now: Instant) {
+        let earliest = match self.last {
+            Some(last) => (last + Duration::from_millis(self.policy.min_gap_ms)).max(now),
+            None => now,
+        };
+        if earliest < self.next {
+            self.next = earliest;
+        }
+        self.asked.get_or_insert(reason);
+        self.interval = Duration::from_millis(self.policy.base_ms);
+    }
+
+    // A flood response taught the node something new
+    pub fn topology_changed(&mut self) {
+        self.changed = true;
+    }
+
+    // How long the node can wait for packets or commands before asking again
+    pub fn timeout(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+
+    // Whether to flood now, and why; the next one is planned as if the flood was sent
+    pub fn poll(&mut self, now: Instant) -> Option<FloodReason> {
+        if now < self.next {
+            return None;
+        }
+        let max = Duration::from_millis(self.policy.max_ms);
+        if self.last.is_some() && self.asked.is_none() {
+            // nobody asked for this one, back off if the last one found nothing
+            self.interval = if self.changed {
+                Duration::from_millis(self.policy.base_ms)
+            } else {
+                (self.interval * self.policy.factor).min(max)
+            };
+        }
+        self.last = Some(now);
+        self.next = now + self.interval;
+        self.changed = false;
+        self.floods += 1;
+        Some(self.asked.take().unwrap_or(FloodReason::Periodic))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Floods sent in `secs` seconds polled every 100ms, with a crash at `crash` that the node
+    // hears about through an ErrorInRouting nack; the topology changes after the first flood
+    // and after the first one that follows the crash
+    fn run(policy: FloodPolicy, secs: u64, crash: u64) -> (u64, Option<Duration>) {
+        let start = Instant::now();
+        let mut scheduler = FloodScheduler::new(policy, start);
+        let mut learned_crash = None;
+        let mut first = true;
+        for tick in 0..secs * 10 {
+            let now = start + Duration::from_millis(tick * 100);
+            if tick == crash * 10 {
+                scheduler.trigger(FloodReason::ErrorInRouting, now);
+            }
+            if scheduler.poll(now).is_some() {
+                if first || (tick >= crash * 10 && learned_crash.is_none()) {
+                    scheduler.topology_changed();
+                }
+                first = false;
+                if tick >= crash * 10 && learned_crash.is_none() {
+                    learned_crash = Some(now - (start + Duration::from_secs(crash)));
+                }
+            }
+        }
+        (scheduler.floods(), learned_crash)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let start = Instant::now();
+        let mut scheduler = FloodScheduler::new(FloodPolicy::default(), start);
+        assert_eq!(scheduler.poll(start), Some(FloodReason::Startup));
+        assert_eq!(scheduler.poll(start), None);
+        assert_eq!(scheduler.timeout(start), Duration::from_secs(2));
+        // nothing changes, the interval doubles up to the max
+        let mut now = start;
+        let mut gaps = Vec::new();
+        for _ in 0..7 {
+            let wait = scheduler.timeout(now);
+            now += wait;
+            assert_eq!(scheduler.poll(now), Some(FloodReason::Periodic));
+            gaps.push(scheduler.timeout(now).as_secs());
+        }
+        assert_eq!(gaps, vec![4, 8, 16, 32, 60, 60, 60]);
+
+        // a trigger right after a flood waits for the minimum gap, then the backoff starts over
+        scheduler.trigger(FloodReason::NeighbourChanged, now);
+        scheduler.trigger(FloodReason::NoRoute, now);
+        assert_eq!(scheduler.timeout(now), Duration::from_millis(500));
+        now += Duration::from_millis(500);
+        assert_eq!(scheduler.poll(now), Some(FloodReason::NeighbourChanged));
+        assert_eq!(scheduler.timeout(now), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_fewer_floods() {
+        let every_5s = FloodPolicy { min_gap_ms: 5_000, base_ms: 5_000, max_ms: 5_000, factor: 1 };
+        let (fixed, fixed_delay) = run(every_5s, 600, 300);
+        let (adaptive, adaptive_delay) = run(FloodPolicy::default(), 600, 300);
+        assert_eq!(fixed, 120);
+        assert!(adaptive * 5 < fixed, "{} floods against {}", adaptive, fixed);
+        // and the crash is learned right away instead of at the next tick
+        assert_eq!(adaptive_delay, Some(Duration::ZERO));
+        assert!(fixed_delay.unwrap() <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_policy() {
+        assert!(FloodPolicy::default().check().is_ok());
+        assert!(FloodPolicy { max_ms: 1_000, ..FloodPolicy::default() }.check().is_err());
+        let policy: FloodPolicy = toml::from_str("max_ms = 10000").unwrap();
+        assert_eq!(policy, FloodPolicy { max_ms: 10_000, ..FloodPolicy::default() });
+    }
+}
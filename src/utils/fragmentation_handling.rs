@@ -126,6 +126,52 @@ pub fn deconstruct_message(msg: Message) -> Result<Vec<u8>, String> {
     }
 }
 
+// Name of the message a recognition bit stands for, used by the controller's packet inspector
+pub fn message_kind(recognition_bit: u8) -> Option<&'static str> {
+    match recognition_bit {
+        STRINGBIT => Some("String"),
+        AUDIOBIT => Some("Audio"),
+        IMAGEBIT => Some("Image"),
+        DEFAULTBIT => Some("DefaultsRequest"),
+        MEDIABIT => Some("ContentRequest (media)"),
+        TEXTBIT => Some("ContentRequest (text)"),
+        CHATSTRINGBIT => Some("ChatMessages (text)"),
+        CHATIMAGEBIT => Some("ChatMessages (image)"),
+        CHATAUDIOBIT => Some("ChatMessages (audio)"),
+        DEFRESPONSEBIT => Some("DefaultResponse"),
+        CONTENTRESPONSEBIT => Some("ContentResponse"),
+        _ => None,
+    }
+}
+
+// Short description of a request for the controller, without the payload
+pub fn request_name(message: &Message) -> String {
+    match message {
+        Message::DefaultsRequest(request) => format!("{:?}", request),
+        Message::ContentRequest(ContentRequest::GETTEXT(path)) => format!("GETTEXT {}", path),
+        Message::ContentRequest(ContentRequest::GETMEDIA(path)) => format!("GETMEDIA {}", path),
+        Message::ChatMessages(ChatMessages::CHATSTRING(src, _, dst, _)) => format!("CHATSTRING {} -> {}", src, dst),
+        Message::ChatMessages(ChatMessages::CHATIMAGE(src, _, dst, _)) => format!("CHATIMAGE {} -> {}", src, dst),
+        Message::ChatMessages(ChatMessages::CHATAUDIO(src, _, dst, _)) => format!("CHATAUDIO {} -> {}", src, dst),
+        Message::DefaultResponse(_) => "DefaultResponse".to_string(),
+        Message::ContentResponse(_) => "ContentResponse".to_string(),
+        Message::String(_) => "String".to_string(),
+        Message::Image(_) => "Image".to_string(),
+        Message::Audio(_) => "Audio".to_string(),
+    }
+}
+
+// Times a nacked fragment is resent before the sender gives up on it
+pub const MAX_RESEND_ATTEMPTS: u32 = 30;
+
+// Whether the payload is an image or a track, not worth showing as text
+pub fn carries_media(recognition_bit: u8) -> bool {
+    matches!(
+        recognition_bit,
+        AUDIOBIT | IMAGEBIT | CHATIMAGEBIT | CHATAUDIOBIT | CONTENTRESPONSEBIT
+    )
+}
+
 // Trait to handle message fragmentation
 //      Every impl Fragemntation has a diff recognition bit, that is the first element
 //      of the vector of the message's bytes. It will be used then to be the first fragment
@@ -313,6 +359,7 @@ pub enum DefaultsRequest {
     GETALLMEDIALINKS, //request all media links insede of content server
     GETALLAVAILABLE,  //get all client available for chatting
     GETSERVERTYPE,    //get servertype
+    CANCEL(u64),      //peer gave up on the transfer with this session_id
 }
 
 impl Fragmentation<DefaultsRequest> for DefaultsRequest {
@@ -333,6 +380,13 @@ impl Fragmentation<DefaultsRequest> for DefaultsRequest {
             DefaultsRequest::GETSERVERTYPE => {
                 vec![DEFAULTBIT, 5]
             }
+            DefaultsRequest::CANCEL(session_id) => {
+                //trailing 0xFF keeps remove_trailing_zeros from eating the id
+                let mut vec = vec![DEFAULTBIT, 6];
+                vec.extend_from_slice(&session_id.to_be_bytes());
+                vec.push(0xFF);
+                vec
+            }
         }
     }
 }
@@ -349,6 +403,14 @@ impl Assembler<DefaultsRequest> for DefaultsRequest {
                 3 => Ok(DefaultsRequest::GETALLMEDIALINKS),
                 4 => Ok(DefaultsRequest::GETALLAVAILABLE),
                 5 => Ok(DefaultsRequest::GETSERVERTYPE),
+                6 => {
+                    if fragments[1].length < 9 {
+                        return Err("Cancel request without session id".to_string());
+                    }
+                    let mut bytes = [0u8; 8];
+                    bytes.copy_from_slice(&fragments[1].data[1..9]);
+                    Ok(DefaultsRequest::CANCEL(u64::from_be_bytes(bytes)))
+                }
                 _ => Err("Default request identifier does not match".to_string()),
             }
         }
@@ -1443,4 +1505,18 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test17() {
+        // session id ending in zero bytes must survive the receiver trimming trailing zeros
+        let req = DefaultsRequest::CANCEL(0x0A00);
+        let fr = <DefaultsRequest as Fragmentation<DefaultsRequest>>::fragment(req);
+        let mut ser = serialize(fr.clone());
+        assert_eq!(ser.len(), 2);
+        let res = reconstruct_message(fr[0], &mut ser);
+        match res {
+            Ok(Message::DefaultsRequest(df)) => assert_eq!(df, req),
+            _ => panic!("Cancel request not reconstructed"),
+        }
+    }
 }
@@ -4,8 +4,15 @@ use crate::{
     utils::{
         client::{chat_client::ChatClient, web_browser::WebBrowser},
         controller::{NodeCommand, NodeEvent},
+        drone_registry::{self, DroneArgs, DRONE_NAMES},
+        flood::FloodPolicy,
+        links::Links,
+        mock_drone::MockPolicy,
+        sessions::SessionPolicy,
+        sim_config::{server_kind_name, SimConfig},
     },
 };
+use bevy::log::info;
 use crossbeam_channel::*;
 use rand::*;
 use std::{
@@ -15,24 +22,37 @@ use toml::{self};
 use wg_2024::{
     config::{Client, Config, Server},
     controller::{DroneCommand, DroneEvent},
-    drone::Drone,
     network::NodeId,
     packet::Packet,
 };
 
-fn parse_config(file: &str) -> Config {
+pub fn parse_config(file: &str) -> Result<Config, String> {
     println!("{file}");
-    let file_str = fs::read_to_string(file).unwrap();
-    toml::from_str(&file_str).unwrap()
+    let file_str = fs::read_to_string(file).map_err(|e| format!("Unable to read {}: {}", file, e))?;
+    toml::from_str(&file_str).map_err(|e| format!("Unable to parse {}: {}", file, e))
 }
 
-const CHATAPP: u8 = 0;
-const WEBAPP: u8 = 1;
+pub const CHATAPP: u8 = 0;
+pub const WEBAPP: u8 = 1;
 
-fn helper1(counters: &mut [i32]) -> usize {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DroneImplChoice {
+    Mixed,
+    Single(usize),
+}
+
+/// Answers to the questions `initialize` would otherwise ask on stdin,
+/// `None` keeps the interactive prompt.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    pub app: Option<u8>,
+    pub drone_impl: Option<DroneImplChoice>,
+    pub seed: Option<u64>, // makes the drone mix repeatable
+}
+
+fn helper1(counters: &mut [i32], rng: &mut impl Rng) -> usize {
     let mut val ;
     loop {
-        let mut rng = rand::thread_rng();
         val = rng.r#gen::<usize>() % 10;
         if *counters.iter().min().unwrap() == counters[val] {
             counters[val] += 1;
@@ -46,10 +66,9 @@ fn helper1(counters: &mut [i32]) -> usize {
     val
 }
 
-fn helper2(counters: &mut [i32]) -> usize {
+fn helper2(counters: &mut [i32], rng: &mut impl Rng) -> usize {
     let mut val;
     loop {
-        let mut rng = rand::thread_rng();
         val = rng.r#gen::<usize>() % 10;
         if counters[val] != 1 {
             // println!("Value:[{}], Counters:[{:?}]",val,counters);
@@ -60,68 +79,78 @@ fn helper2(counters: &mut [i32]) -> usize {
     val
 }
 
-fn helper3(config: &Config) -> u8 {
+// None when the config fits both apps and the user has to pick one
+pub fn infer_app(config: &Config) -> Option<u8> {
     let s_len = config.server.len();
     let c_len = config.client.len();
-    let mut res = 0;
-    if c_len == 1  && s_len>1{
-        res = WEBAPP;
-    } else if c_len == 2 {
-        if s_len == 1 {
-            res = CHATAPP;
-        } else if s_len > 1 {
-            println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-            std::io::stdout().flush().unwrap();
-            let mut str =  String::new();
-            loop {
-            std::io::stdin().read_line(&mut str).ok();
-                match str.trim().parse::<u8>(){
-                    Ok(n)=>{
-                        if n==0 || n==1 {
-                            res = n;
-                            break;
-                        } else {
-                            println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-                            std::io::stdout().flush().unwrap();
-                        }
-                    }, 
-                    Err(_)=>{
-                        println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-                    }
-                }
-            }
+    if c_len == 1 && s_len > 1 {
+        Some(WEBAPP)
+    } else if (c_len == 2 || c_len > 3) && s_len > 1 {
+        None
+    } else {
+        Some(CHATAPP)
+    }
+}
+
+pub fn ask_app() -> u8 {
+    loop {
+        println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
+        std::io::stdout().flush().unwrap();
+        let mut str = String::new();
+        std::io::stdin().read_line(&mut str).ok();
+        match str.trim().parse::<u8>() {
+            Ok(n) if n == CHATAPP || n == WEBAPP => return n,
+            _ => {}
         }
-    } else if c_len > 3 {
-        if s_len <= 1 {
-            res = CHATAPP
-        } else if s_len >= 2 {
-            println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-            std::io::stdout().flush().unwrap();
-            let mut str =  String::new();
-            loop {
-                std::io::stdin().read_line(&mut str).ok();
-                match str.trim().parse::<u8>(){
-                    Ok(n)=>{
-                        if n==0 || n==1 {
-                            res = n;
-                            break;
-                        } else {
-                            println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-                            std::io::stdout().flush().unwrap();
-                        }
-                    }, 
-                    Err(_)=>{
-                        println!("Choose:\n\t0 for ChatApp\n\t1 for WebApp ");
-                    }
+    }
+}
+
+// Different for every node but fixed for a given run seed
+pub fn node_seed(base_seed: u64, id: NodeId) -> u64 {
+    base_seed ^ (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+// Each direction of a link gets its own seed, `from` -> `to` doesn't roll like `to` -> `from`
+pub fn link_seed(base_seed: u64, from: NodeId, to: NodeId) -> u64 {
+    node_seed(node_seed(base_seed, from).rotate_left(32), to)
+}
+
+fn helper3(config: &Config) -> u8 {
+    infer_app(config).unwrap_or_else(ask_app)
+}
+
+pub fn ask_drone_impl() -> usize {
+    let last = DRONE_NAMES.len() - 1;
+    println!("Choose for one implementation only:\n");
+    for (index, name) in DRONE_NAMES.iter().enumerate() {
+        println!("\t-{}: {}", index, name);
+    }
+    loop {
+        std::io::stdout().flush().unwrap();
+        let mut str = String::new();
+        std::io::stdin().read_line(&mut str).unwrap();
+        match str.trim().parse::<usize>(){
+            Ok(n)=>{
+                if n<=last {
+                    return n;
+                } else{
+                    println!("Choose a number between 0 and {}", last);
                 }
+            },
+            Err(_)=>{
+                println!("Enter a valid input");
             }
         }
     }
-    res
 }
 
 pub fn choose_config_cli()->(PathBuf,bool){
-    let config_path = std::env::current_exe()
+    let config = ask_config();
+    (config, ask_one_impl())
+}
+
+pub fn configs_dir() -> PathBuf {
+    std::env::current_exe()
         .unwrap()
         .parent()
         .unwrap()
@@ -132,9 +161,13 @@ pub fn choose_config_cli()->(PathBuf,bool){
         .parent()
         .unwrap()
         .to_path_buf()
-        .join("configs/");
+        .join("configs/")
+}
+
+// Lists the files in configs/ and loops until a valid one is picked
+pub fn ask_config() -> PathBuf {
     println!("Chose from the following configuration files by entering the corresponding number");    
-    let reader = std::fs::read_dir(config_path).expect("No config dir found");
+    let reader = std::fs::read_dir(configs_dir()).expect("No config dir found");
     let configs = reader.into_iter().enumerate().map(|(i,c)| (i,c.ok().unwrap().path())).collect::<HashMap<usize,PathBuf>>();
     for (i, entry) in &configs {
         println!("{}\t{}", i, entry.to_string_lossy());
@@ -154,27 +187,9 @@ pub fn choose_config_cli()->(PathBuf,bool){
             Ok(n) => {
                 if let Some(c) = configs.get(&n) {
                     println!("Chosen: {}", c.to_string_lossy());
-                    if check_initializer(c.to_str().unwrap()) {
-                        println!("Choose 1 to use just one impl for the drones, choose 2 for multiple impl");
-                        std::io::stdout().flush().unwrap();
-                        let mut str = String::new();
-                        std::io::stdin().read_line(&mut str).unwrap();
-                        match str.trim().parse::<u8>(){
-                            Ok(m) => {
-                                if m == 1 {
-                                    return (c.clone(),true);
-                                } else if m == 2 {
-                                    return (c.clone(),false);
-                                } else {
-                                    println!("Chose between 1 and 2");
-                                }
-                            },
-                            Err(_)=>{
-                                println!("Enter a valid number");
-                            }
-                        }
-                    } else {
-                        println!("Config file doesn't respect protocol")
+                    match check_initializer(&c.to_string_lossy()) {
+                        Ok(()) => return c.clone(),
+                        Err(e) => println!("{}\nConfig file doesn't respect protocol", e),
                     }
                 } else {
                     println!("Invalid number: No config at index {}", n);
@@ -185,8 +200,26 @@ pub fn choose_config_cli()->(PathBuf,bool){
             }
         }
     }
-        
+}
 
+// true when all drones should share one implementation
+pub fn ask_one_impl() -> bool {
+    loop {
+        println!("Choose 1 to use just one impl for the drones, choose 2 for multiple impl");
+        std::io::stdout().flush().unwrap();
+        let mut str = String::new();
+        std::io::stdin().read_line(&mut str).unwrap();
+        match str.trim().parse::<u8>(){
+            Ok(1) => return true,
+            Ok(2) => return false,
+            Ok(_) => {
+                println!("Chose between 1 and 2");
+            },
+            Err(_)=>{
+                println!("Enter a valid number");
+            }
+        }
+    }
 }
 
 fn build(
@@ -197,141 +230,34 @@ fn build(
     packet_send: HashMap<NodeId, crossbeam_channel::Sender<Packet>>,
     pdr: f32,
     val: usize,
+    policy: MockPolicy,
+    seed: u64,
 ) {
-    match val {
-        0 => {
-            println!("BagelBomber Id[{}]", id);
-            let mut drone = bagel_bomber::BagelBomber::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        1 => {
-            println!("BetteCallDrone Id[{}]", id);
-            let mut drone = drone_bettercalldrone::BetterCallDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        2 => {
-            println!("RustRoveri Id[{}]", id);
-            let mut drone = rust_roveri::drone::RustRoveri::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        3 => {
-            println!("GetDroned Id[{}]", id);
-            let mut drone = getdroned::GetDroned::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        4 => {
-            println!("C++Enjoyers Id[{}]", id);
-            let mut drone = ap2024_unitn_cppenjoyers_drone::CppEnjoyersDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        5 => {
-            println!("D.R.O.N.E Id[{}]", id);
-            let mut drone = d_r_o_n_e_drone::MyDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        6 => {
-            println!("NNP Id[{}]", id);
-            let mut drone = null_pointer_drone::MyDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        7 => {
-            println!("Rustafarian Id[{}]", id);
-            let mut drone = rustafarian_drone::RustafarianDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        8 => {
-            println!("DrOnes[{}]", id);
-            let mut drone = dr_ones::Drone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        9 => {
-            println!("Rusteze Id[{}]", id);
-            let mut drone = rusteze_drone::RustezeDrone::new(
-                id,
-                drone_event_send,
-                controller_drone_recv,
-                packet_recv,
-                packet_send,
-                pdr,
-            );
-            drone.run();
-        }
-        _ => {
-            println!("Error modulo");
-        }
-    }
+    drone_registry::run(
+        val,
+        DroneArgs {
+            id,
+            controller_send: drone_event_send,
+            controller_recv: controller_drone_recv,
+            packet_recv,
+            packet_send,
+            pdr,
+            policy,
+            seed,
+        },
+    );
 }
 
-fn build_and_run_client(
+pub fn build_and_run_client(
     id: NodeId,
     controller_send: Sender<NodeEvent>,
     controller_recv: Receiver<NodeCommand>,
     packet_recv: Receiver<Packet>,
     packet_send: HashMap<NodeId, Sender<Packet>>,
     app_value: u8,
+    seed: u64,
+    flood: FloodPolicy,
+    sessions: SessionPolicy,
 ) -> (
     Option<Sender<ChatCommand>>,
     Option<Receiver<ChatEvent>>,
@@ -354,7 +280,10 @@ fn build_and_run_client(
                 packet_send,
                 chat_commands_receiver,
                 chat_events_sender,
-            );
+            )
+            .with_seed(seed)
+            .with_flood_policy(flood)
+            .with_session_policy(sessions);
             client.handle_channels();
         });
         (chat_sender, chat_receiver) = (Some(chat_commands_sender), Some(chat_events_receiver));
@@ -368,7 +297,10 @@ fn build_and_run_client(
                 packet_send,
                 web_commands_receiver,
                 web_events_sender,
-            );
+            )
+            .with_seed(seed)
+            .with_flood_policy(flood)
+            .with_session_policy(sessions);
             client.handle_channels();
         });
         (web_sender, web_receiver) = (Some(web_commands_sender), Some(web_events_receiver));
@@ -376,7 +308,7 @@ fn build_and_run_client(
     (chat_sender, chat_receiver, web_sender, web_receiver)
 }
 
-fn build_and_run_server(
+pub fn build_and_run_server(
     id: NodeId,
     controller_send: Sender<NodeEvent>,
     controller_recv: Receiver<NodeCommand>,
@@ -384,6 +316,10 @@ fn build_and_run_server(
     packet_send: HashMap<NodeId, Sender<Packet>>,
     app_value: u8,
     last_type: u8,
+    kind: Option<u8>, // text or media for web servers, alternating when None
+    seed: u64,
+    flood: FloodPolicy,
+    sessions: SessionPolicy,
 ) -> u8 {
     let mut serv_type = 0;
     if app_value == CHATAPP {
@@ -396,11 +332,16 @@ fn build_and_run_server(
                 controller_recv,
                 packet_recv,
                 packet_send,
-            );
+            )
+            .with_seed(seed)
+            .with_flood_policy(flood)
+            .with_session_policy(sessions);
             server.handle_channels();
         });
     } else if app_value == WEBAPP {
-        if last_type == super::backup_server::MEDIASERVER {
+        if let Some(kind) = kind {
+            serv_type = kind;
+        } else if last_type == super::backup_server::MEDIASERVER {
             serv_type = super::backup_server::TEXTSERVER;
         } else if last_type == super::backup_server::TEXTSERVER {
             serv_type = super::backup_server::MEDIASERVER;
@@ -415,13 +356,27 @@ fn build_and_run_server(
                 controller_recv,
                 packet_recv,
                 packet_send,
-            );
+            )
+            .with_seed(seed)
+            .with_flood_policy(flood)
+            .with_session_policy(sessions);
             server.handle_channels();
         });
     }
     return serv_type;
 }
 
+// Logged so a run can be repeated by pinning the same implementations in the config, like
+// the start of every drone in drone_registry::run
+fn print_drone_impls(drone_impls: &HashMap<NodeId, usize>) {
+    let mut ids = drone_impls.keys().collect::<Vec<_>>();
+    ids.sort();
+    info!("Drone implementations:");
+    for id in ids {
+        info!("\t{} = {}", id, DRONE_NAMES[drone_impls[id]]);
+    }
+}
+
 pub fn initialize(
     path_to_file: &str,
     the_one: bool,
@@ -435,8 +390,29 @@ pub fn initialize(
     ),
     Box<dyn std::error::Error + Send + Sync>,
 > {
-    let config = parse_config(path_to_file);
-    let app_magic_value = helper3(&config);
+    let options = InitOptions {
+        app: None,
+        drone_impl: if the_one { None } else { Some(DroneImplChoice::Mixed) },
+        seed: None,
+    };
+    initialize_with(path_to_file, &options)
+}
+
+pub fn initialize_with(
+    path_to_file: &str,
+    options: &InitOptions,
+) -> Result<
+    (
+        Vec<JoinHandle<()>>,
+        HashMap<u8, super::super::frontend::chat_gui::GuiChannels>,
+        HashMap<u8, super::super::frontend::web_gui::GuiChannels>,
+        super::controller::SimulationController,
+        Config,
+    ),
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let config = parse_config(path_to_file)?;
+    let app_magic_value = options.app.unwrap_or_else(|| helper3(&config));
 
     let mut dd = HashMap::new();
     let mut controller_drones = HashMap::new();
@@ -465,40 +441,49 @@ pub fn initialize(
     let mut handles = Vec::new();
 
     let mut counters = [0; 10];
+    let mut rng = match options.seed {
+        Some(seed) => rngs::StdRng::seed_from_u64(seed),
+        None => rngs::StdRng::from_entropy(),
+    };
+    // every node and link gets its own seed out of this one
+    let base_seed = rng.next_u64();
     let len = config.drone.len();
-    let mut str = String::new();
-    let mut val = 10;
-    if the_one {
-        println!("Choose for one implementation only:\n
-                 \t-0: BagelBomber
-                 \t-1: BetterCallDrone
-                 \t-2: RustRoveri
-                 \t-3: GetDroned
-                 \t-4: C++Enjoyers
-                 \t-5: D.R.O.N.E
-                 \t-6: NNP
-                 \t-7: Rustafarian
-                 \t-8: DrOnes
-                 \t-9: Rusteze\n
-        ");
-        loop {
-            std::io::stdout().flush().unwrap();
-            std::io::stdin().read_line(&mut str).unwrap();
-            match str.trim().parse::<usize>(){
-                Ok(n)=>{
-                    if n<=9 {
-                        val=n;
-                        break;
-                    } else{
-                        println!("Choose a number between 0 and 9");
-                    }
-                },
-                Err(_)=>{
-                    println!("Enter a valid input");
-                }
-            }
+    // drones with an `implementation` field keep it, the others follow the choice
+    let sim = SimConfig::from_file(path_to_file)?;
+    let pinned = sim.implementations()?;
+    let mocks = sim.mock_policies()?;
+    let default_mock = sim.default_mock();
+    let kinds = sim.server_kinds()?;
+    let floods = sim.flood_policies()?;
+    let default_flood = sim.default_flood()?;
+    let sessions = sim.session_policy()?;
+    let link_params = sim.link_params()?;
+    for (from, to) in link_params.keys() {
+        if !neighbours_in(&config, *from).contains(to) {
+            return Err(format!("Link {} -> {} is not in the topology", from, to).into());
+        }
+    }
+    // every packet channel goes through a link shim, see links.rs
+    let mut links = Links::new(
+        sim.default_link()?,
+        link_params,
+        cs_send.clone(),
+        drone_event_send.clone(),
+        base_seed,
+    );
+    for (id, kind) in &kinds {
+        if (app_magic_value == CHATAPP) != (*kind == super::backup_server::CHATSERVER) {
+            return Err(format!("Server {} can't be a {} server in this app", id, server_kind_name(*kind)).into());
         }
     }
+    if len <= 10 {
+        // the mock drone isn't one of the crates mixed runs spread
+        for index in pinned.values().filter(|index| **index < counters.len()) {
+            counters[*index] = 1;
+        }
+    }
+    let mut choice = options.drone_impl;
+    let mut drone_impls = HashMap::new();
     for drone in config.clone().drone.into_iter() {
         // controller
         let (controller_drone_send, controller_drone_recv) = unbounded();
@@ -510,16 +495,23 @@ pub fn initialize(
             .connected_node_ids
             .clone()
             .into_iter()
-            .map(|id| (id, packet_channels[&id].0.clone()))
+            .map(|id| {
+                let back = packet_channels[&drone.id].0.clone();
+                (id, links.connect(drone.id, id, packet_channels[&id].0.clone(), back))
+            })
             .collect();
         dd.insert(drone.id, drone.connected_node_ids.clone());
-        if !the_one {
-            if len <= 10 {
-                val = helper2(&mut counters);
-            } else {
-                val = helper1(&mut counters);
-            }
-        }
+        let val = match pinned.get(&drone.id) {
+            Some(index) => *index,
+            None => match *choice.get_or_insert_with(|| DroneImplChoice::Single(ask_drone_impl())) {
+                DroneImplChoice::Single(n) => n,
+                DroneImplChoice::Mixed if len <= 10 => helper2(&mut counters, &mut rng),
+                DroneImplChoice::Mixed => helper1(&mut counters, &mut rng),
+            },
+        };
+        drone_impls.insert(drone.id, val);
+        let policy = mocks.get(&drone.id).cloned().unwrap_or_else(|| default_mock.clone());
+        let seed = node_seed(base_seed, drone.id);
         handles.push(thread::spawn(move || {
             build(
                 drone.id,
@@ -529,9 +521,12 @@ pub fn initialize(
                 packet_send,
                 drone.pdr,
                 val,
+                policy,
+                seed,
             );
         }));
     }
+    print_drone_impls(&drone_impls);
     let mut gui_web = HashMap::new();
     let mut gui_chat = HashMap::new();
 
@@ -546,7 +541,10 @@ pub fn initialize(
         let packet_send = drone
             .connected_drone_ids
             .into_iter()
-            .map(|id| (id, packet_channels[&id].0.clone()))
+            .map(|id| {
+                let back = packet_channels[&drone.id].0.clone();
+                (id, links.connect(drone.id, id, packet_channels[&id].0.clone(), back))
+            })
             .collect();
 
         let (cs, ce, ws, we) = build_and_run_client(
@@ -556,6 +554,9 @@ pub fn initialize(
             packet_recv,
             packet_send,
             app_magic_value,
+            node_seed(base_seed, drone.id),
+            floods.get(&drone.id).copied().unwrap_or(default_flood),
+            sessions,
         );
         if app_magic_value == CHATAPP {
             let chat_channels =
@@ -580,7 +581,10 @@ pub fn initialize(
         let packet_send = drone
             .connected_drone_ids
             .into_iter()
-            .map(|id| (id, packet_channels[&id].0.clone()))
+            .map(|id| {
+                let back = packet_channels[&drone.id].0.clone();
+                (id, links.connect(drone.id, id, packet_channels[&id].0.clone(), back))
+            })
             .collect();
         let current = build_and_run_server(
             drone.id,
@@ -590,6 +594,10 @@ pub fn initialize(
             packet_send,
             app_magic_value,
             last,
+            kinds.get(&drone.id).copied(),
+            node_seed(base_seed, drone.id),
+            floods.get(&drone.id).copied().unwrap_or(default_flood),
+            sessions,
         );
         last = current;
     }
@@ -601,10 +609,29 @@ pub fn initialize(
         cs_recv,
         drone_event_send,
         senders,
-    );
+        drone_impls,
+    )
+    .with_seed(options.seed)
+    .with_flood_policy(default_flood)
+    .with_session_policy(sessions)
+    .with_mock_policy(default_mock)
+    .with_links(links)
+    .with_topology(&config)
+    .with_app(app_magic_value, last, base_seed, cs_send);
     Ok((handles, gui_chat, gui_web, controller, config.clone()))
 }
 
+fn neighbours_in(config: &Config, id: NodeId) -> Vec<NodeId> {
+    let drones = config.drone.iter().map(|d| (d.id, &d.connected_node_ids));
+    let clients = config.client.iter().map(|c| (c.id, &c.connected_drone_ids));
+    let servers = config.server.iter().map(|s| (s.id, &s.connected_drone_ids));
+    drones
+        .chain(clients)
+        .chain(servers)
+        .find(|(node, _)| *node == id)
+        .map_or(Vec::new(), |(_, neighbours)| neighbours.clone())
+}
+
 fn check_neighbors_id(current: NodeId, neighbors: &Vec<NodeId>) -> bool {
     neighbors.into_iter().all(|f| *f != current)
         && (neighbors.iter().copied().collect::<HashSet<_>>().len() == neighbors.len())
@@ -629,7 +656,7 @@ fn check_client_server_connection(clients: &Vec<Client>, servers: &Vec<Server>)-
     res
 }
 
-fn check_bidirectionality(config: &Config)->bool {
+fn check_bidirectionality(config: &Config) -> Result<(), String> {
     // Helper: build a map of id -> neighbors
     let mut all_entities: HashMap<u8, &Vec<u8>> = HashMap::new();
 
@@ -648,18 +675,14 @@ fn check_bidirectionality(config: &Config)->bool {
         for neighbor_id in *neighbors {
             if let Some(neighbor_neighbors) = all_entities.get(neighbor_id) {
                 if !neighbor_neighbors.contains(id) {
-                    println!("❌ ID {} has neighbor {}, but not reciprocated.", id, neighbor_id);
-                    return false;
+                    return Err(format!("ID {} has neighbor {}, but not reciprocated", id, neighbor_id));
                 }
             } else {
-                println!("⚠️ ID {} refers to non-existent neighbor {}", id, neighbor_id);
-                return false;
+                return Err(format!("ID {} refers to non-existent neighbor {}", id, neighbor_id));
             }
         }
     }
-
-    println!("✅ All links are bidirectional.");
-    true
+    Ok(())
 }
 
 fn is_connected(drones: &Vec<wg_2024::config::Drone>, c_ids: Vec<u8>, s_ids: Vec<u8>) -> bool {
@@ -701,87 +724,70 @@ fn is_connected(drones: &Vec<wg_2024::config::Drone>, c_ids: Vec<u8>, s_ids: Vec
 
 
 
-fn check_initializer(path_to_file: &str) -> bool {
-    let config_data = std::fs::read_to_string(path_to_file).expect("Unable to read config file");
+pub fn check_initializer(path_to_file: &str) -> Result<(), String> {
+    let config_data = std::fs::read_to_string(path_to_file)
+        .map_err(|e| format!("Unable to read config file {}: {}", path_to_file, e))?;
     // having our structs implement the Deserialize trait allows us to use the toml::from_str function to deserialize the config file into each of them
-    let config: Config = toml::from_str(&config_data).expect("Unable to parse TOML");
+    let config: Config = toml::from_str(&config_data)
+        .map_err(|e| format!("Unable to parse TOML in {}: {}", path_to_file, e))?;
 
+    check_config(&config).map_err(|e| format!("Configs Restriction not met: {}", e))
+}
 
-    let mut current;
-    let mut last = 0;
-    let mut res = true;
-     
+/// The protocol rules every topology has to follow, the first one broken is returned.
+/// Used on the config files and on the topologies built in the controller.
+pub fn check_config(config: &Config) -> Result<(), String> {
     if config.drone.len() < 2 {
-        println!("Configs Restriction not met: Drones must be > 1 ");
-        res = false;
-    } else if (config.client.len()<2 && config.server.len()<2) 
-            || config.client.len()==0 
-            || config.server.len()==0  
+        return Err("Drones must be > 1".to_string());
+    }
+    if (config.client.len() < 2 && config.server.len() < 2)
+        || config.client.is_empty()
+        || config.server.is_empty()
     {
-        println!("Configs Restriction not met: Servers must be > 1 and Client >2 for ChatApp; Servers must be > 2 and Client >1 for ChatApp;");
-        res = false;
-    } else {
-        if check_bidirectionality(&config) {
-            let c_ids = config.clone().client.into_iter().map(|c| c.id).collect::<Vec<u8>>();
-            let s_ids = config.clone().server.into_iter().map(|s| s.id).collect::<Vec<u8>>();
-            if is_connected(&config.drone,c_ids,s_ids){
-                for drone in config.drone {
-                    current = drone.id;
-                    if check_neighbors_id(current, &drone.connected_node_ids) {
-                        if check_pdr(drone.pdr) {
-                            if current != last {
-                                last = drone.id;
-                            } else {
-                                res = false;
-                            }
-                        } else {
-                            res = false;
-                        }
-                    } else {
-                        res = false;
-                    }
-                }
-                if res {
-                    for client in config.client {
-                        current = client.id;
-                        if client.connected_drone_ids.len()<1 || client.connected_drone_ids.len()>2 {
-                            res = false;
-                        } else if check_neighbors_id(current, &client.connected_drone_ids) {
-                            if current != last {
-                                last = client.id;
-                            } else {
-                                res = false;
-                            }
-                        } else {
-                            res = false;
-                        }
-                    }
-                
-                }
-                if res {
-                    for server in config.server {
-                        current = server.id;
-                        if server.connected_drone_ids.len()<2 {
-                            res = false;
-                        } else if check_neighbors_id(current, &server.connected_drone_ids) {
-                            if current != last {
-                                last = server.id;
-                            } else {
-                                res = false;
-                            }
-                        } else {
-                            res = false;
-                        }
-                    }
-                }
-            } else {
-                res = false;
-            }
-        } else {
-            res = false;
+        return Err("Servers must be > 1 and Client >2 for ChatApp; Servers must be > 2 and Client >1 for ChatApp".to_string());
+    }
+    check_bidirectionality(config)?;
+    let c_ids = config.client.iter().map(|c| c.id).collect::<Vec<u8>>();
+    let s_ids = config.server.iter().map(|s| s.id).collect::<Vec<u8>>();
+    if !is_connected(&config.drone, c_ids, s_ids) {
+        return Err("The drones don't form a connected network".to_string());
+    }
+
+    let mut seen = HashSet::new();
+    for drone in &config.drone {
+        if !seen.insert(drone.id) {
+            return Err(format!("ID {} is used more than once", drone.id));
         }
-    }       
-    res
+        if !check_neighbors_id(drone.id, &drone.connected_node_ids) {
+            return Err(format!("Drone {} lists itself or the same neighbor twice", drone.id));
+        }
+        if !check_pdr(drone.pdr) {
+            return Err(format!("Drone {} has a PDR outside of 0..1", drone.id));
+        }
+    }
+    for client in &config.client {
+        if !seen.insert(client.id) {
+            return Err(format!("ID {} is used more than once", client.id));
+        }
+        if client.connected_drone_ids.is_empty() || client.connected_drone_ids.len() > 2 {
+            return Err(format!("Client {} must be connected to 1 or 2 drones", client.id));
+        }
+        if !check_neighbors_id(client.id, &client.connected_drone_ids) {
+            return Err(format!("Client {} lists itself or the same neighbor twice", client.id));
+        }
+    }
+    for server in &config.server {
+        if !seen.insert(server.id) {
+            return Err(format!("ID {} is used more than once", server.id));
+        }
+        if server.connected_drone_ids.len() < 2 {
+            return Err(format!("Server {} must be connected to at least 2 drones", server.id));
+        }
+        if !check_neighbors_id(server.id, &server.connected_drone_ids) {
+            return Err(format!("Server {} lists itself or the same neighbor twice", server.id));
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -790,7 +796,19 @@ mod test {
 
     #[test]
     fn test_init() {
-        assert_eq!(check_initializer("./configs/config.toml"), true);
+        assert_eq!(check_initializer("./configs/config.toml"), Ok(()));
+        assert!(check_initializer("./configs/missing.toml").is_err());
+    }
+
+    #[test]
+    fn test_check_config() {
+        let mut config = parse_config("./configs/config.toml").unwrap();
+        assert_eq!(check_config(&config), Ok(()));
+        config.drone[0].pdr = 1.5;
+        assert!(check_config(&config).is_err());
+        config.drone[0].pdr = 0.0;
+        config.client[0].connected_drone_ids = vec![3];
+        assert!(check_config(&config).unwrap_err().contains("not reciprocated"));
     }
 
     #[test]
@@ -800,6 +818,16 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_bad_config() {
+        assert!(parse_config("./configs/missing.toml").is_err());
+        let path = std::env::temp_dir().join("initializer_bad_config.toml");
+        fs::write(&path, "[[drone]]\nid = \"one\"\n").unwrap();
+        let path = path.to_string_lossy().to_string();
+        assert!(parse_config(&path).unwrap_err().contains("Unable to parse"));
+        assert!(initialize_with(&path, &InitOptions::default()).is_err());
+    }
+
     #[test]
     fn test_neigbors() {
         let neighbors: Vec<u8> = [2, 3, 4, 5].to_vec();
@@ -809,9 +837,79 @@ mod test {
         assert_eq!(check_neighbors_id(1, &neighbors_not), false);
     }
 
+    #[test]
+    fn test_seeded_run_is_reproducible() {
+        use crate::utils::links::LinkParams;
+        use std::time::{Duration, Instant};
+        use wg_2024::{network::SourceRoutingHeader, packet::Fragment};
+
+        const FRAGMENTS: u64 = 40;
+        // what becomes of every fragment sent 0 -> mock drone 1 -> 2 over lossy links
+        let run = |seed: u64| {
+            let base_seed = rngs::StdRng::seed_from_u64(seed).next_u64();
+            let (event_send, event_recv) = unbounded::<NodeEvent>();
+            let (drone_event_send, drone_event_recv) = unbounded::<DroneEvent>();
+            let lossy = LinkParams { loss: 0.3, ..Default::default() };
+            let mut links =
+                Links::new(lossy, HashMap::new(), event_send, drone_event_send.clone(), base_seed);
+            let (send_0, _at_0) = unbounded::<Packet>();
+            let (send_1, recv_1) = unbounded::<Packet>();
+            let (send_2, at_2) = unbounded::<Packet>();
+            let to_1 = links.connect(0, 1, send_1.clone(), send_0.clone());
+            let packet_send = HashMap::from([(0, send_0), (2, links.connect(1, 2, send_2, send_1))]);
+            let (command_send, command_recv) = unbounded();
+            let seed_1 = node_seed(base_seed, 1);
+            thread::spawn(move || {
+                build(
+                    1,
+                    command_recv,
+                    drone_event_send,
+                    recv_1,
+                    packet_send,
+                    0.3,
+                    drone_registry::MOCK,
+                    MockPolicy::default(),
+                    seed_1,
+                );
+            });
+
+            for index in 0..FRAGMENTS {
+                let header = SourceRoutingHeader::with_first_hop(vec![0, 1, 2]);
+                let fragment = Fragment::new(index, FRAGMENTS, [0; 128]);
+                to_1.send(Packet::new_fragment(header, index, fragment)).unwrap();
+            }
+            let mut outcomes = vec![""; FRAGMENTS as usize];
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while outcomes.contains(&"") && Instant::now() < deadline {
+                for event in event_recv.try_iter() {
+                    if let NodeEvent::LinkDropped { from, session_id, .. } = event {
+                        let lost = if from == 0 { "lost before 1" } else { "lost after 1" };
+                        outcomes[session_id as usize] = lost;
+                    }
+                }
+                for event in drone_event_recv.try_iter() {
+                    if let DroneEvent::PacketDropped(packet) = event {
+                        outcomes[packet.session_id as usize] = "dropped by 1";
+                    }
+                }
+                for packet in at_2.try_iter() {
+                    outcomes[packet.session_id as usize] = "arrived";
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            drop(command_send);
+            outcomes
+        };
+        let first = run(42);
+        assert!(!first.contains(&""), "{:?}", first);
+        assert!(first.contains(&"arrived") && first.iter().any(|outcome| *outcome != "arrived"));
+        assert_eq!(first, run(42));
+        assert_ne!(first, run(43));
+    }
+
     // #[test]
     fn check_build() {
-        let config = parse_config("./configs/config.toml");
+        let config = parse_config("./configs/config.toml").unwrap();
         let mut dd = HashMap::new();
         let mut controller_drones = HashMap::new();
         let (drone_event_send, _drone_event_recv) = unbounded();
@@ -865,6 +963,8 @@ mod test {
                     packet_send,
                     drone.pdr,
                     0,
+                    MockPolicy::default(),
+                    0,
                 );
 
                 // println!("{}  {:?}", drone.id, drone.packet_send.clone());
@@ -0,0 +1,593 @@
+// Emulated links. Every packet channel between two nodes goes through a shim thread that
+// holds the packets for the link delay and jitter, spaces them out by the link bandwidth and
+// drops what doesn't fit in its queue or is lost; with the default parameters a packet goes
+// straight through. Only fragments are lost, like with the PDR of a drone: the sender gets a
+// Nack back and the other packets go through the controller instead. `LinkQueue` works the delivery times out and never reads the clock itself, the
+// shim passes `Instant::now()` in. A `Gate` shared by every shim pauses the whole network, or lets
+// a single packet cross a single link.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{select_biased, unbounded, Receiver, Sender};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use wg_2024::{
+    controller::DroneEvent,
+    network::{NodeId, SourceRoutingHeader},
+    packet::{Nack, NackType, Packet, PacketType},
+};
+
+use super::{controller::NodeEvent, initializer::link_seed};
+
+// How long an empty shim sleeps before looking at its channels again
+const IDLE: Duration = Duration::from_secs(1);
+// How often a shim holding a packet back looks at the gate again
+const HELD: Duration = Duration::from_millis(20);
+
+// [simulation.links] for every link, a [[link]] with `from` and `to` for a single direction
+// delay_ms = 0    (time a packet takes to cross the link)
+// jitter_ms = 0   (the delay varies by up to this much either way, packets keep their order)
+// bandwidth = 0   (bytes per second, 0 for unlimited)
+// queue = 0       (packets waiting for the link to be free, more are dropped; 0 for unlimited)
+// loss = 0.0      (share of the fragments lost on the way, answered with a Nack like a drop)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkParams {
+    pub delay_ms: u64,
+    pub jitter_ms: u64,
+    pub bandwidth: u64,
+    pub queue: usize,
+    pub loss: f32,
+}
+
+impl LinkParams {
+    pub fn check(&self) -> Result<(), String> {
+        if self.jitter_ms > self.delay_ms || !(0.0..=1.0).contains(&self.loss) {
+            return Err(format!(
+                "Invalid link {:?}, needs jitter_ms <= delay_ms and a loss between 0 and 1",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Why a shim dropped a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkDrop {
+    QueueFull,
+    Lost,
+}
+
+impl LinkDrop {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkDrop::QueueFull => "queue full",
+            LinkDrop::Lost => "lost",
+        }
+    }
+}
+
+// Bytes a packet takes on the wire, close enough for the bandwidth: header, ids and payload
+pub fn wire_size(packet: &Packet) -> usize {
+    let header = 8 + 1 + packet.routing_header.hops.len(); // session id, hop index, hops
+    header
+        + match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => 8 + 8 + 1 + fragment.length as usize,
+            PacketType::Ack(_) => 8,
+            PacketType::Nack(_) => 8 + 2,
+            PacketType::FloodRequest(request) => 8 + 1 + 2 * request.path_trace.len(),
+            PacketType::FloodResponse(response) => 8 + 2 * response.path_trace.len(),
+        }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    start: Instant, // when it gets on the link
+    due: Instant,   // when it comes out at the other end
+    packet: Packet,
+}
+
+// The packets on a single direction of a link, in the order they were sent
+#[derive(Debug)]
+pub struct LinkQueue {
+    params: LinkParams,
+    packets: VecDeque<InFlight>,
+    free_at: Option<Instant>, // when the last packet is all on the link
+}
+
+impl LinkQueue {
+    pub fn new(params: LinkParams) -> Self {
+        Self {
+            params,
+            packets: VecDeque::new(),
+            free_at: None,
+        }
+    }
+
+    pub fn params(&self) -> LinkParams {
+        self.params
+    }
+
+    // Packets already on the link keep the times they got
+    pub fn set_params(&mut self, params: LinkParams) {
+        self.params = params;
+    }
+
+    // Packets waiting for the link to be free
+    pub fn waiting(&self, now: Instant) -> usize {
+        self.packets.iter().filter(|p| p.start > now).count()
+    }
+
+    // Plans the delivery of a packet, `jitter` goes from -1 to 1. The packet is given back
+    // when the queue is full.
+    pub fn push(&mut self, packet: Packet, now: Instant, jitter: f64) -> Result<(), Packet> {
+        if self.params.queue > 0 && self.waiting(now) >= self.params.queue {
+            return Err(packet);
+        }
+        let start = self.free_at.map_or(now, |free| free.max(now));
+        let sending = match self.params.bandwidth {
+            0 => Duration::ZERO,
+            bandwidth => Duration::from_nanos(wire_size(&packet) as u64 * 1_000_000_000 / bandwidth),
+        };
+        self.free_at = Some(start + sending);
+        let delay = self.params.delay_ms as f64 + jitter.clamp(-1.0, 1.0) * self.params.jitter_ms as f64;
+        let mut due = start + sending + Duration::from_secs_f64(delay.max(0.0) / 1000.0);
+        // a link never reorders what goes through it
+        if let Some(last) = self.packets.back() {
+            due = due.max(last.due);
+        }
+        self.packets.push_back(InFlight { start, due, packet });
+        Ok(())
+    }
+
+    pub fn pop_due(&mut self, now: Instant) -> Option<Packet> {
+        if self.packets.front()?.due > now {
+            return None;
+        }
+        self.packets.pop_front().map(|p| p.packet)
+    }
+
+    // How long until the next packet comes out, None when the link is empty
+    pub fn until_due(&self, now: Instant) -> Option<Duration> {
+        self.packets.front().map(|p| p.due.saturating_duration_since(now))
+    }
+}
+
+pub enum LinkCommand {
+    Set(LinkParams),
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    paused: bool,
+    steps: usize, // packets allowed across while paused
+}
+
+// Holds every packet that is due on any link while paused
+#[derive(Debug, Clone, Default)]
+pub struct Gate(Arc<Mutex<GateState>>);
+
+impl Gate {
+    pub fn pause(&self) {
+        self.0.lock().unwrap().paused = true;
+    }
+
+    // Packets held back all go at once, in the order of their links
+    pub fn resume(&self) {
+        *self.0.lock().unwrap() = GateState::default();
+    }
+
+    // Lets the next due packet, on whichever link, across; pauses first when needed
+    pub fn step(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.paused = true;
+        state.steps += 1;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.lock().unwrap().paused
+    }
+
+    // Steps not taken by a packet yet
+    pub fn pending_steps(&self) -> usize {
+        self.0.lock().unwrap().steps
+    }
+
+    // Whether a due packet can cross now, uses up a step when paused
+    fn pass(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if !state.paused {
+            return true;
+        }
+        if state.steps == 0 {
+            return false;
+        }
+        state.steps -= 1;
+        true
+    }
+}
+
+// The thread between `from` and the packet channel of `to`
+struct LinkShim {
+    from: NodeId,
+    to: NodeId,
+    queue: LinkQueue,
+    rng: StdRng,
+    packet_recv: Receiver<Packet>,
+    packet_send: Sender<Packet>,
+    back_send: Sender<Packet>, // the channel of `from`, for the Nacks of lost fragments
+    command_recv: Receiver<LinkCommand>,
+    event_send: Sender<NodeEvent>,
+    controller_send: Sender<DroneEvent>,
+    gate: Gate,
+}
+
+impl LinkShim {
+    fn run(&mut self) {
+        loop {
+            if !self.deliver() {
+                return;
+            }
+            let wait = self.wait().unwrap_or(IDLE);
+            select_biased! {
+                recv(self.command_recv) -> command => match command {
+                    Ok(LinkCommand::Set(params)) => self.queue.set_params(params),
+                    Err(_) => return self.drain(), // the link was removed
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.push(packet),
+                    Err(_) => return self.drain(), // `from` dropped its sender
+                },
+                default(wait) => {}
+            }
+        }
+    }
+
+    fn push(&mut self, packet: Packet) {
+        // both rolls every time, so a seed gives the same run whatever the parameters
+        let lost = self.rng.r#gen::<f32>() < self.queue.params().loss;
+        let jitter = self.rng.gen_range(-1.0..=1.0);
+        if lost {
+            return self.lose(packet);
+        }
+        if let Err(packet) = self.queue.push(packet, Instant::now(), jitter) {
+            self.dropped(&packet, LinkDrop::QueueFull);
+        }
+    }
+
+    // A lost fragment is Nacked back to `from` as if `to` had dropped it, the packets that
+    // can't be dropped go through the controller
+    fn lose(&self, packet: Packet) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            self.controller_send.send(DroneEvent::ControllerShortcut(packet)).ok();
+            return;
+        };
+        self.dropped(&packet, LinkDrop::Lost);
+        let header = &packet.routing_header;
+        let hops = header.hops.iter().take(header.hop_index + 1).rev().copied().collect::<Vec<_>>();
+        if hops.len() < 2 {
+            return;
+        }
+        let nack = Nack { fragment_index: fragment.fragment_index, nack_type: NackType::Dropped };
+        let nack = Packet::new_nack(SourceRoutingHeader::with_first_hop(hops), packet.session_id, nack);
+        self.back_send.send(nack).ok();
+    }
+
+    fn dropped(&self, packet: &Packet, reason: LinkDrop) {
+        self.event_send
+            .send(NodeEvent::LinkDropped { from: self.from, to: self.to, session_id: packet.session_id, reason })
+            .ok();
+    }
+
+    // Sends what is due and the gate lets through, false once `to` is gone
+    fn deliver(&mut self) -> bool {
+        let now = Instant::now();
+        while self.queue.until_due(now) == Some(Duration::ZERO) && self.gate.pass() {
+            let Some(packet) = self.queue.pop_due(now) else {
+                break;
+            };
+            if self.packet_send.send(packet).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Until the next packet is due, or a little while when the gate holds one back
+    fn wait(&self) -> Option<Duration> {
+        self.queue
+            .until_due(Instant::now())
+            .map(|wait| if wait.is_zero() { HELD } else { wait })
+    }
+
+    // Nothing new comes in, what is already on the link still arrives
+    fn drain(&mut self) {
+        while let Ok(packet) = self.packet_recv.try_recv() {
+            self.push(packet);
+        }
+        while let Some(wait) = self.wait() {
+            thread::sleep(wait);
+            if !self.deliver() {
+                return;
+            }
+        }
+    }
+}
+
+// The shims of every link of the run, the controller keeps it to change them while it runs
+pub struct Links {
+    defaults: LinkParams,
+    params: HashMap<(NodeId, NodeId), LinkParams>, // set by the config or the controller, by direction
+    shims: HashMap<(NodeId, NodeId), Sender<LinkCommand>>,
+    event_send: Sender<NodeEvent>,
+    controller_send: Sender<DroneEvent>,
+    seed: u64,
+    gate: Gate,
+}
+
+impl Links {
+    pub fn new(
+        defaults: LinkParams,
+        params: HashMap<(NodeId, NodeId), LinkParams>,
+        event_send: Sender<NodeEvent>,
+        controller_send: Sender<DroneEvent>,
+        seed: u64,
+    ) -> Self {
+        Self {
+            defaults,
+            params,
+            shims: HashMap::new(),
+            event_send,
+            controller_send,
+            seed,
+            gate: Gate::default(),
+        }
+    }
+
+    pub fn gate(&self) -> &Gate {
+        &self.gate
+    }
+
+    pub fn defaults(&self) -> LinkParams {
+        self.defaults
+    }
+
+    pub fn params(&self, from: NodeId, to: NodeId) -> LinkParams {
+        self.params.get(&(from, to)).copied().unwrap_or(self.defaults)
+    }
+
+    // Directions with parameters of their own, sorted
+    pub fn custom(&self) -> Vec<((NodeId, NodeId), LinkParams)> {
+        let mut custom = self.params.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        custom.sort_by_key(|(k, _)| *k);
+        custom
+    }
+
+    // Starts the shim in front of `packet_send`, the channel of `to`; `from` sends on the returned one
+    // and gets the Nacks of its lost fragments on `back_send`
+    pub fn connect(
+        &mut self,
+        from: NodeId,
+        to: NodeId,
+        packet_send: Sender<Packet>,
+        back_send: Sender<Packet>,
+    ) -> Sender<Packet> {
+        let (shim_send, shim_recv) = unbounded();
+        let (command_send, command_recv) = unbounded();
+        let mut shim = LinkShim {
+            from,
+            to,
+            queue: LinkQueue::new(self.params(from, to)),
+            rng: StdRng::seed_from_u64(link_seed(self.seed, from, to)),
+            packet_recv: shim_recv,
+            packet_send,
+            back_send,
+            command_recv,
+            event_send: self.event_send.clone(),
+            controller_send: self.controller_send.clone(),
+            gate: self.gate.clone(),
+        };
+        thread::spawn(move || shim.run());
+        // an older shim of the same direction stops here
+        self.shims.insert((from, to), command_send);
+        shim_send
+    }
+
+    pub fn disconnect(&mut self, from: NodeId, to: NodeId) {
+        self.shims.remove(&(from, to));
+    }
+
+    pub fn set(&mut self, from: NodeId, to: NodeId, params: LinkParams) -> Result<(), String> {
+        params.check()?;
+        self.params.insert((from, to), params);
+        if let Some(shim) = self.shims.get(&(from, to)) {
+            shim.send(LinkCommand::Set(params)).ok();
+        }
+        Ok(())
+    }
+
+    // New defaults, the parameters of single links are dropped
+    pub fn set_all(&mut self, params: LinkParams) -> Result<(), String> {
+        params.check()?;
+        self.defaults = params;
+        self.params.clear();
+        for shim in self.shims.values() {
+            shim.send(LinkCommand::Set(params)).ok();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fragment(session_id: u64) -> Packet {
+        Packet::new_fragment(
+            wg_2024::network::SourceRoutingHeader::with_first_hop(vec![1, 2]),
+            session_id,
+            wg_2024::packet::Fragment::new(0, 1, [0; 128]),
+        )
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_delay_and_bandwidth() {
+        let start = Instant::now();
+        let mut link = LinkQueue::new(LinkParams { delay_ms: 100, ..Default::default() });
+        link.push(fragment(1), start, 0.0).unwrap();
+        assert_eq!(link.until_due(start), Some(Duration::from_millis(100)));
+        assert!(link.pop_due(ms(start, 99)).is_none());
+        assert_eq!(link.pop_due(ms(start, 100)).unwrap().session_id, 1);
+
+        // one fragment every 100ms at 10 of them per second
+        let size = wire_size(&fragment(0)) as u64;
+        let mut link = LinkQueue::new(LinkParams { bandwidth: size * 10, queue: 2, ..Default::default() });
+        for session_id in 0..4 {
+            let res = link.push(fragment(session_id), start, 0.0);
+            assert_eq!(res.is_ok(), session_id < 3, "{}", session_id);
+        }
+        assert_eq!(link.waiting(start), 2);
+        assert_eq!(link.until_due(start), Some(Duration::from_millis(100)));
+        let arrived = (1..=3)
+            .filter_map(|n| link.pop_due(ms(start, n * 100)).map(|p| p.session_id))
+            .collect::<Vec<_>>();
+        assert_eq!(arrived, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_jitter_keeps_order() {
+        let start = Instant::now();
+        let mut link = LinkQueue::new(LinkParams { delay_ms: 100, jitter_ms: 50, ..Default::default() });
+        link.push(fragment(1), start, 1.0).unwrap();
+        link.push(fragment(2), ms(start, 10), -1.0).unwrap();
+        assert_eq!(link.until_due(start), Some(Duration::from_millis(150)));
+        assert_eq!(link.pop_due(ms(start, 150)).unwrap().session_id, 1);
+        assert_eq!(link.pop_due(ms(start, 150)).unwrap().session_id, 2);
+        assert!(LinkParams { jitter_ms: 10, ..Default::default() }.check().is_err());
+        assert!(LinkParams { loss: 1.5, ..Default::default() }.check().is_err());
+    }
+
+    fn new_links(event_send: Sender<NodeEvent>, controller_send: Sender<DroneEvent>) -> Links {
+        Links::new(LinkParams::default(), HashMap::new(), event_send, controller_send, 0)
+    }
+
+    #[test]
+    fn test_gate() {
+        let (event_send, _event_recv) = unbounded();
+        let (controller_send, _controller_recv) = unbounded();
+        let (packet_send, packet_recv) = unbounded();
+        let (back_send, _back_recv) = unbounded();
+        let mut links = new_links(event_send, controller_send);
+        let to_2 = links.connect(1, 2, packet_send.clone(), back_send.clone());
+        let to_3 = links.connect(1, 3, packet_send, back_send);
+        let wait = Duration::from_millis(200);
+        links.gate().pause();
+        to_2.send(fragment(1)).unwrap();
+        to_3.send(fragment(2)).unwrap();
+        to_2.send(fragment(3)).unwrap();
+        assert!(packet_recv.recv_timeout(wait).is_err());
+
+        // one hop per step, on whichever link has one due
+        links.gate().step();
+        let first = packet_recv.recv_timeout(wait).unwrap().session_id;
+        assert!(first == 1 || first == 2, "{}", first);
+        assert!(packet_recv.recv_timeout(wait).is_err());
+        assert_eq!(links.gate().pending_steps(), 0);
+        assert!(links.gate().is_paused());
+
+        links.gate().resume();
+        let mut rest = (0..2)
+            .map(|_| packet_recv.recv_timeout(wait).unwrap().session_id)
+            .collect::<Vec<_>>();
+        rest.push(first);
+        rest.sort();
+        assert_eq!(rest, vec![1, 2, 3]);
+        assert!(!links.gate().is_paused());
+    }
+
+    #[test]
+    fn test_shims() {
+        let (event_send, event_recv) = unbounded();
+        let (controller_send, _controller_recv) = unbounded();
+        let (packet_send, packet_recv) = unbounded();
+        let (back_send, back_recv) = unbounded();
+        let mut links = new_links(event_send, controller_send);
+        let to_2 = links.connect(1, 2, packet_send, back_send);
+        to_2.send(fragment(1)).unwrap();
+        assert_eq!(packet_recv.recv_timeout(Duration::from_secs(1)).unwrap().session_id, 1);
+
+        links.set(1, 2, LinkParams { delay_ms: 200, ..Default::default() }).unwrap();
+        assert_eq!(links.custom().len(), 1);
+        let sent = Instant::now();
+        to_2.send(fragment(2)).unwrap();
+        assert_eq!(packet_recv.recv_timeout(Duration::from_secs(1)).unwrap().session_id, 2);
+        assert!(sent.elapsed() >= Duration::from_millis(200));
+
+        // a lossy direction drops every fragment, says so and Nacks it back to the sender
+        links.set(1, 2, LinkParams { loss: 1.0, ..Default::default() }).unwrap();
+        to_2.send(fragment(4)).unwrap();
+        let event = event_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(
+            event,
+            NodeEvent::LinkDropped { from: 1, to: 2, session_id: 4, reason: LinkDrop::Lost }
+        ));
+        let nack = back_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(nack.session_id, 4);
+        assert_eq!(nack.routing_header.hops, vec![2, 1]);
+        assert_eq!(nack.routing_header.hop_index, 1);
+        assert!(matches!(
+            nack.pack_type,
+            PacketType::Nack(Nack { fragment_index: 0, nack_type: NackType::Dropped })
+        ));
+        assert!(packet_recv.try_recv().is_err());
+        links.set(1, 2, LinkParams { delay_ms: 200, ..Default::default() }).unwrap();
+
+        // the packets on the link when it is removed still arrive
+        to_2.send(fragment(3)).unwrap();
+        links.disconnect(1, 2);
+        assert_eq!(packet_recv.recv_timeout(Duration::from_secs(1)).unwrap().session_id, 3);
+        assert!(event_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_loss_spares_acks_and_nacks() {
+        let (event_send, event_recv) = unbounded();
+        let (controller_send, controller_recv) = unbounded();
+        let (packet_send, packet_recv) = unbounded();
+        let (back_send, back_recv) = unbounded();
+        let mut links = new_links(event_send, controller_send);
+        let to_2 = links.connect(1, 2, packet_send, back_send);
+        links.set(1, 2, LinkParams { loss: 1.0, ..Default::default() }).unwrap();
+
+        let header = wg_2024::network::SourceRoutingHeader::with_first_hop(vec![1, 2, 3]);
+        to_2.send(Packet::new_ack(header.clone(), 5, 0)).unwrap();
+        let nack = Nack { fragment_index: 1, nack_type: NackType::Dropped };
+        to_2.send(Packet::new_nack(header, 6, nack)).unwrap();
+
+        // both reach the controller, which sends them on to node 3
+        let wait = Duration::from_secs(1);
+        let arrived = (0..2)
+            .map(|_| match controller_recv.recv_timeout(wait).unwrap() {
+                DroneEvent::ControllerShortcut(packet) => packet,
+                other => panic!("{:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(arrived[0].pack_type, PacketType::Ack(_)));
+        assert_eq!(arrived[0].session_id, 5);
+        assert!(matches!(arrived[1].pack_type, PacketType::Nack(_)));
+        assert_eq!(arrived[1].session_id, 6);
+        assert!(event_recv.try_recv().is_err());
+        assert!(packet_recv.try_recv().is_err());
+        assert!(back_recv.try_recv().is_err());
+    }
+}
@@ -0,0 +1,589 @@
+// A drone that follows the protocol like the real ones, plus a policy that misbehaves on purpose
+// and always the same way: given fragment indices are dropped, nacked, duplicated or corrupted,
+// packets get delayed or reordered and the drone can crash after a number of packets. Its PDR
+// rolls come from a generator seeded with its id, or with its node seed in a run, so a run with
+// it can be repeated exactly.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{select_biased, Receiver, Sender};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use wg_2024::{
+    controller::{DroneCommand, DroneEvent},
+    drone::Drone,
+    network::{NodeId, SourceRoutingHeader},
+    packet::{FloodRequest, FloodResponse, Nack, NackType, NodeType, Packet, PacketType},
+};
+
+// How long reordered packets wait for the rest of their group when nothing else comes in
+const IDLE: Duration = Duration::from_millis(50);
+
+// [[drone]] with implementation = "Mock" takes a `mock` table, e.g.
+// mock = { nack = [0], duplicate = [2], times = 1, crash_after = 40 }
+// drop = []           (fragment indices lost without a Nack, reported as dropped)
+// nack = []           (fragment indices answered with a Nack Dropped, as a PDR drop)
+// duplicate = []      (fragment indices forwarded twice)
+// corrupt = []        (fragment indices forwarded with their data flipped)
+// times = 1           (how many times a fragment of a session gets it, 0 for every time)
+// reorder = 0         (forwards packets in groups of this many, last one first)
+// delay_ms = 0        (holds each packet this long before forwarding it, still taking commands)
+// crash_after = none  (crashes as on a Crash command after this many packets)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MockPolicy {
+    pub drop: Vec<u64>,
+    pub nack: Vec<u64>,
+    pub duplicate: Vec<u64>,
+    pub corrupt: Vec<u64>,
+    pub times: u32,
+    pub reorder: usize,
+    pub delay_ms: u64,
+    pub crash_after: Option<usize>,
+}
+
+impl Default for MockPolicy {
+    fn default() -> Self {
+        Self {
+            drop: Vec::new(),
+            nack: Vec::new(),
+            duplicate: Vec::new(),
+            corrupt: Vec::new(),
+            times: 1,
+            reorder: 0,
+            delay_ms: 0,
+            crash_after: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Misbehaviour {
+    Drop,
+    Nack,
+    Duplicate,
+    Corrupt,
+}
+
+pub struct MockDrone {
+    id: NodeId,
+    controller_send: Sender<DroneEvent>,
+    controller_recv: Receiver<DroneCommand>,
+    packet_recv: Receiver<Packet>,
+    packet_send: HashMap<NodeId, Sender<Packet>>,
+    pdr: f32,
+    rng: StdRng,
+    policy: MockPolicy,
+    hits: HashMap<(Misbehaviour, u64, u64), u32>, // by session and fragment index
+    floods: HashSet<(u64, NodeId)>,
+    held: Vec<(NodeId, Packet)>, // waiting for the rest of their reorder group
+    delayed: VecDeque<(Instant, NodeId, Packet)>, // waiting out `delay_ms`, in the order they came
+    handled: usize,
+    crashed: bool,
+}
+
+impl Drone for MockDrone {
+    fn new(
+        id: NodeId,
+        controller_send: Sender<DroneEvent>,
+        controller_recv: Receiver<DroneCommand>,
+        packet_recv: Receiver<Packet>,
+        packet_send: HashMap<NodeId, Sender<Packet>>,
+        pdr: f32,
+    ) -> Self {
+        Self {
+            id,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            pdr,
+            rng: StdRng::seed_from_u64(id as u64),
+            policy: MockPolicy::default(),
+            hits: HashMap::new(),
+            floods: HashSet::new(),
+            held: Vec::new(),
+            delayed: VecDeque::new(),
+            handled: 0,
+            crashed: false,
+        }
+    }
+
+    fn run(&mut self) {
+        while !self.crashed {
+            let wait = self.until_delayed().map_or(IDLE, |wait| wait.min(IDLE));
+            select_biased! {
+                recv(self.controller_recv) -> command => match command {
+                    Ok(command) => self.command(command),
+                    Err(_) => return,
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.handle(packet),
+                    Err(_) => return,
+                },
+                // a reorder group waits for the delayed packets that come before its end
+                default(wait) => {
+                    if self.delayed.is_empty() {
+                        self.release();
+                    }
+                }
+            }
+            self.forward_delayed(Instant::now());
+        }
+        self.drain();
+    }
+}
+
+impl MockDrone {
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: MockPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn command(&mut self, command: DroneCommand) {
+        match command {
+            DroneCommand::AddSender(id, sender) => {
+                self.packet_send.insert(id, sender);
+            }
+            DroneCommand::RemoveSender(id) => {
+                self.packet_send.remove(&id);
+            }
+            DroneCommand::SetPacketDropRate(pdr) => self.pdr = pdr,
+            DroneCommand::Crash => self.crashed = true,
+        }
+    }
+
+    fn handle(&mut self, packet: Packet) {
+        self.handled += 1;
+        match packet.pack_type.clone() {
+            PacketType::FloodRequest(request) => self.flood(packet, request),
+            _ => self.route(packet),
+        }
+        if self.policy.crash_after.is_some_and(|n| self.handled >= n) {
+            self.crashed = true;
+        }
+    }
+
+    // Whether the policy applies `what` to this fragment now, counting the times it did
+    fn hit(&mut self, what: Misbehaviour, session_id: u64, index: u64) -> bool {
+        let indices = match what {
+            Misbehaviour::Drop => &self.policy.drop,
+            Misbehaviour::Nack => &self.policy.nack,
+            Misbehaviour::Duplicate => &self.policy.duplicate,
+            Misbehaviour::Corrupt => &self.policy.corrupt,
+        };
+        if !indices.contains(&index) {
+            return false;
+        }
+        let hits = self.hits.entry((what, session_id, index)).or_insert(0);
+        if self.policy.times > 0 && *hits >= self.policy.times {
+            return false;
+        }
+        *hits += 1;
+        true
+    }
+
+    fn route(&mut self, mut packet: Packet) {
+        let position = packet.routing_header.hop_index;
+        if packet.routing_header.hops.get(position) != Some(&self.id) {
+            return self.refuse(packet, position, NackType::UnexpectedRecipient(self.id));
+        }
+        let Some(&next) = packet.routing_header.hops.get(position + 1) else {
+            return self.refuse(packet, position, NackType::DestinationIsDrone);
+        };
+        if !self.packet_send.contains_key(&next) {
+            return self.refuse(packet, position, NackType::ErrorInRouting(next));
+        }
+        packet.routing_header.hop_index += 1;
+
+        let mut copies = 1;
+        if let PacketType::MsgFragment(fragment) = &mut packet.pack_type {
+            let (session_id, index) = (packet.session_id, fragment.fragment_index);
+            // rolled for every fragment before the policy is looked at, so the policy doesn't
+            // shift the PDR rolls of later fragments
+            let unlucky = self.rng.r#gen::<f32>() < self.pdr;
+            if self.hit(Misbehaviour::Drop, session_id, index) {
+                self.controller_send.send(DroneEvent::PacketDropped(packet)).ok();
+                return;
+            }
+            if self.hit(Misbehaviour::Nack, session_id, index) || unlucky {
+                // reported past this drone like a Drop, the stats charge it to hops[hop_index - 1]
+                self.controller_send.send(DroneEvent::PacketDropped(packet.clone())).ok();
+                return self.nack(&packet, position, index, NackType::Dropped);
+            }
+            if self.hit(Misbehaviour::Corrupt, session_id, index) {
+                for byte in fragment.data.iter_mut().take(fragment.length as usize) {
+                    *byte = !*byte;
+                }
+            }
+            if self.hit(Misbehaviour::Duplicate, session_id, index) {
+                copies = 2;
+            }
+        }
+        for _ in 0..copies {
+            self.delay(next, packet.clone());
+        }
+    }
+
+    fn delay(&mut self, next: NodeId, packet: Packet) {
+        if self.policy.delay_ms == 0 {
+            return self.hold(next, packet);
+        }
+        let due = Instant::now() + Duration::from_millis(self.policy.delay_ms);
+        self.delayed.push_back((due, next, packet));
+    }
+
+    fn hold(&mut self, next: NodeId, packet: Packet) {
+        self.held.push((next, packet));
+        if self.held.len() >= self.policy.reorder {
+            self.release();
+        }
+    }
+
+    // How long until the first delayed packet can go, None when there is none
+    fn until_delayed(&self) -> Option<Duration> {
+        self.delayed.front().map(|(due, _, _)| due.saturating_duration_since(Instant::now()))
+    }
+
+    fn forward_delayed(&mut self, now: Instant) {
+        while self.delayed.front().is_some_and(|(due, _, _)| *due <= now) {
+            let Some((_, next, packet)) = self.delayed.pop_front() else {
+                break;
+            };
+            self.hold(next, packet);
+        }
+    }
+
+    // Sends what is held, a reorder group goes last packet first
+    fn release(&mut self) {
+        while let Some((next, packet)) = self.held.pop() {
+            self.send(next, packet);
+        }
+    }
+
+    // Everything still waiting goes now, the delayed packets after the reorder group
+    fn flush(&mut self) {
+        self.release();
+        while let Some((_, next, packet)) = self.delayed.pop_front() {
+            self.send(next, packet);
+        }
+    }
+
+    fn send(&self, to: NodeId, packet: Packet) {
+        let sent = self.packet_send.get(&to).is_some_and(|sender| sender.send(packet.clone()).is_ok());
+        if sent {
+            self.controller_send.send(DroneEvent::PacketSent(packet)).ok();
+        } else if !matches!(packet.pack_type, PacketType::MsgFragment(_) | PacketType::FloodRequest(_)) {
+            // acks, nacks and flood responses can't be lost, the controller delivers them
+            self.controller_send.send(DroneEvent::ControllerShortcut(packet)).ok();
+        }
+    }
+
+    // Fragments get a Nack back, the rest goes through the controller
+    fn refuse(&mut self, packet: Packet, position: usize, nack_type: NackType) {
+        match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => {
+                let index = fragment.fragment_index;
+                self.nack(&packet, position, index, nack_type);
+            }
+            _ => {
+                self.controller_send.send(DroneEvent::ControllerShortcut(packet)).ok();
+            }
+        }
+    }
+
+    // Back to the source along the hops before `position`, the place of this drone in the route
+    fn nack(&self, packet: &Packet, position: usize, fragment_index: u64, nack_type: NackType) {
+        let hops = &packet.routing_header.hops;
+        let mut back = vec![self.id];
+        back.extend(hops[..position.min(hops.len())].iter().rev());
+        if back.len() < 2 {
+            return;
+        }
+        let to = back[1];
+        let nack = Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(back),
+            packet.session_id,
+            Nack { fragment_index, nack_type },
+        );
+        self.send(to, nack);
+    }
+
+    fn flood(&mut self, mut packet: Packet, mut request: FloodRequest) {
+        let previous = request.path_trace.last().map_or(request.initiator_id, |(id, _)| *id);
+        request.path_trace.push((self.id, NodeType::Drone));
+        let first_time = self.floods.insert((request.flood_id, request.initiator_id));
+        let others = self
+            .packet_send
+            .keys()
+            .copied()
+            .filter(|id| *id != previous)
+            .collect::<Vec<_>>();
+        if first_time && !others.is_empty() {
+            packet.pack_type = PacketType::FloodRequest(request);
+            for id in others {
+                self.send(id, packet.clone());
+            }
+            return;
+        }
+        let mut hops = request.path_trace.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        hops.reverse();
+        let response = Packet::new_flood_response(
+            SourceRoutingHeader::with_first_hop(hops),
+            packet.session_id,
+            FloodResponse { flood_id: request.flood_id, path_trace: request.path_trace },
+        );
+        self.send(previous, response);
+    }
+
+    // After a Crash, until every neighbour let go of its channel: acks, nacks and flood
+    // responses still get through, fragments get an ErrorInRouting and flood requests are dropped
+    fn drain(&mut self) {
+        self.flush();
+        loop {
+            select_biased! {
+                recv(self.controller_recv) -> command => match command {
+                    Ok(DroneCommand::RemoveSender(id)) => {
+                        self.packet_send.remove(&id);
+                    }
+                    Ok(_) => {}
+                    Err(_) => break, // the controller is gone, only the packets are left
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.crashed_packet(packet),
+                    Err(_) => return,
+                },
+            }
+        }
+        while let Ok(packet) = self.packet_recv.recv() {
+            self.crashed_packet(packet);
+        }
+    }
+
+    fn crashed_packet(&mut self, packet: Packet) {
+        match &packet.pack_type {
+            PacketType::FloodRequest(_) => {}
+            PacketType::MsgFragment(fragment) => {
+                let (position, index) = (packet.routing_header.hop_index, fragment.fragment_index);
+                self.nack(&packet, position, index, NackType::ErrorInRouting(self.id));
+            }
+            _ => self.route(packet),
+        }
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use std::thread;
+    use wg_2024::packet::Fragment;
+
+    struct Bench {
+        controller_recv: Receiver<DroneEvent>,
+        command_send: Sender<DroneCommand>,
+        packet_send: Sender<Packet>,
+        at_1: Receiver<Packet>,
+        at_3: Receiver<Packet>,
+    }
+
+    // Drone 2 between 1 and 3
+    fn bench(policy: MockPolicy) -> Bench {
+        let (controller_send, controller_recv) = unbounded();
+        let (command_send, command_recv) = unbounded();
+        let (packet_send, packet_recv) = unbounded();
+        let (send_1, at_1) = unbounded();
+        let (send_3, at_3) = unbounded();
+        let mut drone = MockDrone::new(
+            2,
+            controller_send,
+            command_recv,
+            packet_recv,
+            HashMap::from([(1, send_1), (3, send_3)]),
+            0.0,
+        )
+        .with_policy(policy);
+        thread::spawn(move || drone.run());
+        Bench { controller_recv, command_send, packet_send, at_1, at_3 }
+    }
+
+    fn fragment(index: u64) -> Packet {
+        let header = SourceRoutingHeader::with_first_hop(vec![1, 2, 3]);
+        Packet::new_fragment(header, 7, Fragment::new(index, 8, [1; 128]))
+    }
+
+    fn index_of(packet: Packet) -> u64 {
+        match packet.pack_type {
+            PacketType::MsgFragment(fragment) => fragment.fragment_index,
+            PacketType::Nack(nack) => nack.fragment_index,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn wait() -> Duration {
+        Duration::from_millis(300)
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = MockPolicy {
+            drop: vec![1],
+            nack: vec![2],
+            duplicate: vec![3],
+            corrupt: vec![4],
+            ..Default::default()
+        };
+        let bench = bench(policy);
+        for index in 0..5 {
+            bench.packet_send.send(fragment(index)).unwrap();
+        }
+        let forwarded = (0..4).map(|_| bench.at_3.recv_timeout(wait()).unwrap()).collect::<Vec<_>>();
+        let indices = forwarded.iter().cloned().map(index_of).collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 3, 3, 4]);
+        assert_eq!(forwarded[0].routing_header.hop_index, 2);
+        let PacketType::MsgFragment(corrupted) = &forwarded[3].pack_type else {
+            panic!();
+        };
+        assert_eq!(corrupted.data[0], !1);
+
+        let nack = bench.at_1.recv_timeout(wait()).unwrap();
+        assert_eq!(nack.routing_header.hops, vec![2, 1]);
+        assert!(matches!(&nack.pack_type, PacketType::Nack(Nack { fragment_index: 2, nack_type: NackType::Dropped })));
+        let dropped = bench
+            .controller_recv
+            .try_iter()
+            .filter(|event| matches!(event, DroneEvent::PacketDropped(_)))
+            .count();
+        assert_eq!(dropped, 2);
+
+        // times = 1, so the resends get through
+        bench.packet_send.send(fragment(1)).unwrap();
+        bench.packet_send.send(fragment(2)).unwrap();
+        assert_eq!(index_of(bench.at_3.recv_timeout(wait()).unwrap()), 1);
+        assert_eq!(index_of(bench.at_3.recv_timeout(wait()).unwrap()), 2);
+        assert!(bench.at_1.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_policy_keeps_pdr_rolls() {
+        // the fragments the PDR nacks only depend on the seed, a dropped one still uses its roll
+        let unlucky = |policy: MockPolicy| {
+            let bench = bench(policy);
+            bench.command_send.send(DroneCommand::SetPacketDropRate(0.5)).unwrap();
+            for index in 0..20 {
+                bench.packet_send.send(fragment(index)).unwrap();
+            }
+            std::iter::from_fn(|| bench.at_1.recv_timeout(wait()).ok()).map(index_of).collect::<Vec<_>>()
+        };
+        let plain = unlucky(MockPolicy::default());
+        assert!(!plain.is_empty() && plain.len() < 20, "{:?}", plain);
+        let dropping = unlucky(MockPolicy { drop: vec![0], ..Default::default() });
+        assert_eq!(plain.into_iter().filter(|index| *index != 0).collect::<Vec<_>>(), dropping);
+    }
+
+    #[test]
+    fn test_drops_charged_to_drone() {
+        use crate::utils::controller::stats::NetworkStats;
+
+        let bench = bench(MockPolicy { drop: vec![0], ..Default::default() });
+        bench.packet_send.send(fragment(0)).unwrap();
+        // and one the PDR drops
+        bench.command_send.send(DroneCommand::SetPacketDropRate(1.0)).unwrap();
+        bench.packet_send.send(fragment(1)).unwrap();
+        assert_eq!(index_of(bench.at_1.recv_timeout(wait()).unwrap()), 1);
+
+        let mut stats = NetworkStats::default();
+        let mut dropped = 0;
+        for event in bench.controller_recv.try_iter() {
+            if let DroneEvent::PacketDropped(packet) = &event {
+                assert_eq!(packet.routing_header.hop_index, 2);
+                dropped += 1;
+            }
+            stats.record_drone_event(&event, 0.0);
+        }
+        assert_eq!(dropped, 2);
+        assert_eq!(stats.node(2).unwrap().dropped, 2);
+        assert!(stats.node(1).is_none_or(|node| node.dropped == 0));
+    }
+
+    #[test]
+    fn test_routing_errors() {
+        let bench = bench(MockPolicy::default());
+        let mut wrong = fragment(0);
+        wrong.routing_header.hops = vec![1, 5, 3];
+        bench.packet_send.send(wrong).unwrap();
+        let mut last = fragment(1);
+        last.routing_header.hops = vec![1, 2];
+        bench.packet_send.send(last).unwrap();
+        let mut unknown = fragment(2);
+        unknown.routing_header.hops = vec![1, 2, 9];
+        bench.packet_send.send(unknown).unwrap();
+        let nacks = (0..3)
+            .map(|_| match bench.at_1.recv_timeout(wait()).unwrap().pack_type {
+                PacketType::Nack(nack) => nack.nack_type,
+                other => panic!("{:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            nacks.as_slice(),
+            [NackType::UnexpectedRecipient(2), NackType::DestinationIsDrone, NackType::ErrorInRouting(9)]
+        ));
+
+        // a flood goes on to 3, and comes back as a response the second time
+        let request = FloodRequest { flood_id: 1, initiator_id: 1, path_trace: vec![(1, NodeType::Client)] };
+        let flood = Packet::new_flood_request(SourceRoutingHeader::with_first_hop(vec![]), 9, request);
+        bench.packet_send.send(flood.clone()).unwrap();
+        bench.packet_send.send(flood).unwrap();
+        assert!(matches!(bench.at_3.recv_timeout(wait()).unwrap().pack_type, PacketType::FloodRequest(_)));
+        let response = bench.at_1.recv_timeout(wait()).unwrap();
+        assert_eq!(response.routing_header.hops, vec![2, 1]);
+        assert!(matches!(response.pack_type, PacketType::FloodResponse(_)));
+    }
+
+    #[test]
+    fn test_reorder_and_crash() {
+        let bench = bench(MockPolicy { reorder: 2, crash_after: Some(3), ..Default::default() });
+        for index in 0..3 {
+            bench.packet_send.send(fragment(index)).unwrap();
+        }
+        // a group of two last first, the third one alone once nothing else comes
+        let indices = (0..3).map(|_| index_of(bench.at_3.recv_timeout(wait()).unwrap())).collect::<Vec<_>>();
+        assert_eq!(indices, vec![1, 0, 2]);
+
+        // crashed, fragments go back as ErrorInRouting until its channel is dropped
+        bench.packet_send.send(fragment(3)).unwrap();
+        assert!(bench.at_3.recv_timeout(wait()).is_err());
+        let nack = bench.at_1.recv_timeout(wait()).unwrap();
+        assert!(matches!(nack.pack_type, PacketType::Nack(Nack { nack_type: NackType::ErrorInRouting(2), .. })));
+        drop(bench.packet_send);
+        thread::sleep(IDLE * 2);
+        assert!(bench.command_send.send(DroneCommand::SetPacketDropRate(0.5)).is_err());
+    }
+
+    #[test]
+    fn test_delay() {
+        let bench = bench(MockPolicy { delay_ms: 200, ..Default::default() });
+        let sent = Instant::now();
+        bench.packet_send.send(fragment(0)).unwrap();
+        bench.packet_send.send(fragment(1)).unwrap();
+        // both wait at the same time, not one after the other
+        let indices = (0..2).map(|_| index_of(bench.at_3.recv_timeout(wait()).unwrap())).collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 1]);
+        assert!(sent.elapsed() >= Duration::from_millis(200));
+        assert!(sent.elapsed() < Duration::from_millis(400));
+
+        // commands still go through while a packet waits
+        bench.packet_send.send(fragment(2)).unwrap();
+        bench.command_send.send(DroneCommand::RemoveSender(3)).unwrap();
+        assert!(bench.at_3.recv_timeout(wait() + wait()).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Bookkeeping of what a client or server holds per session, so the holders don't grow forever:
+// messages being reassembled time out when their sender goes quiet and share byte budgets per
+// peer and overall, finished sessions and seen flood ids are forgotten after a while. Reassemblies
+// that finished or were given up on stay known as long, so their late fragments don't start them over.
+// Like flood.rs it never reads the clock, the node passes `Instant::now()` in.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use wg_2024::{network::NodeId, packet::FRAGMENT_DSIZE};
+
+// How often the node looks for something to drop
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+// Most evicted or timed out reassemblies remembered at once, the oldest are forgotten first
+const MAX_TOMBSTONES: usize = 4096;
+
+// [simulation.sessions] for every client and server, missing fields keep the defaults
+// incomplete_timeout_ms = 30000  (a message with no new fragment for this long is dropped)
+// sent_timeout_ms = 120000       (a message never fully acked for this long is dropped)
+// finished_ttl_ms = 10000        (how long finished sessions are kept for late acks, nacks and fragments)
+// flood_id_ttl_ms = 60000        (how long a seen flood is remembered)
+// max_fragments = 100000         (messages announcing more fragments are refused)
+// peer_budget_bytes = 33554432   (reassembly buffers of a single sender)
+// global_budget_bytes = 134217728
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SessionPolicy {
+    pub incomplete_timeout_ms: u64,
+    pub sent_timeout_ms: u64,
+    pub finished_ttl_ms: u64,
+    pub flood_id_ttl_ms: u64,
+    pub max_fragments: u64,
+    pub peer_budget_bytes: usize,
+    pub global_budget_bytes: usize,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            incomplete_timeout_ms: 30_000,
+            sent_timeout_ms: 120_000,
+            finished_ttl_ms: 10_000,
+            flood_id_ttl_ms: 60_000,
+            max_fragments: 100_000,
+            peer_budget_bytes: 32 << 20,
+            global_budget_bytes: 128 << 20,
+        }
+    }
+}
+
+impl SessionPolicy {
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_fragments == 0 || self.peer_budget_bytes > self.global_budget_bytes {
+            return Err(format!(
+                "Invalid session policy {:?}, needs max_fragments > 0 and peer_budget_bytes <= global_budget_bytes",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+// What was dropped and why, a node reports the ones of every sweep
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvictionStats {
+    pub timed_out: u64,   // incomplete messages, received or sent
+    pub over_budget: u64, // reassemblies dropped to make room for a newer one
+    pub refused: u64,     // messages announcing too many fragments
+    pub finished: u64,    // sessions done with, dropped after finished_ttl_ms
+    pub flood_ids: u64,
+}
+
+impl EvictionStats {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Anything lost that a sender or a reader could notice, finished sessions don't count
+    pub fn lost(&self) -> u64 {
+        self.timed_out + self.over_budget + self.refused
+    }
+
+    pub fn add(&mut self, other: &EvictionStats) {
+        self.timed_out += other.timed_out;
+        self.over_budget += other.over_budget;
+        self.refused += other.refused;
+        self.finished += other.finished;
+        self.flood_ids += other.flood_ids;
+    }
+}
+
+// The keys the node has to drop from its holders, all (session_id, peer or own id)
+#[derive(Debug, Default, PartialEq)]
+pub struct Sweep {
+    pub incoming: Vec<(u64, NodeId)>, // incomplete reassemblies
+    pub outgoing: Vec<(u64, NodeId)>, // sent messages, acked or given up on
+    pub finished: Vec<(u64, NodeId)>, // reassemblies done, failed or cancelled
+    pub flood_ids: Vec<(u64, NodeId)>,
+    pub stats: EvictionStats,
+}
+
+#[derive(Debug, Clone)]
+struct Incoming {
+    last: Instant,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionGc {
+    policy: SessionPolicy,
+    incoming: HashMap<(u64, NodeId), Incoming>,
+    outgoing: HashMap<(u64, NodeId), Instant>, // last send or ack
+    finished: HashMap<(u64, NodeId), Instant>,
+    tombstones: HashMap<(u64, NodeId), Instant>, // reassemblies evicted or timed out
+    flood_ids: VecDeque<(Instant, (u64, NodeId))>,
+    pending: EvictionStats, // evictions not reported yet
+    next_sweep: Option<Instant>,
+}
+
+impl SessionGc {
+    pub fn new(policy: SessionPolicy) -> Self {
+        Self {
+            policy,
+            incoming: HashMap::new(),
+            outgoing: HashMap::new(),
+            finished: HashMap::new(),
+            tombstones: HashMap::new(),
+            flood_ids: VecDeque::new(),
+            pending: EvictionStats::default(),
+            next_sweep: None,
+        }
+    }
+
+    // Forgets every session, as a node does on crash
+    pub fn clear(&mut self) {
+        *self = Self::new(self.policy);
+    }
+
+    // Bytes held for reassemblies from `peer`, every peer when None
+    pub fn buffered(&self, peer: Option<NodeId>) -> usize {
+        self.incoming
+            .iter()
+            .filter(|((_, src), _)| peer.is_none_or(|peer| *src == peer))
+            .map(|(_, incoming)| incoming.bytes)
+            .sum()
+    }
+
+    // A new reassembly is about to start. Refused when it can't fit a budget on its own,
+    // otherwise the oldest reassemblies of the same peer, then of anyone, make room for it.
+    // Returns the ones to drop.
+    pub fn admit(
+        &mut self,
+        key: (u64, NodeId),
+        total_n_fragments: u64,
+        now: Instant,
+    ) -> Result<Vec<(u64, NodeId)>, String> {
+        let bytes = total_n_fragments.saturating_mul(FRAGMENT_DSIZE as u64) as usize;
+        let budget = self.policy.peer_budget_bytes.min(self.policy.global_budget_bytes);
+        if total_n_fragments > self.policy.max_fragments || bytes > budget {
+            self.pending.refused += 1;
+            return Err(format!(
+                "{} fragments from {} is more than the node takes",
+                total_n_fragments, key.1
+            ));
+        }
+        let mut evicted = Vec::new();
+        for (peer, budget) in [
+            (Some(key.1), self.policy.peer_budget_bytes),
+            (None, self.policy.global_budget_bytes),
+        ] {
+            while self.buffered(peer) + bytes > budget {
+                let Some(oldest) = self.evict_oldest(peer, now) else {
+                    break;
+                };
+                evicted.push(oldest);
+            }
+        }
+        self.incoming.insert(key, Incoming { last: now, bytes });
+        Ok(evicted)
+    }
+
+    fn evict_oldest(&mut self, peer: Option<NodeId>, now: Instant) -> Option<(u64, NodeId)> {
+        let key = *self
+            .incoming
+            .iter()
+            .filter(|((_, src), _)| peer.is_none_or(|peer| *src == peer))
+            .min_by_key(|(_, incoming)| incoming.last)?
+            .0;
+        self.incoming.remove(&key);
+        self.bury(key, now);
+        self.pending.over_budget += 1;
+        Some(key)
+    }
+
+    fn bury(&mut self, key: (u64, NodeId), now: Instant) {
+        if self.tombstones.len() >= MAX_TOMBSTONES {
+            let oldest = self.tombstones.iter().min_by_key(|(_, at)| **at).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.tombstones.remove(&oldest);
+            }
+        }
+        self.tombstones.insert(key, now);
+    }
+
+    // Whether the reassembly of `key` finished or was given up on, its late fragments are only acked
+    pub fn is_over(&self, key: &(u64, NodeId)) -> bool {
+        self.finished.contains_key(key) || self.tombstones.contains_key(key)
+    }
+
+    // A fragment of a reassembly came in
+    pub fn received(&mut self, key: (u64, NodeId), now: Instant) {
+        if let Some(incoming) = self.incoming.get_mut(&key) {
+            incoming.last = now;
+        }
+    }
+
+    // The reassembly completed, failed or was cancelled
+    pub fn finish(&mut self, key: (u64, NodeId), now: Instant) {
+        self.incoming.remove(&key);
+        self.finished.insert(key, now);
+    }
+
+    // A message was sent or one of its fragments acked
+    pub fn sent(&mut self, key: (u64, NodeId), now: Instant) {
+        self.outgoing.insert(key, now);
+    }
+
+    pub fn flood_seen(&mut self, key: (u64, NodeId), now: Instant) {
+        self.flood_ids.push_back((now, key));
+    }
+
+    // What to drop, once every SWEEP_EVERY; `acked` says whether a sent message got all its acks
+    pub fn sweep(&mut self, now: Instant, acked: impl Fn(&(u64, NodeId)) -> bool) -> Option<Sweep> {
+        if self.next_sweep.is_some_and(|next| now < next) {
+            return None;
+        }
+        self.next_sweep = Some(now + SWEEP_EVERY);
+        let older = |last: Instant, ms: u64| now.saturating_duration_since(last) >= Duration::from_millis(ms);
+        let mut sweep = Sweep::default();
+
+        sweep.incoming = self
+            .incoming
+            .iter()
+            .filter(|(_, incoming)| older(incoming.last, self.policy.incomplete_timeout_ms))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &sweep.incoming {
+            self.incoming.remove(key);
+            self.bury(*key, now);
+        }
+        self.pending.timed_out += sweep.incoming.len() as u64;
+
+        for (key, last) in &self.outgoing {
+            if acked(key) && older(*last, self.policy.finished_ttl_ms) {
+                self.pending.finished += 1;
+            } else if older(*last, self.policy.sent_timeout_ms) {
+                self.pending.timed_out += 1;
+            } else {
+                continue;
+            }
+            sweep.outgoing.push(*key);
+        }
+        for key in &sweep.outgoing {
+            self.outgoing.remove(key);
+        }
+
+        sweep.finished = self
+            .finished
+            .iter()
+            .filter(|(_, done)| older(**done, self.policy.finished_ttl_ms))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &sweep.finished {
+            self.finished.remove(key);
+        }
+        self.pending.finished += sweep.finished.len() as u64;
+        let ttl = self.policy.finished_ttl_ms;
+        self.tombstones.retain(|_, at| !older(*at, ttl));
+
+        while let Some((seen, key)) = self.flood_ids.front().copied() {
+            if !older(seen, self.policy.flood_id_ttl_ms) {
+                break;
+            }
+            self.flood_ids.pop_front();
+            sweep.flood_ids.push(key);
+        }
+        self.pending.flood_ids += sweep.flood_ids.len() as u64;
+
+        sweep.stats = std::mem::take(&mut self.pending);
+        Some(sweep)
+    }
+
+    // How long the node can wait before sweeping again
+    pub fn until_sweep(&self, now: Instant) -> Duration {
+        self.next_sweep.map_or(Duration::ZERO, |next| next.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let start = Instant::now();
+        let mut gc = SessionGc::new(SessionPolicy::default());
+        gc.admit((1, 21), 10, start).unwrap();
+        gc.admit((2, 22), 10, start).unwrap();
+        gc.sent((7, 31), start);
+        gc.sent((8, 31), start);
+        gc.flood_seen((1, 21), start);
+        assert!(gc.sweep(start, |_| false).unwrap().stats.is_empty());
+        assert!(gc.sweep(ms(start, 500), |_| false).is_none());
+
+        // 22 keeps sending, 21 went quiet
+        gc.received((2, 22), ms(start, 20_000));
+        let sweep = gc.sweep(ms(start, 30_000), |key| key.0 == 7).unwrap();
+        assert_eq!(sweep.incoming, vec![(1, 21)]);
+        assert_eq!(sweep.outgoing, vec![(7, 31)]); // acked, the other one may still be
+        assert_eq!(sweep.stats, EvictionStats { timed_out: 1, finished: 1, ..Default::default() });
+        assert!(gc.is_over(&(1, 21)));
+        assert!(!gc.is_over(&(2, 22)));
+
+        gc.finish((2, 22), ms(start, 31_000));
+        assert!(gc.is_over(&(2, 22)));
+        let sweep = gc.sweep(ms(start, 61_000), |_| false).unwrap();
+        assert_eq!(sweep.finished, vec![(2, 22)]);
+        assert_eq!(sweep.flood_ids, vec![(1, 21)]);
+        assert!(sweep.outgoing.is_empty());
+        assert!(!gc.is_over(&(1, 21)) && !gc.is_over(&(2, 22)));
+        let sweep = gc.sweep(ms(start, 120_000), |_| false).unwrap();
+        assert_eq!(sweep.outgoing, vec![(8, 31)]);
+        assert_eq!(sweep.stats.timed_out, 1);
+        assert_eq!(gc.buffered(None), 0);
+    }
+
+    #[test]
+    fn test_budgets() {
+        let start = Instant::now();
+        let policy = SessionPolicy {
+            max_fragments: 100,
+            peer_budget_bytes: 10 * FRAGMENT_DSIZE,
+            global_budget_bytes: 15 * FRAGMENT_DSIZE,
+            ..SessionPolicy::default()
+        };
+        let mut gc = SessionGc::new(policy);
+        // a peer announcing a huge message gets nothing allocated
+        assert!(gc.admit((1, 21), u64::MAX, start).is_err());
+        assert!(gc.admit((1, 21), 11, start).is_err());
+
+        assert_eq!(gc.admit((1, 21), 6, ms(start, 1)), Ok(vec![]));
+        assert_eq!(gc.admit((2, 22), 6, ms(start, 2)), Ok(vec![]));
+        // over the budget of 21, its oldest message goes
+        assert_eq!(gc.admit((3, 21), 6, ms(start, 3)), Ok(vec![(1, 21)]));
+        // over the global budget, the oldest of anyone goes
+        assert_eq!(gc.admit((4, 23), 4, ms(start, 4)), Ok(vec![(2, 22)]));
+        assert_eq!(gc.buffered(Some(21)), 6 * FRAGMENT_DSIZE);
+        assert_eq!(gc.buffered(None), 10 * FRAGMENT_DSIZE);
+        assert!(gc.is_over(&(1, 21)) && gc.is_over(&(2, 22)));
+        assert!(!gc.is_over(&(3, 21)));
+
+        let stats = gc.sweep(ms(start, 5), |_| false).unwrap().stats;
+        assert_eq!(stats, EvictionStats { over_budget: 2, refused: 2, ..Default::default() });
+        assert!(SessionPolicy { peer_budget_bytes: usize::MAX, ..policy }.check().is_err());
+    }
+}
@@ -0,0 +1,488 @@
+// Extra sections of the topology file that `wg_2024::config::Config` doesn't know about.
+// They're read from the same toml, so a config stays a single file.
+
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+
+use wg_2024::network::NodeId;
+
+use crate::utils::{
+    backup_server::{CHATSERVER, MEDIASERVER, TEXTSERVER},
+    drone_registry::{self, DRONE_NAMES},
+    flood::FloodPolicy,
+    links::LinkParams,
+    mock_drone::MockPolicy,
+    sessions::SessionPolicy,
+    initializer::{DroneImplChoice, CHATAPP, WEBAPP},
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimConfig {
+    #[serde(default)]
+    pub simulation: SimulationSection,
+    #[serde(default)]
+    pub drone: Vec<DroneSection>,
+    #[serde(default)]
+    pub client: Vec<ClientSection>,
+    #[serde(default)]
+    pub server: Vec<ServerSection>,
+    #[serde(default)]
+    pub link: Vec<LinkSection>,
+}
+
+// The fields of a [[drone]] that aren't part of the protocol config
+// implementation = "<name>" | "<0-10>"
+// x = -120.0, y = 300.0 (where the controller draws it, every node type takes them)
+// mock = { nack = [0] } (only with implementation = "Mock", see mock_drone::MockPolicy)
+#[derive(Debug, Clone, Deserialize)]
+pub struct DroneSection {
+    pub id: NodeId,
+    pub implementation: Option<String>,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub mock: Option<MockPolicy>,
+}
+
+// flood = { max_ms = 10000 } (see flood::FloodPolicy), clients and servers take it
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientSection {
+    pub id: NodeId,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub flood: Option<FloodPolicy>,
+}
+
+// The fields of a [[server]] that aren't part of the protocol config
+// kind = "text" | "media" | "chat", web servers alternate between text and media without it
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerSection {
+    pub id: NodeId,
+    pub kind: Option<String>,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub flood: Option<FloodPolicy>,
+}
+
+// One direction of a link, both with `both = true`; the fields left out keep the
+// [simulation.links] ones (see links::LinkParams)
+// from = 1, to = 2, delay_ms = 50, jitter_ms = 10, bandwidth = 20000, queue = 32, loss = 0.1
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LinkSection {
+    pub from: NodeId,
+    pub to: NodeId,
+    #[serde(default)]
+    pub both: bool,
+    pub delay_ms: Option<u64>,
+    pub jitter_ms: Option<u64>,
+    pub bandwidth: Option<u64>,
+    pub queue: Option<usize>,
+    pub loss: Option<f32>,
+}
+
+impl LinkSection {
+    pub fn apply(&self, base: LinkParams) -> LinkParams {
+        LinkParams {
+            delay_ms: self.delay_ms.unwrap_or(base.delay_ms),
+            jitter_ms: self.jitter_ms.unwrap_or(base.jitter_ms),
+            bandwidth: self.bandwidth.unwrap_or(base.bandwidth),
+            queue: self.queue.unwrap_or(base.queue),
+            loss: self.loss.unwrap_or(base.loss),
+        }
+    }
+
+    pub fn directions(&self) -> Vec<(NodeId, NodeId)> {
+        if self.both {
+            vec![(self.from, self.to), (self.to, self.from)]
+        } else {
+            vec![(self.from, self.to)]
+        }
+    }
+}
+
+// [simulation]
+// app = "chat" | "web"
+// drone_impl = "<name>" | "<0-10>" | "mixed"
+// seed = 42
+// headless = false
+// [simulation.flood] the flood policy of every client and server without their own
+// [simulation.sessions] timeouts and memory caps of every client and server (see sessions::SessionPolicy)
+// [simulation.links] delay, jitter, bandwidth and queue of every link without a [[link]]
+// [simulation.mock] the policy of every mock drone without its own, spawned ones included
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimulationSection {
+    pub app: Option<String>,
+    pub drone_impl: Option<String>,
+    pub seed: Option<u64>,
+    pub headless: Option<bool>,
+    pub flood: Option<FloodPolicy>,
+    pub sessions: Option<SessionPolicy>,
+    pub links: Option<LinkParams>,
+    pub mock: Option<MockPolicy>,
+}
+
+impl SimConfig {
+    pub fn from_file(file: &str) -> Result<Self, String> {
+        let file_str = fs::read_to_string(file).map_err(|e| e.to_string())?;
+        Self::from_str(&file_str)
+    }
+
+    pub fn from_str(file_str: &str) -> Result<Self, String> {
+        toml::from_str(file_str).map_err(|e| e.to_string())
+    }
+}
+
+impl SimConfig {
+    // Drones pinned to an implementation, fails on names that aren't in the registry
+    pub fn implementations(&self) -> Result<HashMap<NodeId, usize>, String> {
+        self.drone
+            .iter()
+            .filter_map(|d| d.implementation.as_deref().map(|name| (d.id, name)))
+            .map(|(id, name)| match drone_registry::index_of(name) {
+                Some(index) => Ok((id, index)),
+                None => Err(format!(
+                    "Unknown implementation {} for drone {}, expected one of {:?}",
+                    name, id, DRONE_NAMES
+                )),
+            })
+            .collect()
+    }
+
+    // Policies of the mock drones that have one, the other mock drones follow `default_mock`
+    pub fn mock_policies(&self) -> Result<HashMap<NodeId, MockPolicy>, String> {
+        let implementations = self.implementations()?;
+        self.drone
+            .iter()
+            .filter_map(|d| Some((d.id, d.mock.clone()?)))
+            .map(|(id, policy)| match implementations.get(&id) {
+                Some(&drone_registry::MOCK) => Ok((id, policy)),
+                _ => Err(format!("Drone {} has a mock policy but isn't a Mock drone", id)),
+            })
+            .collect()
+    }
+
+    // Nodes with both coordinates set, the controller keeps them there
+    pub fn positions(&self) -> HashMap<NodeId, (f32, f32)> {
+        let drones = self.drone.iter().map(|d| (d.id, d.x, d.y));
+        let clients = self.client.iter().map(|c| (c.id, c.x, c.y));
+        let servers = self.server.iter().map(|s| (s.id, s.x, s.y));
+        drones
+            .chain(clients)
+            .chain(servers)
+            .filter_map(|(id, x, y)| Some((id, (x?, y?))))
+            .collect()
+    }
+
+    // Servers pinned to a kind, as the backup_server constants
+    pub fn server_kinds(&self) -> Result<HashMap<NodeId, u8>, String> {
+        self.server
+            .iter()
+            .filter_map(|s| s.kind.as_deref().map(|kind| (s.id, kind)))
+            .map(|(id, kind)| parse_server_kind(kind).map(|kind| (id, kind)))
+            .collect()
+    }
+}
+
+impl SimConfig {
+    // The flood policy of every client and server with one, the rest use `default_flood`
+    pub fn flood_policies(&self) -> Result<HashMap<NodeId, FloodPolicy>, String> {
+        let clients = self.client.iter().map(|c| (c.id, c.flood));
+        let servers = self.server.iter().map(|s| (s.id, s.flood));
+        clients
+            .chain(servers)
+            .filter_map(|(id, policy)| Some((id, policy?)))
+            .map(|(id, policy)| {
+                policy.check().map_err(|e| format!("Node {}: {}", id, e))?;
+                Ok((id, policy))
+            })
+            .collect()
+    }
+
+    pub fn default_flood(&self) -> Result<FloodPolicy, String> {
+        let policy = self.simulation.flood.unwrap_or_default();
+        policy.check()?;
+        Ok(policy)
+    }
+
+    pub fn session_policy(&self) -> Result<SessionPolicy, String> {
+        let policy = self.simulation.sessions.unwrap_or_default();
+        policy.check()?;
+        Ok(policy)
+    }
+
+    pub fn default_mock(&self) -> MockPolicy {
+        self.simulation.mock.clone().unwrap_or_default()
+    }
+
+    pub fn default_link(&self) -> Result<LinkParams, String> {
+        let params = self.simulation.links.unwrap_or_default();
+        params.check()?;
+        Ok(params)
+    }
+
+    // Parameters of every direction a [[link]] sets, a later entry wins over an earlier one
+    pub fn link_params(&self) -> Result<HashMap<(NodeId, NodeId), LinkParams>, String> {
+        let defaults = self.default_link()?;
+        let mut params = HashMap::new();
+        for link in &self.link {
+            for (from, to) in link.directions() {
+                let base = params.get(&(from, to)).copied().unwrap_or(defaults);
+                let set = link.apply(base);
+                set.check().map_err(|e| format!("Link {} -> {}: {}", from, to, e))?;
+                params.insert((from, to), set);
+            }
+        }
+        Ok(params)
+    }
+}
+
+impl SimulationSection {
+    pub fn app(&self) -> Result<Option<u8>, String> {
+        self.app.as_deref().map(parse_app).transpose()
+    }
+
+    pub fn drone_impl(&self) -> Result<Option<DroneImplChoice>, String> {
+        self.drone_impl.as_deref().map(parse_drone_impl).transpose()
+    }
+}
+
+pub fn parse_app(s: &str) -> Result<u8, String> {
+    match s.to_lowercase().as_str() {
+        "chat" => Ok(CHATAPP),
+        "web" => Ok(WEBAPP),
+        other => Err(format!("Unknown app {}, expected chat or web", other)),
+    }
+}
+
+pub fn parse_server_kind(s: &str) -> Result<u8, String> {
+    match s.to_lowercase().as_str() {
+        "text" => Ok(TEXTSERVER),
+        "media" => Ok(MEDIASERVER),
+        "chat" => Ok(CHATSERVER),
+        other => Err(format!("Unknown server kind {}, expected text, media or chat", other)),
+    }
+}
+
+pub fn server_kind_name(kind: u8) -> &'static str {
+    match kind {
+        TEXTSERVER => "text",
+        MEDIASERVER => "media",
+        _ => "chat",
+    }
+}
+
+pub fn parse_drone_impl(s: &str) -> Result<DroneImplChoice, String> {
+    if s.eq_ignore_ascii_case("mixed") {
+        return Ok(DroneImplChoice::Mixed);
+    }
+    drone_registry::index_of(s)
+        .map(DroneImplChoice::Single)
+        .ok_or_else(|| {
+            format!(
+                "Unknown drone implementation {}, expected mixed or one of {:?}",
+                s, DRONE_NAMES
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_simulation_section() {
+        let sim = SimConfig::from_str(
+            r#"
+            [simulation]
+            app = "web"
+            drone_impl = "rustroveri"
+            seed = 7
+
+            [[drone]]
+            id = 1
+            connected_node_ids = [2]
+            pdr = 0.0
+            implementation = "Rusteze"
+
+            [[drone]]
+            id = 2
+            connected_node_ids = [1]
+            pdr = 0.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sim.implementations(), Ok(HashMap::from([(1, 9)])));
+        assert_eq!(sim.simulation.app(), Ok(Some(WEBAPP)));
+        assert_eq!(sim.simulation.drone_impl(), Ok(Some(DroneImplChoice::Single(2))));
+        assert_eq!(sim.simulation.seed, Some(7));
+        assert_eq!(sim.simulation.headless, None);
+    }
+
+    #[test]
+    fn test_no_simulation_section() {
+        let sim = SimConfig::from_file("./configs/config.toml").unwrap();
+        assert!(sim.simulation.app.is_none());
+        assert_eq!(sim.drone.len(), 3);
+        assert!(sim.implementations().unwrap().is_empty());
+        assert_eq!(sim.simulation.drone_impl(), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_drone_impl() {
+        assert_eq!(parse_drone_impl("Mixed"), Ok(DroneImplChoice::Mixed));
+        assert_eq!(parse_drone_impl("9"), Ok(DroneImplChoice::Single(9)));
+        assert_eq!(parse_drone_impl("D.R.O.N.E"), Ok(DroneImplChoice::Single(5)));
+        assert_eq!(parse_drone_impl("mock"), Ok(DroneImplChoice::Single(drone_registry::MOCK)));
+        assert!(parse_drone_impl("11").is_err());
+        assert!(parse_drone_impl("nope").is_err());
+    }
+
+    #[test]
+    fn test_server_kinds() {
+        let sim = SimConfig::from_str(
+            r#"
+            [[server]]
+            id = 31
+            connected_drone_ids = [1, 2]
+            kind = "Media"
+
+            [[server]]
+            id = 32
+            connected_drone_ids = [1, 2]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sim.server_kinds(), Ok(HashMap::from([(31, MEDIASERVER)])));
+        assert_eq!(server_kind_name(MEDIASERVER), "media");
+        assert!(parse_server_kind("video").is_err());
+    }
+
+    #[test]
+    fn test_unknown_implementation() {
+        let sim = SimConfig::from_str(
+            r#"
+            [[drone]]
+            id = 4
+            implementation = "Parrot"
+            "#,
+        )
+        .unwrap();
+        assert!(sim.implementations().is_err());
+    }
+
+    #[test]
+    fn test_mock_policies() {
+        let sim = SimConfig::from_str(
+            r#"
+            [[drone]]
+            id = 1
+            implementation = "Mock"
+            mock = { nack = [0, 3], times = 0, crash_after = 50 }
+
+            [[drone]]
+            id = 2
+            implementation = "mock"
+            "#,
+        )
+        .unwrap();
+        let policy = MockPolicy { nack: vec![0, 3], times: 0, crash_after: Some(50), ..Default::default() };
+        assert_eq!(sim.mock_policies(), Ok(HashMap::from([(1, policy)])));
+        assert_eq!(sim.default_mock(), MockPolicy::default());
+
+        let sim = SimConfig::from_str(
+            r#"
+            [simulation.mock]
+            delay_ms = 20
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sim.default_mock(), MockPolicy { delay_ms: 20, ..Default::default() });
+
+        let sim = SimConfig::from_str(
+            r#"
+            [[drone]]
+            id = 1
+            implementation = "Rusteze"
+            mock = { drop = [1] }
+            "#,
+        )
+        .unwrap();
+        assert!(sim.mock_policies().is_err());
+    }
+
+    #[test]
+    fn test_flood_policies() {
+        let sim = SimConfig::from_str(
+            r#"
+            [simulation.flood]
+            max_ms = 30000
+
+            [[client]]
+            id = 21
+            connected_drone_ids = [1]
+            flood = { base_ms = 5000, max_ms = 5000, factor = 1 }
+
+            [[server]]
+            id = 31
+            connected_drone_ids = [1]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sim.default_flood().unwrap().max_ms, 30_000);
+        let policies = sim.flood_policies().unwrap();
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[&21].base_ms, 5_000);
+        assert_eq!(policies[&21].min_gap_ms, FloodPolicy::default().min_gap_ms);
+
+        let sim = SimConfig::from_str("[[server]]\nid = 31\nflood = { factor = 0 }").unwrap();
+        assert!(sim.flood_policies().is_err());
+    }
+
+    #[test]
+    fn test_session_policy() {
+        let sim = SimConfig::from_str("[simulation.sessions]\nincomplete_timeout_ms = 5000").unwrap();
+        let policy = sim.session_policy().unwrap();
+        assert_eq!(policy.incomplete_timeout_ms, 5_000);
+        assert_eq!(policy.max_fragments, SessionPolicy::default().max_fragments);
+        assert_eq!(SimConfig::default().session_policy().unwrap(), SessionPolicy::default());
+
+        let sim = SimConfig::from_str("[simulation.sessions]\nmax_fragments = 0").unwrap();
+        assert!(sim.session_policy().is_err());
+    }
+
+    #[test]
+    fn test_link_params() {
+        let sim = SimConfig::from_str(
+            r#"
+            [simulation.links]
+            delay_ms = 20
+
+            [[link]]
+            from = 1
+            to = 2
+            both = true
+            bandwidth = 10000
+
+            [[link]]
+            from = 2
+            to = 1
+            delay_ms = 100
+            jitter_ms = 30
+            loss = 0.25
+            "#,
+        )
+        .unwrap();
+        let params = sim.link_params().unwrap();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[&(1, 2)], LinkParams { delay_ms: 20, bandwidth: 10_000, ..Default::default() });
+        assert_eq!(
+            params[&(2, 1)],
+            LinkParams { delay_ms: 100, jitter_ms: 30, bandwidth: 10_000, loss: 0.25, ..Default::default() }
+        );
+
+        let sim = SimConfig::from_str("[[link]]\nfrom = 1\nto = 2\njitter_ms = 5").unwrap();
+        assert!(sim.link_params().is_err());
+        let sim = SimConfig::from_str("[[link]]\nfrom = 1\nto = 2\nloss = 2.0").unwrap();
+        assert!(sim.link_params().is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use bevy::log::info;
-use wg_2024::{network::NodeId, packet::NodeType};
+use serde::Serialize;
+use wg_2024::{config::Config, network::NodeId, packet::NodeType};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Node {
@@ -25,6 +26,121 @@ impl Node {
     }
 }
 
+// Copy of what a node learned, sent to the controller on NodeCommand::ShowTopology
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct TopologySnapshot {
+    pub nodes: Vec<(NodeId, NodeType)>,
+    pub edges: Vec<(NodeId, NodeId)>, // lower id first, sorted
+    pub paths: Vec<(Vec<NodeId>, u64)>, // with their weights
+    pub current_path: Option<(Vec<NodeId>, u64)>,
+}
+
+impl TopologySnapshot {
+    // The ground truth of a config, it has no paths since nobody routes on it
+    pub fn from_config(config: &Config) -> Self {
+        let mut nodes = config
+            .drone
+            .iter()
+            .map(|d| (d.id, NodeType::Drone))
+            .chain(config.client.iter().map(|c| (c.id, NodeType::Client)))
+            .chain(config.server.iter().map(|s| (s.id, NodeType::Server)))
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|(id, _)| *id);
+        let mut edges = config
+            .drone
+            .iter()
+            .map(|d| (d.id, &d.connected_node_ids))
+            .chain(config.client.iter().map(|c| (c.id, &c.connected_drone_ids)))
+            .chain(config.server.iter().map(|s| (s.id, &s.connected_drone_ids)))
+            .flat_map(|(id, others)| others.iter().map(move |other| (id.min(*other), id.max(*other))))
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        Self { nodes, edges, ..Self::default() }
+    }
+
+    // Graphviz undirected graph, the links of the current path are drawn bold and red
+    pub fn to_dot(&self, name: &str) -> String {
+        let on_path = |a: NodeId, b: NodeId| {
+            self.current_path
+                .as_ref()
+                .is_some_and(|(path, _)| path.windows(2).any(|w| (w[0], w[1]) == (a, b) || (w[1], w[0]) == (a, b)))
+        };
+        let mut dot = format!("graph \"{}\" {{\n", name.replace('"', "'"));
+        for (id, node_type) in &self.nodes {
+            let shape = match node_type {
+                NodeType::Drone => "circle",
+                NodeType::Client => "box",
+                NodeType::Server => "doublecircle",
+            };
+            dot.push_str(&format!(
+                "    {} [label=\"{:?} {}\", shape={}];\n",
+                id, node_type, id, shape
+            ));
+        }
+        for (a, b) in &self.edges {
+            if on_path(*a, *b) {
+                dot.push_str(&format!("    {} -- {} [color=red, penwidth=2];\n", a, b));
+            } else {
+                dot.push_str(&format!("    {} -- {};\n", a, b));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let export = SnapshotJson {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(id, node_type)| NodeJson { id: *id, kind: kind_name(*node_type) })
+                .collect(),
+            edges: self.edges.clone(),
+            paths: self
+                .paths
+                .iter()
+                .map(|(hops, weight)| PathJson { hops: hops.clone(), weight: *weight })
+                .collect(),
+            current_path: self
+                .current_path
+                .as_ref()
+                .map(|(hops, weight)| PathJson { hops: hops.clone(), weight: *weight }),
+        };
+        // nothing in it can fail to serialize
+        serde_json::to_string_pretty(&export).unwrap()
+    }
+}
+
+// wg_2024 types aren't Serialize, the json goes through these
+#[derive(Serialize)]
+struct SnapshotJson {
+    nodes: Vec<NodeJson>,
+    edges: Vec<(NodeId, NodeId)>,
+    paths: Vec<PathJson>,
+    current_path: Option<PathJson>,
+}
+
+#[derive(Serialize)]
+struct NodeJson {
+    id: NodeId,
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct PathJson {
+    hops: Vec<NodeId>,
+    weight: u64,
+}
+
+fn kind_name(node_type: NodeType) -> &'static str {
+    match node_type {
+        NodeType::Drone => "drone",
+        NodeType::Client => "client",
+        NodeType::Server => "server",
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Topology {
     nodes: HashMap<NodeId, Node>,
@@ -49,6 +165,46 @@ impl Topology {
             .collect()
     }
 
+    pub fn snapshot(&self) -> TopologySnapshot {
+        let mut nodes = self
+            .nodes
+            .values()
+            .map(|node| (node.value, node.node_type))
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|(id, _)| *id);
+        let mut edges = self
+            .nodes
+            .values()
+            .flat_map(|node| {
+                node.adjacents
+                    .iter()
+                    .map(move |(id, _)| (node.value.min(*id), node.value.max(*id)))
+            })
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        TopologySnapshot {
+            nodes,
+            edges,
+            paths: self.paths.clone().unwrap_or_default(),
+            current_path: self.current_path.clone(),
+        }
+    }
+
+    pub fn to_dot(&self, name: &str) -> String {
+        self.snapshot().to_dot(name)
+    }
+
+    pub fn to_json(&self) -> String {
+        self.snapshot().to_json()
+    }
+
+    // Known nodes and links, a link counts once even if both ends list it
+    pub fn size(&self) -> (usize, usize) {
+        let adjacencies = self.nodes.values().map(|node| node.adjacents.len()).sum::<usize>();
+        (self.nodes.len(), adjacencies.div_ceil(2))
+    }
+
     pub fn get_all_clients(&self) -> Vec<NodeId> {
         self.nodes
             .values()
@@ -383,4 +539,29 @@ mod tests {
         let neighbors = topology.get_neighbors(1).unwrap();
         assert_eq!(neighbors, vec![2]);
     }
+
+    #[test]
+    fn test_export() {
+        let mut topology = Topology::new();
+        topology.update_topology(
+            (21, NodeType::Client),
+            vec![(1, NodeType::Drone), (2, NodeType::Drone), (31, NodeType::Server)],
+        );
+        topology.find_all_paths(21, 31);
+        topology.update_current_path();
+
+        let dot = topology.to_dot("client 21");
+        assert!(dot.starts_with("graph \"client 21\" {"));
+        assert!(dot.contains("21 [label=\"Client 21\", shape=box];"));
+        assert!(dot.contains("31 [label=\"Server 31\", shape=doublecircle];"));
+        assert!(dot.contains("1 -- 21 [color=red, penwidth=2];"));
+        assert!(dot.trim_end().ends_with('}'));
+
+        let json: serde_json::Value = serde_json::from_str(&topology.to_json()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 4);
+        assert_eq!(json["nodes"][0]["kind"], "drone");
+        assert_eq!(json["edges"], serde_json::json!([[1, 2], [1, 21], [2, 31]]));
+        assert_eq!(json["current_path"]["hops"], serde_json::json!([21, 1, 2, 31]));
+        assert_eq!(json["paths"].as_array().unwrap().len(), 1);
+    }
 }